@@ -36,6 +36,8 @@ pub struct ApkBuild {
 
     /// A list of "virtual provisions" that this package provides
     provides: Vec<String>,
+    /// A list of packages whose files this package is allowed to overwrite
+    replaces: Vec<String>,
     /// A list of packages this package depends on to run
     depends: Vec<String>,
     /// A list of packages this package depends on to build
@@ -118,6 +120,7 @@ impl ApkBuild {
         push_array!(arch);
         push_array!(license);
         push_array!(provides);
+        push_array!(replaces);
         push_array!(depends);
         push_array!(makedepends);
         push_if_some!(install);
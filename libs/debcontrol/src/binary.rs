@@ -50,6 +50,8 @@ pub struct BinaryDebControl {
     provides: Vec<String>,
     replaces: Vec<String>,
     enchances: Vec<String>,
+    /// Additional fields in the `Name: value` format appended to the control file
+    user_defined_fields: Vec<String>,
 }
 
 impl BinaryDebControl {
@@ -128,9 +130,14 @@ Essential:      {}
         if_not_empty_entries!(conflicts,   "Conflicts:      {}\n");
         if_not_empty_entries!(provides,    "Provides:       {}\n");
         if_not_empty_entries!(replaces,    "Replaces:       {}\n");
-        if_not_empty_entries!(enchances,   "Enchances:      {}\n");
+        if_not_empty_entries!(enchances,   "Enhances:       {}\n");
         };
 
+        for field in &self.user_defined_fields {
+            control.push_str(field);
+            control.push('\n');
+        }
+
         control
     }
 }
@@ -170,6 +177,7 @@ mod tests {
             ],
             replaces: vec!["rustc".to_string(), "cargo".to_string()],
             enchances: vec!["rustc".to_string(), "cargo".to_string()],
+            user_defined_fields: vec!["Pkger-Commit: 0ab32f".to_string()],
         };
         const OUT: &str = r#"Package:        debcontrol
 Version:        1.0.0-0
@@ -187,7 +195,8 @@ Depends:        rustc, cargo
 Conflicts:      rustc, cargo
 Provides:       rustc, cargo, debcontrol
 Replaces:       rustc, cargo
-Enchances:      rustc, cargo
+Enhances:       rustc, cargo
+Pkger-Commit: 0ab32f
 "#;
         let got = DebControlBuilder::binary_package_builder("debcontrol")
             .source("package.tar.gz")
@@ -207,6 +216,7 @@ Enchances:      rustc, cargo
             .add_replaces_entries(vec!["rustc", "cargo"])
             .add_enchances_entries(vec!["rustc", "cargo"])
             .add_provides_entries(vec!["debcontrol"])
+            .add_user_defined_fields_entries(vec!["Pkger-Commit: 0ab32f"])
             .build();
         println!("{}", got.render());
 
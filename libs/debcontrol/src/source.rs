@@ -145,7 +145,7 @@ Essential:           {}
         if_not_empty_entries!(conflicts,   "Conflicts:           {}\n");
         if_not_empty_entries!(provides,    "Provides:            {}\n");
         if_not_empty_entries!(replaces,    "Replaces:            {}\n");
-        if_not_empty_entries!(enchances,   "Enchances:           {}\n");
+        if_not_empty_entries!(enchances,   "Enhances:            {}\n");
         };
 
         control
@@ -0,0 +1,215 @@
+use pkgspec::SpecStruct;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, PartialEq, SpecStruct)]
+pub struct Ebuild {
+    /// Version of the ebuild API, defaults to 8
+    eapi: Option<String>,
+    /// Eclasses inherited by the ebuild
+    inherit: Vec<String>,
+
+    /// A short description of the package
+    description: String,
+    /// Homepage of the package
+    homepage: Option<String>,
+    /// A list of source files required to build the package
+    src_uri: Vec<String>,
+    /// URI of the git repository fetched by the `git-r3` eclass
+    egit_repo_uri: Option<String>,
+    /// Branch of the git repository
+    egit_branch: Option<String>,
+    /// Commit or tag of the git repository
+    egit_commit: Option<String>,
+    /// Directory of the git checkout
+    egit_checkout_dir: Option<String>,
+    /// Path to the directory of unpacked sources
+    s: Option<String>,
+
+    /// License(s) of the package
+    license: Vec<String>,
+    /// Slot of the package, defaults to 0
+    slot: Option<String>,
+    /// Architectures on which the package is known to work like `~amd64`
+    keywords: Vec<String>,
+    /// USE flags used by the ebuild
+    iuse: Vec<String>,
+    /// Features like `test` or `strip` that are disabled for the package
+    restrict: Vec<String>,
+
+    /// A list of packages this package depends on to build and link against
+    depend: Vec<String>,
+    /// A list of packages this package depends on to run
+    rdepend: Vec<String>,
+    /// A list of packages this package depends on to build that run on the build host
+    bdepend: Vec<String>,
+
+    /// Specifies the src_prepare function of the ebuild
+    src_prepare: Option<String>,
+    /// Specifies the src_configure function of the ebuild
+    src_configure: Option<String>,
+    /// Specifies the src_compile function of the ebuild
+    src_compile: Option<String>,
+    /// Specifies the src_install function of the ebuild
+    src_install: Option<String>,
+}
+
+impl Ebuild {
+    /// Renders this ebuild and saves it to the given path
+    pub fn save_to<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        fs::write(path, self.render())
+    }
+
+    /// Renders this ebuild
+    pub fn render(&self) -> String {
+        let mut ebuild = format!("EAPI={}\n", self.eapi.as_deref().unwrap_or("8"));
+
+        if !self.inherit.is_empty() {
+            ebuild.push_str(&format!("\ninherit {}\n", self.inherit.join(" ")));
+        }
+        ebuild.push('\n');
+
+        macro_rules! format_value {
+            ($key:expr, $value:expr) => {
+                ebuild.push_str(&format!(
+                    "{}=\"{}\"\n",
+                    $key.to_uppercase(),
+                    $value.replace('\\', "\\\\").replace('"', "\\\"")
+                ));
+            };
+        }
+
+        macro_rules! push_field {
+            ($field:ident) => {
+                format_value!(stringify!($field), &self.$field);
+            };
+        }
+
+        macro_rules! push_if_some {
+            ($field:ident) => {
+                if let Some(value) = &self.$field {
+                    format_value!(stringify!($field), value);
+                }
+            };
+        }
+
+        macro_rules! push_array {
+            ($field:ident) => {
+                if !self.$field.is_empty() {
+                    format_value!(stringify!($field), self.$field.join(" "));
+                }
+            };
+        }
+
+        // lists of packages and files are easier to read with one entry per line
+        macro_rules! push_list {
+            ($field:ident) => {
+                match self.$field.len() {
+                    0 => {}
+                    1 => push_array!($field),
+                    _ => {
+                        ebuild.push_str(&format!(
+                            "{}=\"\n\t{}\n\"\n",
+                            stringify!($field).to_uppercase(),
+                            self.$field.join("\n\t")
+                        ));
+                    }
+                }
+            };
+        }
+
+        macro_rules! push_func {
+            ($field:ident) => {
+                if let Some(body) = &self.$field {
+                    ebuild.push_str(&format!("\n{}() {{\n{}\n}}\n", stringify!($field), body));
+                }
+            };
+        }
+
+        push_field!(description);
+        push_if_some!(homepage);
+        push_list!(src_uri);
+        push_if_some!(egit_repo_uri);
+        push_if_some!(egit_branch);
+        push_if_some!(egit_commit);
+        push_if_some!(egit_checkout_dir);
+        push_if_some!(s);
+        ebuild.push('\n');
+
+        push_array!(license);
+        format_value!("slot", self.slot.as_deref().unwrap_or("0"));
+        push_array!(keywords);
+        push_array!(iuse);
+        push_array!(restrict);
+
+        if !(self.depend.is_empty() && self.rdepend.is_empty() && self.bdepend.is_empty()) {
+            ebuild.push('\n');
+        }
+        push_list!(rdepend);
+        push_list!(depend);
+        push_list!(bdepend);
+
+        push_func!(src_prepare);
+        push_func!(src_configure);
+        push_func!(src_compile);
+        push_func!(src_install);
+
+        ebuild
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn builds_an_ebuild() {
+        let got = Ebuild::builder()
+            .description("short \"description\"...")
+            .homepage("https://some.invalid.url")
+            .add_src_uri_entries(vec!["https://some.invalid.url/${P}.tar.gz"])
+            .s("${WORKDIR}")
+            .add_license_entries(vec!["MIT"])
+            .add_keywords_entries(vec!["~amd64"])
+            .add_rdepend_entries(vec!["dev-libs/openssl", "sys-libs/zlib"])
+            .add_depend_entries(vec!["${RDEPEND}"])
+            .src_compile("\tcargo build --release || die")
+            .src_install("\tcd \"${D}\" || die\n\tinstall -D target/release/bin usr/bin/bin || die")
+            .build()
+            .render();
+
+        let expect = r#"EAPI=8
+
+DESCRIPTION="short \"description\"..."
+HOMEPAGE="https://some.invalid.url"
+SRC_URI="https://some.invalid.url/${P}.tar.gz"
+S="${WORKDIR}"
+
+LICENSE="MIT"
+SLOT="0"
+KEYWORDS="~amd64"
+
+RDEPEND="
+	dev-libs/openssl
+	sys-libs/zlib
+"
+DEPEND="${RDEPEND}"
+
+src_compile() {
+	cargo build --release || die
+}
+
+src_install() {
+	cd "${D}" || die
+	install -D target/release/bin usr/bin/bin || die
+}
+"#;
+
+        assert_eq!(expect, got);
+    }
+}
@@ -1,4 +1,5 @@
 use pkgspec::SpecStruct;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io;
 use std::path::Path;
@@ -18,6 +19,9 @@ pub struct RpmSpec {
     /// The full URL for more information about the program. Most often this is the upstream project website for the
     /// software being packaged.
     url: Option<String>,
+    /// Version control system location of the packaged source like `git+https://host/repo#commit`.
+    /// Requires RPM 4.15 or newer.
+    vcs: Option<String>,
     /// Copyright notice
     copyright: Option<String>,
     /// A person maintaining this package
@@ -56,6 +60,12 @@ pub struct RpmSpec {
     provides: Vec<String>,
     /// Packages that are required by this package at runtime
     requires: Vec<String>,
+    /// Packages that have to be installed before the `%pre` scriptlet runs
+    requires_pre: Vec<String>,
+    /// Packages that have to be installed before the `%post` scriptlet runs
+    requires_post: Vec<String>,
+    /// Packages that have to be installed until the `%preun` scriptlet runs
+    requires_preun: Vec<String>,
     /// Packages that are required by this package during the build
     build_requires: Vec<String>,
 
@@ -81,6 +91,9 @@ pub struct RpmSpec {
     preun_script: Option<String>,
     /// Scriptlet that is executed just after the package is uninstalled from the targetsystem.
     postun_script: Option<String>,
+    /// Scriptlet that is executed at the end of the transaction, after all packages were installed
+    /// or upgraded.
+    posttrans_script: Option<String>,
 
     files: Vec<String>,
     /// This identifies the file listed as documentation and it will be installed and labeled as such by RPM. This is
@@ -95,6 +108,12 @@ pub struct RpmSpec {
     ///
     /// Example: `%{_libdir}/%{name}`
     dir_files: Vec<String>,
+    /// Configuration files that are replaced on upgrade, the modified file on the target system is
+    /// saved with .rpmsave appended to the end of the filename.
+    config_files: Vec<String>,
+    /// Configuration files that are not replaced on upgrade if they were modified, the new file is
+    /// saved with .rpmnew appended to the end of the filename instead.
+    config_noreplace_files: Vec<String>,
     /// Specifies that the following file is a configuration file and therefore should not be overwritten (or replaced)
     /// on a package install or update if the file has been modified from the original installation checksum. In the event
     /// that there is a change, the file will be created with .rpmnew appended to the end of the filename upon upgrade or
@@ -113,6 +132,11 @@ pub struct RpmSpec {
     /// Set the value of `AutoReqProv` field in the spec. If set to `false` RPM won't do automatic
     /// dependencies processing.
     auto_req_prov: Option<bool>,
+
+    #[skip]
+    /// Mode, owner and group of files in the `%files` section rendered as `%attr` like
+    /// `0755,root,root`
+    file_attrs: BTreeMap<String, String>,
 }
 
 impl RpmSpec {
@@ -156,16 +180,6 @@ impl RpmSpec {
                     }
                 }
             };
-            (file $field:ident, $name:expr) => {
-                if !self.$field.is_empty() {
-                    spec.push_str(&format!("\n%{}\n", $name));
-                    for entry in &self.$field {
-                        spec.push('"');
-                        spec.push_str(entry.as_str());
-                        spec.push_str("\"\n");
-                    }
-                }
-            };
         }
 
         macro_rules! if_some_script {
@@ -183,6 +197,7 @@ impl RpmSpec {
         if_some_push!(epoch,                  "Epoch:         {}\n");
         if_some_push!(vendor,                 "Vendor:        {}\n");
         if_some_push!(url,                    "URL:           {}\n");
+        if_some_push!(vcs,                    "VCS:           {}\n");
         if_some_push!(copyright,              "Copyright:     {}\n");
         if_some_push!(packager,               "Packager:      {}\n");
         if_some_push!(group,                  "Group:         {}\n");
@@ -195,6 +210,9 @@ impl RpmSpec {
         if_not_empty_entries!(obsoletes,      "obsoletes:     {}\n");
         if_not_empty_entries!(provides,       "provides:      {}\n");
         if_not_empty_entries!(requires,       "requires:      {}\n");
+        if_not_empty_entries!(requires_pre,   "Requires(pre): {}\n");
+        if_not_empty_entries!(requires_post,  "Requires(post): {}\n");
+        if_not_empty_entries!(requires_preun, "Requires(preun): {}\n");
         if_not_empty_entries!(build_requires, "BuildRequires: {}\n");
         if_not_empty_entries!(..i patches,    "Patch{}:        {}\n");
         if_not_empty_entries!(..i sources,    "Source{}:       {}\n");
@@ -213,17 +231,11 @@ impl RpmSpec {
         if_some_script!("post", post_script);
         if_some_script!("preun", preun_script);
         if_some_script!("postun", postun_script);
+        if_some_script!("posttrans", posttrans_script);
         if_not_empty_entries!(macros, "%global {}\n");
         };
         spec.push_str("\n%files\n");
-        for entry in &self.files {
-            spec.push('"');
-            spec.push_str(entry.as_str());
-            spec.push_str("\"\n");
-        }
-        if_not_empty_entries!(file doc_files, "doc");
-        if_not_empty_entries!(file license_files, "license");
-        if_not_empty_entries!(file dir_files, "dir");
+        spec.push_str(&self.render_files());
         spec.push_str("\n%changelog\n");
         for entry in &self.changelog {
             spec.push_str(entry.as_str());
@@ -232,6 +244,35 @@ impl RpmSpec {
 
         spec
     }
+
+    /// Renders the entries of the `%files` section without the section header.
+    pub fn render_files(&self) -> String {
+        let mut files = String::new();
+        let attr = |entry: &str| {
+            self.file_attrs
+                .get(entry)
+                .map(|attr| format!("%attr({}) ", attr))
+                .unwrap_or_default()
+        };
+        for entry in &self.files {
+            files.push_str(&attr(entry));
+            files.push('"');
+            files.push_str(entry.as_str());
+            files.push_str("\"\n");
+        }
+        for (attribute, entries) in [
+            ("doc", &self.doc_files),
+            ("license", &self.license_files),
+            ("dir", &self.dir_files),
+            ("config", &self.config_files),
+            ("config(noreplace)", &self.config_noreplace_files),
+        ] {
+            for entry in entries {
+                files.push_str(&format!("{}%{} \"{}\"\n", attr(entry), attribute, entry));
+            }
+        }
+        files
+    }
 }
 
 impl RpmSpecBuilder {
@@ -254,6 +295,17 @@ impl RpmSpecBuilder {
         self.inner.auto_req_prov = Some(false);
         self
     }
+
+    /// Sets the `%attr` of the `file` listed in the `%files` section, like `0755,root,root`.
+    /// A `-` keeps the mode, owner or group of the file in the build root.
+    pub fn add_file_attr<F, A>(mut self, file: F, attr: A) -> Self
+    where
+        F: Into<String>,
+        A: Into<String>,
+    {
+        self.inner.file_attrs.insert(file.into(), attr.into());
+        self
+    }
 }
 
 #[cfg(test)]
@@ -277,6 +329,7 @@ install -m 644 README /docs/README"#;
             .prep_script(r#"cat /etc/os-release"#)
             .check_script("uptime")
             .url("https://some.invalid.url")
+            .vcs("git:https://some.invalid.url/rpmspec.git")
             .version("0.1.0")
             .release("1")
             .epoch("42")
@@ -293,8 +346,13 @@ install -m 644 README /docs/README"#;
             .add_files_entries(vec!["/bin/test.bin", "/docs/README"])
             .add_doc_files_entries(vec!["README"])
             .add_license_files_entries(vec!["LICENSE"])
+            .add_config_files_entries(vec!["/etc/rpmspec/defaults.conf"])
+            .add_config_noreplace_files_entries(vec!["/etc/rpmspec/rpmspec.conf"])
             .add_provides_entries(vec!["rpmspec"])
             .add_requires_entries(vec!["rust"])
+            .add_requires_pre_entries(vec!["shadow-utils"])
+            .add_requires_post_entries(vec!["alternatives"])
+            .add_requires_preun_entries(vec!["alternatives"])
             .add_build_requires_entries(vec!["rust", "cargo"])
             .add_obsoletes_entries(vec!["rpmspec-old"])
             .add_conflicts_entries(vec!["rpmspec2"])
@@ -303,8 +361,11 @@ install -m 644 README /docs/README"#;
             .post_script("false")
             .preun_script("echo 123")
             .postun_script("true")
+            .posttrans_script("systemctl daemon-reload")
             .add_macro("githash", None::<&str>, "0ab32f")
             .add_macro("python", Some("-c"), "import os")
+            .add_file_attr("/bin/test.bin", "0755,root,root")
+            .add_file_attr("/etc/rpmspec/rpmspec.conf", "0640,-,rpmspec")
             .disable_auto_req_prov()
             .build();
 
@@ -317,6 +378,7 @@ install -m 644 README /docs/README"#;
             epoch: Some("42".to_string()),
             vendor: Some("Vendor".to_string()),
             url: Some("https://some.invalid.url".to_string()),
+            vcs: Some("git:https://some.invalid.url/rpmspec.git".to_string()),
             copyright: Some("2021 test".to_string()),
             build_arch: Some("noarch".to_string()),
             exclude_arch: Some("x86_64".to_string()),
@@ -337,14 +399,20 @@ install -m 644 README /docs/README"#;
             post_script: Some("false".to_string()),
             preun_script: Some("echo 123".to_string()),
             postun_script: Some("true".to_string()),
+            posttrans_script: Some("systemctl daemon-reload".to_string()),
             files: vec!["/bin/test.bin".to_string(), "/docs/README".to_string()],
             doc_files: vec!["README".to_string()],
             license_files: vec!["LICENSE".to_string()],
             dir_files: vec![],
+            config_files: vec!["/etc/rpmspec/defaults.conf".to_string()],
+            config_noreplace_files: vec!["/etc/rpmspec/rpmspec.conf".to_string()],
             conflicts: vec!["rpmspec2".to_string()],
             obsoletes: vec!["rpmspec-old".to_string()],
             provides: vec!["rpmspec".to_string()],
             requires: vec!["rust".to_string()],
+            requires_pre: vec!["shadow-utils".to_string()],
+            requires_post: vec!["alternatives".to_string()],
+            requires_preun: vec!["alternatives".to_string()],
             build_requires: vec!["rust".to_string(), "cargo".to_string()],
             config_noreplace: Some("%{_sysconfdir}/%{name}/%{name}.conf".to_string()),
             changelog: vec![],
@@ -353,6 +421,13 @@ install -m 644 README /docs/README"#;
                 "python(-c) import os".to_string(),
             ],
             auto_req_prov: Some(false),
+            file_attrs: BTreeMap::from([
+                ("/bin/test.bin".to_string(), "0755,root,root".to_string()),
+                (
+                    "/etc/rpmspec/rpmspec.conf".to_string(),
+                    "0640,-,rpmspec".to_string(),
+                ),
+            ]),
         };
 
         assert_eq!(expect, spec);
@@ -364,6 +439,7 @@ Summary:       short summary
 Epoch:         42
 Vendor:        Vendor
 URL:           https://some.invalid.url
+VCS:           git:https://some.invalid.url/rpmspec.git
 Copyright:     2021 test
 Packager:      Wojciech Kępka <wojciech@wkepka.dev>
 Group:         group
@@ -376,6 +452,9 @@ Conflicts:     rpmspec2
 obsoletes:     rpmspec-old
 provides:      rpmspec
 requires:      rust
+Requires(pre): shadow-utils
+Requires(post): alternatives
+Requires(preun): alternatives
 BuildRequires: rust
 BuildRequires: cargo
 Patch0:        patch.1
@@ -413,18 +492,19 @@ echo 123
 %postun
 true
 
+%posttrans
+systemctl daemon-reload
+
 %global githash 0ab32f
 %global python(-c) import os
 
 %files
-"/bin/test.bin"
+%attr(0755,root,root) "/bin/test.bin"
 "/docs/README"
-
-%doc
-"README"
-
-%license
-"LICENSE"
+%doc "README"
+%license "LICENSE"
+%config "/etc/rpmspec/defaults.conf"
+%attr(0640,-,rpmspec) %config(noreplace) "/etc/rpmspec/rpmspec.conf"
 
 %changelog
 "#;
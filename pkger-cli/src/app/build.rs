@@ -1,19 +1,174 @@
 use crate::app::Application;
+use crate::ci::{Annotation, CiAnnotations};
+use crate::exit::ExitCode;
 use crate::job::{JobCtx, JobResult};
-use crate::opts::BuildOpts;
-use pkger_core::build::{container::SESSION_LABEL_KEY, Context};
+use crate::lint;
+use crate::logs::{self, JOB_SPAN};
+use crate::opts::{BuildOpts, WarmupOpts};
+use crate::preview;
+use crate::progress::ProgressFormat;
+use crate::summary::{self, JobSummary};
+use pkger_core::build::{container::SESSION_LABEL_KEY, deps, progress::ProgressSender, Context};
 use pkger_core::container;
 use pkger_core::docker::DockerConnectionPool;
 use pkger_core::image::Image;
-use pkger_core::recipe::{BuildTarget, ImageTarget, Recipe};
+use pkger_core::recipe::{BuildArch, BuildTarget, ImageTarget, Recipe, Source};
+use pkger_core::units::{format_duration, format_size};
 use pkger_core::{err, ErrContext, Error, Result};
 
+use futures::channel::mpsc;
 use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::Arc;
 use tokio::task;
 use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 
+/// Free space always required in the output directory on top of the size of packages estimated
+/// from previous builds.
+const MIN_FREE_SPACE: u64 = 100 * 1024 * 1024;
+
+/// Options of a build session that apply to all of its tasks.
+#[derive(Clone, Debug)]
+pub struct SessionOpts {
+    pub quiet: bool,
+    pub commit_image: Option<String>,
+    pub progress: Option<ProgressFormat>,
+    pub skip_up_to_date: bool,
+    pub retry_failed: u32,
+    pub publish: bool,
+    /// Only add built packages to the signing manifest instead of signing them
+    pub no_sign: bool,
+    pub ci_annotations: Option<CiAnnotations>,
+}
+
+impl SessionOpts {
+    pub fn new(opts: &BuildOpts, quiet: bool) -> Self {
+        Self {
+            quiet,
+            commit_image: opts.commit_image.clone(),
+            progress: opts.progress,
+            skip_up_to_date: opts.skip_up_to_date,
+            retry_failed: opts.retry_failed,
+            publish: opts.publish,
+            no_sign: opts.no_sign || opts.sandbox,
+            ci_annotations: opts.ci_annotations,
+        }
+    }
+}
+
+/// Files changed since a git revision in the repository of the recipes and in the repositories
+/// of local files used by the recipes.
+struct ChangedFiles<'a> {
+    rev: &'a str,
+    /// Changed files by the top level directory of their repository, `None` if the repository
+    /// doesn't have the revision
+    repos: HashMap<PathBuf, Option<HashSet<PathBuf>>>,
+}
+
+impl<'a> ChangedFiles<'a> {
+    /// Finds the files changed since `rev` in the repository of `recipes_dir`, which has to be a
+    /// part of a git repository that has the revision.
+    fn new(rev: &'a str, recipes_dir: &Path) -> Result<Self> {
+        let top = git_toplevel(recipes_dir)?;
+        let changed = git_changed_files(&top, rev)?;
+        Ok(Self {
+            rev,
+            repos: HashMap::from([(top, Some(changed))]),
+        })
+    }
+
+    /// Returns true if any file in the directory of the `recipe` or any of its includes, patches
+    /// and local sources changed.
+    fn affects(&mut self, recipe: &Recipe) -> bool {
+        std::iter::once(recipe.recipe_dir.clone())
+            .chain(recipe.referenced_files())
+            .any(|path| self.is_changed(&path))
+    }
+
+    /// Returns true if the file at `path` or any file in the directory at `path` changed. Files
+    /// outside of the repository of the recipes, like local sources, are compared with the
+    /// revision in their own repository, when they are in one that has it.
+    fn is_changed(&mut self, path: &Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let dir = if path.is_dir() {
+            path.as_path()
+        } else {
+            path.parent().unwrap_or(path.as_path())
+        };
+        let top = match git_toplevel(dir) {
+            Ok(top) => top,
+            Err(e) => {
+                debug!(path = %path.display(), reason = %e, "not in a git repository, skipping");
+                return false;
+            }
+        };
+        let rev = self.rev;
+        self.repos
+            .entry(top)
+            .or_insert_with_key(|top| match git_changed_files(top, rev) {
+                Ok(changed) => Some(changed),
+                Err(e) => {
+                    warn!(repository = %top.display(), reason = %format!("{:?}", e), "failed to find changed files, skipping");
+                    None
+                }
+            })
+            .as_ref()
+            .map(|changed| changed.iter().any(|file| file.starts_with(&path)))
+            .unwrap_or(false)
+    }
+}
+
+/// Returns the canonical top level directory of the git repository that contains `dir`.
+fn git_toplevel(dir: &Path) -> Result<PathBuf> {
+    let output = process::Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(dir)
+        .output()
+        .context("failed to run git")?;
+    if !output.status.success() {
+        return err!(
+            "git rev-parse failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let top = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    top.canonicalize()
+        .context(format!("failed to resolve `{}`", top.display()))
+}
+
+/// Returns paths of files that differ from the git revision `rev` in the repository with the top
+/// level directory `top`.
+fn git_changed_files(top: &Path, rev: &str) -> Result<HashSet<PathBuf>> {
+    let span = info_span!("changed-files", since = %rev, repository = %top.display());
+    let _enter = span.enter();
+
+    let output = process::Command::new("git")
+        .args(["diff", "--name-only", "-z", rev, "--"])
+        .current_dir(top)
+        .output()
+        .context("failed to run git")?;
+
+    if !output.status.success() {
+        return err!(
+            "git diff failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|file| !file.is_empty())
+        .map(|file| top.join(file))
+        .collect())
+}
+
 #[derive(Debug, PartialEq)]
 pub enum BuildTask {
     Simple {
@@ -26,14 +181,118 @@ pub enum BuildTask {
     },
 }
 
+impl BuildTask {
+    /// Returns the recipe, the name of the image and the build target of this task.
+    fn parts(&self) -> (&Recipe, &str, BuildTarget) {
+        match self {
+            BuildTask::Custom { recipe, target } => {
+                (recipe, target.image.as_str(), target.build_target)
+            }
+            BuildTask::Simple { recipe, target } => (recipe, Image::simple(*target).1, *target),
+        }
+    }
+
+    fn recipe(&self) -> &Arc<Recipe> {
+        match self {
+            BuildTask::Custom { recipe, .. } | BuildTask::Simple { recipe, .. } => recipe,
+        }
+    }
+
+    /// Returns the name of the recipe and the image of this task that identify packages built by
+    /// it for `pkger:<recipe>` build dependencies.
+    fn key(&self) -> (&str, &str) {
+        let (recipe, image, _) = self.parts();
+        (&recipe.metadata.name, image)
+    }
+}
+
+/// Groups tasks into stages so that every task runs in a stage after the tasks it depends on.
+/// `deps` contains the indices of tasks that each task depends on.
+fn stages(deps: &[Vec<usize>]) -> Result<Vec<Vec<usize>>> {
+    let mut stage_of: Vec<Option<usize>> = vec![None; deps.len()];
+    let mut stages: Vec<Vec<usize>> = vec![];
+    while stage_of.iter().any(Option::is_none) {
+        let ready: Vec<_> = (0..deps.len())
+            .filter(|&i| stage_of[i].is_none())
+            .filter(|&i| {
+                deps[i]
+                    .iter()
+                    .all(|&dep| matches!(stage_of[dep], Some(stage) if stage < stages.len()))
+            })
+            .collect();
+        if ready.is_empty() {
+            return err!("`pkger:` build dependencies of recipes form a cycle");
+        }
+        for &i in &ready {
+            stage_of[i] = Some(stages.len());
+        }
+        stages.push(ready);
+    }
+    Ok(stages)
+}
+
+/// Loads a recipe file outside of the recipes directory or reads it from stdin if `path` is `-`.
+fn load_recipe_file(path: &Path) -> Result<Recipe> {
+    if path == Path::new("-") {
+        let mut data = vec![];
+        io::stdin()
+            .read_to_end(&mut data)
+            .context("failed to read recipe from stdin")?;
+        let dir = env::current_dir().context("failed to get current directory")?;
+        return Recipe::from_standalone(&data, dir).context("failed to load recipe from stdin");
+    }
+    if !path.exists() {
+        return err!("recipe file `{}` does not exist", path.display())
+            .context(ExitCode::RecipeNotFound);
+    }
+    let data = fs::read(path).context(format!("failed to read recipe `{}`", path.display()))?;
+    let dir = path
+        .canonicalize()
+        .context(format!("failed to resolve recipe `{}`", path.display()))?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    Recipe::from_standalone(&data, dir)
+        .context(format!("failed to load recipe `{}`", path.display()))
+}
+
+/// Logs the `message` about a skipped target as a warning or returns it as an error in `strict`
+/// mode.
+fn warn_or_fail(strict: bool, message: String) -> Result<()> {
+    if strict {
+        err!("{} (strict mode)", message)
+    } else {
+        warn!("{}, skipping", message);
+        Ok(())
+    }
+}
+
 impl Application {
     pub fn process_build_opts(&mut self, opts: BuildOpts) -> Result<Vec<BuildTask>> {
         let span = info_span!("process-build-opts");
         let _enter = span.enter();
         let mut tasks = Vec::new();
         let mut recipes = Vec::new();
+        let build_all = opts.all
+            || ((opts.changed_since.is_some() || opts.only_failed) && opts.recipes.is_empty());
+        let strict = opts.strict || self.config.strict;
+        if strict {
+            self.recipes = Arc::new((*self.recipes).clone().strict(true));
+        }
+        if opts.file.is_some() && (build_all || !opts.recipes.is_empty()) {
+            return err!(
+                "`--file` can't be combined with names of recipes, `--all`, `--changed-since` or `--only-failed`"
+            );
+        }
+        if opts.source_path.is_some()
+            && (opts.all || opts.changed_since.is_some() || opts.only_failed)
+        {
+            return err!(
+                "`--source-path` can't be combined with `--all`, `--changed-since` or `--only-failed`"
+            );
+        }
 
-        if opts.all {
+        if build_all {
             recipes = self
                 .recipes
                 .load_all()
@@ -41,55 +300,137 @@ impl Application {
                 .into_iter()
                 .map(Arc::new)
                 .collect();
-        } else if !opts.recipes.is_empty() {
+        } else if !opts.recipes.is_empty() || opts.file.is_some() {
+            let source_path = if let Some(path) = &opts.source_path {
+                if opts.recipes.len() + usize::from(opts.file.is_some()) != 1 {
+                    return err!("`--source-path` can only be used when building a single recipe");
+                }
+                let path = path
+                    .canonicalize()
+                    .context("failed to resolve source path")?;
+                if !path.is_dir() {
+                    return err!("source path `{}` is not a directory", path.display());
+                }
+                Some(path)
+            } else {
+                None
+            };
+            let mut loaded = vec![];
+            if let Some(file) = &opts.file {
+                trace!(file = %file.display(), "loading");
+                loaded.push(load_recipe_file(file)?);
+            }
             for recipe_name in opts.recipes {
                 trace!(recipe = %recipe_name, "loading");
-                recipes.push(Arc::new(
-                    self.recipes.load(&recipe_name).context("loading recipe")?,
-                ));
+                if !self.config.recipes_dir.join(&recipe_name).is_dir() {
+                    return err!(
+                        "recipe `{}` does not exist in `{}`",
+                        recipe_name,
+                        self.config.recipes_dir.display()
+                    )
+                    .context(ExitCode::RecipeNotFound);
+                }
+                loaded.push(self.recipes.load(&recipe_name).context("loading recipe")?);
+            }
+            for mut recipe in loaded {
+                if let Some(path) = &source_path {
+                    debug!(recipe = %recipe.metadata.name, source = %path.display(), "overriding recipe source");
+                    recipe.metadata.source = vec![Source::from(path.to_string_lossy().as_ref())];
+                    recipe.metadata.git = None;
+                }
+                recipes.push(Arc::new(recipe));
             }
         } else {
             warn!("no recipes to build");
             warn!("if you meant to build all recipes run `pkger build --all`");
             warn!("or only specified recipes with `pkger build <RECIPES>...`");
+            warn!("or a single recipe file with `pkger build -f <FILE>`");
             return Ok(tasks);
         }
 
+        if !opts.set.is_empty() {
+            let overrides = opts
+                .set
+                .iter()
+                .map(|entry| match entry.split_once('=') {
+                    Some((key, value)) if !key.is_empty() => Ok((key, value)),
+                    _ => err!("invalid `--set` value `{}`, expected `KEY=VALUE`", entry),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            for recipe in &mut recipes {
+                let recipe = Arc::make_mut(recipe);
+                for (key, value) in &overrides {
+                    recipe
+                        .set(key, value)
+                        .context(format!("failed to override `{}`", key))?;
+                }
+            }
+        }
+
+        if opts.version_suffix.is_some() || opts.release.is_some() {
+            for recipe in &mut recipes {
+                let recipe = Arc::make_mut(recipe);
+                if let Some(suffix) = &opts.version_suffix {
+                    recipe
+                        .append_version_suffix(suffix)
+                        .context("invalid `--version-suffix`")?;
+                }
+                if let Some(release) = &opts.release {
+                    recipe.set_release(release).context("invalid `--release`")?;
+                }
+            }
+        }
+
+        if let Some(rev) = &opts.changed_since {
+            let mut changed = ChangedFiles::new(rev, &self.config.recipes_dir)
+                .context("failed to find changed recipes")?;
+            recipes.retain(|recipe| changed.affects(recipe));
+            trace!(recipes = ?recipes.iter().map(|recipe| &recipe.metadata.name).collect::<Vec<_>>(), "changed recipes");
+            if recipes.is_empty() {
+                info!(since = %rev, "no recipes changed");
+                return Ok(tasks);
+            }
+        }
+
+        if self.config.lint.before_build {
+            let failed: Vec<_> = recipes
+                .iter()
+                .filter(|recipe| !lint::check_recipe(recipe, &self.config.lint))
+                .map(|recipe| recipe.metadata.name.as_str())
+                .collect();
+            if !failed.is_empty() {
+                return err!("recipes `{}` failed the lint", failed.join("`, `"));
+            }
+        }
+
         macro_rules! add_task_if_target_found {
             ($target:ident, $recipe:ident, $self:ident, $tasks:ident) => {
-                if let Some(target) = $self
+                let targets: Vec<_> = $self
                     .config
                     .images
                     .iter()
-                    .find(|target| &target.image == $target)
-                {
-                    $tasks.push(BuildTask::Custom {
-                        recipe: $recipe.clone(),
-                        target: target.clone(),
-                    });
+                    .filter(|target| target.matches($target))
+                    .collect();
+                if !targets.is_empty() {
+                    for target in targets {
+                        $tasks.push(BuildTask::Custom {
+                            recipe: $recipe.clone(),
+                            target: target.clone(),
+                        });
+                    }
                 } else {
-                    warn!(image = %$target, "not found in configuration");
+                    warn_or_fail(
+                        strict,
+                        format!("image `{}` not found in configuration", $target),
+                    )?;
                 }
             };
         }
 
-        if opts.all {
+        if build_all {
             debug!("building all recipes for all targets");
             for recipe in &recipes {
-                if recipe.metadata.all_images {
-                    for image in &self.config.images {
-                        tasks.push(BuildTask::Custom {
-                            target: image.clone(),
-                            recipe: recipe.clone(),
-                        });
-                    }
-                } else if !recipe.images().is_empty() {
-                    for target_image in recipe.images() {
-                        add_task_if_target_found!(target_image, recipe, self, tasks);
-                    }
-                } else {
-                    warn!(recipe = %recipe.metadata.name, "recipe has no image targets, skipping");
-                }
+                self.add_recipe_tasks(recipe, &mut tasks, strict)?;
             }
         } else if let Some(targets) = &opts.simple {
             debug!("building only specified recipes for simple targets");
@@ -115,37 +456,164 @@ impl Application {
                         if recipe.images().iter().any(|target| target == image) {
                             // then we fetch the target from configuration images
                             add_task_if_target_found!(image, recipe, self, tasks);
+                            continue;
+                        }
+                        // otherwise only variants of the image that the recipe contains are built
+                        let variants: Vec<_> = self
+                            .config
+                            .images
+                            .iter()
+                            .filter(|target| {
+                                target.matches(image)
+                                    && recipe.images().iter().any(|name| target.matches(name))
+                            })
+                            .collect();
+                        if !variants.is_empty() {
+                            for target in variants {
+                                tasks.push(BuildTask::Custom {
+                                    recipe: recipe.clone(),
+                                    target: target.clone(),
+                                });
+                            }
                         } else {
-                            warn!(recipe = %recipe.metadata.name, %image, "image not found in recipe targets");
+                            warn_or_fail(
+                                strict,
+                                format!(
+                                    "image `{}` not found in targets of recipe `{}`",
+                                    image, recipe.metadata.name
+                                ),
+                            )?;
                         }
                     }
                 } else {
-                    warn!(recipe = %recipe.metadata.name, "recipe has no image targets, skipping");
+                    warn_or_fail(
+                        strict,
+                        format!("recipe `{}` has no image targets", recipe.metadata.name),
+                    )?;
                 }
             }
         } else {
             trace!("building only specified recipes for all targets");
             for recipe in &recipes {
-                if recipe.metadata.all_images {
-                    for image in &self.config.images {
-                        tasks.push(BuildTask::Custom {
-                            target: image.clone(),
-                            recipe: recipe.clone(),
-                        });
-                    }
-                } else if !recipe.images().is_empty() {
-                    for target_image in recipe.images() {
-                        add_task_if_target_found!(target_image, recipe, self, tasks);
+                self.add_recipe_tasks(recipe, &mut tasks, strict)?;
+            }
+        }
+
+        self.warn_emulation(&tasks);
+        self.connect_docker(opts.docker.as_deref())?;
+        Ok(tasks)
+    }
+
+    /// Warns about tasks that can't run natively on the architecture of the host, either because
+    /// the recipe targets a foreign architecture or because the default simple image is not
+    /// published for the host.
+    fn warn_emulation(&self, tasks: &[BuildTask]) {
+        let host = BuildArch::host();
+        trace!(host = %host);
+        let mut warned_recipes = HashSet::new();
+        let mut warned_targets = HashSet::new();
+        for task in tasks {
+            let (recipe, _, target) = task.parts();
+            let arch = &recipe.metadata.arch;
+            if arch.requires_emulation(&host)
+                && warned_recipes.insert(recipe.metadata.name.as_str())
+            {
+                warn!(
+                    recipe = %recipe.metadata.name,
+                    "recipe targets `{}` which requires emulation on `{}` hosts, the build fails unless the images are built for `{}` and QEMU is registered with binfmt_misc",
+                    arch, host, arch
+                );
+            }
+            if matches!(task, BuildTask::Simple { .. })
+                && !Image::simple_supports(target, &host)
+                && self
+                    .config
+                    .custom_simple_images
+                    .as_ref()
+                    .and_then(|images| images.name_for_target(target))
+                    .is_none()
+                && warned_targets.insert(target)
+            {
+                warn!(
+                    "the default `{}` image is not available for `{}` hosts and will run under emulation, set `custom_simple_images.{}` to an image for this architecture",
+                    Image::simple(target).0,
+                    host,
+                    target.as_ref()
+                );
+            }
+        }
+    }
+
+    /// Returns image targets of the `recipe` matched with images from the configuration. An image
+    /// with variants matches all of them. Images of the recipe that are missing from the
+    /// configuration are returned as errors.
+    pub fn recipe_targets<'a>(
+        &'a self,
+        recipe: &'a Recipe,
+    ) -> Vec<std::result::Result<&'a ImageTarget, &'a str>> {
+        if recipe.metadata.all_images {
+            self.config.images.iter().map(Ok).collect()
+        } else {
+            recipe
+                .images()
+                .iter()
+                .flat_map(|image| {
+                    let targets: Vec<_> = self
+                        .config
+                        .images
+                        .iter()
+                        .filter(|target| target.matches(image))
+                        .map(Ok)
+                        .collect();
+                    if targets.is_empty() {
+                        vec![Err(image.as_str())]
+                    } else {
+                        targets
                     }
-                } else {
-                    warn!(recipe = %recipe.metadata.name, "recipe has no image targets, skipping");
-                }
+                })
+                .collect()
+        }
+    }
+
+    /// Adds a task for each image target of the `recipe` found in the configuration. In `strict`
+    /// mode images missing from the configuration and recipes without targets are errors.
+    fn add_recipe_tasks(
+        &self,
+        recipe: &Arc<Recipe>,
+        tasks: &mut Vec<BuildTask>,
+        strict: bool,
+    ) -> Result<()> {
+        let targets = self.recipe_targets(recipe);
+        if targets.is_empty() {
+            warn_or_fail(
+                strict,
+                format!("recipe `{}` has no image targets", recipe.metadata.name),
+            )?;
+        }
+        for target in targets {
+            match target {
+                Ok(target) => tasks.push(BuildTask::Custom {
+                    recipe: recipe.clone(),
+                    target: target.clone(),
+                }),
+                Err(image) => warn_or_fail(
+                    strict,
+                    format!(
+                        "image `{}` of recipe `{}` not found in configuration",
+                        image, recipe.metadata.name
+                    ),
+                )?,
             }
         }
+        Ok(())
+    }
 
+    /// Initializes the connection to Docker with `uri` passed as an argument, the one from the
+    /// configuration or the default one.
+    pub fn connect_docker(&mut self, uri: Option<&str>) -> Result<()> {
         self.docker = Arc::new(
             // check if docker uri provided as cli arg
-            match &opts.docker {
+            match uri {
                 Some(uri) => {
                     trace!(uri = %uri, "using docker uri from opts");
                     DockerConnectionPool::new(uri)
@@ -161,81 +629,616 @@ impl Application {
                     }
                 }
             }
-            .context("Failed to initialize docker connection")?,
+            .context("Failed to initialize docker connection")
+            .context(ExitCode::DockerUnreachable)?,
         );
-        Ok(tasks)
+        Ok(())
     }
 
-    pub async fn process_tasks(&mut self, tasks: Vec<BuildTask>, quiet: bool) -> Result<()> {
-        let span = info_span!("process-jobs");
-        async move {
-            let jobs = FuturesUnordered::new();
-            let start = std::time::SystemTime::now();
+    /// Verifies that the Docker daemon responds before any job is started.
+    async fn ping_docker(&self) -> Result<()> {
+        self.docker
+            .connect()
+            .ping()
+            .await
+            .map(|_| ())
+            .context("failed to ping docker daemon")
+            .context(ExitCode::DockerUnreachable)
+    }
 
-            for task in tasks {
-                let (recipe, image, target, is_simple) = match task {
-                    BuildTask::Custom { recipe, target } => {
-                        let image = Image::new(target.image.clone(), self.user_images_dir.join(&target.image));
-                        (recipe, image, target, false)
-                    }
-                    BuildTask::Simple { recipe, target } => {
-                        let image = Image::try_get_or_new_simple(&self.app_dir.path().join("images"), target, self.config.custom_simple_images.as_ref().and_then(|c| c.name_for_target(target)))?;
-                        let name = image.name.clone();
-                        (recipe, image, ImageTarget::new(name, target, None::<&str>), true)
-                    }
-                };
-                let ctx = Context::new(
-                    &self.session_id,
+    /// Prints whether each task would build a recipe that changed since its last successful build.
+    /// With `diff` also prints how the packaging metadata changed.
+    pub async fn explain(&self, tasks: &[BuildTask], diff: bool) {
+        let state = self.images_state.read().await;
+        for task in tasks {
+            let (recipe, image, target) = task.parts();
+            let reason = match state.recipe_changed(&recipe.metadata.name, image, &recipe.hash) {
+                Some(true) => "recipe changed since last successful build",
+                Some(false) => "recipe unchanged since last successful build",
+                None => "never built",
+            };
+            let failures = state
+                .stats(&recipe.metadata.name, image)
+                .filter(|stats| stats.failures > 0)
+                .map(|stats| {
+                    format!(
+                        ", failed {} of {} builds ({:.0}%)",
+                        stats.failures,
+                        stats.runs,
+                        stats.failure_rate()
+                    )
+                })
+                .unwrap_or_default();
+            println!(
+                "{} on {} ({}): {}{}",
+                recipe.metadata.name,
+                image,
+                target.as_ref(),
+                reason,
+                failures
+            );
+            if diff {
+                preview::print_packaging_diff(
                     recipe,
                     image,
-                    self.docker.connect(),
                     target,
-                    self.config.output_dir.as_path(),
-                    self.images_state.clone(),
-                    is_simple,
-                    self.gpg_key.clone(),
-                    self.config.ssh.clone(),
-                    quiet,
+                    state.last_build(&recipe.metadata.name, image),
                 );
-                let id = ctx.id().to_string();
+            }
+        }
+    }
 
-                jobs.push((id, task::spawn(JobCtx::Build(ctx).run())));
+    /// Builds images of all recipes and caches their dependencies without building any packages.
+    pub async fn warmup(&mut self, opts: WarmupOpts, quiet: bool) -> Result<()> {
+        let span = info_span!("warmup");
+        async move {
+            let mut tasks = self.process_build_opts(BuildOpts {
+                all: true,
+                docker: opts.docker,
+                ..Default::default()
+            })?;
+            if let Some(images) = &opts.images {
+                tasks.retain(|task| {
+                    images.iter().any(|image| match task {
+                        BuildTask::Custom { target, .. } => target.matches(image),
+                        _ => image == task.parts().1,
+                    })
+                });
+            }
+            if tasks.is_empty() {
+                warn!("no images to warm up");
+                return Ok(());
             }
 
-            let mut results = vec![];
+            let start = std::time::SystemTime::now();
+            let jobs = FuturesUnordered::new();
+            for task in &tasks {
+                let ctx = self.build_context(task, quiet, &None, false, None)?;
+                let id = ctx.id().to_string();
+                jobs.push((id, task::spawn(JobCtx::Warmup(ctx).run())));
+            }
 
-            for (id,  mut job) in jobs {
-                tokio::select! {
-                    res = &mut job => {
-                        if let Err(e) = res {
+            let mut task_failed = false;
+            for (id, mut job) in jobs {
+                let result = tokio::select! {
+                    res = &mut job => match res {
+                        Ok(result) => result,
+                        Err(e) => {
                             error!(reason = %e, "failed to join the handle for a job");
                             continue;
                         }
-                        results.push(res.unwrap());
+                    },
+                    _ = self.is_running() => JobResult::failure(
+                        id,
+                        start.elapsed().unwrap_or_default(),
+                        "job cancelled by ctrl-c signal",
+                    ),
+                };
+                match result {
+                    JobResult::Failure { id, duration, reason } => {
+                        task_failed = true;
+                        error!(id = %id, reason = %reason, duration = %format_duration(duration), "warm up failed");
                     }
-                    _ = self.is_running() => {
-                        results.push(
-                            JobResult::Failure {
-                                id,
-                                duration: start.elapsed().unwrap_or_default(),
-                                reason: "job cancelled by ctrl-c signal".to_string()
-                            }
+                    JobResult::Success { id, duration, output, .. } => {
+                        info!(id = %id, image = %output, duration = %format_duration(duration), "image warmed up");
+                    }
+                    JobResult::UpToDate { .. } => {}
+                }
+            }
+
+            if self.images_state.read().await.has_changed() {
+                self.save_images_state().await;
+            }
+
+            let docker = self.docker.connect();
+            if let Err(e) =
+                container::cleanup(&docker, SESSION_LABEL_KEY, self.session_id.to_string()).await
+            {
+                error!(session = %self.session_id, reason = ?e, "failed to cleanup containers");
+            }
+
+            if task_failed {
+                err!("failed to warm up at least one of the images")
+            } else {
+                Ok(())
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Keeps only the tasks that failed in the last session that ran any builds.
+    pub async fn retain_failed(&self, tasks: &mut Vec<BuildTask>) {
+        let state = self.images_state.read().await;
+        let failed = state.failed_in_last_session();
+        tasks.retain(|task| {
+            let (recipe, image, _) = task.parts();
+            failed
+                .iter()
+                .any(|target| target.recipe() == recipe.metadata.name && target.image() == image)
+        });
+    }
+
+    /// Verifies that the output directory is writable and has enough free space for the packages
+    /// of `tasks` before any of them starts. The needed space is estimated from the sizes of
+    /// packages produced by previous builds of the same recipes on the same images.
+    async fn check_output_dir(&self, tasks: &[BuildTask]) -> Result<()> {
+        let output_dir = self.config.output_dir.as_path();
+        fs::create_dir_all(output_dir).context(format!(
+            "failed to create output directory `{}`",
+            output_dir.display()
+        ))?;
+
+        let probe = output_dir.join(format!(".pkger-{}", self.session_id));
+        if let Err(e) = fs::write(&probe, b"").and_then(|_| fs::remove_file(&probe)) {
+            return err!(
+                "output directory `{}` is not writable: {}",
+                output_dir.display(),
+                e
+            );
+        }
+
+        let estimated: u64 = {
+            let state = self.images_state.read().await;
+            tasks
+                .iter()
+                .filter_map(|task| {
+                    let (recipe, image, _) = task.parts();
+                    state
+                        .last_build(&recipe.metadata.name, image)
+                        .and_then(|record| record.output.as_ref())
+                        .and_then(|output| fs::metadata(output).ok())
+                        .map(|metadata| metadata.len())
+                })
+                .sum()
+        };
+        let needed = estimated + MIN_FREE_SPACE;
+
+        let available = match fs2::available_space(output_dir) {
+            Ok(available) => available,
+            Err(e) => {
+                warn!(reason = %e, "failed to check free space in the output directory");
+                return Ok(());
+            }
+        };
+        trace!(available = %available, needed = %needed);
+
+        if available < needed {
+            return err!(
+                "not enough free space in output directory `{}`: {} available, about {} needed based on previous builds",
+                output_dir.display(),
+                format_size(available),
+                format_size(needed)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns for each task the indices of the tasks it depends on with `pkger:<recipe>` build
+    /// dependencies and packages of dependencies that are not built in this session but were
+    /// built for the same image before.
+    async fn task_deps(&self, tasks: &[BuildTask]) -> Result<Vec<(Vec<usize>, Vec<PathBuf>)>> {
+        let state = self.images_state.read().await;
+        let mut task_deps = vec![];
+        for task in tasks {
+            let (recipe, image, target) = task.parts();
+            let mut names: Vec<_> = deps::pkger_recipes(recipe, image, target)
+                .into_iter()
+                .collect();
+            names.sort_unstable();
+
+            let mut session = vec![];
+            let mut previous = vec![];
+            for name in names {
+                if let Some(i) = tasks.iter().position(|other| other.key() == (name, image)) {
+                    session.push(i);
+                    continue;
+                }
+                match state
+                    .last_build(name, image)
+                    .and_then(|record| record.output.as_ref())
+                    .filter(|package| package.exists())
+                {
+                    Some(package) => {
+                        debug!(recipe = %recipe.metadata.name, package = %package.display(), "using previously built package as build dependency");
+                        previous.push(package.clone());
+                    }
+                    None => {
+                        return err!(
+                            "recipe `{}` depends on `pkger:{}` which is not built for image `{}`, build both recipes together",
+                            recipe.metadata.name,
+                            name,
+                            image
+                        )
+                    }
+                }
+            }
+            task_deps.push((session, previous));
+        }
+        Ok(task_deps)
+    }
+
+    /// Creates a build context of the given task.
+    fn build_context(
+        &self,
+        task: &BuildTask,
+        quiet: bool,
+        commit_image: &Option<String>,
+        skip_up_to_date: bool,
+        progress: Option<&ProgressSender>,
+    ) -> Result<Context> {
+        let (recipe, image, target, is_simple) = match task {
+            BuildTask::Custom { recipe, target } => {
+                let image = Image::new(
+                    target.image.clone(),
+                    self.user_images_dir.join(target.dir()),
+                );
+                (recipe.clone(), image, target.clone(), false)
+            }
+            BuildTask::Simple { recipe, target } => {
+                let image = Image::try_get_or_new_simple(
+                    &self.app_dir.path().join("images"),
+                    *target,
+                    self.config
+                        .custom_simple_images
+                        .as_ref()
+                        .and_then(|c| c.name_for_target(*target)),
+                )?;
+                let name = image.name.clone();
+                (
+                    recipe.clone(),
+                    image,
+                    ImageTarget::new(name, *target, None::<&str>),
+                    true,
+                )
+            }
+        };
+        let mut ctx = Context::new(
+            &self.session_id,
+            recipe,
+            image,
+            self.docker.connect(),
+            target,
+            self.config.output_dir.as_path(),
+            self.cache_dir.as_path(),
+            self.images_state.clone(),
+            is_simple,
+            self.gpg_key.clone(),
+            if self.sandbox.is_some() {
+                None
+            } else {
+                self.config.ssh.clone()
+            },
+            quiet,
+            commit_image.clone(),
+        );
+        ctx.set_project(&self.project);
+        ctx.set_skip_up_to_date(skip_up_to_date);
+        ctx.set_output_layout(self.config.output_layout);
+        ctx.set_artifact_policy(self.artifact_policy);
+        ctx.set_source_cache(self.config.source_cache);
+        ctx.set_pass_env(self.config.pass_env.clone());
+        ctx.set_docker_host(self.docker.host());
+        if let Some(sandbox) = &self.sandbox {
+            ctx.set_sandbox(sandbox.clone());
+        }
+        if let Some(network) = &self.config.network {
+            ctx.set_network(network.clone());
+        }
+        if let Some(security) = &self.config.security {
+            ctx.set_security(security.clone());
+        }
+        if let Some(tx) = progress {
+            ctx.set_progress(tx.clone());
+        }
+        Ok(ctx)
+    }
+
+    pub async fn process_tasks(&mut self, tasks: Vec<BuildTask>, opts: SessionOpts) -> Result<()> {
+        let span = info_span!("process-jobs");
+        async move {
+            let SessionOpts {
+                quiet,
+                commit_image,
+                progress,
+                skip_up_to_date,
+                retry_failed,
+                publish,
+                no_sign,
+                ci_annotations,
+            } = opts;
+            self.ping_docker().await?;
+            self.check_output_dir(&tasks).await?;
+            let start = std::time::SystemTime::now();
+
+            let (progress_tx, progress_printer) = if let Some(format) = progress {
+                let (tx, mut rx) = mpsc::unbounded();
+                let printer = task::spawn(async move {
+                    while let Some(event) = rx.next().await {
+                        format.print(&event);
+                    }
+                });
+                (Some(tx), Some(printer))
+            } else {
+                (None, None)
+            };
+
+            let session_logs = self.logs_dir.join(self.session_id.to_string());
+            if let Err(e) = fs::create_dir_all(&session_logs) {
+                warn!(reason = %e, dir = %session_logs.display(), "failed to create logs directory");
+            }
+
+            let mut results = vec![];
+            let mut cancelled = false;
+            // recipes, images and targets of the jobs by their IDs
+            let mut job_tasks: HashMap<String, (Arc<Recipe>, String, BuildTarget)> = HashMap::new();
+            let mut recipes: Vec<Arc<Recipe>> = vec![];
+            for task in &tasks {
+                if !recipes.iter().any(|recipe| Arc::ptr_eq(recipe, task.recipe())) {
+                    recipes.push(task.recipe().clone());
+                }
+            }
+
+            let keys: Vec<_> = tasks
+                .iter()
+                .map(|task| {
+                    let (recipe, image) = task.key();
+                    (recipe.to_string(), image.to_string())
+                })
+                .collect();
+            let task_deps = self.task_deps(&tasks).await?;
+            let stages = stages(
+                &task_deps
+                    .iter()
+                    .map(|(session, _)| session.clone())
+                    .collect::<Vec<_>>(),
+            )?;
+            let mut tasks: Vec<_> = tasks.into_iter().map(Some).collect();
+            // packages built in this session by the name of the recipe and the image
+            let mut built: HashMap<(String, String), PathBuf> = HashMap::new();
+
+            for stage in stages {
+                if cancelled {
+                    break;
+                }
+
+                let mut pending = vec![];
+                for i in stage {
+                    let task = if let Some(task) = tasks[i].take() {
+                        task
+                    } else {
+                        continue;
+                    };
+                    let (session_deps, previous) = &task_deps[i];
+                    let mut packages = previous.clone();
+                    let mut failed_dep = None;
+                    for &dep in session_deps {
+                        match built.get(&keys[dep]) {
+                            Some(package) => packages.push(package.clone()),
+                            None => failed_dep = Some(&keys[dep].0),
+                        }
+                    }
+                    if let Some(dep) = failed_dep {
+                        let ctx = self.build_context(&task, quiet, &commit_image, skip_up_to_date, progress_tx.as_ref())?;
+                        job_tasks.insert(ctx.id().to_string(), (task.recipe().clone(), task.key().1.to_string(), task.parts().2));
+                        let result = JobResult::failure(
+                            ctx.id(),
+                            Default::default(),
+                            format!("build dependency `pkger:{}` failed to build", dep),
                         );
+                        if let Some(tx) = &progress_tx {
+                            let _ = tx.unbounded_send(result.as_progress_event());
+                        }
+                        results.push(result);
+                        continue;
+                    }
+                    pending.push((task, packages));
+                }
+
+                for attempt in 0..=retry_failed {
+                    if attempt > 0 {
+                        info!(attempt = %attempt, tasks = %pending.len(), "retrying failed tasks");
+                    }
+
+                    let jobs = FuturesUnordered::new();
+                    for (task, packages) in pending.drain(..) {
+                        let mut ctx = self.build_context(&task, quiet, &commit_image, skip_up_to_date, progress_tx.as_ref())?;
+                        ctx.set_pkger_deps(packages.clone());
+                        let id = ctx.id().to_string();
+                        job_tasks.insert(id.clone(), (task.recipe().clone(), task.key().1.to_string(), task.parts().2));
+                        let log = session_logs.join(format!("{}.log", id));
+                        let job = JobCtx::Build(ctx)
+                            .run()
+                            .instrument(info_span!(JOB_SPAN, id = %id, log = %log.display()));
+
+                        jobs.push((id, task, packages, task::spawn(job)));
+                    }
+
+                    let mut failed = vec![];
+
+                    for (id, task, packages, mut job) in jobs {
+                        let result = tokio::select! {
+                            res = &mut job => {
+                                match res {
+                                    Ok(result) => result,
+                                    Err(e) => {
+                                        error!(reason = %e, "failed to join the handle for a job");
+                                        continue;
+                                    }
+                                }
+                            }
+                            _ = self.is_running() => {
+                                cancelled = true;
+                                JobResult::Failure {
+                                    id,
+                                    duration: start.elapsed().unwrap_or_default(),
+                                    reason: "job cancelled by ctrl-c signal".to_string()
+                                }
+                            }
+                        };
+                        if let Some(tx) = &progress_tx {
+                            let _ = tx.unbounded_send(result.as_progress_event());
+                        }
+                        match &result {
+                            JobResult::Success { output, .. }
+                            | JobResult::UpToDate { output: Some(output), .. } => {
+                                let (recipe, image) = task.key();
+                                built.insert(
+                                    (recipe.to_string(), image.to_string()),
+                                    PathBuf::from(output),
+                                );
+                            }
+                            _ => {}
+                        }
+                        if matches!(result, JobResult::Failure { .. }) && !cancelled {
+                            failed.push((task, packages, result));
+                        } else {
+                            results.push(result);
+                        }
+                    }
+
+                    if failed.is_empty() {
+                        break;
+                    }
+                    if cancelled || attempt == retry_failed {
+                        results.extend(failed.into_iter().map(|(_, _, result)| result));
+                        break;
                     }
+                    for (_, _, result) in &failed {
+                        if let JobResult::Failure { id, reason, .. } = result {
+                            warn!(id = %id, reason = %reason, "job failed, retrying");
+                        }
+                    }
+                    pending = failed
+                        .into_iter()
+                        .map(|(task, packages, _)| (task, packages))
+                        .collect();
                 }
             }
 
-            let mut task_failed = false;
+            if !results.is_empty() {
+                self.images_state
+                    .write()
+                    .await
+                    .set_last_session(self.session_id.to_string());
+            }
+
+            drop(progress_tx);
+            if let Some(printer) = progress_printer {
+                if let Err(e) = printer.await {
+                    error!(reason = %e, "failed to join the progress printer");
+                }
+            }
+
+            // reason of the first step of the session that failed, failed jobs take precedence
+            let mut failure: Option<ExitCode> = None;
+
+            // image build logs of the jobs that built an image
+            let image_logs: HashMap<String, PathBuf> = results
+                .iter()
+                .map(|result| {
+                    let id = result.id();
+                    (id.to_string(), logs::image_log_path(&session_logs.join(format!("{}.log", id))))
+                })
+                .filter(|(_, log)| log.exists())
+                .collect();
+
+            for result in &results {
+                if let JobResult::Failure { id, duration, reason } = result {
+                    match image_logs.get(id) {
+                        Some(image_log) => error!(id = %id, reason = %reason, duration = %format_duration(*duration), image_log = %image_log.display(), "job failed"),
+                        None => error!(id = %id, reason = %reason, duration = %format_duration(*duration), "job failed"),
+                    }
+                }
+            }
 
-            results.iter().for_each(|err| match err {
-                JobResult::Failure { id, duration, reason } => {
-                    task_failed = true;
-                    error!(id = %id, reason = %reason, duration = %format!("{}s", duration.as_secs_f32()), "job failed");
+            if !results.is_empty() {
+                let jobs: Vec<_> = results
+                    .iter()
+                    .filter_map(|result| {
+                        job_tasks.get(result.id()).map(|(recipe, image, target)| JobSummary {
+                            recipe: &recipe.metadata.name,
+                            image,
+                            target: *target,
+                            result,
+                        })
+                    })
+                    .collect();
+                let table = summary::table(&jobs, start.elapsed().unwrap_or_default());
+                if progress.is_some() {
+                    // stdout is reserved for progress events
+                    eprint!("{}", table.render(true));
+                } else {
+                    table.print();
+                }
+            }
+
+            let packages: Vec<_> = results
+                .iter()
+                .flat_map(|result| match result {
+                    JobResult::Success { packages, .. } => packages.as_slice(),
+                    _ => &[],
+                })
+                .map(PathBuf::from)
+                .collect();
+            if let Some(ci) = ci_annotations {
+                let mut annotations = vec![];
+                for recipe in &recipes {
+                    annotations.extend(
+                        lint::lint(recipe, &self.config.lint)
+                            .iter()
+                            .map(|finding| Annotation::lint(recipe, finding)),
+                    );
                 }
-                JobResult::Success { id, duration, output } => {
-                    info!(id = %id, output = %output, duration = %format!("{}s", duration.as_secs_f32()), "job succeded");
+                for result in &results {
+                    if let JobResult::Failure { id, reason, .. } = result {
+                        if let Some((recipe, image, _)) = job_tasks.get(id) {
+                            annotations.push(Annotation::failure(recipe, image, reason));
+                        }
+                    }
+                }
+                if let Err(e) = ci.report(&annotations, &results, &image_logs) {
+                    failure.get_or_insert(ExitCode::Failure);
+                    error!(reason = %format!("{:?}", e), "failed to report results to CI");
+                }
+            }
+            if no_sign {
+                if let Err(e) = self.update_signing_manifest(&packages) {
+                    failure.get_or_insert(ExitCode::SigningFailure);
+                    error!(reason = %format!("{:?}", e), "failed to update signing manifest");
                 }
-            });
+            }
+            if let Err(e) = self.update_repositories(&packages) {
+                failure.get_or_insert(ExitCode::Failure);
+                error!(reason = %format!("{:?}", e), "failed to update repositories");
+            }
+            if publish {
+                if let Err(e) = self.publish(&packages).await {
+                    failure.get_or_insert(ExitCode::PublishFailure);
+                    error!(reason = %format!("{:?}", e), "failed to publish packages");
+                }
+            }
 
             if self.images_state.read().await.has_changed() {
                 self.save_images_state().await;
@@ -253,11 +1256,84 @@ impl Application {
                 }
             }
 
-            if task_failed {
-                err!("at least one of the tasks failed")
-            } else {
-                Ok(())
+            let failed_jobs = results.iter().filter(|result| matches!(result, JobResult::Failure { .. })).count();
+            if failed_jobs == results.len() && failed_jobs > 0 {
+                failure = Some(ExitCode::BuildFailure);
+            } else if failed_jobs > 0 {
+                failure = Some(ExitCode::PartialFailure);
+            }
+            match failure {
+                Some(code) => err!("at least one of the tasks failed").context(code),
+                None => Ok(()),
             }
         }.instrument(span).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const TEST_RECIPE: &[u8] = include_bytes!("../../../example/recipes/test-suite/recipe.yml");
+
+    #[test]
+    fn orders_tasks_by_dependencies() {
+        // 0 <- 1 <- 2, 3 is independent
+        let deps = vec![vec![], vec![0], vec![1, 0], vec![]];
+        assert_eq!(stages(&deps).unwrap(), vec![vec![0, 3], vec![1], vec![2]]);
+
+        let cycle = vec![vec![1], vec![0], vec![]];
+        assert_eq!(
+            stages(&cycle).unwrap_err().to_string(),
+            "`pkger:` build dependencies of recipes form a cycle"
+        );
+    }
+
+    #[test]
+    fn selects_recipes_with_changed_includes() {
+        let git = |dir: &Path, args: &[&str]| {
+            let status = process::Command::new("git")
+                .args(["-c", "user.name=pkger", "-c", "user.email=pkger@localhost"])
+                .args(["-c", "commit.gpgsign=false"])
+                .args(args)
+                .current_dir(dir)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        let tmp = TempDir::new("pkger-changed").unwrap();
+        let recipes_dir = tmp.path().join("recipes");
+        for name in ["with-include", "without-include"] {
+            fs::create_dir_all(recipes_dir.join(name)).unwrap();
+        }
+        fs::write(recipes_dir.join("common.yml"), "env:\n  COMMON: enabled\n").unwrap();
+        let mut with_include = b"include: [../common.yml]\n".to_vec();
+        with_include.extend_from_slice(TEST_RECIPE);
+        fs::write(recipes_dir.join("with-include/recipe.yml"), &with_include).unwrap();
+        fs::write(recipes_dir.join("without-include/recipe.yml"), TEST_RECIPE).unwrap();
+        git(tmp.path(), &["init", "-q"]);
+        git(tmp.path(), &["add", "-A"]);
+        git(tmp.path(), &["commit", "-q", "-m", "recipes"]);
+
+        let load = |name: &str, data: &[u8]| {
+            Recipe::from_standalone(data, recipes_dir.join(name)).unwrap()
+        };
+        let included = load("with-include", with_include.as_slice());
+        let other = load("without-include", TEST_RECIPE);
+
+        let mut changed = ChangedFiles::new("HEAD", &recipes_dir).unwrap();
+        assert!(!changed.affects(&included));
+        assert!(!changed.affects(&other));
+
+        fs::write(recipes_dir.join("common.yml"), "env:\n  COMMON: disabled\n").unwrap();
+        let mut changed = ChangedFiles::new("HEAD", &recipes_dir).unwrap();
+        assert!(changed.affects(&included));
+        assert!(!changed.affects(&other));
+
+        fs::write(recipes_dir.join("without-include/README"), "").unwrap();
+        git(tmp.path(), &["add", "-A"]);
+        let mut changed = ChangedFiles::new("HEAD", &recipes_dir).unwrap();
+        assert!(changed.affects(&other));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::app::Application;
+use crate::lint;
+use crate::opts::CheckOpts;
+use crate::preview;
+use pkger_core::docker::DockerConnectionPool;
+use pkger_core::recipe::Recipe;
+use pkger_core::{blocking, err, ErrContext, Result};
+
+use std::io::Write;
+use std::process::{self, Stdio};
+use tracing::{info, info_span, trace, warn, Instrument};
+
+/// Image used to run shellcheck when the binary is not available on the host.
+static SHELLCHECK_IMAGE: &str = "koalaman/shellcheck:stable";
+/// Shells supported by shellcheck.
+static SUPPORTED_SHELLS: &[&str] = &["sh", "bash", "dash", "ksh"];
+
+impl Application {
+    pub async fn check(&self, opts: CheckOpts) -> Result<()> {
+        let span = info_span!("check");
+        async move {
+            let names = if opts.recipes.is_empty() {
+                self.recipes.list()?
+            } else {
+                opts.recipes
+            };
+
+            let mut failed = false;
+            for name in names {
+                trace!(recipe = %name, "loading");
+                let recipe = match self.recipes.load(&name) {
+                    Ok(recipe) => recipe,
+                    Err(e) => {
+                        failed = true;
+                        warn!(recipe = %name, reason = %format!("{:?}", e), "invalid recipe");
+                        continue;
+                    }
+                };
+
+                if !lint::check_recipe(&recipe, &self.config.lint) {
+                    failed = true;
+                    warn!(recipe = %name, "lint found errors");
+                    continue;
+                }
+
+                if opts.shellcheck && !shellcheck_recipe(&recipe, &self.docker).await? {
+                    failed = true;
+                    warn!(recipe = %name, "shellcheck found issues");
+                    continue;
+                }
+                info!(recipe = %name, "ok");
+
+                if opts.diff {
+                    // the state is only locked after shellcheck so that other tasks aren't blocked
+                    // while it runs
+                    let state = self.images_state.read().await;
+                    for target in self.recipe_targets(&recipe).into_iter().flatten() {
+                        println!(
+                            "{} on {} ({}):",
+                            name,
+                            target.image,
+                            target.build_target.as_ref()
+                        );
+                        preview::print_packaging_diff(
+                            &recipe,
+                            &target.image,
+                            target.build_target,
+                            state.last_build(&recipe.metadata.name, &target.image),
+                        );
+                    }
+                }
+            }
+
+            if failed {
+                err!("at least one of the recipes failed the check")
+            } else {
+                Ok(())
+            }
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+/// Runs shellcheck over the scripts of each phase of the recipe printing all findings. Returns
+/// `false` if any issues were found.
+async fn shellcheck_recipe(recipe: &Recipe, docker: &DockerConnectionPool) -> Result<bool> {
+    let mut passed = true;
+    let mut phases = vec![];
+    if let Some(script) = &recipe.configure_script {
+        phases.push((
+            "configure",
+            script.shell.as_deref().or(recipe.shell.as_deref()),
+            script.steps_as_script(),
+        ));
+    }
+    phases.push((
+        "build",
+        recipe
+            .build_script
+            .shell
+            .as_deref()
+            .or(recipe.shell.as_deref()),
+        recipe.build_script.steps_as_script(),
+    ));
+    if let Some(script) = &recipe.install_script {
+        phases.push((
+            "install",
+            script.shell.as_deref().or(recipe.shell.as_deref()),
+            script.steps_as_script(),
+        ));
+    }
+
+    for (phase, shell, script) in phases {
+        let shell = shell
+            .and_then(|shell| shell.rsplit('/').next())
+            .unwrap_or("sh");
+        if !SUPPORTED_SHELLS.contains(&shell) {
+            warn!(recipe = %recipe.metadata.name, phase = %phase, shell = %shell, "shell not supported by shellcheck, skipping");
+            continue;
+        }
+
+        let output = shellcheck(script, shell, docker).await?;
+        if !output.is_empty() {
+            passed = false;
+            println!("{} - {}:\n{}", recipe.metadata.name, phase, output);
+        }
+    }
+
+    Ok(passed)
+}
+
+/// Runs shellcheck on the given script using the host binary if available, otherwise uses the
+/// docker CLI to run shellcheck in a container of the `docker` daemon.
+async fn shellcheck(script: String, shell: &str, docker: &DockerConnectionPool) -> Result<String> {
+    let args = ["--shell", shell, "-"];
+    let mut host = process::Command::new("shellcheck");
+    host.args(args);
+    let mut container = docker.cli();
+    container
+        .args(["run", "--rm", "-i", SHELLCHECK_IMAGE])
+        .args(args);
+    blocking::run(move || run_shellcheck(&script, host, container)).await
+}
+
+fn run_shellcheck(
+    script: &str,
+    mut host: process::Command,
+    mut container: process::Command,
+) -> Result<String> {
+    let child = host
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .or_else(|_| {
+            trace!("shellcheck not found on host, using container");
+            container
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+        });
+    let mut child = child.context("failed to run shellcheck")?;
+
+    child
+        .stdin
+        .take()
+        .context("failed to open stdin of shellcheck")?
+        .write_all(script.as_bytes())
+        .context("failed to pass script to shellcheck")?;
+
+    let output = child
+        .wait_with_output()
+        .context("failed to wait for shellcheck")?;
+
+    // shellcheck exits with 1 when issues are found
+    match output.status.code() {
+        Some(0) | Some(1) => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+        _ => err!(
+            "shellcheck failed - {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
@@ -0,0 +1,221 @@
+use crate::app::Application;
+use crate::opts::DoctorOpts;
+use pkger_core::container::DockerContainer;
+use pkger_core::docker::{
+    api::{ContainerCreateOpts, ImageBuildChunk, PullOpts},
+    Docker, ExecContainerOpts,
+};
+use pkger_core::network::DEFAULT_MODE;
+use pkger_core::{err, ErrContext, Error, Result};
+
+use futures::StreamExt;
+use tracing::{info, info_span, trace, warn};
+
+/// Script run in the container of each checked network. Prints the addresses and routes of the
+/// container followed by the result of the probe passed as the first argument if any.
+static CHECK_SCRIPT: &str = r#"cat /proc/net/if_inet6 /proc/net/ipv6_route 2>/dev/null
+if [ -n "$1" ]; then
+    if ping -6 -c 1 -W 3 "$1" >/dev/null 2>&1; then echo reachable; else echo unreachable; fi
+fi"#;
+
+#[derive(Debug, Default, PartialEq)]
+/// IPv6 connectivity of a container as seen from the inside.
+struct Ipv6Status {
+    /// Whether any interface other than loopback has a global address
+    global_address: bool,
+    /// Whether there is a default route that doesn't go through loopback
+    default_route: bool,
+    /// Result of the probe if one was requested
+    reachable: Option<bool>,
+}
+
+impl Ipv6Status {
+    /// Parses the output of `CHECK_SCRIPT`. Lines of `/proc/net/if_inet6` have 6 fields, lines of
+    /// `/proc/net/ipv6_route` have 10 fields and the result of the probe is a single word.
+    fn parse(output: &str) -> Self {
+        let mut status = Ipv6Status::default();
+        for line in output.lines() {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [_, _, _, "00", _, interface] if *interface != "lo" => {
+                    status.global_address = true;
+                }
+                [destination, "00", _, _, _, _, _, _, _, interface]
+                    if *interface != "lo" && destination.chars().all(|c| c == '0') =>
+                {
+                    status.default_route = true;
+                }
+                ["reachable"] => status.reachable = Some(true),
+                ["unreachable"] => status.reachable = Some(false),
+                _ => {}
+            }
+        }
+        status
+    }
+
+    /// Returns the reason why the network has no IPv6 connectivity if any.
+    fn problem(&self) -> Option<&'static str> {
+        if !self.global_address {
+            Some("no global IPv6 address, IPv6 is probably not enabled on the network")
+        } else if !self.default_route {
+            Some("no default IPv6 route")
+        } else if self.reachable == Some(false) {
+            Some("probe is unreachable over IPv6")
+        } else {
+            None
+        }
+    }
+}
+
+impl Application {
+    pub async fn doctor(&self, opts: DoctorOpts) -> Result<()> {
+        let span = info_span!("doctor");
+        let _enter = span.enter();
+
+        let docker = self.docker.connect();
+        pull_image(&docker, &opts.image).await?;
+        let mut failed = false;
+        for network in self.networks(&opts.network) {
+            let status =
+                check_network(&docker, &network, &opts.image, opts.probe.as_deref()).await?;
+            trace!(network = %network, status = ?status);
+            match status.problem() {
+                Some(reason) => {
+                    failed = true;
+                    warn!(network = %network, reason = %reason, "no IPv6 connectivity");
+                }
+                None => info!(network = %network, "ok"),
+            }
+        }
+
+        if failed {
+            err!("at least one of the networks has no IPv6 connectivity")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the distinct network modes used by build containers of the configuration and of
+    /// all custom images followed by the `extra` networks.
+    fn networks(&self, extra: &[String]) -> Vec<String> {
+        let global = self.config.network.clone().unwrap_or_default();
+        let mut networks = vec![];
+        let modes = std::iter::once(global.mode.clone())
+            .chain(
+                self.config
+                    .images
+                    .iter()
+                    .filter_map(|image| image.network.as_deref())
+                    .map(|network| global.merge(network).mode),
+            )
+            .map(|mode| mode.unwrap_or_else(|| DEFAULT_MODE.to_string()))
+            .chain(extra.iter().cloned());
+        for mode in modes {
+            if !networks.contains(&mode) {
+                networks.push(mode);
+            }
+        }
+        networks
+    }
+}
+
+/// Pulls the `image` the checks are run in unless it already exists.
+async fn pull_image(docker: &Docker, image: &str) -> Result<()> {
+    if docker.images().get(image).inspect().await.is_ok() {
+        trace!(image = %image, "image exists");
+        return Ok(());
+    }
+
+    info!(image = %image, "pulling image");
+    let images = docker.images();
+    let opts = PullOpts::builder().image(image).build();
+    let mut stream = images.pull(&opts);
+    while let Some(chunk) = stream.next().await {
+        if let ImageBuildChunk::Error { error, .. } = chunk? {
+            return err!("failed to pull image `{}` - {}", image, error);
+        }
+    }
+    Ok(())
+}
+
+/// Runs `CHECK_SCRIPT` in a container of the `image` attached to the `network`.
+async fn check_network(
+    docker: &Docker,
+    network: &str,
+    image: &str,
+    probe: Option<&str>,
+) -> Result<Ipv6Status> {
+    let mut container = DockerContainer::new(docker);
+    container
+        .spawn(
+            &ContainerCreateOpts::builder(image)
+                .network_mode(network)
+                .cmd(["sh", "-c", "sleep 600"])
+                .build(),
+        )
+        .await
+        .context(format!(
+            "failed to start container on network `{}`",
+            network
+        ))?;
+
+    let output = container
+        .exec(
+            &ExecContainerOpts::builder()
+                .cmd(["sh", "-c", CHECK_SCRIPT, "sh", probe.unwrap_or_default()])
+                .attach_stdout(true)
+                .attach_stderr(true)
+                .build(),
+            true,
+        )
+        .await;
+    container.remove().await?;
+    let output = output.context(format!("failed to check network `{}`", network))?;
+
+    if output.exit_code != 0 {
+        return err!(
+            "failed to check network `{}` - {}",
+            network,
+            output.stderr.join("").trim()
+        );
+    }
+
+    Ok(Ipv6Status::parse(&output.stdout.join("")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ipv6_status() {
+        let output = r#"00000000000000000000000000000001 01 80 10 80       lo
+fe800000000000000042acfffe110002 2a 40 20 80     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0"#;
+        let status = Ipv6Status::parse(output);
+        assert_eq!(
+            status,
+            Ipv6Status {
+                global_address: false,
+                default_route: false,
+                reachable: None,
+            }
+        );
+        assert!(status.problem().is_some());
+
+        let output = r#"fd00000000000000000000000000000a 2a 40 00 00     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fd000000000000000000000000000001 00000400 00000001 00000000 00000003     eth0
+reachable"#;
+        let status = Ipv6Status::parse(output);
+        assert_eq!(
+            status,
+            Ipv6Status {
+                global_address: true,
+                default_route: true,
+                reachable: Some(true),
+            }
+        );
+        assert_eq!(status.problem(), None);
+    }
+}
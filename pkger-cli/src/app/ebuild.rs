@@ -0,0 +1,41 @@
+use crate::app::Application;
+use crate::opts::EbuildOpts;
+use pkger_core::{ErrContext, Result};
+
+use std::fs;
+use tracing::{info, info_span, trace};
+
+impl Application {
+    pub fn ebuild(&self, opts: EbuildOpts) -> Result<()> {
+        let span = info_span!("ebuild");
+        let _enter = span.enter();
+
+        let names = if opts.recipes.is_empty() {
+            self.recipes.list()?
+        } else {
+            opts.recipes
+        };
+
+        for name in names {
+            trace!(recipe = %name, "loading");
+            let recipe = self
+                .recipes
+                .load(&name)
+                .context(format!("failed to load recipe `{}`", name))?;
+            let ebuild = recipe.as_ebuild(&opts.image).render();
+
+            if let Some(overlay) = &opts.overlay {
+                let path = overlay.join(recipe.ebuild_path());
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).context("failed to create ebuild directory")?;
+                }
+                fs::write(&path, ebuild)
+                    .context(format!("failed to save ebuild to `{}`", path.display()))?;
+                info!(recipe = %name, path = %path.display(), "saved ebuild");
+            } else {
+                println!("# {}\n{}", recipe.ebuild_path().display(), ebuild);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use crate::app::Application;
+use crate::opts::ExportOpts;
+use pkger_core::recipe::BuildTarget;
+use pkger_core::render;
+use pkger_core::{ErrContext, Result};
+
+use std::convert::TryFrom;
+use std::fs;
+use tracing::{info, info_span, trace, warn};
+
+impl Application {
+    pub fn export(&self, opts: ExportOpts) -> Result<()> {
+        let span = info_span!("export");
+        let _enter = span.enter();
+
+        let target = BuildTarget::try_from(opts.target.as_str())?;
+        let names = if opts.recipes.is_empty() {
+            self.recipes.list()?
+        } else {
+            opts.recipes
+        };
+
+        for name in names {
+            trace!(recipe = %name, "loading");
+            let recipe = self
+                .recipes
+                .load(&name)
+                .context(format!("failed to load recipe `{}`", name))?;
+            let files = render::packaging_files(&recipe, target, &opts.image)
+                .context(format!("failed to render packaging files of `{}`", name))?;
+            if files.is_empty() {
+                warn!(recipe = %name, target = %target.as_ref(), "no packaging files to render");
+                continue;
+            }
+
+            if let Some(output_dir) = &opts.output_dir {
+                let dir = output_dir.join(&name).join(&opts.image);
+                fs::create_dir_all(&dir).context("failed to create export directory")?;
+                for file in files {
+                    let path = dir.join(&file.name);
+                    fs::write(&path, file.content)
+                        .context(format!("failed to save `{}`", path.display()))?;
+                    info!(recipe = %name, path = %path.display(), "saved packaging file");
+                }
+            } else {
+                for file in files {
+                    println!("# {}/{}\n{}", name, file.name, file.content);
+                }
+            }
+        }
+        Ok(())
+    }
+}
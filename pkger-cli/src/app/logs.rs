@@ -0,0 +1,121 @@
+use crate::app::{system_time_to_date_time, Application};
+use crate::logs::{self, END_OF_LOG};
+use crate::opts::LogsOpts;
+use crate::table::{IntoCell, IntoTable};
+use pkger_core::units::format_size;
+use pkger_core::{err, ErrContext, Error, Result};
+
+use chrono::SecondsFormat;
+use colored::Color;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// How often a followed log is checked for new lines
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+impl Application {
+    pub fn logs(&self, opts: LogsOpts) -> Result<()> {
+        let mut sessions = logs::sessions(&self.logs_dir)?;
+        if let Some(session) = &opts.session {
+            sessions.retain(|s| &s.id == session);
+            if sessions.is_empty() {
+                return err!("no logs found for session `{}`", session);
+            }
+        }
+
+        match &opts.job {
+            Some(job) => {
+                let log = logs::find_job(&sessions, job)
+                    .context(format!("no logs found for job or recipe `{}`", job))?;
+                if opts.image {
+                    let image_log = log
+                        .image_log
+                        .as_ref()
+                        .context(format!("job `{}` has no log of image builds", log.id))?;
+                    self.print_log(image_log, opts.tail, opts.follow)
+                } else {
+                    self.print_log(&log.path, opts.tail, opts.follow)
+                }
+            }
+            None => {
+                let mut table = vec![];
+                for session in &sessions {
+                    table.push(vec![format!("{}:", session.id)
+                        .cell()
+                        .bold()
+                        .color(Color::Blue)
+                        .right()]);
+                    for job in &session.jobs {
+                        table.push(vec![
+                            "".cell(),
+                            job.id.as_str().cell().left().color(Color::BrightBlue),
+                            system_time_to_date_time(job.modified)
+                                .to_rfc3339_opts(SecondsFormat::Secs, true)
+                                .cell()
+                                .color(Color::White),
+                            format_size(job.size).cell().color(Color::White),
+                            if job.image_log.is_some() { "yes" } else { "" }
+                                .cell()
+                                .color(Color::White),
+                        ]);
+                    }
+                }
+                table
+                    .into_table()
+                    .with_header_cells(vec![
+                        "Session".cell().bold(),
+                        "Job".cell().bold(),
+                        "Modified".cell().bold(),
+                        "Size".cell().bold(),
+                        "Image log".cell().bold(),
+                    ])
+                    .print();
+                Ok(())
+            }
+        }
+    }
+
+    /// Prints the `log` optionally limited to the last `tail` lines. When `follow` is set new lines
+    /// are printed as they are written until the job finishes or pkger is interrupted.
+    fn print_log(&self, log: &Path, tail: Option<usize>, follow: bool) -> Result<()> {
+        let mut file = fs::File::open(log).context("failed to open log file")?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .context("failed to read log file")?;
+
+        // a line that is still being written is only printed once it is complete
+        let complete = if follow {
+            content.rfind('\n').map(|i| i + 1).unwrap_or_default()
+        } else {
+            content.len()
+        };
+        let mut partial = content[complete..].to_string();
+        let lines: Vec<_> = content[..complete].lines().collect();
+        let skip = tail
+            .map(|tail| lines.len().saturating_sub(tail))
+            .unwrap_or_default();
+        lines
+            .iter()
+            .skip(skip)
+            .for_each(|line| println!("{}", line));
+
+        let mut finished = lines.last() == Some(&END_OF_LOG);
+        while follow && !finished && self.is_running.load(Ordering::SeqCst) {
+            std::thread::sleep(FOLLOW_INTERVAL);
+            let mut new = String::new();
+            file.read_to_string(&mut new)
+                .context("failed to read log file")?;
+            partial.push_str(&new);
+            while let Some(end) = partial.find('\n') {
+                let line: String = partial.drain(..=end).collect();
+                let line = line.trim_end_matches('\n');
+                println!("{}", line);
+                finished = line == END_OF_LOG;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,21 +1,47 @@
 mod build;
+mod check;
+mod doctor;
+mod ebuild;
+mod export;
+mod logs;
+mod repo;
+mod sign;
 
+use crate::app::build::SessionOpts;
 use crate::completions;
 use crate::config::Configuration;
+use crate::exit::ExitCode;
 use crate::gen;
+use crate::logs::LOGS_DIR;
 use crate::metadata::PackageMetadata;
-use crate::opts::{Command, CopyObject, EditObject, ListObject, NewObject, Opts};
+use crate::opts::{
+    CacheCommand, CacheVerifyOpts, Command, CopyObject, EditObject, ListObject, MigrateOpts,
+    NewObject, Opts, PruneImagesOpts, StatsObject, APP_NAME,
+};
 use crate::table::{Cell, IntoCell, IntoTable};
+use crate::wizard;
+use pkger_core::build::artifacts::ArtifactPolicy;
+use pkger_core::build::cache::DEFAULT_CACHE_DIR;
+use pkger_core::build::sandbox::Sandbox;
+use pkger_core::build::store::{OutputLayout, STORE_DIR};
+use pkger_core::container::fix_name;
 use pkger_core::docker::DockerConnectionPool;
-use pkger_core::gpg::GpgKey;
-use pkger_core::image::Image;
-use pkger_core::image::{state::DEFAULT_STATE_FILE, ImagesState};
+use pkger_core::gpg::{self, GpgKey};
+use pkger_core::image::{self, Image};
+use pkger_core::image::{
+    state::{project_state_file, DEFAULT_STATE_FILE},
+    ImagesState,
+};
 use pkger_core::recipe;
+use pkger_core::units::format_size;
 use pkger_core::{ErrContext, Error, Result};
 
 use async_rwlock::RwLock;
 use chrono::{offset::TimeZone, SecondsFormat, Utc};
 use colored::Color;
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -50,6 +76,102 @@ fn create_app_dirs() -> Result<TempDir> {
     Ok(tempdir)
 }
 
+/// Returns the default directory of the images state file. When `XDG_STATE_HOME` is set the state
+/// is kept in a `pkger` directory inside of it, otherwise the cache directory of the OS is used.
+fn default_state_dir() -> PathBuf {
+    match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(APP_NAME),
+        _ => dirs::cache_dir().unwrap_or_default(),
+    }
+}
+
+/// Returns the path of the images state shared by all projects in previous versions.
+fn previous_state_file() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_default()
+        .join(DEFAULT_STATE_FILE)
+}
+
+/// Returns the identifier of the project that the configuration belongs to. It is either the
+/// `project_name` from the configuration or a short hash of the path to the configuration file.
+fn project_id(config: &Configuration) -> String {
+    if let Some(name) = &config.project_name {
+        return fix_name(name);
+    }
+    let path = config
+        .path
+        .canonicalize()
+        .unwrap_or_else(|_| config.path.clone());
+    let hash = format!("{:x}", Sha256::digest(path.to_string_lossy().as_bytes()));
+    hash[..12].to_string()
+}
+
+/// Resolves `name` to a directory in `dir` unless it is a path to a directory.
+fn resolve_dir(dir: &Path, name: &str) -> PathBuf {
+    let path = Path::new(name);
+    if path.is_absolute() || path.components().count() > 1 {
+        path.to_path_buf()
+    } else {
+        dir.join(name)
+    }
+}
+
+/// Returns the name of the last component of `path`.
+fn dir_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .context(format!("invalid path `{}`", path.display()))
+}
+
+/// Updates the `name` and optionally the `version` fields in the metadata of the recipe in
+/// `recipe_dir`. The file is edited line by line so that comments and formatting are preserved.
+fn rename_recipe(recipe_dir: &Path, name: &str, version: Option<&str>) -> Result<()> {
+    let mut path = recipe_dir.join("recipe.yml");
+    if !path.exists() {
+        path = recipe_dir.join("recipe.yaml");
+    }
+    let content = fs::read_to_string(&path).context("failed to read recipe file")?;
+
+    let mut in_metadata = false;
+    let mut indent = None;
+    let mut renamed = false;
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        let line_indent = line.len() - trimmed.len();
+        if line_indent == 0 && !trimmed.is_empty() && !trimmed.starts_with('#') {
+            in_metadata = trimmed.trim_end() == "metadata:";
+            lines.push(line.to_string());
+            continue;
+        }
+        if in_metadata && !trimmed.is_empty() && !trimmed.starts_with('#') {
+            let indent = *indent.get_or_insert(line_indent);
+            if line_indent == indent {
+                if trimmed.starts_with("name:") {
+                    lines.push(format!("{}name: {}", &line[..indent], name));
+                    renamed = true;
+                    continue;
+                }
+                if let Some(version) = version {
+                    if trimmed.starts_with("version:") {
+                        lines.push(format!("{}version: {}", &line[..indent], version));
+                        continue;
+                    }
+                }
+            }
+        }
+        lines.push(line.to_string());
+    }
+
+    if !renamed {
+        warn!(recipe = %path.display(), "`name` field not found in metadata, not renaming");
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    fs::write(&path, content).context("failed to save recipe file")
+}
+
 fn open_editor<P: AsRef<Path>>(path: P) -> Result<ExitStatus> {
     let editor = env::var("EDITOR").context("expected $EDITOR env variable set")?;
     let mut cmd = process::Command::new(editor)
@@ -61,8 +183,11 @@ fn open_editor<P: AsRef<Path>>(path: P) -> Result<ExitStatus> {
 
 fn load_gpg_key(config: &Configuration) -> Result<Option<GpgKey>> {
     if let Some(key) = &config.gpg_key {
-        let pass = rpassword::read_password_from_tty(Some("Gpg key password:"))
-            .context("failed to read password for gpg key")?;
+        let pass = match &config.gpg_pass {
+            Some(pass) => pass.clone(),
+            None => rpassword::read_password_from_tty(Some("Gpg key password:"))
+                .context("failed to read password for gpg key")?,
+        };
         if let Some(name) = &config.gpg_name {
             Ok(Some(GpgKey::new(key, name, &pass)?))
         } else {
@@ -115,30 +240,43 @@ pub struct Application {
     recipes: Arc<recipe::Loader>,
     docker: Arc<DockerConnectionPool>,
     images_state: Arc<RwLock<ImagesState>>,
+    cache_dir: PathBuf,
+    project: String,
     user_images_dir: PathBuf,
     is_running: Arc<AtomicBool>,
     app_dir: TempDir,
     gpg_key: Option<GpgKey>,
+    sandbox: Option<Sandbox>,
+    artifact_policy: ArtifactPolicy,
     session_id: Uuid,
+    logs_dir: PathBuf,
 }
 
 impl Application {
     pub fn new(config: Configuration) -> Result<Self> {
         let app_dir = create_app_dirs()?;
         let recipes = recipe::Loader::new(&config.recipes_dir)
-            .context("failed to initialize recipe loader")?;
+            .context("failed to initialize recipe loader")
+            .context(ExitCode::Config)?
+            .strict(config.strict);
         let user_images_dir = config
             .images_dir
             .clone()
             .unwrap_or_else(|| app_dir.path().join("images"));
 
-        let state_path = match dirs::cache_dir() {
-            Some(dir) => dir.join(DEFAULT_STATE_FILE),
-            None => PathBuf::from(DEFAULT_STATE_FILE),
-        };
+        let state_dir = config.state_dir.clone().unwrap_or_else(default_state_dir);
+        if !state_dir.as_os_str().is_empty() && !state_dir.exists() {
+            fs::create_dir_all(&state_dir).context("failed to create state directory")?;
+        }
+        let project = project_id(&config);
+        trace!(project = %project);
+        let state_path = state_dir.join(project_state_file(&project));
+        let logs_dir = state_dir.join(LOGS_DIR).join(&project);
 
         let images_state = Arc::new(RwLock::new(
-            match ImagesState::load(&state_path).context("failed to load images state") {
+            match ImagesState::load_or_import(&state_path, &previous_state_file())
+                .context("failed to load images state")
+            {
                 Ok(state) => state,
                 Err(e) => {
                     let e = format!("{:?}", e);
@@ -150,16 +288,28 @@ impl Application {
 
         trace!(?images_state);
 
+        let cache_dir = match (&config.cache_dir, dirs::cache_dir()) {
+            (Some(dir), _) => dir.clone(),
+            (None, Some(dir)) => dir.join(DEFAULT_CACHE_DIR),
+            (None, None) => PathBuf::from(DEFAULT_CACHE_DIR),
+        };
+
+        let artifact_policy = config.on_existing_artifact;
         let app = Application {
             config: Arc::new(config),
             recipes: Arc::new(recipes),
             docker: Arc::new(DockerConnectionPool::default()),
             images_state,
+            cache_dir,
+            project,
             user_images_dir,
             is_running: Arc::new(AtomicBool::new(true)),
             app_dir,
             gpg_key: None,
+            sandbox: None,
+            artifact_policy,
             session_id: Uuid::new_v4(),
+            logs_dir,
         };
         let is_running = app.is_running.clone();
         set_ctrlc_handler(is_running);
@@ -169,28 +319,88 @@ impl Application {
     pub async fn process_opts(&mut self, opts: Opts) -> Result<()> {
         match opts.command {
             Command::Build(build_opts) => {
-                if !build_opts.no_sign {
-                    self.gpg_key = load_gpg_key(&self.config)?;
+                if build_opts.sandbox {
+                    if build_opts.publish {
+                        return err!("`--publish` can't be used with `--sandbox`");
+                    }
+                    self.sandbox = Some(Sandbox {
+                        max_output_size: build_opts.max_output_size * 1024 * 1024,
+                        source_path: build_opts.source_path.clone(),
+                    });
+                } else if !build_opts.no_sign && !build_opts.explain {
+                    self.gpg_key = load_gpg_key(&self.config).context(ExitCode::SigningFailure)?;
                 }
-                let tasks = self
+                if let Some(policy) = build_opts.on_existing_artifact {
+                    self.artifact_policy = policy;
+                }
+                let explain = build_opts.explain;
+                let diff = build_opts.diff;
+                let only_failed = build_opts.only_failed;
+                let session_opts = SessionOpts::new(&build_opts, opts.quiet);
+                let mut tasks = self
                     .process_build_opts(build_opts)
                     .context("processing build opts")?;
-                self.process_tasks(tasks, opts.quiet).await?;
+                if only_failed {
+                    self.retain_failed(&mut tasks).await;
+                    if tasks.is_empty() {
+                        info!("no tasks failed in the previous session");
+                        return Ok(());
+                    }
+                }
+                if explain {
+                    self.explain(&tasks, diff).await;
+                    return Ok(());
+                }
+                self.process_tasks(tasks, session_opts).await?;
                 Ok(())
             }
+            Command::Warmup(warmup_opts) => self.warmup(warmup_opts, opts.quiet).await,
             Command::List {
                 object,
                 raw,
                 verbose,
             } => {
-                colored::control::set_override(!raw);
+                if raw {
+                    colored::control::set_override(false);
+                }
                 match object {
                     ListObject::Images => self.list_images(verbose),
                     ListObject::Recipes => self.list_recipes(verbose),
-                    ListObject::Packages { images } => self.list_packages(images, verbose),
+                    ListObject::Packages { images } => self.list_packages(images, verbose).await,
+                    ListObject::Targets { recipes } => self.list_targets(recipes),
+                }
+            }
+            Command::Stats { object, raw } => {
+                if raw {
+                    colored::control::set_override(false);
+                }
+                match object {
+                    StatsObject::Deps => self.stats_deps().await,
                 }
             }
+            Command::Check(opts) => {
+                if opts.shellcheck {
+                    self.connect_docker(None)?;
+                }
+                self.check(opts).await
+            }
+            Command::Sign(opts) => {
+                self.gpg_key = load_gpg_key(&self.config).context(ExitCode::SigningFailure)?;
+                self.sign(opts).context(ExitCode::SigningFailure)
+            }
+            Command::Logs(opts) => self.logs(opts),
+            Command::Ebuild(opts) => self.ebuild(opts),
+            Command::Export(opts) => self.export(opts),
+            Command::Doctor(opts) => {
+                self.connect_docker(None)?;
+                self.doctor(opts).await
+            }
             Command::CleanCache => self.clean_cache().await,
+            Command::Cache { command } => match command {
+                CacheCommand::Verify(opts) => self.verify_cache(opts).await,
+            },
+            Command::PruneImages(opts) => self.prune_images(opts).await,
+            Command::Migrate(opts) => self.migrate_state(opts).await,
             Command::Init { .. } => unreachable!(),
             Command::Edit { object } => self.edit(object),
             Command::New { object } => self.create(object),
@@ -208,7 +418,12 @@ impl Application {
 
     fn create(&self, object: NewObject) -> Result<()> {
         match object {
-            NewObject::Image { name } => {
+            NewObject::Image {
+                name,
+                template,
+                from,
+            } => {
+                image::validate_name(&name)?;
                 let path = self.config.images_dir.clone().context("can't create an image when images directory is not specified in the configuration.")?.join(&name);
                 if path.exists() {
                     return err!("image `{}` already exists", name);
@@ -217,25 +432,47 @@ impl Application {
                 fs::create_dir(&path).context("failed to create a directory for the image")?;
                 let path = path.join("Dockerfile");
                 println!("creating a Dockerfile ~> `{}`", path.display());
-                fs::write(path, "").context("failed to create a Dockerfile")
+                fs::write(path, gen::dockerfile(template, from.as_deref()))
+                    .context("failed to create a Dockerfile")
             }
-            NewObject::Recipe(opts) => {
-                let path = self.config.recipes_dir.join(&opts.name);
+            NewObject::Recipe(mut opts) => {
+                let wizard = if opts.interactive {
+                    let images = self
+                        .config
+                        .images
+                        .iter()
+                        .map(|target| target.image.clone())
+                        .collect::<Vec<_>>();
+                    Some(wizard::recipe(
+                        &mut opts,
+                        &self.config.recipes_dir,
+                        &images,
+                    )?)
+                } else {
+                    None
+                };
+                let name = opts
+                    .name
+                    .clone()
+                    .context("missing name of the recipe to create")?;
+                recipe::validate_name(&name)?;
+                let path = self.config.recipes_dir.join(&name);
 
                 if path.exists() {
-                    return err!("recipe `{}` already exists", &opts.name);
+                    return err!("recipe `{}` already exists", &name);
                 }
 
                 let recipe = gen::recipe(opts);
+                let content = if let Some(wizard) = wizard {
+                    wizard.render(recipe)?
+                } else {
+                    serde_yaml::to_string(&recipe).context("failed to serialize recipe")?
+                };
                 println!("creating directory for recipe ~> `{}`", path.display());
                 fs::create_dir(&path).context("failed to create a directory for the recipe")?;
                 let path = path.join("recipe.yml");
                 println!("saving recipe ~> `{}`", path.display());
-                fs::write(
-                    path,
-                    &serde_yaml::to_string(&recipe).context("failed to serialize recipe")?,
-                )
-                .context("failed to save recipe file")
+                fs::write(path, content).context("failed to save recipe file")
             }
         }
     }
@@ -319,8 +556,9 @@ impl Application {
         span.in_scope(|| match object {
             CopyObject::Image { source, dest } => {
                 if let Some(images_dir) = &self.config.images_dir {
-                    let base_path = images_dir.join(&source);
-                    let dest_path = images_dir.join(&dest);
+                    let base_path = resolve_dir(images_dir, &source);
+                    let dest_path = resolve_dir(images_dir, &dest);
+                    image::validate_name(&dir_name(&dest_path)?)?;
                     if !base_path.exists() {
                         return err!("source image `{}` doesn't exists", source);
                     }
@@ -336,9 +574,15 @@ impl Application {
                     err!("no custom images directory defined in configuration")
                 }
             }
-            CopyObject::Recipe { source, dest } => {
-                let base_path = self.config.recipes_dir.join(&source);
-                let dest_path = self.config.recipes_dir.join(&dest);
+            CopyObject::Recipe {
+                source,
+                dest,
+                version,
+            } => {
+                let base_path = resolve_dir(&self.config.recipes_dir, &source);
+                let dest_path = resolve_dir(&self.config.recipes_dir, &dest);
+                let name = dir_name(&dest_path)?;
+                recipe::validate_name(&name)?;
                 if !base_path.exists() {
                     return err!("source recipe `{}` doesn't exists", source);
                 }
@@ -346,7 +590,10 @@ impl Application {
                     return err!("recipe `{}` already exists", dest);
                 }
                 info!("{} ~> {}", base_path.display(), dest_path.display());
-                copy_dir(base_path, dest_path).context("failed to copy source recipe directory")?;
+                copy_dir(base_path, &dest_path)
+                    .context("failed to copy source recipe directory")?;
+                rename_recipe(&dest_path, &name, version.as_deref())
+                    .context("failed to rename copied recipe")?;
                 info!("done.");
                 Ok(())
             }
@@ -368,6 +615,188 @@ impl Application {
         Ok(())
     }
 
+    /// Imports images from the state of the legacy pkger so that they can be pruned. The
+    /// configuration is migrated before the application is initialized.
+    async fn migrate_state(&mut self, opts: MigrateOpts) -> Result<()> {
+        let span = info_span!("migrate-state");
+        let _entered = span.enter();
+
+        let legacy_path = opts.legacy_state.unwrap_or_else(previous_state_file);
+        if !legacy_path.exists() {
+            info!(path = %legacy_path.display(), "no legacy images state found");
+            return Ok(());
+        }
+
+        let mut state = self.images_state.write().await;
+        let imported = state
+            .import_legacy(&legacy_path)
+            .context(format!("failed to migrate `{}`", legacy_path.display()))?;
+        if imported > 0 {
+            state.save()?;
+        }
+        info!(
+            images = %imported,
+            state = %state.locations().display(),
+            "imported legacy images, run `pkger prune-images` to remove them once superseded"
+        );
+        Ok(())
+    }
+
+    /// Checks that all images referenced by the state exist in Docker and removes states of the
+    /// missing ones.
+    async fn verify_cache(&mut self, opts: CacheVerifyOpts) -> Result<()> {
+        let span = info_span!("verify-cache");
+        let _entered = span.enter();
+
+        self.connect_docker(opts.docker.as_deref())?;
+        let docker = self.docker.connect();
+        let ids: Vec<_> = self
+            .images_state
+            .read()
+            .await
+            .image_ids()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let mut existing = HashSet::new();
+        for id in ids {
+            if docker.images().get(&id).inspect().await.is_ok() {
+                existing.insert(id);
+            } else {
+                trace!(id = %id, "image doesn't exist");
+            }
+        }
+
+        let mut state = self.images_state.write().await;
+        let missing: Vec<_> = state
+            .images
+            .iter()
+            .filter(|(_, image_state)| !existing.contains(&image_state.id))
+            .map(|(target, image_state)| (target.clone(), image_state.clone()))
+            .collect();
+        if missing.is_empty() {
+            info!(images = %state.images.len(), "all images exist");
+        } else {
+            missing
+                .iter()
+                .map(|(target, image_state)| {
+                    vec![
+                        target.recipe().cell().left().color(Color::BrightBlue),
+                        target.image().cell().left().color(Color::Blue),
+                        image_state
+                            .tag
+                            .as_str()
+                            .cell()
+                            .left()
+                            .color(Color::BrightYellow),
+                        image_state.id.as_str().cell().left(),
+                    ]
+                })
+                .collect::<Vec<_>>()
+                .into_table()
+                .with_headers(vec![
+                    "Recipe".cell().bold(),
+                    "Image".cell().bold(),
+                    "Tag".cell().bold(),
+                    "ID".cell().bold(),
+                ])
+                .print();
+        }
+        if opts.dry_run {
+            return Ok(());
+        }
+
+        let removed = state.reconcile(&existing);
+        if !removed.is_empty() {
+            info!(states = %removed.len(), "removed states of missing images");
+        }
+        if state.has_changed() {
+            state.save()?;
+        }
+        Ok(())
+    }
+
+    /// Removes images built by pkger that were superseded by newer builds keeping `opts.keep` of
+    /// the most recent ones of each image and tag.
+    async fn prune_images(&mut self, opts: PruneImagesOpts) -> Result<()> {
+        let span = info_span!("prune-images");
+        let _entered = span.enter();
+
+        self.connect_docker(opts.docker.as_deref())?;
+        let superseded = self
+            .images_state
+            .read()
+            .await
+            .superseded_images(opts.keep)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        if superseded.is_empty() {
+            info!("no superseded images found");
+            return Ok(());
+        }
+
+        if opts.dry_run {
+            superseded
+                .iter()
+                .map(|image| {
+                    vec![
+                        image.image.as_str().cell().left().color(Color::Blue),
+                        image.tag.as_str().cell().left().color(Color::BrightYellow),
+                        image.id.as_str().cell().left(),
+                        system_time_to_date_time(image.timestamp)
+                            .to_rfc3339_opts(SecondsFormat::Secs, true)
+                            .cell()
+                            .left()
+                            .color(Color::White),
+                    ]
+                })
+                .collect::<Vec<_>>()
+                .into_table()
+                .with_headers(vec![
+                    "Image".cell().bold(),
+                    "Tag".cell().bold(),
+                    "ID".cell().bold(),
+                    "Built".cell().bold(),
+                ])
+                .print();
+            return Ok(());
+        }
+
+        let docker = self.docker.connect();
+        let mut state = self.images_state.write().await;
+        let mut freed = 0;
+        for image in superseded {
+            let handle = docker.images().get(&image.id);
+            let size = match handle.inspect().await {
+                Ok(details) => details.size,
+                Err(_) => {
+                    trace!(id = %image.id, "image doesn't exist anymore");
+                    state.forget_image(&image.id);
+                    continue;
+                }
+            };
+            match handle.delete().await {
+                Ok(_) => {
+                    info!(image = %image.image, tag = %image.tag, id = %image.id, size = %format_size(size), "removed image");
+                    state.forget_image(&image.id);
+                    freed += size;
+                }
+                Err(e) => {
+                    warn!(image = %image.image, id = %image.id, reason = %e, "failed to remove image");
+                }
+            }
+        }
+
+        if freed > 0 {
+            info!(size = %format_size(freed), "freed space");
+        }
+        if state.has_changed() {
+            state.save()?;
+        }
+        Ok(())
+    }
+
     fn list_recipes(&self, verbose: bool) -> Result<()> {
         if verbose {
             let mut table = vec![];
@@ -418,15 +847,173 @@ impl Application {
         Ok(())
     }
 
-    fn list_packages(&self, images_filter: Option<Vec<String>>, verbose: bool) -> Result<()> {
+    /// Prints image targets that each recipe would be built for after matching its images with the
+    /// configuration. Images missing from the configuration, recipes without targets and recipes
+    /// that fail to load are highlighted.
+    fn list_targets(&self, recipes: Vec<String>) -> Result<()> {
+        let names = if recipes.is_empty() {
+            self.recipes.list()?
+        } else {
+            recipes
+        };
+
+        let mut table = vec![];
+        for name in names {
+            let recipe = match self.recipes.load(&name) {
+                Ok(recipe) => recipe,
+                Err(e) => {
+                    table.push(vec![
+                        name.cell().left().color(Color::BrightBlue),
+                        "".cell(),
+                        "".cell(),
+                        format!("failed to load: {:#}", e)
+                            .cell()
+                            .left()
+                            .color(Color::Red),
+                    ]);
+                    continue;
+                }
+            };
+
+            let targets = self.recipe_targets(&recipe);
+            if targets.is_empty() {
+                table.push(vec![
+                    name.as_str().cell().left().color(Color::BrightBlue),
+                    "".cell(),
+                    "".cell(),
+                    "no image targets".cell().left().color(Color::Yellow),
+                ]);
+            }
+            for target in targets {
+                let row = match target {
+                    Ok(target) => vec![
+                        name.as_str().cell().left().color(Color::BrightBlue),
+                        target.image.as_str().cell().left(),
+                        target
+                            .build_target
+                            .as_ref()
+                            .cell()
+                            .left()
+                            .color(Color::White),
+                        "ok".cell().left().color(Color::Green),
+                    ],
+                    Err(image) => vec![
+                        name.as_str().cell().left().color(Color::BrightBlue),
+                        image.cell().left(),
+                        "".cell(),
+                        "missing from configuration".cell().left().color(Color::Red),
+                    ],
+                };
+                table.push(row);
+            }
+        }
+
+        table
+            .into_table()
+            .with_headers(vec![
+                "Recipe".cell().bold(),
+                "Image".cell().bold(),
+                "Target".cell().bold(),
+                "Status".cell().bold(),
+            ])
+            .print();
+
+        Ok(())
+    }
+
+    /// Prints the number and size of dependencies cached for each recipe on each image, the largest
+    /// first, so that bloated dependency lists can be found.
+    async fn stats_deps(&self) -> Result<()> {
+        let state = self.images_state.read().await;
+        let mut stats = state
+            .images
+            .iter()
+            .filter(|(_, image)| image.deps_size.is_some())
+            .collect::<Vec<_>>();
+        if stats.is_empty() {
+            info!("no cached images found, run a build or `pkger warmup` first");
+            return Ok(());
+        }
+        stats.sort_by_key(|(_, image)| Reverse(image.deps_size));
+
+        let mut total = 0;
         let mut table = vec![];
-        let images = fs::read_dir(&self.config.output_dir)?.filter_map(|e| match e {
-            Ok(e) => Some(e.path()),
-            Err(e) => {
-                warn!(reason = %format!("{:?}", e), "invalid entry");
-                None
+        for (target, image) in stats {
+            let size = image.deps_size.unwrap_or_default();
+            total += size;
+            let slowest = state
+                .builds
+                .get(target)
+                .and_then(|build| build.steps.first())
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            table.push(vec![
+                target.recipe().cell().left().color(Color::BrightBlue),
+                target.image().cell().left(),
+                image.deps.len().to_string().cell(),
+                format_size(size).cell().color(Color::BrightYellow),
+                slowest.cell().left().color(Color::White),
+            ]);
+        }
+        table.push(vec![
+            "Total".cell().left().bold(),
+            "".cell(),
+            "".cell(),
+            format_size(total).cell().bold(),
+            "".cell(),
+        ]);
+
+        table
+            .into_table()
+            .with_headers(vec![
+                "Recipe".cell().bold(),
+                "Image".cell().bold(),
+                "Deps".cell().bold(),
+                "Size".cell().bold(),
+                "Slowest step".cell().bold(),
+            ])
+            .print();
+
+        Ok(())
+    }
+
+    /// Returns directories of the output directory that contain packages. With the store layout
+    /// these are the `<recipe>/<version>/<image>` directories with links to stored packages.
+    fn package_dirs(&self) -> Result<Vec<PathBuf>> {
+        fn read_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+            Ok(fs::read_dir(dir)?
+                .filter_map(|e| match e {
+                    Ok(e) => Some(e.path()),
+                    Err(e) => {
+                        warn!(reason = %format!("{:?}", e), "invalid entry");
+                        None
+                    }
+                })
+                .collect())
+        }
+
+        let mut dirs = read_dir(&self.config.output_dir)?;
+        if self.config.output_layout == OutputLayout::Store {
+            let store_dir = self.config.output_dir.join(STORE_DIR);
+            for _ in 0..2 {
+                dirs = dirs
+                    .into_iter()
+                    .filter(|dir| dir.is_dir() && *dir != store_dir)
+                    .map(|dir| read_dir(&dir))
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .flatten()
+                    .collect();
             }
-        });
+        }
+        Ok(dirs)
+    }
+
+    async fn list_packages(&self, images_filter: Option<Vec<String>>, verbose: bool) -> Result<()> {
+        let mut table = vec![];
+        let state = self.images_state.read().await;
+        let mut recipe_hashes = HashMap::new();
+        let images = self.package_dirs()?.into_iter();
 
         let images: Vec<_> = if let Some(filter) = images_filter {
             images
@@ -449,7 +1036,12 @@ impl Application {
                 .file_name()
                 .unwrap_or_else(|| image.as_os_str())
                 .to_string_lossy();
-            table.push(vec![format!("{}:", image_name)
+            let header = image
+                .strip_prefix(&self.config.output_dir)
+                .unwrap_or(&image)
+                .display()
+                .to_string();
+            table.push(vec![format!("{}:", header)
                 .cell()
                 .bold()
                 .color(Color::Blue)
@@ -457,7 +1049,9 @@ impl Application {
 
             match fs::read_dir(&image) {
                 Ok(packages) => {
-                    for package in packages {
+                    for package in packages.filter(
+                        |entry| !matches!(entry, Ok(entry) if gpg::is_public_key(&entry.path()) || entry.path().is_dir()),
+                    ) {
                         match package.context("invalid dir entry").and_then(|entry| {
                             PackageMetadata::try_from_dir_entry(&entry)
                                 .map(|v| (v, entry.path()))
@@ -465,11 +1059,7 @@ impl Application {
                         }) {
                             Ok((package, path)) => {
                                 if verbose {
-                                    let version = if let Some(release) = package.release() {
-                                        format!("{}-{}", package.version(), release)
-                                    } else {
-                                        package.version().to_string()
-                                    };
+                                    let version = package.full_version();
                                     let timestamp = package
                                         .created()
                                         .map(|c| {
@@ -477,11 +1067,25 @@ impl Application {
                                                 .to_rfc3339_opts(SecondsFormat::Secs, true)
                                         })
                                         .unwrap_or_default();
+                                    let hash = recipe_hashes
+                                        .entry(package.name().to_string())
+                                        .or_insert_with(|| {
+                                            self.recipes
+                                                .load(package.name())
+                                                .ok()
+                                                .map(|recipe| recipe.hash)
+                                        });
+                                    let status = match hash.as_ref().and_then(|hash| {
+                                        state.recipe_changed(package.name(), &image_name, hash)
+                                    }) {
+                                        Some(true) => "recipe changed",
+                                        _ => "",
+                                    };
 
                                     table.push(vec![
                                         "".cell(),
                                         package.name().cell().left().color(Color::BrightBlue),
-                                        package.package_type().as_ref().cell(),
+                                        package.extension().cell(),
                                         package
                                             .arch()
                                             .as_ref()
@@ -490,7 +1094,14 @@ impl Application {
                                             .cell()
                                             .color(Color::White),
                                         version.cell().color(Color::BrightYellow),
+                                        package
+                                            .size()
+                                            .map(format_size)
+                                            .unwrap_or_default()
+                                            .cell()
+                                            .color(Color::White),
                                         timestamp.cell().left().color(Color::White),
+                                        status.cell().left().color(Color::Yellow),
                                     ]);
                                 } else {
                                     table.push(vec![
@@ -523,7 +1134,9 @@ impl Application {
                 "Type".cell().bold(),
                 "Arch".cell().bold(),
                 "Version".cell().bold(),
+                "Size".cell().bold(),
                 "Created".cell().bold(),
+                "Status".cell().bold(),
             ]
         } else {
             vec!["Image".cell().bold(), "Name".cell().bold()]
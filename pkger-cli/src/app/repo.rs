@@ -0,0 +1,154 @@
+use crate::app::Application;
+use crate::publish;
+use crate::repo::apt::{self, AptRepo};
+use crate::repo::rpm::RpmRepo;
+use crate::repo::simulate::{self, RepoIndex};
+use pkger_core::recipe::BuildTarget;
+use pkger_core::{err, ErrContext, Error, Result};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{error, info, info_span, warn, Instrument};
+
+/// Returns packages from `packages` that have the given `extension` skipping source RPMs.
+fn with_extension<'p>(packages: &'p [PathBuf], extension: &str) -> Vec<&'p Path> {
+    packages
+        .iter()
+        .filter(|package| {
+            package
+                .extension()
+                .map(|ext| ext == extension)
+                .unwrap_or(false)
+                && !package.to_string_lossy().ends_with(".src.rpm")
+        })
+        .map(PathBuf::as_path)
+        .collect()
+}
+
+impl Application {
+    /// Adds the built `packages` to the repositories defined in the configuration and regenerates
+    /// the metadata of each repository that changed.
+    pub fn update_repositories(&self, packages: &[PathBuf]) -> Result<()> {
+        let span = info_span!("update-repositories");
+        let _enter = span.enter();
+
+        if let Some(config) = &self.config.apt_repo {
+            let debs = with_extension(packages, "deb");
+            if !debs.is_empty() {
+                let repo = AptRepo::new(config);
+                for deb in debs {
+                    let path = repo
+                        .add(deb)
+                        .context("failed to add package to APT repository")?;
+                    info!(package = %path.display(), "added package to APT repository");
+                }
+                repo.update(self.gpg_key.as_ref())
+                    .context("failed to update APT repository")?;
+            }
+        }
+
+        if let Some(config) = &self.config.rpm_repo {
+            let rpms = with_extension(packages, "rpm");
+            if !rpms.is_empty() {
+                let repo = RpmRepo::new(config, &self.docker);
+                for rpm in rpms {
+                    let path = repo
+                        .add(rpm)
+                        .context("failed to add package to RPM repository")?;
+                    info!(package = %path.display(), "added package to RPM repository");
+                }
+                repo.update(self.gpg_key.as_ref())
+                    .context("failed to update RPM repository")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Uploads the built `packages` to the hosted repositories defined in the `publish` section
+    /// of the configuration.
+    pub async fn publish(&self, packages: &[PathBuf]) -> Result<()> {
+        let span = info_span!("publish");
+        async move {
+            if self.config.publish.is_empty() {
+                warn!("no repositories to publish to defined in the configuration");
+                return Ok(());
+            }
+
+            let state = self.images_state.read().await;
+            let mut failed = false;
+            // indices of the repositories are downloaded once and reused for all packages
+            let mut indices: HashMap<usize, Result<RepoIndex>> = HashMap::new();
+            for package in packages {
+                let (target, image) = if let Some(build) = state.find_build(package) {
+                    build
+                } else {
+                    warn!(package = %package.display(), "build of the package not found, skipping");
+                    continue;
+                };
+                let build_target = *target.build_target();
+
+                for (i, config) in self
+                    .config
+                    .publish
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, config)| config.accepts(build_target))
+                {
+                    if let Some(simulate) = &config.simulate {
+                        if build_target == BuildTarget::Deb {
+                            let index = indices
+                                .entry(i)
+                                .or_insert_with(|| RepoIndex::load(simulate));
+                            if let Err(e) = simulate_deb(index, package) {
+                                failed = true;
+                                error!(package = %package.display(), repo = %config.repo, reason = %format!("{:?}", e), "not publishing package");
+                                continue;
+                            }
+                        } else {
+                            warn!(package = %package.display(), repo = %config.repo, "simulation is only supported for DEB packages");
+                        }
+                    }
+
+                    match publish::publish(config, package, build_target, &image.os) {
+                        Ok(_) => {
+                            info!(package = %package.display(), repo = %config.repo, "published package")
+                        }
+                        Err(e) => {
+                            failed = true;
+                            error!(package = %package.display(), repo = %config.repo, reason = %format!("{:?}", e), "failed to publish package");
+                        }
+                    }
+                }
+            }
+
+            if failed {
+                err!("failed to publish at least one package")
+            } else {
+                Ok(())
+            }
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+/// Checks if publishing the DEB `package` to the repository with the `index` would downgrade,
+/// conflict with or break packages in it.
+fn simulate_deb(index: &Result<RepoIndex>, package: &Path) -> Result<()> {
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => return err!("failed to load repository index - {:?}", e),
+    };
+    let control = apt::read_control(package)?;
+    let files = apt::read_files(package)?;
+    let problems = index.simulate(&control, &files);
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        err!(
+            "publishing would break the repository:\n{}",
+            simulate::report(&problems)
+        )
+    }
+}
@@ -0,0 +1,159 @@
+use crate::app::Application;
+use crate::manifest::{self, Artifact, ArtifactKind, SigningManifest, MANIFEST_FILE};
+use crate::opts::SignOpts;
+use crate::repo::sign;
+use pkger_core::gpg::GpgKey;
+use pkger_core::{err, ErrContext, Error, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info, info_span, trace};
+
+/// Name of the file with checksums of signed packages saved next to the signing manifest
+const CHECKSUMS_FILE: &str = "SHA256SUMS";
+
+/// Collects DEB and RPM packages from `dir` and its subdirectories. Symlinks are skipped so that
+/// packages saved with the `store` output layout are only signed once.
+fn find_packages(dir: &Path, packages: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).context("failed to read directory")? {
+        let entry = entry.context("invalid dir entry")?;
+        let file_type = entry.file_type().context("failed to read file type")?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            find_packages(&path, packages)?;
+        } else if file_type.is_file() && ArtifactKind::from_path(&path).is_some() {
+            packages.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Verifies that the unsigned package was not modified since it was added to the manifest and
+/// signs it. Returns the checksum of the signed package.
+fn sign_artifact(key: &GpgKey, dir: &Path, artifact: &Artifact) -> Result<String> {
+    let package = dir.join(&artifact.path);
+    let sha256 = manifest::sha256_file(&package)?;
+    if sha256 != artifact.sha256 {
+        return err!(
+            "package changed since it was built, expected sha256 `{}` found `{}`",
+            artifact.sha256,
+            sha256
+        );
+    }
+
+    match artifact.kind {
+        ArtifactKind::Deb => sign::sign_deb(key, &package),
+        ArtifactKind::Rpm => sign::sign_rpm(key, &package),
+    }?;
+
+    let package_dir = package.parent().unwrap_or(dir);
+    let public_key = package_dir.join(key.public_key_file());
+    if !public_key.exists() {
+        sign::export_public_key(key, &public_key).context("failed to export public key")?;
+        debug!(key = %public_key.display(), "exported public key");
+    }
+
+    manifest::sha256_file(&package)
+}
+
+impl Application {
+    /// Adds the unsigned `packages` built with `--no-sign` to the signing manifest in the output
+    /// directory.
+    pub fn update_signing_manifest(&self, packages: &[PathBuf]) -> Result<()> {
+        let path = self.config.output_dir.join(MANIFEST_FILE);
+        let mut manifest = SigningManifest::load(&path)?;
+        let mut added = 0;
+        for package in packages {
+            if manifest.add(&self.config.output_dir, package)? {
+                added += 1;
+            }
+        }
+        if added > 0 {
+            manifest.save(&path)?;
+            info!(manifest = %path.display(), packages = %added, "added unsigned packages to signing manifest");
+        }
+        Ok(())
+    }
+
+    /// Signs the packages from a signing manifest or a directory and saves signed checksums of
+    /// them to `SHA256SUMS` and `SHA256SUMS.asc`.
+    pub fn sign(&self, opts: SignOpts) -> Result<()> {
+        let span = info_span!("sign");
+        let _enter = span.enter();
+
+        let key = self
+            .gpg_key
+            .as_ref()
+            .context("signing requires `gpg_key` and `gpg_name` to be set in the configuration")?;
+
+        let path = opts.path.unwrap_or_else(|| self.config.output_dir.clone());
+        let (dir, manifest_path) = if path.is_dir() {
+            (path.clone(), path.join(MANIFEST_FILE))
+        } else {
+            let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+            (dir, path.clone())
+        };
+        trace!(dir = %dir.display(), manifest = %manifest_path.display());
+
+        let manifest = if manifest_path.exists() {
+            SigningManifest::load(&manifest_path)?
+        } else if path.is_dir() {
+            debug!("no signing manifest found, signing all packages");
+            let mut packages = vec![];
+            find_packages(&dir, &mut packages)?;
+            let mut manifest = SigningManifest::default();
+            for package in &packages {
+                manifest.add(&dir, package)?;
+            }
+            manifest
+        } else {
+            return err!("signing manifest `{}` does not exist", path.display());
+        };
+
+        if manifest.artifacts.is_empty() {
+            info!("no packages to sign");
+            return Ok(());
+        }
+
+        let checksums_path = dir.join(CHECKSUMS_FILE);
+        let mut checksums = if checksums_path.exists() {
+            manifest::parse_checksums(
+                &fs::read_to_string(&checksums_path).context("failed to read checksums")?,
+            )
+        } else {
+            Default::default()
+        };
+
+        let mut failed = vec![];
+        for artifact in manifest.artifacts {
+            match sign_artifact(key, &dir, &artifact) {
+                Ok(sha256) => {
+                    info!(package = %artifact.path.display(), "signed package");
+                    checksums.insert(artifact.path, sha256);
+                }
+                Err(e) => {
+                    error!(package = %artifact.path.display(), reason = %format!("{:?}", e), "failed to sign package");
+                    failed.push(artifact);
+                }
+            }
+        }
+
+        fs::write(&checksums_path, manifest::render_checksums(&checksums))
+            .context("failed to save checksums")?;
+        let signature = dir.join(format!("{}.asc", CHECKSUMS_FILE));
+        sign::detach_sign(key, &checksums_path, &signature).context("failed to sign checksums")?;
+        info!(checksums = %checksums_path.display(), "signed checksums");
+
+        if failed.is_empty() {
+            if manifest_path.exists() {
+                fs::remove_file(&manifest_path).context("failed to remove signing manifest")?;
+            }
+            Ok(())
+        } else {
+            if manifest_path.exists() {
+                SigningManifest { artifacts: failed }.save(&manifest_path)?;
+            }
+            err!("failed to sign at least one package")
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! Annotations of build results for CI systems. Failed jobs and lint findings are reported in a
+//! form that the CI displays next to the recipe in pull requests and a markdown summary of all
+//! jobs is written for the job page.
+
+use crate::job::JobResult;
+use crate::lint::{Finding, Severity};
+use crate::Error;
+use pkger_core::recipe::Recipe;
+use pkger_core::units::format_duration;
+use pkger_core::{ErrContext, Result};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File with the Code Quality report read by GitLab
+pub const GITLAB_REPORT_FILE: &str = "gl-code-quality-report.json";
+/// File with the job summary when the CI doesn't provide one
+pub const SUMMARY_FILE: &str = "pkger-summary.md";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CiAnnotations {
+    Github,
+    Gitlab,
+}
+
+impl FromStr for CiAnnotations {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "github" => Ok(CiAnnotations::Github),
+            "gitlab" => Ok(CiAnnotations::Gitlab),
+            _ => Err(Error::msg(format!("invalid CI annotations format `{}`", s))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotation {
+    pub level: Level,
+    /// Path of the recipe file relative to the current directory when possible
+    pub file: Option<PathBuf>,
+    /// Line in the file, starting from 1
+    pub line: Option<usize>,
+    pub title: String,
+    pub message: String,
+}
+
+/// Returns the path of the file of the `recipe`.
+fn recipe_file(recipe: &Recipe) -> PathBuf {
+    let path = recipe.recipe_dir.join("recipe.yml");
+    if path.exists() {
+        path
+    } else {
+        recipe.recipe_dir.join("recipe.yaml")
+    }
+}
+
+/// Returns the number of the first line of `content` containing the first line of `needle`.
+pub fn find_line(content: &str, needle: &str) -> Option<usize> {
+    let needle = needle.lines().next()?.trim();
+    if needle.is_empty() {
+        return None;
+    }
+    content
+        .lines()
+        .position(|line| line.contains(needle))
+        .map(|i| i + 1)
+}
+
+/// Returns the command of the step that failed, the reason of a failed step contains it as
+/// ``<phase> step `<command>` failed``.
+fn failed_step(reason: &str) -> Option<&str> {
+    let start = reason.find(" step `")? + " step `".len();
+    let end = reason[start..].find("` failed")?;
+    Some(&reason[start..start + end])
+}
+
+impl Annotation {
+    fn new(
+        level: Level,
+        recipe: &Recipe,
+        needle: Option<&str>,
+        title: String,
+        message: String,
+    ) -> Self {
+        let path = recipe_file(recipe);
+        let line = needle.and_then(|needle| {
+            fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| find_line(&content, needle))
+        });
+        let file = env::current_dir()
+            .ok()
+            .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf))
+            .unwrap_or(path);
+        Annotation {
+            level,
+            file: Some(file),
+            line,
+            title,
+            message,
+        }
+    }
+
+    /// Annotates the recipe file with a failed job. If the job failed in a step of a script the
+    /// line of the step is annotated.
+    pub fn failure(recipe: &Recipe, image: &str, reason: &str) -> Self {
+        Annotation::new(
+            Level::Error,
+            recipe,
+            failed_step(reason),
+            format!("{} failed to build on {}", recipe.metadata.name, image),
+            reason.to_string(),
+        )
+    }
+
+    /// Annotates the recipe file with a lint finding.
+    pub fn lint(recipe: &Recipe, finding: &Finding) -> Self {
+        Annotation::new(
+            if finding.severity == Severity::Error {
+                Level::Error
+            } else {
+                Level::Warning
+            },
+            recipe,
+            finding.location.as_deref(),
+            format!("{}: {}", recipe.metadata.name, finding.rule.as_ref()),
+            finding.message.clone(),
+        )
+    }
+}
+
+/// Escapes data of a GitHub workflow command.
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a property of a GitHub workflow command.
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Renders the annotation as a GitHub workflow command.
+pub fn github_command(annotation: &Annotation) -> String {
+    let mut props = vec![];
+    if let Some(file) = &annotation.file {
+        props.push(format!("file={}", escape_property(&file.to_string_lossy())));
+    }
+    if let Some(line) = annotation.line {
+        props.push(format!("line={}", line));
+    }
+    props.push(format!("title={}", escape_property(&annotation.title)));
+    format!(
+        "::{} {}::{}",
+        match annotation.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        },
+        props.join(","),
+        escape_data(&annotation.message)
+    )
+}
+
+#[derive(Serialize)]
+struct CodeQualityLines {
+    begin: usize,
+}
+
+#[derive(Serialize)]
+struct CodeQualityLocation {
+    path: String,
+    lines: CodeQualityLines,
+}
+
+#[derive(Serialize)]
+/// An issue of the GitLab Code Quality report
+struct CodeQualityIssue {
+    description: String,
+    check_name: String,
+    fingerprint: String,
+    severity: &'static str,
+    location: CodeQualityLocation,
+}
+
+impl From<&Annotation> for CodeQualityIssue {
+    fn from(annotation: &Annotation) -> Self {
+        let path = annotation
+            .file
+            .as_ref()
+            .map(|file| file.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let fingerprint = format!(
+            "{:x}",
+            Sha256::digest(
+                format!("{}:{}:{}", path, annotation.title, annotation.message).as_bytes()
+            )
+        );
+        CodeQualityIssue {
+            description: format!("{}: {}", annotation.title, annotation.message),
+            check_name: annotation.title.clone(),
+            fingerprint,
+            severity: match annotation.level {
+                Level::Error => "major",
+                Level::Warning => "minor",
+            },
+            location: CodeQualityLocation {
+                path,
+                lines: CodeQualityLines {
+                    begin: annotation.line.unwrap_or(1),
+                },
+            },
+        }
+    }
+}
+
+/// Renders a markdown table with the results of all jobs and the paths of the logs of image builds
+/// of the jobs in `image_logs`.
+pub fn summary(results: &[JobResult], image_logs: &HashMap<String, PathBuf>) -> String {
+    let cell = |s: &str| s.lines().next().unwrap_or_default().replace('|', "\\|");
+    let mut summary = "## pkger build summary\n\n| Job | Status | Duration | Output | Image log |\n| --- | --- | --- | --- | --- |\n"
+        .to_string();
+    for result in results {
+        let (id, status, duration, output) = match result {
+            JobResult::Success {
+                id,
+                duration,
+                output,
+                conflicts,
+                ..
+            } if !conflicts.is_empty() => (
+                id,
+                "success",
+                duration,
+                format!("{} ({})", output, conflicts.join("; ")),
+            ),
+            JobResult::Success {
+                id,
+                duration,
+                output,
+                ..
+            } => (id, "success", duration, output.clone()),
+            JobResult::Failure {
+                id,
+                duration,
+                reason,
+            } => (id, "**failure**", duration, reason.clone()),
+            JobResult::UpToDate {
+                id,
+                duration,
+                output,
+            } => (
+                id,
+                "up to date",
+                duration,
+                output.clone().unwrap_or_default(),
+            ),
+        };
+        let image_log = image_logs
+            .get(id)
+            .map(|log| log.to_string_lossy())
+            .unwrap_or_default();
+        summary.push_str(&format!(
+            "| {} | {} | {} | {} | {} |\n",
+            cell(id),
+            status,
+            format_duration(*duration),
+            cell(&output),
+            cell(&image_log)
+        ));
+    }
+    summary
+}
+
+impl CiAnnotations {
+    /// Returns the path of the job summary. GitHub provides the path in `GITHUB_STEP_SUMMARY`,
+    /// otherwise the summary is written to `pkger-summary.md` in the current directory.
+    fn summary_path(&self) -> PathBuf {
+        match self {
+            CiAnnotations::Github => env::var_os("GITHUB_STEP_SUMMARY")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(SUMMARY_FILE)),
+            CiAnnotations::Gitlab => PathBuf::from(SUMMARY_FILE),
+        }
+    }
+
+    /// Emits the `annotations` and appends the summary of the `results` to the job summary.
+    pub fn report(
+        &self,
+        annotations: &[Annotation],
+        results: &[JobResult],
+        image_logs: &HashMap<String, PathBuf>,
+    ) -> Result<()> {
+        match self {
+            CiAnnotations::Github => {
+                for annotation in annotations {
+                    println!("{}", github_command(annotation));
+                }
+            }
+            CiAnnotations::Gitlab => {
+                let issues: Vec<_> = annotations.iter().map(CodeQualityIssue::from).collect();
+                fs::write(
+                    GITLAB_REPORT_FILE,
+                    serde_json::to_vec_pretty(&issues)
+                        .context("failed to serialize code quality report")?,
+                )
+                .context("failed to write code quality report")?;
+            }
+        }
+
+        let path = self.summary_path();
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(summary(results, image_logs).as_bytes()))
+            .context(format!(
+                "failed to write job summary to `{}`",
+                path.display()
+            ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn renders_annotations_and_summary() {
+        let content =
+            "metadata:\n  name: pkger\nbuild:\n  steps:\n    - cmd: cargo build --release\n";
+        assert_eq!(find_line(content, "cargo build --release"), Some(5));
+        assert_eq!(find_line(content, "make"), None);
+        assert_eq!(
+            failed_step("build step `cargo build --release` failed\n\nCaused by: exit code 101"),
+            Some("cargo build --release")
+        );
+
+        let annotation = Annotation {
+            level: Level::Error,
+            file: Some(PathBuf::from("recipes/pkger/recipe.yml")),
+            line: Some(5),
+            title: "pkger failed to build on rocky".to_string(),
+            message: "build step failed: 50%\nexit code 101".to_string(),
+        };
+        assert_eq!(
+            github_command(&annotation),
+            "::error file=recipes/pkger/recipe.yml,line=5,title=pkger failed to build on rocky::build step failed: 50%25%0Aexit code 101"
+        );
+
+        let results = vec![
+            JobResult::success(
+                "pkger-pkger-rocky-1",
+                Duration::from_secs(75),
+                "/out/pkger.rpm",
+            ),
+            JobResult::failure("pkger-pkger-debian-1", Duration::from_secs(3), "a | b\nc"),
+            JobResult::Success {
+                id: "pkger-pkger-ubuntu-1".to_string(),
+                duration: Duration::from_secs(12),
+                output: "/out/pkger.1.deb".to_string(),
+                packages: vec!["/out/pkger.1.deb".to_string()],
+                conflicts: vec![
+                    "saved as `/out/pkger.1.deb` next to existing `/out/pkger.deb`".to_string(),
+                ],
+            },
+        ];
+        let image_logs = HashMap::from([(
+            "pkger-pkger-debian-1".to_string(),
+            PathBuf::from("/logs/1/images/pkger-pkger-debian-1.log"),
+        )]);
+        assert_eq!(
+            summary(&results, &image_logs),
+            "## pkger build summary
+
+| Job | Status | Duration | Output | Image log |
+| --- | --- | --- | --- | --- |
+| pkger-pkger-rocky-1 | success | 1m 15s | /out/pkger.rpm |  |
+| pkger-pkger-debian-1 | **failure** | 3.0s | a \\| b | /logs/1/images/pkger-pkger-debian-1.log |
+| pkger-pkger-ubuntu-1 | success | 12.0s | /out/pkger.1.deb (saved as `/out/pkger.1.deb` next to existing `/out/pkger.deb`) |  |
+"
+        );
+    }
+}
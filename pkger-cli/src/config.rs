@@ -1,9 +1,18 @@
+use crate::lint::LintConfig;
+use crate::migrate;
+use crate::secrets;
 use crate::Result;
+use pkger_core::build::artifacts::ArtifactPolicy;
+use pkger_core::build::store::OutputLayout;
+use pkger_core::image;
+use pkger_core::network::NetworkConfig;
 use pkger_core::recipe::{deserialize_images, BuildTarget, ImageTarget};
+use pkger_core::security::SecurityConfig;
 use pkger_core::ssh::SshConfig;
-use pkger_core::ErrContext;
+use pkger_core::{ErrContext, Error};
 
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value as YamlValue;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -11,12 +20,45 @@ use std::path::{Path, PathBuf};
 pub struct Configuration {
     pub recipes_dir: PathBuf,
     pub output_dir: PathBuf,
+    #[serde(default)]
+    pub output_layout: OutputLayout,
+    /// Action taken when built artifacts have the same names as existing files in the output
+    /// directory
+    #[serde(default)]
+    pub on_existing_artifact: ArtifactPolicy,
     pub images_dir: Option<PathBuf>,
+    pub project_name: Option<String>,
+    pub state_dir: Option<PathBuf>,
+    pub cache_dir: Option<PathBuf>,
+    /// Cache sources downloaded over HTTP(S) in `cache_dir` and revalidate them with conditional
+    /// requests
+    #[serde(default)]
+    pub source_cache: bool,
+    /// Names of environment variables of the host forwarded to the containers of all builds
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pass_env: Vec<String>,
     pub filter: Option<String>,
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub lint: LintConfig,
     pub docker: Option<String>,
     pub gpg_key: Option<PathBuf>,
     pub gpg_name: Option<String>,
+    /// Password of the gpg key. When not set it is prompted for
+    pub gpg_pass: Option<String>,
+    /// Age identity used to decrypt encrypted values of this configuration
+    pub age_key_file: Option<PathBuf>,
     pub ssh: Option<SshConfig>,
+    /// DNS settings and extra hosts of all build containers
+    pub network: Option<NetworkConfig>,
+    /// Seccomp and AppArmor profiles and other confinement of all build containers
+    pub security: Option<SecurityConfig>,
+    pub apt_repo: Option<AptRepoConfig>,
+    pub rpm_repo: Option<RpmRepoConfig>,
+    #[serde(default)]
+    pub publish: Vec<PublishConfig>,
     #[serde(deserialize_with = "deserialize_images")]
     pub images: Vec<ImageTarget>,
     #[serde(skip_serializing)]
@@ -28,12 +70,32 @@ pub struct Configuration {
 impl Configuration {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
-        serde_yaml::from_slice(&fs::read(path).context("failed to read configuration file")?)
-            .context("failed to deserialize configuration file")
-            .map(|mut cfg: Configuration| {
-                cfg.path = path.to_path_buf();
-                cfg
-            })
+        let mut value: YamlValue =
+            serde_yaml::from_slice(&fs::read(path).context("failed to read configuration file")?)
+                .context("failed to deserialize configuration file")?;
+        if migrate::is_legacy_config(&value) {
+            return err!(
+                "configuration uses the legacy format with images keyed by their name, run `pkger migrate` to convert it"
+            );
+        }
+        secrets::decrypt(&mut value, path).context("failed to decrypt configuration file")?;
+        let mut cfg: Configuration =
+            serde_yaml::from_value(value).context("failed to deserialize configuration file")?;
+        for target in &cfg.images {
+            image::validate_name(&target.image).context("invalid image in configuration")?;
+        }
+        if let Some(network) = &cfg.network {
+            network
+                .validate()
+                .context("invalid network in configuration")?;
+        }
+        if let Some(security) = &cfg.security {
+            security
+                .validate()
+                .context("invalid security in configuration")?;
+        }
+        cfg.path = path.to_path_buf();
+        Ok(cfg)
     }
 
     pub fn save(&self) -> Result<()> {
@@ -46,6 +108,75 @@ impl Configuration {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+/// Configuration of an APT repository that built DEB packages are added to
+pub struct AptRepoConfig {
+    /// Root directory of the repository
+    pub dir: PathBuf,
+    #[serde(default = "default_codename")]
+    pub codename: String,
+    #[serde(default = "default_component")]
+    pub component: String,
+    pub origin: Option<String>,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+/// Configuration of an RPM repository that built RPM packages are added to
+pub struct RpmRepoConfig {
+    /// Root directory of the repository
+    pub dir: PathBuf,
+    /// Image used to run `createrepo_c` when it is not available on the host
+    pub image: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PublishProvider {
+    PackageCloud,
+    Cloudsmith,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+/// Configuration of a hosted repository that built packages are uploaded to
+pub struct PublishConfig {
+    pub provider: PublishProvider,
+    /// Repository in the form of `user/repo` for PackageCloud or `owner/repo` for Cloudsmith
+    pub repo: String,
+    #[serde(default)]
+    /// Targets that are published to this repository. All supported targets if empty.
+    pub targets: Vec<BuildTarget>,
+    /// API token, takes precedence over `token_env`. Should be encrypted when the configuration
+    /// is stored in a repository.
+    pub token: Option<String>,
+    /// Name of the environment variable containing the API token
+    pub token_env: Option<String>,
+    /// Distribution and version like `ubuntu/focal`. If not set it is detected from the image
+    /// used to build the package.
+    pub distro: Option<String>,
+    /// Metadata of the repository checked before uploading packages
+    pub simulate: Option<SimulateConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+/// Metadata of an APT repository used to simulate publishing of DEB packages. Uploading fails
+/// with a report when a package would downgrade, conflict with or break packages in the
+/// repository.
+pub struct SimulateConfig {
+    /// URL or path of the `Packages` index, optionally compressed with gzip or xz
+    pub packages: String,
+    /// URL or path of the `Contents-<arch>` index used to find conflicting files
+    pub contents: Option<String>,
+}
+
+fn default_codename() -> String {
+    "stable".to_string()
+}
+
+fn default_component() -> String {
+    "main".to_string()
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CustomImagesDefinition {
     pub rpm: Option<String>,
@@ -53,6 +184,8 @@ pub struct CustomImagesDefinition {
     pub pkg: Option<String>,
     pub apk: Option<String>,
     pub gzip: Option<String>,
+    pub msi: Option<String>,
+    pub xbps: Option<String>,
 }
 
 impl CustomImagesDefinition {
@@ -63,6 +196,8 @@ impl CustomImagesDefinition {
             BuildTarget::Pkg => self.pkg.as_deref(),
             BuildTarget::Rpm => self.rpm.as_deref(),
             BuildTarget::Gzip => self.gzip.as_deref(),
+            BuildTarget::Msi => self.msi.as_deref(),
+            BuildTarget::Xbps => self.xbps.as_deref(),
         }
     }
 }
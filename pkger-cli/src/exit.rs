@@ -0,0 +1,86 @@
+//! Exit codes of pkger that let scripts tell apart the reasons of a failure without parsing the
+//! output. Errors are tagged with a code by adding it as a context and pkger exits with the code of
+//! the outermost tag of the error it failed with.
+
+use pkger_core::Error;
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Code `2` is not listed as it's used by clap when arguments are invalid.
+pub enum ExitCode {
+    /// Any failure without a more specific code
+    Failure = 1,
+    /// The configuration couldn't be loaded or is invalid
+    Config = 3,
+    /// One of the recipes to build doesn't exist
+    RecipeNotFound = 4,
+    /// The Docker daemon can't be reached
+    DockerUnreachable = 5,
+    /// All jobs of the build failed
+    BuildFailure = 6,
+    /// Some of the jobs of the build failed while others succeeded
+    PartialFailure = 7,
+    /// Packages couldn't be signed
+    SigningFailure = 8,
+    /// Packages couldn't be published
+    PublishFailure = 9,
+}
+
+impl ExitCode {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Returns the code of the outermost tag of `error` or `Failure` if it has none.
+    pub fn of(error: &Error) -> Self {
+        error
+            .downcast_ref::<ExitCode>()
+            .copied()
+            .unwrap_or(ExitCode::Failure)
+    }
+}
+
+impl fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExitCode::Failure => "execution failed",
+            ExitCode::Config => "invalid configuration",
+            ExitCode::RecipeNotFound => "recipe not found",
+            ExitCode::DockerUnreachable => "docker daemon is unreachable",
+            ExitCode::BuildFailure => "all jobs failed",
+            ExitCode::PartialFailure => "some of the jobs failed",
+            ExitCode::SigningFailure => "failed to sign packages",
+            ExitCode::PublishFailure => "failed to publish packages",
+        })
+    }
+}
+
+impl std::error::Error for ExitCode {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pkger_core::{err, ErrContext, Result};
+
+    #[test]
+    fn finds_exit_code_of_errors() {
+        let untagged: Result<()> = err!("failed to read file");
+        assert_eq!(ExitCode::of(&untagged.unwrap_err()), ExitCode::Failure);
+
+        let tagged = Err::<(), _>(Error::msg("connection refused"))
+            .context(ExitCode::DockerUnreachable)
+            .context("processing build opts")
+            .unwrap_err();
+        assert_eq!(ExitCode::of(&tagged), ExitCode::DockerUnreachable);
+        assert_eq!(ExitCode::of(&tagged).code(), 5);
+
+        let failed: Result<()> = Err(ExitCode::PartialFailure.into());
+        let retagged = failed.context(ExitCode::PublishFailure).unwrap_err();
+        assert_eq!(ExitCode::of(&retagged), ExitCode::PublishFailure);
+        assert_eq!(
+            format!("{:#}", retagged),
+            "failed to publish packages: some of the jobs failed"
+        );
+    }
+}
@@ -1,21 +1,85 @@
-use crate::opts::Opts;
+use crate::opts::{Command, Opts};
 
 use crate::config::Configuration;
+use crate::logs::JobLogLayer;
 use chrono::Utc;
 use colored::Colorize;
+use pkger_core::Error;
 use std::env;
 use std::fmt;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
 use tracing::{field::Field, info_span, trace, Level};
 use tracing_core::{Event, Subscriber};
 use tracing_subscriber::field::{MakeExt, MakeVisitor, RecordFields, VisitFmt};
 use tracing_subscriber::field::{Visit, VisitOutput};
 use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::prelude::*;
 use tracing_subscriber::registry::LookupSpan;
 
 static DEFAULT_FIELD_DELIM: &str = ", ";
 
 //####################################################################################################
 
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// When to use colors in the output
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(Error::msg(format!("invalid color choice `{}`", s))),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Decides whether the output should be colored. With `Auto` a set `CLICOLOR_FORCE` enables
+    /// colors, otherwise `NO_COLOR` or `CLICOLOR=0` disable them and colors are only used when the
+    /// output is a terminal.
+    fn colorize(&self, env: impl Fn(&str) -> Option<String>, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let force = env("CLICOLOR_FORCE");
+                let no_color = env("NO_COLOR");
+                if matches!(force.as_deref(), Some(force) if !force.is_empty() && force != "0") {
+                    true
+                } else if matches!(no_color.as_deref(), Some(no_color) if !no_color.is_empty())
+                    || env("CLICOLOR").as_deref() == Some("0")
+                {
+                    false
+                } else {
+                    is_terminal
+                }
+            }
+        }
+    }
+}
+
+/// Enables or disables colors of all output like logs and tables according to `--color` and the
+/// environment.
+pub fn setup_colors(opts: &Opts) {
+    // logs are written to stderr when progress events are printed to stdout
+    let is_terminal = if matches!(&opts.command, Command::Build(build) if build.progress.is_some())
+    {
+        io::stderr().is_terminal()
+    } else {
+        io::stdout().is_terminal()
+    };
+    colored::control::set_override(opts.color.colorize(|name| env::var(name).ok(), is_terminal));
+}
+
 pub fn setup_tracing(opts: &Opts, config: &Configuration) {
     let span = info_span!("setup-tracing");
     let _enter = span.enter();
@@ -26,6 +90,8 @@ pub fn setup_tracing(opts: &Opts, config: &Configuration) {
         "pkger=trace".to_string()
     } else if opts.debug {
         "pkger=debug".to_string()
+    } else if opts.quiet {
+        "pkger=error".to_string()
     } else {
         "pkger=info".to_string()
     };
@@ -41,12 +107,22 @@ pub fn setup_tracing(opts: &Opts, config: &Configuration) {
     let fields_fmt = PkgerFieldsFmt::from(&fmt_filter);
     let events_fmt = PkgerEventFmt::from(&fmt_filter);
 
-    tracing_subscriber::fmt::fmt()
+    let builder = tracing_subscriber::fmt::fmt()
         .with_max_level(Level::TRACE)
         .with_env_filter(&filter)
         .fmt_fields(fields_fmt)
-        .event_format(events_fmt)
-        .init();
+        .event_format(events_fmt);
+
+    if matches!(&opts.command, Command::Build(build) if build.progress.is_some()) {
+        // keep stdout clean for progress events
+        builder
+            .with_writer(io::stderr)
+            .finish()
+            .with(JobLogLayer)
+            .init();
+    } else {
+        builder.finish().with(JobLogLayer).init();
+    }
 
     trace!(log_filter = %filter);
     trace!(fmt_filter = ?fmt_filter);
@@ -266,3 +342,30 @@ where
         writeln!(writer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decides_whether_to_colorize() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        assert!(ColorChoice::Auto.colorize(env(&[]), true));
+        assert!(!ColorChoice::Auto.colorize(env(&[]), false));
+        assert!(!ColorChoice::Auto.colorize(env(&[("NO_COLOR", "1")]), true));
+        assert!(ColorChoice::Auto.colorize(env(&[("NO_COLOR", "")]), true));
+        assert!(!ColorChoice::Auto.colorize(env(&[("CLICOLOR", "0")]), true));
+        assert!(
+            ColorChoice::Auto.colorize(env(&[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")]), false)
+        );
+        assert!(ColorChoice::Always.colorize(env(&[("NO_COLOR", "1")]), false));
+        assert!(!ColorChoice::Never.colorize(env(&[("CLICOLOR_FORCE", "1")]), true));
+    }
+}
@@ -1,9 +1,83 @@
 use crate::opts::GenRecipeOpts;
-use pkger_core::recipe::{DebRep, MetadataRep, PkgRep, RecipeRep, RpmRep};
+use crate::Error;
+use pkger_core::recipe::{
+    BuildArch, DebRep, MetadataRep, PkgRep, RecipeRep, RpmFiles, RpmRep, SCHEMA_VERSION,
+};
 
 use serde_yaml::{Mapping, Value as YamlValue};
+use std::str::FromStr;
 use tracing::{info_span, trace, warn};
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Templates of Dockerfiles with common build tooling used when generating new images.
+pub enum ImageTemplate {
+    Debian11,
+    Rocky9,
+    Alpine319,
+    Arch,
+}
+
+impl FromStr for ImageTemplate {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "debian11" => Ok(ImageTemplate::Debian11),
+            "rocky9" => Ok(ImageTemplate::Rocky9),
+            "alpine3.19" => Ok(ImageTemplate::Alpine319),
+            "arch" => Ok(ImageTemplate::Arch),
+            _ => Err(Error::msg(format!("invalid image template `{}`", s))),
+        }
+    }
+}
+
+impl ImageTemplate {
+    /// Returns the default base image of this template.
+    pub fn base_image(&self) -> &'static str {
+        match self {
+            ImageTemplate::Debian11 => "debian:11",
+            ImageTemplate::Rocky9 => "rockylinux:9",
+            ImageTemplate::Alpine319 => "alpine:3.19",
+            ImageTemplate::Arch => "archlinux:latest",
+        }
+    }
+
+    /// Returns the instructions that install common build tooling.
+    fn instructions(&self) -> &'static str {
+        match self {
+            ImageTemplate::Debian11 => {
+                r#"ENV DEBIAN_FRONTEND noninteractive
+RUN apt-get update \
+    && apt-get install -y build-essential ca-certificates curl git \
+    && rm -rf /var/lib/apt/lists/*"#
+            }
+            ImageTemplate::Rocky9 => {
+                r#"RUN dnf install -y gcc gcc-c++ make git rpm-build \
+    && dnf clean all"#
+            }
+            ImageTemplate::Alpine319 => r#"RUN apk add --no-cache abuild build-base curl git"#,
+            ImageTemplate::Arch => {
+                r#"RUN pacman -Syu --noconfirm base-devel curl git \
+    && pacman -Scc --noconfirm"#
+            }
+        }
+    }
+}
+
+/// Generates a Dockerfile of a new image. The `FROM` line uses `from` if provided, otherwise the
+/// base image of the `template`.
+pub fn dockerfile(template: Option<ImageTemplate>, from: Option<&str>) -> String {
+    let from = from.or_else(|| template.map(|template| template.base_image()));
+    let mut dockerfile = String::new();
+    if let Some(from) = from {
+        dockerfile.push_str(&format!("FROM {}\n", from));
+    }
+    if let Some(template) = template {
+        dockerfile.push_str(template.instructions());
+        dockerfile.push('\n');
+    }
+    dockerfile
+}
+
 pub fn recipe(opts: Box<GenRecipeOpts>) -> RecipeRep {
     let span = info_span!("gen-recipe");
     let _enter = span.enter();
@@ -40,7 +114,11 @@ pub fn recipe(opts: Box<GenRecipeOpts>) -> RecipeRep {
 
     macro_rules! vec_as_deps {
         ($it:expr) => {{
-            let vec = $it.into_iter().map(YamlValue::from).collect::<Vec<_>>();
+            let vec = $it
+                .into_iter()
+                .flatten()
+                .map(YamlValue::from)
+                .collect::<Vec<_>>();
             if vec.is_empty() {
                 YamlValue::Null
             } else {
@@ -61,7 +139,16 @@ pub fn recipe(opts: Box<GenRecipeOpts>) -> RecipeRep {
         replaces: vec_as_deps!(opts.replaces.clone()),
         enhances: vec_as_deps!(opts.enchances),
 
+        preinst_script: None,
         postinst_script: None,
+        prerm_script: None,
+        postrm_script: None,
+        triggers: None,
+        conffiles: None,
+        fields: Default::default(),
+        compression: None,
+        compression_level: None,
+        changelog: None,
     };
 
     let rpm = RpmRep {
@@ -74,7 +161,15 @@ pub fn recipe(opts: Box<GenRecipeOpts>) -> RecipeRep {
         post_script: None,
         preun_script: None,
         postun_script: None,
-        config_noreplace: opts.config_noreplace,
+        posttrans_script: None,
+        config_noreplace: None,
+        deltarpm: None,
+        files: RpmFiles {
+            config_noreplace: opts.config_noreplace.into_iter().collect(),
+            ..Default::default()
+        },
+        spec_template: None,
+        changelog_file: None,
     };
 
     let pkg = PkgRep {
@@ -82,10 +177,16 @@ pub fn recipe(opts: Box<GenRecipeOpts>) -> RecipeRep {
         backup: opts.backup_files.unwrap_or_default(),
         replaces: vec_as_deps!(opts.replaces),
         optdepends: opts.optdepends.unwrap_or_default(),
+        pre_install_script: None,
+        post_install_script: None,
+        pre_upgrade_script: None,
+        post_upgrade_script: None,
+        pre_remove_script: None,
+        post_remove_script: None,
     };
 
     let metadata = MetadataRep {
-        name: opts.name,
+        name: opts.name.unwrap_or_default(),
         version: opts.version.unwrap_or_else(|| "1.0.0".to_string()),
         description: opts.description.unwrap_or_else(|| "missing".to_string()),
         license: opts.license.unwrap_or_else(|| "missing".to_string()),
@@ -94,32 +195,58 @@ pub fn recipe(opts: Box<GenRecipeOpts>) -> RecipeRep {
 
         maintainer: opts.maintainer,
         url: opts.url,
-        arch: opts.arch,
-        source: opts.source,
+        arch: opts.arch.or_else(|| Some(BuildArch::host().to_string())),
+        source: opts.source.map(YamlValue::from).unwrap_or(YamlValue::Null),
         git,
         skip_default_deps: opts.skip_default_deps,
         exclude: opts.exclude,
+        cache_dirs: vec![],
+        license_files: vec![],
+        doc_files: vec![],
+        man_pages: vec![],
+        completions: None,
+        systemd: None,
+        binaries: None,
+        normalize_text: None,
+        build_info: None,
         group: opts.group,
         release: opts.release,
         epoch: opts.epoch,
+        prefix: None,
 
         build_depends: vec_as_deps!(opts.build_depends),
         depends: vec_as_deps!(opts.depends),
         conflicts: vec_as_deps!(opts.conflicts),
         provides: vec_as_deps!(opts.provides),
+        replaces: YamlValue::Null,
+        renamed_from: vec![],
+        changelog: vec![],
+        subpackages: vec![],
+        files: vec![],
+        symlinks: Default::default(),
+        users: vec![],
+        groups: vec![],
+        alternatives: vec![],
         patches: vec_as_deps!(opts.patches),
 
         deb: Some(deb),
         rpm: Some(rpm),
         pkg: Some(pkg),
         apk: None,
+        msi: None,
+        gzip: None,
+        ebuild: None,
     };
 
     RecipeRep {
+        schema_version: Some(SCHEMA_VERSION),
+        include: vec![],
         metadata,
         env: if env.is_empty() { None } else { Some(env) },
+        pass_env: vec![],
         configure: None,
         build: Default::default(),
         install: None,
+        shell: None,
     }
 }
@@ -1,4 +1,5 @@
-use pkger_core::build::{self, Context};
+use pkger_core::build::progress::{Event, Status};
+use pkger_core::build::{self, Context, Outcome};
 use pkger_core::docker;
 
 use std::time::{Duration, Instant};
@@ -8,12 +9,21 @@ pub enum JobResult {
         id: String,
         duration: Duration,
         output: String,
+        /// Paths of all packages built by the job, the main package first
+        packages: Vec<String>,
+        /// Artifacts that had the same names as existing files and the actions taken for them
+        conflicts: Vec<String>,
     },
     Failure {
         id: String,
         duration: Duration,
         reason: String,
     },
+    UpToDate {
+        id: String,
+        duration: Duration,
+        output: Option<String>,
+    },
 }
 
 impl JobResult {
@@ -26,6 +36,8 @@ impl JobResult {
             id: id.into(),
             duration,
             output: output.into(),
+            packages: vec![],
+            conflicts: vec![],
         }
     }
 
@@ -40,10 +52,64 @@ impl JobResult {
             reason: err.into(),
         }
     }
+
+    pub fn id(&self) -> &str {
+        match self {
+            JobResult::Success { id, .. }
+            | JobResult::Failure { id, .. }
+            | JobResult::UpToDate { id, .. } => id,
+        }
+    }
+
+    /// Returns a progress event corresponding to this result.
+    pub fn as_progress_event(&self) -> Event {
+        match self {
+            JobResult::Success {
+                id,
+                duration,
+                output,
+                conflicts,
+                ..
+            } => Event::Finished {
+                id: id.clone(),
+                status: Status::Success,
+                duration: duration.as_secs_f32(),
+                output: Some(output.clone()),
+                reason: None,
+                conflicts: conflicts.clone(),
+            },
+            JobResult::Failure {
+                id,
+                duration,
+                reason,
+            } => Event::Finished {
+                id: id.clone(),
+                status: Status::Failure,
+                duration: duration.as_secs_f32(),
+                output: None,
+                reason: Some(reason.clone()),
+                conflicts: vec![],
+            },
+            JobResult::UpToDate {
+                id,
+                duration,
+                output,
+            } => Event::Finished {
+                id: id.clone(),
+                status: Status::UpToDate,
+                duration: duration.as_secs_f32(),
+                output: output.clone(),
+                reason: None,
+                conflicts: vec![],
+            },
+        }
+    }
 }
 
 pub enum JobCtx {
     Build(Context),
+    /// Only builds and caches the image of the build
+    Warmup(Context),
 }
 
 impl JobCtx {
@@ -62,10 +128,31 @@ impl JobCtx {
                     };
                     JobResult::failure(ctx.id(), duration, reason)
                 }
-                Ok(output) => JobResult::success(
+                Ok(Outcome::Built(packages, conflicts)) => JobResult::Success {
+                    id: ctx.id().to_string(),
+                    duration: start.elapsed(),
+                    output: packages
+                        .first()
+                        .map(|package| package.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    packages: packages
+                        .iter()
+                        .map(|package| package.to_string_lossy().to_string())
+                        .collect(),
+                    conflicts: conflicts.iter().map(ToString::to_string).collect(),
+                },
+                Ok(Outcome::UpToDate(output)) => JobResult::UpToDate {
+                    id: ctx.id().to_string(),
+                    duration: start.elapsed(),
+                    output: output.map(|output| output.to_string_lossy().to_string()),
+                },
+            },
+            JobCtx::Warmup(mut ctx) => match build::warmup(&mut ctx).await {
+                Err(e) => JobResult::failure(ctx.id(), start.elapsed(), format!("{:?}", e)),
+                Ok(state) => JobResult::success(
                     ctx.id(),
                     start.elapsed(),
-                    output.to_string_lossy().to_string(),
+                    format!("{}:{}", state.image, state.tag),
                 ),
             },
         }
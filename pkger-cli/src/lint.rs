@@ -0,0 +1,303 @@
+//! Lint rules over loaded recipes catching common mistakes that don't prevent a build, like
+//! sources downloaded over plain HTTP or excludes removing the whole package. The severity of
+//! each rule is configured in the `lint` section of the configuration.
+
+use pkger_core::recipe::Recipe;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::{error, warn};
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    /// The description of the recipe is empty
+    MissingDescription,
+    /// The license of the recipe is empty
+    MissingLicense,
+    /// The git source follows a branch instead of a tag
+    UnpinnedGitBranch,
+    /// A source or a git repository is downloaded over plain HTTP
+    InsecureSource,
+    /// An exclude path is absolute, these are ignored during the build
+    AbsoluteExclude,
+    /// An exclude path removes the whole output directory or the prefix of the package
+    BroadExclude,
+}
+
+impl AsRef<str> for Rule {
+    fn as_ref(&self) -> &str {
+        match self {
+            Rule::MissingDescription => "missing-description",
+            Rule::MissingLicense => "missing-license",
+            Rule::UnpinnedGitBranch => "unpinned-git-branch",
+            Rule::InsecureSource => "insecure-source",
+            Rule::AbsoluteExclude => "absolute-exclude",
+            Rule::BroadExclude => "broad-exclude",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The rule is not checked
+    Off,
+    /// Findings are reported but don't fail the check
+    #[default]
+    Warn,
+    /// Findings fail the check
+    Error,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+/// Configuration of the recipe lints
+pub struct LintConfig {
+    #[serde(default)]
+    /// Lint recipes before building them and fail the build on findings with `error` severity
+    pub before_build: bool,
+    #[serde(default)]
+    /// Severity of each rule, rules not listed here have the `warn` severity
+    pub rules: BTreeMap<Rule, Severity>,
+}
+
+impl LintConfig {
+    pub fn severity(&self, rule: Rule) -> Severity {
+        self.rules.get(&rule).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+    /// Text in the recipe file that the finding refers to, used to locate the line of the finding
+    pub location: Option<String>,
+}
+
+/// Returns `true` if the git `reference` looks like a tag of a release, like `v1.2.0` or
+/// `refs/tags/1.2`.
+fn is_pinned(reference: &str) -> bool {
+    reference.starts_with("refs/tags/")
+        || reference
+            .trim_start_matches('v')
+            .starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Returns `true` if the exclude `path` removes the whole output directory or the `prefix`.
+fn is_broad(path: &str, prefix: &str) -> bool {
+    let path = path.trim_start_matches("./").trim_end_matches('/');
+    matches!(path, "" | "." | "*" | "**")
+        || path.starts_with("..")
+        || path == prefix.trim_start_matches('/').trim_end_matches('/')
+}
+
+/// Runs all rules not turned off in the `config` over the `recipe`.
+pub fn lint(recipe: &Recipe, config: &LintConfig) -> Vec<Finding> {
+    let metadata = &recipe.metadata;
+    let mut findings = vec![];
+    let mut report = |rule: Rule, message: String, location: Option<&str>| {
+        let severity = config.severity(rule);
+        if severity != Severity::Off {
+            findings.push(Finding {
+                rule,
+                severity,
+                message,
+                location: location.map(str::to_string),
+            });
+        }
+    };
+
+    if metadata.description.trim().is_empty() {
+        report(
+            Rule::MissingDescription,
+            "description is empty".to_string(),
+            Some("description:"),
+        );
+    }
+    if metadata.license.trim().is_empty() {
+        report(
+            Rule::MissingLicense,
+            "license is empty".to_string(),
+            Some("license:"),
+        );
+    }
+
+    if let Some(git) = &metadata.git {
+        if !is_pinned(git.branch()) {
+            report(
+                Rule::UnpinnedGitBranch,
+                format!(
+                    "git source `{}` follows branch `{}`, use a tag to get reproducible builds",
+                    git.url(),
+                    git.branch()
+                ),
+                Some(git.url()),
+            );
+        }
+        if git.url().starts_with("http://") {
+            report(
+                Rule::InsecureSource,
+                format!("git source `{}` is fetched over plain HTTP", git.url()),
+                Some(git.url()),
+            );
+        }
+    }
+    for source in &metadata.source {
+        for location in
+            std::iter::once(source.source()).chain(source.mirrors().iter().map(String::as_str))
+        {
+            if location.starts_with("http://") {
+                report(
+                    Rule::InsecureSource,
+                    format!("source `{}` is downloaded over plain HTTP", location),
+                    Some(location),
+                );
+            }
+        }
+    }
+
+    for path in metadata.exclude.iter().flatten() {
+        if Path::new(path).is_absolute() {
+            report(
+                Rule::AbsoluteExclude,
+                format!(
+                    "exclude `{}` is absolute and will be ignored, paths are relative to the output directory",
+                    path
+                ),
+                Some(path),
+            );
+        } else if is_broad(path, &metadata.prefix) {
+            report(
+                Rule::BroadExclude,
+                format!("exclude `{}` removes the whole package", path),
+                Some(path),
+            );
+        }
+    }
+
+    findings
+}
+
+/// Lints the `recipe` logging all findings. Returns `false` if any of the findings has the
+/// `error` severity.
+pub fn check_recipe(recipe: &Recipe, config: &LintConfig) -> bool {
+    let mut passed = true;
+    for finding in lint(recipe, config) {
+        let name = &recipe.metadata.name;
+        let rule = finding.rule.as_ref();
+        if finding.severity == Severity::Error {
+            passed = false;
+            error!(recipe = %name, rule = %rule, "{}", finding.message);
+        } else {
+            warn!(recipe = %name, rule = %rule, "{}", finding.message);
+        }
+    }
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pkger_core::recipe::{GitSource, RecipeRep, Source};
+    use std::path::PathBuf;
+
+    const TEST_RECIPE: &[u8] = include_bytes!("../../example/recipes/test-suite/recipe.yml");
+
+    fn findings(recipe: &Recipe, config: &LintConfig) -> Vec<(Rule, Severity, String)> {
+        lint(recipe, config)
+            .into_iter()
+            .map(|finding| (finding.rule, finding.severity, finding.message))
+            .collect()
+    }
+
+    #[test]
+    fn lints_recipes() {
+        let rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let metadata = &mut recipe.metadata;
+        metadata.description = "Package building tool".to_string();
+        metadata.license = String::new();
+        metadata.prefix = "/usr".to_string();
+        metadata.git = Some(GitSource::new(
+            "https://github.com/vv9k/pkger",
+            Some("v0.7.0"),
+        ));
+        metadata.source = vec![Source::from("http://example.com/pkger.tar.gz")];
+        metadata.exclude = Some(vec![
+            "share/info".to_string(),
+            "/etc".to_string(),
+            "./usr/".to_string(),
+        ]);
+
+        let mut config = LintConfig::default();
+        config.rules.insert(Rule::MissingLicense, Severity::Error);
+        config.rules.insert(Rule::InsecureSource, Severity::Off);
+
+        let absolute_exclude = (
+            Rule::AbsoluteExclude,
+            Severity::Warn,
+            "exclude `/etc` is absolute and will be ignored, paths are relative to the output directory".to_string(),
+        );
+        let broad_exclude = (
+            Rule::BroadExclude,
+            Severity::Warn,
+            "exclude `./usr/` removes the whole package".to_string(),
+        );
+        assert_eq!(
+            findings(&recipe, &config),
+            vec![
+                (
+                    Rule::MissingLicense,
+                    Severity::Error,
+                    "license is empty".to_string()
+                ),
+                absolute_exclude.clone(),
+                broad_exclude.clone(),
+            ]
+        );
+
+        recipe.metadata.git = Some(GitSource::from("http://github.com/vv9k/pkger"));
+        assert_eq!(
+            findings(&recipe, &LintConfig::default()),
+            vec![
+                (
+                    Rule::MissingLicense,
+                    Severity::Warn,
+                    "license is empty".to_string()
+                ),
+                (
+                    Rule::UnpinnedGitBranch,
+                    Severity::Warn,
+                    "git source `http://github.com/vv9k/pkger` follows branch `master`, use a tag to get reproducible builds".to_string()
+                ),
+                (
+                    Rule::InsecureSource,
+                    Severity::Warn,
+                    "git source `http://github.com/vv9k/pkger` is fetched over plain HTTP".to_string()
+                ),
+                (
+                    Rule::InsecureSource,
+                    Severity::Warn,
+                    "source `http://example.com/pkger.tar.gz` is downloaded over plain HTTP".to_string()
+                ),
+                absolute_exclude,
+                broad_exclude,
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_config() {
+        let config: LintConfig = serde_yaml::from_str(
+            "before_build: true\nrules:\n  broad-exclude: error\n  missing-license: off\n",
+        )
+        .unwrap();
+        assert!(config.before_build);
+        assert_eq!(config.severity(Rule::BroadExclude), Severity::Error);
+        assert_eq!(config.severity(Rule::MissingLicense), Severity::Off);
+        assert_eq!(config.severity(Rule::InsecureSource), Severity::Warn);
+    }
+}
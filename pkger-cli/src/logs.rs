@@ -0,0 +1,360 @@
+//! Log files of build jobs. Events emitted while a job runs are written to a separate file for
+//! each job in `<state_dir>/logs/<project>/<session>/<job-id>.log` so that they can be read later
+//! with the `logs` subcommand. Output of image builds within a job is written to a separate log in
+//! `<session>/images/<job-id>.log` instead so that failures of image builds are easy to isolate.
+
+use pkger_core::{ErrContext, Result};
+
+use chrono::{SecondsFormat, Utc};
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing_core::span::{Attributes, Id};
+use tracing_core::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Name of the directory with logs in the state directory
+pub const LOGS_DIR: &str = "logs";
+/// Name of the span that wraps a job, its `log` field is the path of the log file
+pub const JOB_SPAN: &str = "job";
+/// Line appended to the log once the job finishes
+pub const END_OF_LOG: &str = "-- end of log --";
+/// Name of the directory of a session with logs of image builds of the jobs
+pub const IMAGE_LOGS_DIR: &str = "images";
+/// Names of the spans that wrap image builds, events within them go to the image log of the job
+const IMAGE_SPANS: &[&str] = &["image-build", "cache-image"];
+
+/// Returns the path of the image build log of the job whose log is at `job_log`.
+pub fn image_log_path(job_log: &Path) -> PathBuf {
+    job_log
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(IMAGE_LOGS_DIR)
+        .join(job_log.file_name().unwrap_or_default())
+}
+
+/// Writes events from within job spans to the log files of the jobs.
+pub struct JobLogLayer;
+
+struct JobLog {
+    file: Mutex<fs::File>,
+    image_path: PathBuf,
+    /// Log of image builds, only created once the job builds an image
+    image_file: Mutex<Option<fs::File>>,
+}
+
+impl JobLog {
+    fn write_line(&self, line: &str) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    fn write_image_line(&self, line: &str) {
+        let mut image_file = match self.image_file.lock() {
+            Ok(image_file) => image_file,
+            Err(_) => return,
+        };
+        if image_file.is_none() {
+            let file = self
+                .image_path
+                .parent()
+                .map(fs::create_dir_all)
+                .unwrap_or(Ok(()))
+                .and_then(|_| {
+                    fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&self.image_path)
+                });
+            match file {
+                Ok(file) => {
+                    self.write_line(&format!(
+                        "output of image builds is written to {}",
+                        self.image_path.display()
+                    ));
+                    *image_file = Some(file);
+                }
+                // the output is kept in the job log rather than lost
+                Err(_) => {
+                    drop(image_file);
+                    self.write_line(line);
+                    return;
+                }
+            }
+        }
+        if let Some(file) = image_file.as_mut() {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    fn finish(&self) {
+        self.write_line(END_OF_LOG);
+        if let Ok(mut image_file) = self.image_file.lock() {
+            if let Some(file) = image_file.as_mut() {
+                let _ = writeln!(file, "{}", END_OF_LOG);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct LogPathVisitor(Option<PathBuf>);
+
+impl Visit for LogPathVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "log" {
+            self.0 = Some(PathBuf::from(format!("{:?}", value)));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "log" {
+            self.0 = Some(PathBuf::from(value));
+        }
+    }
+}
+
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+}
+
+impl<S> Layer<S> for JobLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != JOB_SPAN {
+            return;
+        }
+        let mut visitor = LogPathVisitor::default();
+        attrs.record(&mut visitor);
+        let log = visitor.0.and_then(|path| {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .ok()
+                .map(|file| JobLog {
+                    file: Mutex::new(file),
+                    image_path: image_log_path(&path),
+                    image_file: Mutex::new(None),
+                })
+        });
+        if let (Some(log), Some(span)) = (log, ctx.span(id)) {
+            span.extensions_mut().insert(log);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let scope = if let Some(scope) = ctx.event_scope(event) {
+            scope
+        } else {
+            return;
+        };
+        let mut is_image_build = false;
+        for span in scope {
+            is_image_build |= IMAGE_SPANS.contains(&span.name());
+            let extensions = span.extensions();
+            if let Some(log) = extensions.get::<JobLog>() {
+                let mut visitor = LineVisitor::default();
+                event.record(&mut visitor);
+                let line = format!(
+                    "{} {:<5} {}{}",
+                    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+                    event.metadata().level(),
+                    visitor.message,
+                    visitor.fields
+                );
+                if is_image_build {
+                    log.write_image_line(&line);
+                } else {
+                    log.write_line(&line);
+                }
+                return;
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            if let Some(log) = span.extensions().get::<JobLog>() {
+                log.finish();
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JobLogFile {
+    pub id: String,
+    pub path: PathBuf,
+    /// Path of the log of image builds if the job built any image
+    pub image_log: Option<PathBuf>,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+#[derive(Debug)]
+pub struct SessionLogs {
+    pub id: String,
+    /// Logs of the jobs ordered from the oldest
+    pub jobs: Vec<JobLogFile>,
+}
+
+impl SessionLogs {
+    /// Returns the time of the last change to any of the logs in the session.
+    pub fn modified(&self) -> SystemTime {
+        self.jobs
+            .iter()
+            .map(|job| job.modified)
+            .max()
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+}
+
+/// Returns logs of all sessions in `logs_dir` ordered from the newest session.
+pub fn sessions(logs_dir: &Path) -> Result<Vec<SessionLogs>> {
+    let mut sessions = vec![];
+    if !logs_dir.exists() {
+        return Ok(sessions);
+    }
+    for entry in fs::read_dir(logs_dir).context("failed to read logs directory")? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let mut jobs = vec![];
+        for job in fs::read_dir(entry.path())? {
+            let path = job?.path();
+            if path.extension().map(|ext| ext != "log").unwrap_or(true) {
+                continue;
+            }
+            let metadata = fs::metadata(&path)?;
+            let image_log = image_log_path(&path);
+            jobs.push(JobLogFile {
+                id: path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                size: metadata.len(),
+                modified: metadata.modified()?,
+                image_log: Some(image_log).filter(|log| log.exists()),
+                path,
+            });
+        }
+        jobs.sort_by_key(|job| job.modified);
+        sessions.push(SessionLogs {
+            id: entry.file_name().to_string_lossy().to_string(),
+            jobs,
+        });
+    }
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.modified()));
+    Ok(sessions)
+}
+
+/// Returns `true` if the job with `id` in the `pkger-<recipe>-<image>-<timestamp>` form built
+/// the `recipe`.
+fn is_job_of(id: &str, recipe: &str) -> bool {
+    id.strip_prefix("pkger-")
+        .and_then(|id| id.strip_prefix(recipe))
+        .and_then(|id| id.strip_prefix('-'))
+        .and_then(|id| id.rsplit_once('-'))
+        .map(|(image, timestamp)| {
+            !image.is_empty() && timestamp.chars().all(|c| c.is_ascii_digit())
+        })
+        .unwrap_or_default()
+}
+
+/// Finds the log of the job with ID `query` or the newest log of a job that built the recipe
+/// named `query`.
+pub fn find_job<'a>(sessions: &'a [SessionLogs], query: &str) -> Option<&'a JobLogFile> {
+    let jobs = || {
+        sessions
+            .iter()
+            .flat_map(|session| session.jobs.iter().rev())
+    };
+    jobs()
+        .find(|job| job.id == query)
+        .or_else(|| jobs().find(|job| is_job_of(&job.id, query)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use tracing::{info, info_span};
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn writes_and_finds_job_logs() {
+        let tmp = TempDir::new("pkger-logs").unwrap();
+        let session_dir = tmp.path().join("session-1");
+        fs::create_dir_all(&session_dir).unwrap();
+        let log = session_dir.join("pkger-pkger-debian-1700000000.log");
+
+        let subscriber = tracing_subscriber::registry().with(JobLogLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            info!("outside of any job");
+            let span = info_span!("job", log = %log.display());
+            span.in_scope(|| {
+                info_span!("build").in_scope(|| info!(image = "debian", "building"));
+                info_span!("image-build").in_scope(|| info!("Step 1/3 : FROM debian"));
+            });
+        });
+
+        let content = fs::read_to_string(&log).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("INFO  building image=debian"));
+        let image_log = session_dir
+            .join(IMAGE_LOGS_DIR)
+            .join("pkger-pkger-debian-1700000000.log");
+        assert!(lines[1].ends_with(&format!(
+            "output of image builds is written to {}",
+            image_log.display()
+        )));
+        assert_eq!(lines[2], END_OF_LOG);
+        let content = fs::read_to_string(&image_log).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("INFO  Step 1/3 : FROM debian"));
+        assert_eq!(lines[1], END_OF_LOG);
+
+        let sessions = sessions(tmp.path()).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, "session-1");
+        let id = "pkger-pkger-debian-1700000000";
+        assert_eq!(find_job(&sessions, id).unwrap().path, log);
+        assert_eq!(find_job(&sessions, id).unwrap().image_log, Some(image_log));
+        assert_eq!(find_job(&sessions, "pkger").unwrap().id, id);
+        assert!(find_job(&sessions, "pkger-debian").is_none());
+        assert!(find_job(&sessions, "other").is_none());
+    }
+}
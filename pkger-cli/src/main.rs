@@ -2,30 +2,46 @@
 extern crate pkger_core;
 
 use std::fs;
+use std::path::Path;
 use std::process;
 
 use tracing::error;
 
 use app::Application;
 use config::Configuration;
+use exit::ExitCode;
 use opts::Opts;
 use pkger_core::{ErrContext, Error, Result};
 
 mod app;
+mod ci;
 mod completions;
 mod config;
+mod exit;
 mod fmt;
 mod gen;
 mod job;
+mod lint;
+mod logs;
+mod manifest;
 mod metadata;
+mod migrate;
 mod opts;
+mod preview;
+mod progress;
+mod publish;
+mod repo;
+mod secrets;
+mod summary;
 mod table;
+mod wizard;
 
 static DEFAULT_CONFIG_FILE: &str = ".pkger.yml";
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::from_args();
+    fmt::setup_colors(&opts);
 
     if let opts::Command::Init(opts) = opts.command {
         let config_dir = dirs::config_dir().context("missing config directory")?;
@@ -53,12 +69,28 @@ async fn main() -> Result<()> {
         let cfg = Configuration {
             recipes_dir,
             output_dir,
+            output_layout: Default::default(),
+            on_existing_artifact: Default::default(),
             images_dir: Some(images_dir),
+            project_name: None,
+            state_dir: None,
+            cache_dir: None,
+            source_cache: false,
+            pass_env: vec![],
             filter: opts.filter,
+            strict: false,
+            lint: Default::default(),
             docker: opts.docker,
             gpg_key: opts.gpg_key,
             gpg_name: opts.gpg_name,
+            gpg_pass: None,
+            age_key_file: None,
             ssh: None,
+            network: None,
+            security: None,
+            apt_repo: None,
+            rpm_repo: None,
+            publish: vec![],
             images: vec![],
             path: config_path,
             custom_simple_images: None,
@@ -97,12 +129,24 @@ async fn main() -> Result<()> {
                 .to_string(),
             None => DEFAULT_CONFIG_FILE.to_string(),
         });
+    if let opts::Command::Migrate(_) = &opts.command {
+        if let Err(e) = migrate::migrate_config(Path::new(&config_path)) {
+            eprintln!("`{}` - {:?}", config_path, e);
+            process::exit(ExitCode::Config.code());
+        }
+    }
     let result = Configuration::load(&config_path);
     if let Err(e) = &result {
         eprintln!("`{}` - {:?}", config_path, e);
-        process::exit(1);
+        process::exit(ExitCode::Config.code());
+    }
+    let mut config = result.unwrap();
+    if let Some(state_dir) = opts.state_dir.clone() {
+        config.state_dir = Some(state_dir);
+    }
+    if let Some(cache_dir) = opts.cache_dir.clone() {
+        config.cache_dir = Some(cache_dir);
     }
-    let config = result.unwrap();
 
     fmt::setup_tracing(&opts, &config);
 
@@ -110,13 +154,13 @@ async fn main() -> Result<()> {
         Ok(app) => app,
         Err(error) => {
             error!(reason = %format!("{:?}", error), "failed to initialize pkger");
-            process::exit(1);
+            process::exit(ExitCode::of(&error).code());
         }
     };
 
     if let Err(error) = app.process_opts(opts).await {
         error!(reason = %format!("{:?}", error), "execution failed");
-        process::exit(1);
+        process::exit(ExitCode::of(&error).code());
     }
     Ok(())
 }
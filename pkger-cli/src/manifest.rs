@@ -0,0 +1,179 @@
+//! Manifest of unsigned packages written by `pkger build --no-sign` so that they can be signed
+//! later with `pkger sign`, possibly on a different machine that has the key.
+
+use pkger_core::{ErrContext, Result};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the signing manifest saved in the output directory
+pub const MANIFEST_FILE: &str = "pkger-sign.yml";
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactKind {
+    Deb,
+    Rpm,
+}
+
+impl ArtifactKind {
+    /// Recognizes the kind of the package from its extension. Source RPMs are not signed.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        if path.to_string_lossy().ends_with(".src.rpm") {
+            return None;
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("deb") => Some(ArtifactKind::Deb),
+            Some("rpm") => Some(ArtifactKind::Rpm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct Artifact {
+    /// Path of the package relative to the directory of the manifest
+    pub path: PathBuf,
+    pub kind: ArtifactKind,
+    /// Checksum of the unsigned package used to verify that it didn't change before signing
+    pub sha256: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+pub struct SigningManifest {
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+}
+
+impl SigningManifest {
+    /// Loads the manifest from `path` returning an empty manifest if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read(path).context("failed to read signing manifest")?;
+        serde_yaml::from_slice(&content).context("failed to parse signing manifest")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content =
+            serde_yaml::to_string(self).context("failed to serialize signing manifest")?;
+        fs::write(path, content).context("failed to save signing manifest")
+    }
+
+    /// Adds the `package` located in `dir` to the manifest replacing a previous entry of the
+    /// same path. Returns `false` if the package can't be signed.
+    pub fn add(&mut self, dir: &Path, package: &Path) -> Result<bool> {
+        let kind = if let Some(kind) = ArtifactKind::from_path(package) {
+            kind
+        } else {
+            return Ok(false);
+        };
+        let path = package.strip_prefix(dir).unwrap_or(package).to_path_buf();
+        let sha256 = sha256_file(&dir.join(&path))?;
+
+        self.artifacts.retain(|artifact| artifact.path != path);
+        self.artifacts.push(Artifact { path, kind, sha256 });
+        Ok(true)
+    }
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("failed to open `{}`", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).context("failed to hash package")?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Parses a `SHA256SUMS` file in the format of `sha256sum` to a map of paths to checksums.
+pub fn parse_checksums(content: &str) -> BTreeMap<PathBuf, String> {
+    content
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(sha256, path)| (PathBuf::from(path), sha256.to_string()))
+        .collect()
+}
+
+/// Renders a `SHA256SUMS` file in the format of `sha256sum`.
+pub fn render_checksums(checksums: &BTreeMap<PathBuf, String>) -> String {
+    checksums
+        .iter()
+        .map(|(path, sha256)| format!("{}  {}\n", sha256, path.display()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_signable_packages() {
+        let tmp = tempdir::TempDir::new("pkger-manifest").unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("debian10")).unwrap();
+        for name in [
+            "debian10/pkger-0.1.0-0.amd64.deb",
+            "pkger-0.1.0-0.x86_64.rpm",
+            "pkger-0.1.0-0.src.rpm",
+        ] {
+            fs::write(dir.join(name), b"package").unwrap();
+        }
+
+        let mut manifest = SigningManifest::default();
+        assert!(manifest
+            .add(dir, &dir.join("debian10/pkger-0.1.0-0.amd64.deb"))
+            .unwrap());
+        assert!(manifest
+            .add(dir, &dir.join("debian10/pkger-0.1.0-0.amd64.deb"))
+            .unwrap());
+        assert!(manifest
+            .add(dir, &dir.join("pkger-0.1.0-0.x86_64.rpm"))
+            .unwrap());
+        assert!(!manifest
+            .add(dir, &dir.join("pkger-0.1.0-0.src.rpm"))
+            .unwrap());
+
+        let path = dir.join(MANIFEST_FILE);
+        manifest.save(&path).unwrap();
+        let loaded = SigningManifest::load(&path).unwrap();
+
+        let sha256 = "bc4a71180870f7945155fbb02f4b0a2e3faa2a62d6d31b7039013055ed19869a".to_string();
+        assert_eq!(
+            loaded.artifacts,
+            vec![
+                Artifact {
+                    path: PathBuf::from("debian10/pkger-0.1.0-0.amd64.deb"),
+                    kind: ArtifactKind::Deb,
+                    sha256: sha256.clone(),
+                },
+                Artifact {
+                    path: PathBuf::from("pkger-0.1.0-0.x86_64.rpm"),
+                    kind: ArtifactKind::Rpm,
+                    sha256: sha256.clone(),
+                },
+            ]
+        );
+        let checksums = format!(
+            "{0}  debian10/pkger-0.1.0-0.amd64.deb\n{0}  pkger-0.1.0-0.x86_64.rpm\n",
+            sha256
+        );
+        assert_eq!(
+            parse_checksums(&checksums),
+            loaded
+                .artifacts
+                .iter()
+                .map(|artifact| (artifact.path.clone(), artifact.sha256.clone()))
+                .collect()
+        );
+        assert_eq!(render_checksums(&parse_checksums(&checksums)), checksums);
+        assert_eq!(
+            SigningManifest::load(&dir.join("missing.yml")).unwrap(),
+            SigningManifest::default()
+        );
+    }
+}
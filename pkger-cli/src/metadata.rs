@@ -1,3 +1,4 @@
+use pkger_core::archive::Compression;
 use pkger_core::recipe::{BuildArch, BuildTarget};
 use pkger_core::{ErrContext, Result};
 
@@ -7,14 +8,24 @@ use std::convert::TryFrom;
 use std::fs::{DirEntry, Metadata};
 use std::time::SystemTime;
 
+// the version may be prefixed with an epoch like `1:` as in names of PKG packages
 lazy_static! {
-    static ref DEB_RE: Regex = Regex::new(r"([\w.-]+?)-(\d+[.]\d+[.]\d+)[.]([\w_-]+)").unwrap();
+    static ref DEB_RE: Regex =
+        Regex::new(r"([\w.-]+?)-(?:(\d+):)?(\d+[.]\d+[.]\d+)[.]([\w_-]+)").unwrap();
     static ref RPM_RE: Regex =
-        Regex::new(r"([\w_.-]+?)-(\d+[.]\d+[.]\d+)-(\d+)[.]([\w_-]+)").unwrap();
+        Regex::new(r"([\w_.-]+?)-(?:(\d+):)?(\d+[.]\d+[.]\d+)-(\d+)[.]([\w_-]+)").unwrap();
     static ref PKG_RE: Regex =
-        Regex::new(r"([\w_.+@-]+?)-(\d+[.]\d+[.]\d+)-(\d+)-([\w_-]+)").unwrap();
-    static ref GZIP_RE: Regex = Regex::new(r"([\S]+?)-(\d+[.]\d+[.]\d+)").unwrap();
-    static ref APK_RE: Regex = Regex::new(r"([\w_.+@-]+?)-(\d+[.]\d+[.]\d+)-r(\d+)").unwrap();
+        Regex::new(r"([\w_.+@-]+?)-(?:(\d+):)?(\d+[.]\d+[.]\d+)-(\d+)-([\w_-]+)").unwrap();
+    static ref GZIP_RE: Regex = Regex::new(r"([\S]+?)-(?:(\d+):)?(\d+[.]\d+[.]\d+)").unwrap();
+    static ref APK_RE: Regex =
+        Regex::new(r"([\w_.+@-]+?)-(?:(\d+):)?(\d+[.]\d+[.]\d+)-r(\d+)").unwrap();
+    static ref XBPS_RE: Regex =
+        Regex::new(r"([\w_.+@-]+?)-(?:(\d+):)?(\d+[.]\d+[.]\d+)_(\d+)[.]([\w_-]+)").unwrap();
+}
+
+/// Returns the epoch captured by one of the regexes of package names if any.
+fn epoch(captures: &regex::Captures<'_>) -> Option<String> {
+    captures.get(2).map(|epoch| epoch.as_str().to_string())
 }
 
 #[cfg(unix)]
@@ -32,10 +43,13 @@ fn size(md: &Metadata) -> u64 {
 #[derive(Debug, PartialEq)]
 pub struct PackageMetadata {
     name: String,
+    epoch: Option<String>,
     version: String,
     release: Option<String>,
     arch: Option<BuildArch>,
     package_type: BuildTarget,
+    /// Compression of tarballs built by the `gzip` target
+    compression: Option<Compression>,
     created: Option<SystemTime>,
     size: Option<u64>, // in bytes
 }
@@ -45,48 +59,58 @@ impl PackageMetadata {
         &self.name
     }
 
-    pub fn version(&self) -> &str {
-        &self.version
-    }
-
-    pub fn release(&self) -> &Option<String> {
-        &self.release
+    /// Returns the version in the `[epoch:]version[-release]` format.
+    pub fn full_version(&self) -> String {
+        let mut version = self.version.clone();
+        if let Some(epoch) = &self.epoch {
+            version = format!("{}:{}", epoch, version);
+        }
+        if let Some(release) = &self.release {
+            version = format!("{}-{}", version, release);
+        }
+        version
     }
 
     pub fn arch(&self) -> &Option<BuildArch> {
         &self.arch
     }
 
-    pub fn package_type(&self) -> BuildTarget {
-        self.package_type
+    /// Returns the extension of the package like `deb` or `tar.zst`
+    pub fn extension(&self) -> &str {
+        self.compression
+            .map(|compression| compression.extension())
+            .unwrap_or_else(|| self.package_type.as_ref())
     }
 
     pub fn created(&self) -> Option<SystemTime> {
         self.created
     }
 
-    #[allow(dead_code)]
     pub fn size(&self) -> Option<u64> {
         self.size
     }
 
     pub fn try_from_dir_entry(e: &DirEntry) -> Result<Self> {
         let path = e.path();
-        let extension = path.extension().context("expected file extension")?;
-        let package_type = BuildTarget::try_from(extension.to_string_lossy().as_ref())?;
-        let path = path
-            .file_stem()
+        let file_name = path
+            .file_name()
             .context("expected a file name")?
             .to_string_lossy();
-        let path = path.as_ref();
+        let (stem, package_type, compression) = split_extension(&file_name)?;
 
+        // follow links to packages in the store
         let (created, size) = e
+            .path()
             .metadata()
             .map(|md| (md.created().ok(), Some(size(&md))))
             .ok()
             .unwrap_or((None, None));
 
-        Self::try_from_str(path, package_type, created, size)
+        Self::try_from_str(stem, package_type, created, size)
+            .map(|metadata| Self {
+                compression,
+                ..metadata
+            })
             .context("invalid package name, the name did not match any scheme")
     }
 
@@ -102,58 +126,84 @@ impl PackageMetadata {
                 .next()
                 .map(|captures| PackageMetadata {
                     name: captures[1].to_string(),
-                    version: captures[2].to_string(),
+                    epoch: epoch(&captures),
+                    version: captures[3].to_string(),
                     release: None,
-                    arch: BuildArch::try_from(&captures[3]).ok(),
+                    arch: BuildArch::try_from(&captures[4]).ok(),
                     package_type,
+                    compression: None,
                     created,
                     size,
                 }),
-            BuildTarget::Rpm => RPM_RE
+            BuildTarget::Rpm | BuildTarget::Msi => {
+                RPM_RE
+                    .captures_iter(s)
+                    .next()
+                    .map(|captures| PackageMetadata {
+                        name: captures[1].to_string(),
+                        epoch: epoch(&captures),
+                        version: captures[3].to_string(),
+                        release: Some(captures[4].to_string()),
+                        arch: BuildArch::try_from(&captures[5]).ok(),
+                        package_type,
+                        compression: None,
+                        created,
+                        size,
+                    })
+            }
+            BuildTarget::Pkg => PKG_RE
                 .captures_iter(s)
                 .next()
                 .map(|captures| PackageMetadata {
                     name: captures[1].to_string(),
-                    version: captures[2].to_string(),
-                    release: Some(captures[3].to_string()),
-                    arch: BuildArch::try_from(&captures[4]).ok(),
+                    epoch: epoch(&captures),
+                    version: captures[3].to_string(),
+                    release: Some(captures[4].to_string()),
+                    arch: BuildArch::try_from(&captures[5]).ok(),
                     package_type,
+                    compression: None,
                     created,
                     size,
                 }),
-            BuildTarget::Pkg => PKG_RE
+            BuildTarget::Gzip => GZIP_RE
                 .captures_iter(s)
                 .next()
                 .map(|captures| PackageMetadata {
                     name: captures[1].to_string(),
-                    version: captures[2].to_string(),
-                    release: Some(captures[3].to_string()),
-                    arch: BuildArch::try_from(&captures[4]).ok(),
+                    epoch: epoch(&captures),
+                    version: captures[3].to_string(),
+                    release: None,
+                    arch: None,
                     package_type,
+                    compression: None,
                     created,
                     size,
                 }),
-            BuildTarget::Gzip => GZIP_RE
+            BuildTarget::Apk => APK_RE
                 .captures_iter(s)
                 .next()
                 .map(|captures| PackageMetadata {
                     name: captures[1].to_string(),
-                    version: captures[2].to_string(),
-                    release: None,
+                    epoch: epoch(&captures),
+                    version: captures[3].to_string(),
+                    release: Some(captures[4].to_string()),
                     arch: None,
                     package_type,
+                    compression: None,
                     created,
                     size,
                 }),
-            BuildTarget::Apk => APK_RE
+            BuildTarget::Xbps => XBPS_RE
                 .captures_iter(s)
                 .next()
                 .map(|captures| PackageMetadata {
                     name: captures[1].to_string(),
-                    version: captures[2].to_string(),
-                    release: Some(captures[3].to_string()),
-                    arch: None,
+                    epoch: epoch(&captures),
+                    version: captures[3].to_string(),
+                    release: Some(captures[4].to_string()),
+                    arch: Some(BuildArch::from(&captures[5])),
                     package_type,
+                    compression: None,
                     created,
                     size,
                 }),
@@ -161,9 +211,23 @@ impl PackageMetadata {
     }
 }
 
+/// Splits the file name of a package to the stem, the target and the compression of tarballs.
+fn split_extension(file_name: &str) -> Result<(&str, BuildTarget, Option<Compression>)> {
+    if let Some((stem, compression)) = file_name.rsplit_once(".tar.").and_then(|(stem, ext)| {
+        Compression::from_extension(&format!("tar.{}", ext)).map(|compression| (stem, compression))
+    }) {
+        return Ok((stem, BuildTarget::Gzip, Some(compression)));
+    }
+    let (stem, extension) = file_name
+        .rsplit_once('.')
+        .context("expected file extension")?;
+    Ok((stem, BuildTarget::try_from(extension)?, None))
+}
+
 #[cfg(test)]
 mod tests {
-    use super::PackageMetadata;
+    use super::{split_extension, PackageMetadata};
+    use pkger_core::archive::Compression;
     use pkger_core::recipe::{BuildArch, BuildTarget};
     use std::time::SystemTime;
 
@@ -174,10 +238,12 @@ mod tests {
         assert_eq!(
             PackageMetadata {
                 name: "test-instantclient-19.10-basic".to_string(),
+                epoch: None,
                 version: "1.0.0".to_string(),
                 release: None,
                 arch: Some(BuildArch::x86_64),
                 package_type: BuildTarget::Deb,
+                compression: None,
                 created: None,
                 size: None,
             },
@@ -194,10 +260,12 @@ mod tests {
         assert_eq!(
             PackageMetadata {
                 name: "tst-dev-tools".to_string(),
+                epoch: None,
                 version: "1.0.1".to_string(),
                 release: Some("0".to_string()),
                 arch: Some(BuildArch::x86_64),
                 package_type: BuildTarget::Rpm,
+                compression: None,
                 created: Some(time),
                 size: None,
             },
@@ -205,6 +273,61 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parses_msi() {
+        let path = "tst-dev-tools-1.0.1-0.x64";
+
+        assert_eq!(
+            PackageMetadata {
+                name: "tst-dev-tools".to_string(),
+                epoch: None,
+                version: "1.0.1".to_string(),
+                release: Some("0".to_string()),
+                arch: Some(BuildArch::x86_64),
+                package_type: BuildTarget::Msi,
+                compression: None,
+                created: None,
+                size: None,
+            },
+            PackageMetadata::try_from_str(path, BuildTarget::Msi, None, None).unwrap(),
+        );
+    }
+
+    #[test]
+    fn parses_xbps() {
+        let path = "tst-dev-tools-1.0.1_1.armv7l";
+
+        assert_eq!(
+            PackageMetadata {
+                name: "tst-dev-tools".to_string(),
+                epoch: None,
+                version: "1.0.1".to_string(),
+                release: Some("1".to_string()),
+                arch: Some(BuildArch::Armv7h),
+                package_type: BuildTarget::Xbps,
+                compression: None,
+                created: None,
+                size: None,
+            },
+            PackageMetadata::try_from_str(path, BuildTarget::Xbps, None, None).unwrap(),
+        );
+    }
+
+    #[test]
+    fn parses_epochs() {
+        let path = "pkger-2:0.5.0-1-x86_64";
+
+        let package = PackageMetadata::try_from_str(path, BuildTarget::Pkg, None, None).unwrap();
+        assert_eq!(package.name, "pkger");
+        assert_eq!(package.epoch, Some("2".to_string()));
+        assert_eq!(package.full_version(), "2:0.5.0-1");
+
+        let package =
+            PackageMetadata::try_from_str("pkger-0.5.0", BuildTarget::Gzip, None, None).unwrap();
+        assert_eq!(package.epoch, None);
+        assert_eq!(package.full_version(), "0.5.0");
+    }
+
     #[test]
     fn parses_gzip() {
         let path = "tst-dev-tools-1.0.1";
@@ -212,10 +335,12 @@ mod tests {
         assert_eq!(
             PackageMetadata {
                 name: "tst-dev-tools".to_string(),
+                epoch: None,
                 version: "1.0.1".to_string(),
                 release: None,
                 arch: None,
                 package_type: BuildTarget::Gzip,
+                compression: None,
                 created: None,
                 size: None,
             },
@@ -230,14 +355,33 @@ mod tests {
         assert_eq!(
             PackageMetadata {
                 name: "pkger".to_string(),
+                epoch: None,
                 version: "0.5.0".to_string(),
                 release: Some("0".to_string()),
                 arch: Some(BuildArch::x86_64),
                 package_type: BuildTarget::Pkg,
+                compression: None,
                 created: None,
                 size: None,
             },
             PackageMetadata::try_from_str(path, BuildTarget::Pkg, None, None).unwrap(),
         );
     }
+
+    #[test]
+    fn splits_extensions() {
+        assert_eq!(
+            split_extension("pkger-0.5.0.tar.zst").unwrap(),
+            ("pkger-0.5.0", BuildTarget::Gzip, Some(Compression::Zstd))
+        );
+        assert_eq!(
+            split_extension("pkger-0.5.0.tar.gz").unwrap(),
+            ("pkger-0.5.0", BuildTarget::Gzip, Some(Compression::Gzip))
+        );
+        assert_eq!(
+            split_extension("pkger-0.5.0-0.amd64.deb").unwrap(),
+            ("pkger-0.5.0-0.amd64", BuildTarget::Deb, None)
+        );
+        assert!(split_extension("pkger-0.5.0.tar.lz").is_err());
+    }
 }
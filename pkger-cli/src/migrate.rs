@@ -0,0 +1,115 @@
+//! Conversion of configuration files of the legacy single-crate pkger to the current format.
+
+use crate::config::Configuration;
+use pkger_core::{ErrContext, Error, Result};
+
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+static IMAGES_KEY: &str = "images";
+
+/// Returns true if the configuration uses the legacy format with images keyed by their name
+/// instead of a list of images.
+pub fn is_legacy_config(config: &YamlValue) -> bool {
+    matches!(config.get(IMAGES_KEY), Some(YamlValue::Mapping(_)))
+}
+
+/// Converts images of a legacy configuration keyed by their name to a list of images with the
+/// name as the `name` field. Returns false if the configuration is not in the legacy format.
+fn convert_config(config: &mut YamlValue) -> bool {
+    let config = match config.as_mapping_mut() {
+        Some(config) => config,
+        None => return false,
+    };
+    let key = YamlValue::from(IMAGES_KEY);
+    let legacy = match config.get(&key) {
+        Some(YamlValue::Mapping(images)) => images.clone(),
+        _ => return false,
+    };
+    let converted = legacy
+        .into_iter()
+        .map(|(name, image)| {
+            let mut converted = Mapping::new();
+            converted.insert(YamlValue::from("name"), name);
+            if let YamlValue::Mapping(fields) = image {
+                for (key, value) in fields {
+                    if key.as_str() != Some("name") {
+                        converted.insert(key, value);
+                    }
+                }
+            }
+            YamlValue::Mapping(converted)
+        })
+        .collect();
+    config.insert(key, YamlValue::Sequence(converted));
+    true
+}
+
+/// Migrates the configuration file at `path` from the legacy format in place. The original file
+/// is saved next to it with a `.legacy` extension. Comments of the file are not preserved.
+pub fn migrate_config(path: &Path) -> Result<()> {
+    let mut config: YamlValue =
+        serde_yaml::from_slice(&fs::read(path).context("failed to read configuration file")?)
+            .context("failed to deserialize configuration file")?;
+    if config.get("sops").is_some() {
+        return err!("configuration encrypted with sops has to be decrypted before migrating it");
+    }
+    if !convert_config(&mut config) {
+        println!("configuration `{}` is up to date", path.display());
+        return Ok(());
+    }
+    serde_yaml::from_value::<Configuration>(config.clone())
+        .context("failed to convert legacy configuration")?;
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".legacy");
+    let backup = PathBuf::from(backup);
+    fs::copy(path, &backup).context("failed to back up legacy configuration")?;
+    fs::write(
+        path,
+        serde_yaml::to_string(&config).context("failed to serialize configuration")?,
+    )
+    .context("failed to save configuration")?;
+    println!(
+        "migrated configuration ~> `{}`, legacy configuration saved to `{}`",
+        path.display(),
+        backup.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_legacy_configs() {
+        let mut config: YamlValue = serde_yaml::from_str(
+            r#"
+recipes_dir: /tmp/recipes
+output_dir: /tmp/output
+images:
+  rocky:
+    target: rpm
+    os: Rocky
+  debian:
+    target: deb
+"#,
+        )
+        .unwrap();
+        assert!(is_legacy_config(&config));
+        assert!(convert_config(&mut config));
+        assert!(!is_legacy_config(&config));
+        assert!(!convert_config(&mut config));
+
+        let config: Configuration = serde_yaml::from_value(config).unwrap();
+        let images: Vec<_> = config
+            .images
+            .iter()
+            .map(|image| (image.image.as_str(), image.build_target.as_ref()))
+            .collect();
+        assert_eq!(images, vec![("rocky", "rpm"), ("debian", "deb")]);
+        assert!(config.images[0].os.is_some());
+    }
+}
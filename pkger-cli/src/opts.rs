@@ -1,5 +1,10 @@
+use crate::ci::CiAnnotations;
 use crate::completions::Shell;
+use crate::fmt::ColorChoice;
+use crate::gen::ImageTemplate;
+use crate::progress::ProgressFormat;
 use clap::Parser;
+use pkger_core::build::artifacts::ArtifactPolicy;
 use std::path::PathBuf;
 
 pub const APP_NAME: &str = "pkger";
@@ -27,9 +32,22 @@ pub struct Opts {
     /// Fields, S - Spans. L - Levels is on by default, use L to turn it off. All characters can be
     /// upper or lower case, the order doesn't matter, duplicates and errors are silently ignored.
     pub filter: Option<String>,
+    #[clap(long, default_value = "auto")]
+    /// When to use colors in the output, one of `auto`, `always` or `never`. With `auto` colors
+    /// are only used when the output is a terminal and `NO_COLOR` is not set, `CLICOLOR_FORCE`
+    /// enables them regardless.
+    pub color: ColorChoice,
     #[clap(short, long)]
     /// Path to the config file (default - "~/.pkger.yml").
     pub config: Option<String>,
+    #[clap(long)]
+    /// Override the directory where the state of images is kept (default - "$XDG_STATE_HOME/pkger"
+    /// if set, otherwise the cache directory of the OS).
+    pub state_dir: Option<PathBuf>,
+    #[clap(long)]
+    /// Override the directory where cached files like snapshots of cache directories are kept
+    /// (default - "pkger" in the cache directory of the OS).
+    pub cache_dir: Option<PathBuf>,
 
     #[clap(subcommand)]
     /// Subcommand to run
@@ -46,20 +64,51 @@ impl Opts {
 pub enum Command {
     /// Runs a build creating specified packages on target platforms.
     Build(BuildOpts),
+    /// Builds all images used by recipes and caches dependencies of the recipes in them without
+    /// building any packages so that the following builds start faster.
+    Warmup(WarmupOpts),
     /// Lists the specified objects like images.
     List {
         #[clap(subcommand)]
         /// An object to list like `image`, `recipe` or `package`.
         object: ListObject,
         #[clap(short, long)]
-        /// Disable colored output.
+        /// Disable colored output, same as `--color never`.
         raw: bool,
         #[clap(short, long)]
         /// Should the output be more verbose and include fields like version, arch...
         verbose: bool,
     },
+    /// Prints statistics gathered during previous builds.
+    Stats {
+        #[clap(subcommand)]
+        /// Statistics to print like `deps`.
+        object: StatsObject,
+        #[clap(short, long)]
+        /// Disable colored output, same as `--color never`.
+        raw: bool,
+    },
+    /// Validates recipes and optionally checks their scripts with shellcheck.
+    Check(CheckOpts),
+    /// Signs packages built with `--no-sign` using the key from the configuration.
+    Sign(SignOpts),
+    /// Lists logs of past build jobs or prints the log of a single job.
+    Logs(LogsOpts),
+    /// Generates Gentoo ebuilds from recipes.
+    Ebuild(EbuildOpts),
+    /// Renders packaging files like the spec or the control file of recipes without building them.
+    Export(ExportOpts),
+    /// Checks that the docker networks used by build containers have IPv6 connectivity.
+    Doctor(DoctorOpts),
     /// Deletes the cache files with image state.
     CleanCache,
+    /// Manages the saved state of images built by pkger.
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+    /// Removes images built by pkger that were superseded by newer builds of the same image.
+    PruneImages(PruneImagesOpts),
     /// Edit a recipe or an image.
     Edit {
         #[clap(subcommand)]
@@ -80,10 +129,65 @@ pub enum Command {
     },
     /// Initializes required directories and a configuration file at specified or default locations.
     Init(InitOpts),
+    /// Converts the configuration file and the images state of the legacy single-crate pkger to
+    /// the current formats.
+    Migrate(MigrateOpts),
     /// Prints completions for the specified shell
     PrintCompletions(CompletionsOpts),
 }
 
+#[derive(Debug, Parser)]
+pub struct MigrateOpts {
+    #[clap(long)]
+    /// Path to the images state saved by the legacy pkger (default - ".pkger.state" in the cache
+    /// directory of the OS).
+    pub legacy_state: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct WarmupOpts {
+    #[clap(short, long, use_delimiter = true)]
+    /// Only warm up the specified images, for example `--images rocky,debian`. By default all
+    /// images used by recipes are warmed up.
+    pub images: Option<Vec<String>>,
+    #[clap(long)]
+    /// URL to Docker daemon listening on a unix or tcp socket. Same as for the `build`
+    /// subcommand.
+    pub docker: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct PruneImagesOpts {
+    #[clap(short, long, default_value = "0")]
+    /// Number of the most recent superseded images of each image and tag to keep.
+    pub keep: usize,
+    #[clap(long)]
+    /// Only list the images that would be removed.
+    pub dry_run: bool,
+    #[clap(long)]
+    /// URL to Docker daemon listening on a unix or tcp socket. Same as for the `build`
+    /// subcommand.
+    pub docker: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub enum CacheCommand {
+    /// Verifies that images referenced by the saved state exist in Docker. States of images that
+    /// don't exist anymore are removed so that the next build rebuilds them once.
+    Verify(CacheVerifyOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct CacheVerifyOpts {
+    #[clap(long)]
+    /// Only list the states of images that would be removed.
+    pub dry_run: bool,
+    #[clap(long)]
+    /// URL to Docker daemon listening on a unix or tcp socket. Same as for the `build`
+    /// subcommand.
+    pub docker: Option<String>,
+}
+
 #[derive(Debug, Parser)]
 pub struct InitOpts {
     #[clap(short, long)]
@@ -131,22 +235,43 @@ pub enum ListObject {
         #[clap(short, long)]
         images: Option<Vec<String>>,
     },
+    /// Image targets each recipe would be built for, including images of recipes that are missing
+    /// from the configuration.
+    Targets {
+        /// Only list targets of these recipes.
+        recipes: Vec<String>,
+    },
+}
+
+#[derive(Debug, Parser)]
+pub enum StatsObject {
+    /// Number and size of dependencies installed in the cached image of each recipe on each image
+    /// together with the slowest phase of its last build, the largest first.
+    Deps,
 }
 
 #[derive(Debug, Parser)]
 pub enum CopyObject {
-    /// Copy a recipe
+    /// Copy a recipe. The name in the metadata of the copied recipe is changed to the name of the
+    /// destination.
     Recipe {
-        /// Source recipe to copy
+        /// Source recipe to copy. Can be a name of a recipe in `recipes_dir` or a path to a recipe
+        /// directory.
         source: String,
-        /// What to call the output recipe
+        /// What to call the output recipe. Can be a name of a recipe or a path to a directory
+        /// outside of `recipes_dir`.
         dest: String,
+        #[clap(long)]
+        /// Set the version of the copied recipe.
+        version: Option<String>,
     },
     /// Copy an image
     Image {
-        /// Source image to copy
+        /// Source image to copy. Can be a name of an image in `images_dir` or a path to an image
+        /// directory.
         source: String,
-        /// What to call the output image
+        /// What to call the output image. Can be a name of an image or a path to a directory
+        /// outside of `images_dir`.
         dest: String,
     },
 }
@@ -157,10 +282,18 @@ pub enum NewObject {
     Image {
         /// The name of the image to create.
         name: String,
+        #[clap(long)]
+        /// Generate a Dockerfile with common build tooling. Available templates are: debian11,
+        /// rocky9, alpine3.19, arch
+        template: Option<ImageTemplate>,
+        #[clap(long)]
+        /// The base image used in the `FROM` line of the Dockerfile. Overrides the base image of
+        /// the template.
+        from: Option<String>,
     },
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Default, Parser)]
 pub struct BuildOpts {
     /// Recipes to build. If empty all recipes in the `recipes_dir` directory will be built.
     pub recipes: Vec<String>,
@@ -186,16 +319,218 @@ pub struct BuildOpts {
     /// If set to true, all recipes will be built.
     pub all: bool,
 
+    #[clap(short, long)]
+    /// Build a single recipe file outside of the `recipes_dir`, like one generated from a
+    /// template in CI, or read the recipe from stdin with `-`. Includes, local sources and
+    /// patches are relative to the directory of the file or to the current directory for stdin.
+    pub file: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Action taken when a built artifact has the same name as an existing file in the output
+    /// directory. Available values are: overwrite, skip, error, rename. Defaults to
+    /// `on_existing_artifact` of the configuration.
+    pub on_existing_artifact: Option<ArtifactPolicy>,
+
     #[clap(long)]
-    /// Disable signing packages. This option only has effect when signing is enabled in
-    /// the configuration.
+    /// Disable signing packages. DEB and RPM packages are added to a signing manifest in the
+    /// output directory so that they can be signed later with `pkger sign`.
     pub no_sign: bool,
+
+    #[clap(long)]
+    /// Use a local directory as the source of the recipe instead of the one defined in the
+    /// recipe. Paths matched by patterns in `.pkgerignore` files are skipped.
+    pub source_path: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Override settings of the built recipes in the `KEY=VALUE` format. `prefix` overrides the
+    /// installation prefix, other keys set environment variables of the recipes. Can be specified
+    /// multiple times.
+    pub set: Vec<String>,
+
+    #[clap(long)]
+    /// Only build recipes whose files, includes, patches or local sources changed since the given
+    /// git revision of the recipes directory. Can be combined with `--all` or a list of recipes to narrow down the selection.
+    pub changed_since: Option<String>,
+
+    #[clap(long)]
+    /// Append a suffix to the version of all built recipes, for example `~git20240501` for
+    /// snapshot builds. The suffix can't contain `-`.
+    pub version_suffix: Option<String>,
+
+    #[clap(long)]
+    /// Override the release number of all built recipes.
+    pub release: Option<String>,
+
+    #[clap(long)]
+    /// Commit the container as an image with this tag after a successful build. The tag can
+    /// contain environment variables like `$RECIPE` or `$RECIPE_VERSION`.
+    pub commit_image: Option<String>,
+
+    #[clap(long)]
+    /// Print progress events of the build jobs to stdout in the given format. All logs are
+    /// written to stderr instead. Available formats are: json
+    pub progress: Option<ProgressFormat>,
+
+    #[clap(long)]
+    /// Report failed jobs and lint findings as annotations of the recipe files and write a
+    /// markdown summary of all jobs for the given CI. Available values are: github, gitlab
+    pub ci_annotations: Option<CiAnnotations>,
+
+    #[clap(long)]
+    /// Don't build anything, only print whether the recipes changed since their last successful
+    /// build on each image.
+    pub explain: bool,
+
+    #[clap(long)]
+    /// With `--explain`, also print how the packaging metadata like the spec file or the control
+    /// file changed since the last successful build on each image.
+    pub diff: bool,
+
+    #[clap(long)]
+    /// Skip building recipes whose content, source and image didn't change since their last
+    /// successful build on the image. Recipes with a local directory as source are always built.
+    pub skip_up_to_date: bool,
+
+    #[clap(long, default_value = "0")]
+    /// Retry failed tasks up to the given number of times after all other tasks finished.
+    pub retry_failed: u32,
+
+    #[clap(long)]
+    /// Only build tasks that failed in the previous session. Can be combined with a list of
+    /// recipes to narrow down the selection.
+    pub only_failed: bool,
+
+    #[clap(long)]
+    /// Upload the built packages to hosted repositories defined in the `publish` section of the
+    /// configuration.
+    pub publish: bool,
+
+    #[clap(long)]
+    /// Fail instead of skipping recipes that can't be read, images missing from the configuration
+    /// or recipes without image targets. Can also be enabled with `strict: true` in the
+    /// configuration.
+    pub strict: bool,
+
+    #[clap(long)]
+    /// Build untrusted recipes in hardened containers without secrets like the gpg key, SSH
+    /// settings or forwarded environment variables. Local sources and patches have to be in the
+    /// recipe directory, containers lose network access once sources are fetched and builds fail
+    /// as soon as the build and output directories grow over `--max-output-size`. Packages are not
+    /// signed.
+    pub sandbox: bool,
+
+    #[clap(long, default_value = "1024")]
+    /// Maximum size of the build and output directories of sandboxed builds in MiB.
+    pub max_output_size: u64,
+}
+
+#[derive(Debug, Parser)]
+pub struct CheckOpts {
+    /// Recipes to check. If empty all recipes in the `recipes_dir` directory will be checked.
+    pub recipes: Vec<String>,
+
+    #[clap(long)]
+    /// Run shellcheck over the scripts of each phase. Uses the `shellcheck` binary if available,
+    /// otherwise runs it in a container using the `docker` CLI.
+    pub shellcheck: bool,
+
+    #[clap(long)]
+    /// Print how the packaging metadata like the spec file or the control file of each image
+    /// target changed since the last successful build of the recipe on the image.
+    pub diff: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct LogsOpts {
+    /// ID of the job or name of the recipe to print the log of. For a recipe the log of its
+    /// newest job is printed. If empty the logs of all sessions are listed.
+    pub job: Option<String>,
+
+    #[clap(short, long)]
+    /// Only look at the logs of the session with this ID.
+    pub session: Option<String>,
+
+    #[clap(short = 'n', long)]
+    /// Only print the last N lines of the log.
+    pub tail: Option<usize>,
+
+    #[clap(short, long)]
+    /// Keep printing new lines of the log until the job finishes.
+    pub follow: bool,
+
+    #[clap(short, long)]
+    /// Print the log of image builds of the job instead of the log of the job.
+    pub image: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct EbuildOpts {
+    /// Recipes to generate ebuilds of. If empty ebuilds of all recipes in the `recipes_dir`
+    /// directory are generated.
+    pub recipes: Vec<String>,
+
+    #[clap(short, long)]
+    /// Path to an overlay where the ebuilds are saved as
+    /// `<category>/<name>/<name>-<version>.ebuild`. If not set the ebuilds are printed to stdout.
+    pub overlay: Option<PathBuf>,
+
+    #[clap(short, long, default_value = "gentoo")]
+    /// Name of the image used to resolve dependencies of the recipes.
+    pub image: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct ExportOpts {
+    /// Recipes to render packaging files of. If empty packaging files of all recipes in the
+    /// `recipes_dir` directory are rendered.
+    pub recipes: Vec<String>,
+
+    #[clap(short, long)]
+    /// Target to render packaging files for like `rpm`, `deb` or `pkg`.
+    pub target: String,
+
+    #[clap(short, long)]
+    /// Name of the image used to resolve dependencies of the recipes.
+    pub image: String,
+
+    #[clap(short, long)]
+    /// Directory where the files are saved as `<recipe>/<image>/<file>`. If not set the files are
+    /// printed to stdout.
+    pub output_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct DoctorOpts {
+    #[clap(short, long)]
+    /// Additional docker networks to check besides the ones used by the configuration and images.
+    pub network: Vec<String>,
+
+    #[clap(short, long, default_value = "busybox:stable")]
+    /// Image of the container that the checks are run in. It must provide `sh`, `cat` and `ping`.
+    pub image: String,
+
+    #[clap(short, long)]
+    /// Host or IPv6 address that must be reachable over IPv6 from each network.
+    pub probe: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct SignOpts {
+    /// A signing manifest written by `pkger build --no-sign` or a directory containing one. If
+    /// the directory has no manifest all DEB and RPM packages in it are signed. Defaults to the
+    /// output directory.
+    pub path: Option<PathBuf>,
 }
 
 #[derive(Debug, Parser)]
 pub struct GenRecipeOpts {
     /// Name of the recipe to generate
-    pub name: String,
+    pub name: Option<String>,
+
+    #[clap(short, long)]
+    /// Interactively ask for the most important fields of the recipe and generate a build script
+    /// skeleton for the language of the project. Values of other arguments are used as defaults.
+    pub interactive: bool,
 
     #[clap(long)]
     pub version: Option<String>,
@@ -210,6 +545,7 @@ pub struct GenRecipeOpts {
     /// The website of the package
     pub url: Option<String>,
     #[clap(long)]
+    /// The architecture of the package. Defaults to the architecture of the host.
     pub arch: Option<String>,
     #[clap(long)]
     /// http/https or file system source pointing to a tar archive or some other file
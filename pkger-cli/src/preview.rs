@@ -0,0 +1,117 @@
+//! Line diffs of the packaging metadata, like spec files or control files, between the last
+//! successful build and the current state of a recipe.
+
+use colored::Colorize;
+use pkger_core::image::BuildRecord;
+use pkger_core::recipe::{BuildTarget, Recipe};
+
+/// Number of unchanged lines displayed around each changed line
+const CONTEXT: usize = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum Line<'a> {
+    Unchanged(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+    /// Unchanged lines that are not displayed
+    Skipped,
+}
+
+/// Returns the lines that changed between `old` and `new` with up to `CONTEXT` unchanged lines
+/// around each of them. Returns no lines if nothing changed.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<Line<'a>> {
+    let lines: Vec<_> = diff::lines(old, new)
+        .into_iter()
+        .map(|line| match line {
+            diff::Result::Left(line) => Line::Removed(line),
+            diff::Result::Right(line) => Line::Added(line),
+            diff::Result::Both(line, _) => Line::Unchanged(line),
+        })
+        .collect();
+    let changed: Vec<_> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Line::Unchanged(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changed.is_empty() {
+        return vec![];
+    }
+
+    let total = lines.len();
+    let mut displayed = vec![];
+    let mut last = None;
+    for (i, line) in lines.into_iter().enumerate() {
+        if !changed.iter().any(|&c| c.abs_diff(i) <= CONTEXT) {
+            continue;
+        }
+        if i > last.map(|last| last + 1).unwrap_or(0) {
+            displayed.push(Line::Skipped);
+        }
+        displayed.push(line);
+        last = Some(i);
+    }
+    if last.map(|last| last + 1 < total).unwrap_or_default() {
+        displayed.push(Line::Skipped);
+    }
+    displayed
+}
+
+/// Prints how the packaging metadata of the `recipe` built for the `target` on the `image`
+/// changed since the `last_build`.
+pub fn print_packaging_diff(
+    recipe: &Recipe,
+    image: &str,
+    target: BuildTarget,
+    last_build: Option<&BuildRecord>,
+) {
+    let new = if let Some(new) = recipe.preview_packaging(target, image) {
+        new
+    } else {
+        return;
+    };
+    let old = if let Some(old) = last_build.and_then(|build| build.packaging.as_deref()) {
+        old
+    } else {
+        println!("  no packaging metadata recorded in the last build");
+        return;
+    };
+
+    let lines = diff_lines(old, &new);
+    if lines.is_empty() {
+        println!("  packaging metadata unchanged");
+        return;
+    }
+    for line in lines {
+        match line {
+            Line::Unchanged(line) => println!("    {}", line),
+            Line::Removed(line) => println!("  {}", format!("- {}", line).red()),
+            Line::Added(line) => println!("  {}", format!("+ {}", line).green()),
+            Line::Skipped => println!("    {}", "...".dimmed()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_lines_with_context() {
+        let old = "Package: pkger\nVersion: 0.1.0\nSection: utils\nPriority: optional\nArchitecture: amd64\nDepends: libc6\nMaintainer: vv9k\n";
+        let new = old.replace("0.1.0", "0.2.0");
+
+        assert_eq!(
+            diff_lines(old, &new),
+            vec![
+                Line::Unchanged("Package: pkger"),
+                Line::Removed("Version: 0.1.0"),
+                Line::Added("Version: 0.2.0"),
+                Line::Unchanged("Section: utils"),
+                Line::Unchanged("Priority: optional"),
+                Line::Skipped,
+            ]
+        );
+        assert!(diff_lines(old, old).is_empty());
+    }
+}
@@ -0,0 +1,32 @@
+use crate::Error;
+use pkger_core::build::progress::Event;
+
+use std::str::FromStr;
+use tracing::error;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProgressFormat {
+    Json,
+}
+
+impl FromStr for ProgressFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "json" => Ok(ProgressFormat::Json),
+            _ => Err(Error::msg(format!("invalid progress format `{}`", s))),
+        }
+    }
+}
+
+impl ProgressFormat {
+    /// Prints the event to stdout as a single line.
+    pub fn print(&self, event: &Event) {
+        match self {
+            ProgressFormat::Json => match serde_json::to_string(event) {
+                Ok(line) => println!("{}", line),
+                Err(e) => error!(reason = %e, "failed to serialize progress event"),
+            },
+        }
+    }
+}
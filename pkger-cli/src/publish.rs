@@ -0,0 +1,242 @@
+//! Publishing of built packages to hosted repositories.
+
+use crate::config::{PublishConfig, PublishProvider};
+use pkger_core::recipe::{BuildTarget, Distro, Os};
+use pkger_core::{err, ErrContext, Error, Result};
+
+use serde::Deserialize;
+use std::env;
+use std::io::Write;
+use std::path::Path;
+use std::process::{self, Stdio};
+use tracing::{info_span, trace};
+
+static PACKAGECLOUD_URL: &str = "https://packagecloud.io/api/v1/repos";
+static CLOUDSMITH_UPLOAD_URL: &str = "https://upload.cloudsmith.io";
+static CLOUDSMITH_API_URL: &str = "https://api-prod.cloudsmith.io/v1/packages";
+
+impl PublishProvider {
+    /// Name of the environment variable containing the API token used when `token_env` is not set.
+    fn default_token_env(&self) -> &'static str {
+        match self {
+            PublishProvider::PackageCloud => "PACKAGECLOUD_TOKEN",
+            PublishProvider::Cloudsmith => "CLOUDSMITH_API_KEY",
+        }
+    }
+
+    fn supports(&self, target: BuildTarget) -> bool {
+        match self {
+            PublishProvider::PackageCloud => {
+                matches!(target, BuildTarget::Deb | BuildTarget::Rpm)
+            }
+            PublishProvider::Cloudsmith => {
+                matches!(
+                    target,
+                    BuildTarget::Deb | BuildTarget::Rpm | BuildTarget::Apk
+                )
+            }
+        }
+    }
+}
+
+impl PublishConfig {
+    /// Returns true if packages of `target` should be published with this configuration.
+    pub fn accepts(&self, target: BuildTarget) -> bool {
+        self.provider.supports(target)
+            && (self.targets.is_empty() || self.targets.contains(&target))
+    }
+}
+
+/// Maps the operating system of the image used for the build to the `distribution/version` form
+/// used by hosted repositories like `ubuntu/focal` or `el/8`.
+pub fn distro_version(os: &Os) -> Option<String> {
+    let version = os.version();
+    let major = version.split('.').next().unwrap_or_default();
+    match os.distribution() {
+        Distro::Debian => {
+            let codename = match major {
+                "9" => "stretch",
+                "10" => "buster",
+                "11" => "bullseye",
+                "12" => "bookworm",
+                "13" => "trixie",
+                _ => return None,
+            };
+            Some(format!("debian/{}", codename))
+        }
+        Distro::Ubuntu => {
+            let codename = match version {
+                "18.04" => "bionic",
+                "20.04" => "focal",
+                "22.04" => "jammy",
+                "24.04" => "noble",
+                _ => return None,
+            };
+            Some(format!("ubuntu/{}", codename))
+        }
+        Distro::Rocky | Distro::RedHat | Distro::CentOS if !major.is_empty() => {
+            Some(format!("el/{}", major))
+        }
+        Distro::Fedora if !major.is_empty() => Some(format!("fedora/{}", major)),
+        Distro::Alpine => {
+            let mut parts = version.split('.');
+            match (parts.next(), parts.next()) {
+                (Some(major), Some(minor)) => Some(format!("alpine/v{}.{}", major, minor)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Runs curl with `args` passing `config` (like authentication headers) through stdin so that
+/// secrets don't show up in the list of processes. Returns the stdout of curl.
+pub fn curl(config: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let mut child = process::Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--config", "-"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run curl, make sure it is installed")?;
+
+    child
+        .stdin
+        .take()
+        .context("failed to open stdin of curl")?
+        .write_all(config.as_bytes())
+        .context("failed to pass configuration to curl")?;
+
+    let output = child
+        .wait_with_output()
+        .context("failed to wait for curl")?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        err!(
+            "request failed - {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+}
+
+/// Uploads the `package` built for `target` to the repository configured in `config`. `os` is
+/// the operating system of the image used for the build and is used to find out the
+/// distribution unless it is set in the configuration.
+pub fn publish(config: &PublishConfig, package: &Path, target: BuildTarget, os: &Os) -> Result<()> {
+    let span = info_span!("publish", repo = %config.repo, package = %package.display());
+    let _enter = span.enter();
+
+    let token = match &config.token {
+        Some(token) => token.clone(),
+        None => {
+            let token_env = config
+                .token_env
+                .as_deref()
+                .unwrap_or_else(|| config.provider.default_token_env());
+            env::var(token_env).context(format!(
+                "missing API token in the `{}` environment variable",
+                token_env
+            ))?
+        }
+    };
+
+    let distro = match &config.distro {
+        Some(distro) => distro.clone(),
+        None => distro_version(os).context(format!(
+            "failed to map {} {} to a distribution, set `distro` in the publish configuration",
+            os.name(),
+            os.version()
+        ))?,
+    };
+    trace!(distro = %distro);
+
+    let file = package.to_string_lossy();
+    match config.provider {
+        PublishProvider::PackageCloud => curl(
+            &format!("user = \"{}:\"\n", token),
+            &[
+                "--form",
+                &format!("package[distro_version_id]={}", distro),
+                "--form",
+                &format!("package[package_file]=@{}", file),
+                &format!("{}/{}/packages.json", PACKAGECLOUD_URL, config.repo),
+            ],
+        )
+        .map(|_| ()),
+        PublishProvider::Cloudsmith => {
+            #[derive(Deserialize)]
+            struct Upload {
+                identifier: String,
+            }
+
+            let auth = format!("header = \"X-Api-Key: {}\"\n", token);
+            let name = package
+                .file_name()
+                .context("expected a package file name")?
+                .to_string_lossy();
+            let upload = curl(
+                &auth,
+                &[
+                    "--upload-file",
+                    &file,
+                    &format!("{}/{}/{}", CLOUDSMITH_UPLOAD_URL, config.repo, name),
+                ],
+            )
+            .context("failed to upload package file")?;
+            let upload: Upload =
+                serde_json::from_slice(&upload).context("invalid response to file upload")?;
+
+            let format = match target {
+                BuildTarget::Apk => "alpine",
+                _ => target.as_ref(),
+            };
+            let body = serde_json::json!({
+                "package_file": upload.identifier,
+                "distribution": distro,
+            })
+            .to_string();
+            curl(
+                &auth,
+                &[
+                    "--header",
+                    "Content-Type: application/json",
+                    "--data",
+                    &body,
+                    &format!("{}/{}/upload/{}/", CLOUDSMITH_API_URL, config.repo, format),
+                ],
+            )
+            .context("failed to create package")
+            .map(|_| ())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_distro_versions() {
+        let os = |name: &str, version: &str| Os::new(name, Some(version)).unwrap();
+
+        assert_eq!(
+            distro_version(&os("debian", "11")),
+            Some("debian/bullseye".to_string())
+        );
+        assert_eq!(
+            distro_version(&os("ubuntu", "22.04")),
+            Some("ubuntu/jammy".to_string())
+        );
+        assert_eq!(
+            distro_version(&os("rocky", "9.2")),
+            Some("el/9".to_string())
+        );
+        assert_eq!(
+            distro_version(&os("alpine", "3.19.1")),
+            Some("alpine/v3.19".to_string())
+        );
+        assert_eq!(distro_version(&os("debian", "99")), None);
+    }
+}
@@ -0,0 +1,434 @@
+//! Maintains an APT repository with a `pool`/`dists` layout.
+
+use crate::config::AptRepoConfig;
+use crate::repo::sign;
+use pkger_core::gpg::GpgKey;
+use pkger_core::{err, ErrContext, Error, Result};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, info_span, trace};
+
+/// Magic bytes at the start of an `ar` archive.
+static AR_MAGIC: &[u8] = b"!<arch>\n";
+/// Size of a header of a file in an `ar` archive.
+const AR_HEADER_LEN: usize = 60;
+
+/// A single binary package in the pool of the repository.
+#[derive(Debug)]
+struct PoolPackage {
+    /// Contents of the `control` file of the package
+    control: String,
+    /// Path relative to the root of the repository
+    filename: String,
+    size: u64,
+    sha256: String,
+}
+
+impl PoolPackage {
+    fn field(&self, name: &str) -> Option<&str> {
+        control_field(&self.control, name)
+    }
+
+    fn arch(&self) -> &str {
+        self.field("Architecture").unwrap_or("all")
+    }
+
+    /// Returns the stanza of this package in the `Packages` index.
+    fn stanza(&self) -> String {
+        format!(
+            "{}\nFilename: {}\nSize: {}\nSHA256: {}\n",
+            self.control.trim_end(),
+            self.filename,
+            self.size,
+            self.sha256
+        )
+    }
+}
+
+pub struct AptRepo<'cfg> {
+    config: &'cfg AptRepoConfig,
+}
+
+impl<'cfg> AptRepo<'cfg> {
+    pub fn new(config: &'cfg AptRepoConfig) -> Self {
+        Self { config }
+    }
+
+    /// Copies the `package` to the pool of the repository and returns its new location.
+    pub fn add(&self, package: &Path) -> Result<PathBuf> {
+        let control = read_control(package)?;
+        let name = control_field(&control, "Package")
+            .filter(|name| !name.is_empty())
+            .context("package has no `Package` field")?;
+        let source = control_field(&control, "Source")
+            .and_then(|source| source.split_whitespace().next())
+            .unwrap_or(name);
+
+        let dir = self
+            .config
+            .dir
+            .join("pool")
+            .join(&self.config.component)
+            .join(pool_prefix(source))
+            .join(source);
+        fs::create_dir_all(&dir).context("failed to create pool directory")?;
+
+        let dest = dir.join(
+            package
+                .file_name()
+                .context("expected a package file name")?,
+        );
+        trace!(package = %package.display(), dest = %dest.display(), "copying to pool");
+        fs::copy(package, &dest).context("failed to copy package to pool")?;
+
+        Ok(dest)
+    }
+
+    /// Regenerates the `Packages` indices and the `Release` file from all packages in the pool.
+    /// If `gpg_key` is provided the `Release` file is signed creating `InRelease` and
+    /// `Release.gpg` files and the public key is exported to the root of the repository.
+    pub fn update(&self, gpg_key: Option<&GpgKey>) -> Result<()> {
+        let span = info_span!("apt-repo", dir = %self.config.dir.display());
+        let _enter = span.enter();
+
+        let packages = self.pool_packages()?;
+        let mut arches: BTreeSet<&str> = packages
+            .iter()
+            .map(|package| package.arch())
+            .filter(|arch| *arch != "all")
+            .collect();
+        if arches.is_empty() {
+            arches.insert("all");
+        }
+
+        let dist_dir = self.config.dir.join("dists").join(&self.config.codename);
+        let mut indices = vec![];
+        for arch in &arches {
+            let stanzas: Vec<_> = packages
+                .iter()
+                .filter(|package| package.arch() == *arch || package.arch() == "all")
+                .map(PoolPackage::stanza)
+                .collect();
+            let index = stanzas.join("\n");
+            let index_dir = format!("{}/binary-{}", self.config.component, arch);
+            fs::create_dir_all(dist_dir.join(&index_dir))
+                .context("failed to create index directory")?;
+
+            let mut encoder = GzEncoder::new(vec![], Compression::best());
+            encoder
+                .write_all(index.as_bytes())
+                .and_then(|_| encoder.finish())
+                .map(|gz| {
+                    indices.push((format!("{}/Packages", index_dir), index.into_bytes()));
+                    indices.push((format!("{}/Packages.gz", index_dir), gz));
+                })
+                .context("failed to compress index")?;
+        }
+
+        for (path, data) in &indices {
+            debug!(index = %path, "saving");
+            fs::write(dist_dir.join(path), data).context("failed to save index")?;
+        }
+
+        let release = self.release(&arches, &indices);
+        let release_file = dist_dir.join("Release");
+        fs::write(&release_file, release).context("failed to save Release file")?;
+
+        if let Some(key) = gpg_key {
+            sign::clearsign(key, &release_file, &dist_dir.join("InRelease"))
+                .context("failed to create InRelease file")?;
+            sign::detach_sign(key, &release_file, &dist_dir.join("Release.gpg"))
+                .context("failed to sign Release file")?;
+            sign::export_public_key(key, &self.config.dir.join(key.public_key_file()))
+                .context("failed to export public key")?;
+        }
+
+        info!(packages = %packages.len(), "updated repository");
+        Ok(())
+    }
+
+    /// Returns the contents of the `Release` file listing the `indices`.
+    fn release(&self, arches: &BTreeSet<&str>, indices: &[(String, Vec<u8>)]) -> String {
+        let mut release = String::new();
+        if let Some(origin) = &self.config.origin {
+            release.push_str(&format!("Origin: {}\n", origin));
+        }
+        if let Some(label) = &self.config.label {
+            release.push_str(&format!("Label: {}\n", label));
+        }
+        release.push_str(&format!(
+            "Suite: {0}\nCodename: {0}\nDate: {1}\nArchitectures: {2}\nComponents: {3}\nSHA256:\n",
+            self.config.codename,
+            chrono::Utc::now().format("%a, %d %b %Y %H:%M:%S UTC"),
+            arches.iter().copied().collect::<Vec<_>>().join(" "),
+            self.config.component,
+        ));
+        for (path, data) in indices {
+            release.push_str(&format!(" {} {:>16} {}\n", sha256(data), data.len(), path));
+        }
+        release
+    }
+
+    /// Reads metadata of all packages in the pool.
+    fn pool_packages(&self) -> Result<Vec<PoolPackage>> {
+        let mut files = vec![];
+        find_debs(&self.config.dir.join("pool"), &mut files)?;
+
+        let mut packages = BTreeMap::new();
+        for file in files {
+            let data = fs::read(&file).context("failed to read package")?;
+            let control =
+                control_from_deb(&data).context(format!("invalid package {}", file.display()))?;
+            let filename = file
+                .strip_prefix(&self.config.dir)
+                .context("package outside of the repository")?
+                .to_string_lossy()
+                .to_string();
+            packages.insert(
+                filename.clone(),
+                PoolPackage {
+                    control,
+                    filename,
+                    size: data.len() as u64,
+                    sha256: sha256(&data),
+                },
+            );
+        }
+
+        Ok(packages.into_values().collect())
+    }
+}
+
+/// Returns the name of the directory in the pool that contains the source package `name`. Like
+/// in Debian `lib*` packages are grouped by the first 4 letters, others by the first letter.
+fn pool_prefix(name: &str) -> &str {
+    if name.starts_with("lib") && name.len() > 3 {
+        &name[..4]
+    } else {
+        &name[..1]
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Returns the value of a single line field `name` from a control file.
+pub fn control_field<'ctrl>(control: &'ctrl str, name: &str) -> Option<&'ctrl str> {
+    control.lines().find_map(|line| {
+        line.split_once(':')
+            .filter(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    })
+}
+
+fn find_debs(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).context("failed to read pool directory")? {
+        let path = entry.context("invalid dir entry")?.path();
+        if path.is_dir() {
+            find_debs(&path, files)?;
+        } else if path.extension().map(|ext| ext == "deb").unwrap_or(false) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the `control` file of the DEB package at `path`.
+pub fn read_control(path: &Path) -> Result<String> {
+    let data = fs::read(path).context("failed to read package")?;
+    control_from_deb(&data).context(format!("invalid package {}", path.display()))
+}
+
+/// Returns the name and the data of the first member of the `ar` archive whose name starts with
+/// `prefix`.
+fn ar_member<'data>(data: &'data [u8], prefix: &str) -> Result<(String, &'data [u8])> {
+    if !data.starts_with(AR_MAGIC) {
+        return err!("not an ar archive");
+    }
+
+    let mut offset = AR_MAGIC.len();
+    while offset + AR_HEADER_LEN <= data.len() {
+        let header = &data[offset..offset + AR_HEADER_LEN];
+        let name = String::from_utf8_lossy(&header[..16]);
+        let name = name.trim_end().trim_end_matches('/');
+        let size: usize = String::from_utf8_lossy(&header[48..58])
+            .trim()
+            .parse()
+            .context("invalid size of ar member")?;
+
+        let start = offset + AR_HEADER_LEN;
+        let end = start + size;
+        if end > data.len() {
+            return err!("ar member `{}` is truncated", name);
+        }
+
+        if name.starts_with(prefix) {
+            return Ok((name.to_string(), &data[start..end]));
+        }
+        // members are aligned to 2 bytes
+        offset = end + size % 2;
+    }
+
+    err!("package has no {} member", prefix)
+}
+
+/// Decompresses the tar archive `name` based on its extension.
+fn decompress_tar(name: &str, data: &[u8]) -> Result<Vec<u8>> {
+    match name.rsplit('.').next() {
+        Some("gz") => {
+            let mut tar = vec![];
+            GzDecoder::new(data)
+                .read_to_end(&mut tar)
+                .context(format!("failed to decompress {}", name))?;
+            Ok(tar)
+        }
+        Some("xz") => {
+            let mut tar = vec![];
+            lzma_rs::xz_decompress(&mut io::BufReader::new(data), &mut tar)
+                .map_err(|e| Error::msg(format!("{:?}", e)))
+                .context(format!("failed to decompress {}", name))?;
+            Ok(tar)
+        }
+        Some("zst") => {
+            let mut tar = vec![];
+            ruzstd::StreamingDecoder::new(data)
+                .map_err(|e| Error::msg(e.to_string()))
+                .and_then(|mut decoder| decoder.read_to_end(&mut tar).map_err(Error::from))
+                .context(format!("failed to decompress {}", name))?;
+            Ok(tar)
+        }
+        Some("tar") => Ok(data.to_vec()),
+        _ => err!("unsupported compression of `{}`", name),
+    }
+}
+
+/// Extracts the `control` file from the `control.tar` member of a DEB package.
+fn control_from_deb(data: &[u8]) -> Result<String> {
+    let (name, member) = ar_member(data, "control.tar")?;
+    let tar = decompress_tar(&name, member)?;
+
+    let mut archive = tar::Archive::new(&tar[..]);
+    for entry in archive.entries().context("invalid control archive")? {
+        let mut entry = entry.context("invalid control archive entry")?;
+        let path = entry.path().context("invalid path")?.to_path_buf();
+        if path == Path::new("./control") || path == Path::new("control") {
+            let mut control = String::new();
+            entry
+                .read_to_string(&mut control)
+                .context("failed to read control file")?;
+            return Ok(control);
+        }
+    }
+
+    err!("control archive has no control file")
+}
+
+/// Returns the paths of all files and symlinks from the `data.tar` member of a DEB package
+/// relative to the root directory, like `usr/bin/pkger`.
+fn files_from_deb(data: &[u8]) -> Result<Vec<String>> {
+    let (name, member) = ar_member(data, "data.tar")?;
+    let tar = decompress_tar(&name, member)?;
+
+    let mut files = vec![];
+    let mut archive = tar::Archive::new(&tar[..]);
+    for entry in archive.entries().context("invalid data archive")? {
+        let entry = entry.context("invalid data archive entry")?;
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+        let path = entry.path().context("invalid path")?;
+        files.push(
+            path.to_string_lossy()
+                .trim_start_matches("./")
+                .trim_start_matches('/')
+                .to_string(),
+        );
+    }
+    Ok(files)
+}
+
+/// Reads the list of files of the DEB package at `path`.
+pub fn read_files(path: &Path) -> Result<Vec<String>> {
+    let data = fs::read(path).context("failed to read package")?;
+    files_from_deb(&data).context(format!("invalid package {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CONTROL: &str = "Package: libtest\nVersion: 0.1.0\nArchitecture: amd64\n";
+
+    fn deb() -> Vec<u8> {
+        let mut tar = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(CONTROL.len() as u64);
+        header.set_cksum();
+        tar.append_data(&mut header, "./control", CONTROL.as_bytes())
+            .unwrap();
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(&tar.into_inner().unwrap()).unwrap();
+        let control_tar = gz.finish().unwrap();
+
+        let mut tar = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_cksum();
+        tar.append_data(&mut header, "./usr/bin/", &[][..]).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_cksum();
+        tar.append_data(&mut header, "./usr/bin/test", &b"test"[..])
+            .unwrap();
+        let data_tar = tar.into_inner().unwrap();
+
+        let mut deb = AR_MAGIC.to_vec();
+        for (name, data) in [
+            ("debian-binary", &b"2.0\n"[..]),
+            ("control.tar.gz", &control_tar[..]),
+            ("data.tar", &data_tar[..]),
+        ] {
+            deb.extend(
+                format!(
+                    "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                    name,
+                    0,
+                    0,
+                    0,
+                    100644,
+                    data.len()
+                )
+                .as_bytes(),
+            );
+            deb.extend(data);
+            if data.len() % 2 == 1 {
+                deb.push(b'\n');
+            }
+        }
+        deb
+    }
+
+    #[test]
+    fn reads_control_from_deb() {
+        let control = control_from_deb(&deb()).unwrap();
+
+        assert_eq!(control, CONTROL);
+        assert_eq!(control_field(&control, "Package"), Some("libtest"));
+        assert_eq!(pool_prefix("libtest"), "libt");
+        assert_eq!(pool_prefix("pkger"), "p");
+        assert_eq!(files_from_deb(&deb()).unwrap(), vec!["usr/bin/test"]);
+        assert!(control_from_deb(b"not a deb").is_err());
+    }
+}
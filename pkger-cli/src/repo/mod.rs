@@ -0,0 +1,6 @@
+//! Generation of package repositories from built packages.
+
+pub mod apt;
+pub mod rpm;
+pub mod sign;
+pub mod simulate;
@@ -0,0 +1,113 @@
+//! Maintains a DNF/zypper repository with `repodata` generated by `createrepo_c`.
+
+use crate::config::RpmRepoConfig;
+use crate::repo::sign;
+use pkger_core::docker::DockerConnectionPool;
+use pkger_core::gpg::GpgKey;
+use pkger_core::{err, ErrContext, Error, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use tracing::{info, info_span, trace};
+
+/// Image used to run createrepo_c when the binary is not available on the host.
+static CREATEREPO_IMAGE: &str = "fedora:latest";
+
+pub struct RpmRepo<'cfg> {
+    config: &'cfg RpmRepoConfig,
+    docker: &'cfg DockerConnectionPool,
+}
+
+impl<'cfg> RpmRepo<'cfg> {
+    /// Creates a handle of the repository that runs `createrepo_c` in a container of the `docker`
+    /// daemon if the binary is not available on the host.
+    pub fn new(config: &'cfg RpmRepoConfig, docker: &'cfg DockerConnectionPool) -> Self {
+        Self { config, docker }
+    }
+
+    /// Copies the `package` to the `Packages` directory of the repository and returns its new
+    /// location.
+    pub fn add(&self, package: &Path) -> Result<PathBuf> {
+        let name = package
+            .file_name()
+            .context("expected a package file name")?
+            .to_string_lossy();
+        let prefix = name.chars().next().context("empty package file name")?;
+
+        let dir = self
+            .config
+            .dir
+            .join("Packages")
+            .join(prefix.to_ascii_lowercase().to_string());
+        fs::create_dir_all(&dir).context("failed to create packages directory")?;
+
+        let dest = dir.join(name.as_ref());
+        trace!(package = %package.display(), dest = %dest.display(), "copying to repository");
+        fs::copy(package, &dest).context("failed to copy package to repository")?;
+
+        Ok(dest)
+    }
+
+    /// Regenerates the `repodata` of the repository. If `gpg_key` is provided `repomd.xml` is
+    /// signed and the public key is exported to the root of the repository.
+    pub fn update(&self, gpg_key: Option<&GpgKey>) -> Result<()> {
+        let span = info_span!("rpm-repo", dir = %self.config.dir.display());
+        let _enter = span.enter();
+
+        let dir = self
+            .config
+            .dir
+            .canonicalize()
+            .context("failed to resolve repository directory")?;
+        self.createrepo(&dir)?;
+
+        if let Some(key) = gpg_key {
+            let repomd = dir.join("repodata").join("repomd.xml");
+            sign::detach_sign(key, &repomd, &dir.join("repodata").join("repomd.xml.asc"))
+                .context("failed to sign repomd.xml")?;
+            sign::export_public_key(key, &dir.join(key.public_key_file()))
+                .context("failed to export public key")?;
+        }
+
+        info!("updated repository");
+        Ok(())
+    }
+
+    /// Runs createrepo_c over `dir` using the host binary if available, otherwise uses the docker
+    /// CLI to run it in a container with `dir` mounted. The directory is mounted from the host of
+    /// the Docker daemon so a remote daemon has to share it.
+    fn createrepo(&self, dir: &Path) -> Result<()> {
+        let output = process::Command::new("createrepo_c")
+            .args(["--update", "--quiet"])
+            .arg(dir)
+            .output()
+            .or_else(|_| {
+                let image = self.config.image.as_deref().unwrap_or(CREATEREPO_IMAGE);
+                trace!(image = %image, "createrepo_c not found on host, using container");
+                self.docker
+                    .cli()
+                    .args(["run", "--rm", "-v"])
+                    .arg(format!("{}:/repo", dir.display()))
+                    .args([
+                        image,
+                        "sh",
+                        "-c",
+                        "(command -v createrepo_c >/dev/null || dnf install -y -q createrepo_c) \
+                            && createrepo_c --update --quiet /repo \
+                            && chown -R --reference=/repo /repo/repodata",
+                    ])
+                    .output()
+            })
+            .context("failed to run createrepo_c")?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            err!(
+                "createrepo_c failed - {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Signing of repository metadata and packages with the `gpg`, `dpkg-sig` and `rpmsign`
+//! binaries available on the host.
+
+use pkger_core::gpg::GpgKey;
+use pkger_core::{err, ErrContext, Error, Result};
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use tempdir::TempDir;
+use tracing::trace;
+
+/// Name of the file in the keyring directory that holds the passphrase of the key. The passphrase
+/// is read by `gpg` from this file so that it doesn't show up in arguments visible to other users.
+static PASSPHRASE_FILE: &str = "passphrase";
+
+/// Runs `gpg` with `args` in the temporary `home` directory.
+fn gpg(home: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    trace!(?args, "running gpg");
+    let output = process::Command::new("gpg")
+        .arg("--homedir")
+        .arg(home)
+        .args(["--batch", "--yes", "--pinentry-mode=loopback"])
+        .args(args)
+        .output()
+        .context("failed to run gpg, make sure it is installed")?;
+
+    if !output.status.success() {
+        return err!(
+            "gpg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Returns the path of the passphrase file in the keyring directory `home`.
+fn passphrase_file(home: &Path) -> PathBuf {
+    home.join(PASSPHRASE_FILE)
+}
+
+/// Saves the passphrase of the `key` to the keyring directory `home` readable only by the owner.
+fn save_passphrase(key: &GpgKey, home: &Path) -> Result<()> {
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    opts.open(passphrase_file(home))
+        .and_then(|mut file| file.write_all(key.pass().as_bytes()))
+        .context("failed to save gpg key passphrase")
+}
+
+/// Imports the `key` into a new temporary keyring and returns its home directory, which also
+/// contains the passphrase file of the key.
+fn import_key(key: &GpgKey) -> Result<TempDir> {
+    let home = TempDir::new("pkger-gpg").context("failed to create gpg home directory")?;
+    save_passphrase(key, home.path())?;
+    gpg(
+        home.path(),
+        &[
+            "--passphrase-file",
+            &passphrase_file(home.path()).to_string_lossy(),
+            "--import",
+            &key.path().to_string_lossy(),
+        ],
+    )
+    .context("failed to import gpg key")?;
+    Ok(home)
+}
+
+/// Imports the `key` into a new temporary keyring and runs `gpg` with `args` using it and reading
+/// the passphrase from the passphrase file.
+fn with_key(key: &GpgKey, args: &[&str]) -> Result<Vec<u8>> {
+    let home = import_key(key)?;
+    let passphrase = passphrase_file(home.path()).to_string_lossy().to_string();
+    let mut all_args = vec!["--passphrase-file", passphrase.as_str()];
+    all_args.extend_from_slice(args);
+    gpg(home.path(), &all_args)
+}
+
+/// Runs the signing `cmd` with the keyring in `home` reporting its output on failure.
+fn run_signer(cmd: &mut process::Command, home: &Path, name: &str) -> Result<()> {
+    trace!(?cmd, "running {}", name);
+    let output = cmd
+        .env("GNUPGHOME", home)
+        .output()
+        .with_context(|| format!("failed to run {}, make sure it is installed", name))?;
+
+    if !output.status.success() {
+        return err!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Adds a signature with the `builder` role to the DEB `package`.
+pub fn sign_deb(key: &GpgKey, package: &Path) -> Result<()> {
+    let home = import_key(key)?;
+    run_signer(
+        process::Command::new("dpkg-sig")
+            .arg("-k")
+            .arg(key.name())
+            .arg("-g")
+            .arg(format!(
+                "--batch --pinentry-mode=loopback --passphrase-file {}",
+                passphrase_file(home.path()).display()
+            ))
+            .args(["--sign", "builder"])
+            .arg(package),
+        home.path(),
+        "dpkg-sig",
+    )
+}
+
+/// Adds a signature to the header of the RPM `package`.
+pub fn sign_rpm(key: &GpgKey, package: &Path) -> Result<()> {
+    let home = import_key(key)?;
+    run_signer(
+        process::Command::new("rpmsign")
+            .arg("--define")
+            .arg(format!("_gpg_name {}", key.name()))
+            .arg("--define")
+            .arg(format!("_gpg_path {}", home.path().display()))
+            .arg("--define")
+            .arg(format!(
+                r#"__gpg_sign_cmd %{{__gpg}} --batch --no-verbose --no-armor --pinentry-mode=loopback --passphrase-file {} -u "%{{_gpg_name}}" -sbo %{{__signature_filename}} --digest-algo sha256 %{{__plaintext_filename}}"#,
+                passphrase_file(home.path()).display()
+            ))
+            .arg("--addsign")
+            .arg(package),
+        home.path(),
+        "rpmsign",
+    )
+}
+
+/// Creates a clear text signature of the `file` saving it to `output`.
+pub fn clearsign(key: &GpgKey, file: &Path, output: &Path) -> Result<()> {
+    with_key(
+        key,
+        &[
+            "--local-user",
+            key.name(),
+            "--digest-algo",
+            "SHA256",
+            "--output",
+            &output.to_string_lossy(),
+            "--clearsign",
+            &file.to_string_lossy(),
+        ],
+    )
+    .map(|_| ())
+}
+
+/// Creates an ASCII-armored detached signature of the `file` saving it to `output`.
+pub fn detach_sign(key: &GpgKey, file: &Path, output: &Path) -> Result<()> {
+    with_key(
+        key,
+        &[
+            "--local-user",
+            key.name(),
+            "--digest-algo",
+            "SHA256",
+            "--armor",
+            "--output",
+            &output.to_string_lossy(),
+            "--detach-sign",
+            &file.to_string_lossy(),
+        ],
+    )
+    .map(|_| ())
+}
+
+/// Exports the ASCII-armored public part of the `key` to `output`.
+pub fn export_public_key(key: &GpgKey, output: &Path) -> Result<()> {
+    with_key(
+        key,
+        &[
+            "--armor",
+            "--output",
+            &output.to_string_lossy(),
+            "--export",
+            key.name(),
+        ],
+    )
+    .map(|_| ())
+}
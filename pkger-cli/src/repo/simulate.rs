@@ -0,0 +1,452 @@
+//! Simulation of publishing a DEB package to an existing APT repository. The indices of the
+//! repository are checked for packages that the new package would downgrade, conflict with or
+//! break before it is uploaded.
+
+use crate::config::SimulateConfig;
+use crate::publish::curl;
+use crate::repo::apt::control_field;
+use pkger_core::{ErrContext, Error, Result};
+
+use flate2::read::GzDecoder;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use tracing::{info_span, trace};
+
+/// Compares two Debian package versions in the `[epoch:]upstream[-revision]` form the same way
+/// dpkg does.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn split(version: &str) -> (u64, &str, &str) {
+        let (epoch, rest) = match version.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse().unwrap_or_default(), rest),
+            None => (0, version),
+        };
+        match rest.rsplit_once('-') {
+            Some((upstream, revision)) => (epoch, upstream, revision),
+            None => (epoch, rest, ""),
+        }
+    }
+
+    let (a_epoch, a_upstream, a_revision) = split(a);
+    let (b_epoch, b_upstream, b_revision) = split(b);
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| compare_part(a_upstream, b_upstream))
+        .then_with(|| compare_part(a_revision, b_revision))
+}
+
+/// Weight of a character in the non-digit part of a version, `~` sorts before everything and
+/// letters sort before other characters.
+fn order(c: Option<char>) -> i32 {
+    match c {
+        None => 0,
+        Some('~') => -1,
+        Some(c) if c.is_ascii_alphabetic() => c as i32,
+        Some(c) => c as i32 + 256,
+    }
+}
+
+fn compare_part(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        loop {
+            let ca = a.peek().copied().filter(|c| !c.is_ascii_digit());
+            let cb = b.peek().copied().filter(|c| !c.is_ascii_digit());
+            if ca.is_none() && cb.is_none() {
+                break;
+            }
+            match order(ca).cmp(&order(cb)) {
+                Ordering::Equal => {
+                    a.next();
+                    b.next();
+                }
+                ordering => return ordering,
+            }
+        }
+
+        let number = |chars: &mut std::iter::Peekable<std::str::Chars<'_>>| {
+            let mut digits = String::new();
+            while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+                digits.push(c);
+                chars.next();
+            }
+            digits.trim_start_matches('0').to_string()
+        };
+        let na = number(&mut a);
+        let nb = number(&mut b);
+        match na.len().cmp(&nb.len()).then_with(|| na.cmp(&nb)) {
+            Ordering::Equal if a.peek().is_none() && b.peek().is_none() => return Ordering::Equal,
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+    }
+}
+
+/// A single relation from a field like `Depends`, for example `pkger (>= 0.7.0)`.
+#[derive(Debug, PartialEq)]
+struct Relation<'a> {
+    name: &'a str,
+    constraint: Option<(&'a str, &'a str)>,
+}
+
+impl<'a> Relation<'a> {
+    fn parse(relation: &'a str) -> Option<Self> {
+        let relation = relation.trim();
+        let (name, constraint) = match relation.split_once('(') {
+            Some((name, constraint)) => {
+                let constraint = constraint.trim_end_matches(')').trim();
+                let split = constraint
+                    .find(|c: char| !matches!(c, '<' | '>' | '='))
+                    .unwrap_or(constraint.len());
+                let (op, version) = constraint.split_at(split);
+                (name, Some((op.trim(), version.trim())))
+            }
+            None => (relation, None),
+        };
+        // drop architecture qualifiers like `:any` and restrictions like `[amd64]`
+        let name = name
+            .split(|c: char| c == ':' || c == '[' || c.is_whitespace())
+            .next()
+            .unwrap_or_default();
+        if name.is_empty() {
+            None
+        } else {
+            Some(Relation { name, constraint })
+        }
+    }
+
+    fn satisfied_by(&self, version: &str) -> bool {
+        let (op, required) = if let Some(constraint) = self.constraint {
+            constraint
+        } else {
+            return true;
+        };
+        let ordering = compare_versions(version, required);
+        match op {
+            "<<" => ordering == Ordering::Less,
+            "<=" | "<" => ordering != Ordering::Greater,
+            "=" => ordering == Ordering::Equal,
+            ">=" | ">" => ordering != Ordering::Less,
+            ">>" => ordering == Ordering::Greater,
+            _ => true,
+        }
+    }
+}
+
+/// Returns the groups of alternatives from a relationship field like `a (>= 1) | b, c`.
+fn relations(field: &str) -> Vec<Vec<Relation<'_>>> {
+    field
+        .split(',')
+        .map(|group| group.split('|').filter_map(Relation::parse).collect())
+        .filter(|group: &Vec<_>| !group.is_empty())
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// A package with the same name and a version that is not lower is already in the repository
+    NotNewer { existing: String },
+    /// A file of the package is owned by another package in the repository
+    FileConflict { path: String, owner: String },
+    /// A package in the repository depends on a version of the package that is not satisfied
+    BrokenDependent {
+        package: String,
+        version: String,
+        relation: String,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NotNewer { existing } => write!(
+                f,
+                "version {} of the package is already in the repository",
+                existing
+            ),
+            Problem::FileConflict { path, owner } => write!(
+                f,
+                "file `/{}` is owned by package `{}`, add it to `replaces` to take the file over",
+                path, owner
+            ),
+            Problem::BrokenDependent {
+                package,
+                version,
+                relation,
+            } => write!(
+                f,
+                "package `{}` {} depends on `{}` which is not satisfied anymore",
+                package, version, relation
+            ),
+        }
+    }
+}
+
+/// A package listed in the `Packages` index of the repository.
+#[derive(Debug)]
+struct IndexPackage {
+    control: String,
+}
+
+impl IndexPackage {
+    fn field(&self, name: &str) -> &str {
+        control_field(&self.control, name).unwrap_or_default()
+    }
+}
+
+fn same_arch(a: &str, b: &str) -> bool {
+    a == b || a == "all" || b == "all"
+}
+
+/// Metadata of the repository the package is published to.
+pub struct RepoIndex {
+    packages: Vec<IndexPackage>,
+    /// Content of the `Contents-<arch>` index, kept as text as it is only searched once per
+    /// package
+    contents: Option<String>,
+}
+
+/// Reads the index at `location` that is either a URL or a path, decompressing it based on the
+/// extension.
+fn fetch(location: &str) -> Result<String> {
+    let data = if location.contains("://") {
+        curl("", &["--location", location])?
+    } else {
+        fs::read(location)?
+    };
+
+    let mut index = String::new();
+    if location.ends_with(".gz") {
+        GzDecoder::new(&data[..])
+            .read_to_string(&mut index)
+            .context("failed to decompress index")?;
+    } else if location.ends_with(".xz") {
+        let mut decompressed = vec![];
+        lzma_rs::xz_decompress(&mut io::BufReader::new(&data[..]), &mut decompressed)
+            .map_err(|e| Error::msg(format!("{:?}", e)))
+            .context("failed to decompress index")?;
+        index = String::from_utf8(decompressed).context("index is not valid UTF-8")?;
+    } else {
+        index = String::from_utf8(data).context("index is not valid UTF-8")?;
+    }
+    Ok(index)
+}
+
+impl RepoIndex {
+    /// Downloads the indices of the repository defined in `config`.
+    pub fn load(config: &SimulateConfig) -> Result<Self> {
+        let span = info_span!("load-index", packages = %config.packages);
+        let _enter = span.enter();
+
+        let packages = fetch(&config.packages).context("failed to fetch Packages index")?;
+        let contents = match &config.contents {
+            Some(contents) => Some(fetch(contents).context("failed to fetch Contents index")?),
+            None => None,
+        };
+        let index = RepoIndex::new(&packages, contents);
+        trace!(packages = %index.packages.len(), "loaded index");
+        Ok(index)
+    }
+
+    pub fn new(packages: &str, contents: Option<String>) -> Self {
+        let packages = packages
+            .split("\n\n")
+            .filter(|stanza| !stanza.trim().is_empty())
+            .map(|stanza| IndexPackage {
+                control: stanza.to_string(),
+            })
+            .collect();
+        Self { packages, contents }
+    }
+
+    /// Returns the problems that adding the package with the `control` file and `files` would
+    /// cause in the repository.
+    pub fn simulate(&self, control: &str, files: &[String]) -> Vec<Problem> {
+        let field = |name| control_field(control, name).unwrap_or_default();
+        let (name, version, arch) = (field("Package"), field("Version"), field("Architecture"));
+        let mut problems = vec![];
+
+        for package in &self.packages {
+            if package.field("Package") == name
+                && same_arch(package.field("Architecture"), arch)
+                && compare_versions(version, package.field("Version")) != Ordering::Greater
+            {
+                problems.push(Problem::NotNewer {
+                    existing: package.field("Version").to_string(),
+                });
+            }
+        }
+
+        if let Some(contents) = &self.contents {
+            let replaces: Vec<_> = relations(field("Replaces"))
+                .into_iter()
+                .flatten()
+                .map(|relation| relation.name)
+                .collect();
+            let files: HashSet<_> = files.iter().map(String::as_str).collect();
+            for line in contents.lines() {
+                let (path, owners) = match line.trim_end().rsplit_once(char::is_whitespace) {
+                    Some((path, owners)) => (path.trim(), owners),
+                    None => continue,
+                };
+                if !files.contains(path) {
+                    continue;
+                }
+                for owner in owners.split(',') {
+                    let owner = owner.rsplit('/').next().unwrap_or_default();
+                    if owner != name && !replaces.contains(&owner) {
+                        problems.push(Problem::FileConflict {
+                            path: path.to_string(),
+                            owner: owner.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        // only the newest version of each dependent is checked
+        let mut dependents: BTreeMap<(&str, &str), &IndexPackage> = BTreeMap::new();
+        for package in &self.packages {
+            let key = (package.field("Package"), package.field("Architecture"));
+            if key.0 == name {
+                continue;
+            }
+            let newer = dependents
+                .get(&key)
+                .map(|other| {
+                    compare_versions(package.field("Version"), other.field("Version"))
+                        == Ordering::Greater
+                })
+                .unwrap_or(true);
+            if newer {
+                dependents.insert(key, package);
+            }
+        }
+        for ((dependent, dependent_arch), package) in dependents {
+            if !same_arch(dependent_arch, arch) {
+                continue;
+            }
+            for depends in [package.field("Pre-Depends"), package.field("Depends")] {
+                for group in relations(depends) {
+                    // a group with alternatives from other packages might still be satisfied
+                    if group.iter().any(|relation| relation.name != name) {
+                        continue;
+                    }
+                    if !group.iter().any(|relation| relation.satisfied_by(version)) {
+                        problems.push(Problem::BrokenDependent {
+                            package: dependent.to_string(),
+                            version: package.field("Version").to_string(),
+                            relation: group
+                                .iter()
+                                .map(|relation| match relation.constraint {
+                                    Some((op, version)) => {
+                                        format!("{} ({} {})", relation.name, op, version)
+                                    }
+                                    None => relation.name.to_string(),
+                                })
+                                .collect::<Vec<_>>()
+                                .join(" | "),
+                        });
+                    }
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+/// Renders the `problems` as a list.
+pub fn report(problems: &[Problem]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("  - {}", problem))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PACKAGES: &str = "Package: pkger
+Version: 0.7.0-1
+Architecture: amd64
+
+Package: pkger-plugins
+Version: 0.6.0-1
+Architecture: amd64
+Depends: libc6 (>= 2.31), pkger (<< 0.7.0)
+
+Package: pkger-plugins
+Version: 0.7.0-1
+Architecture: amd64
+Depends: pkger (= 0.7.0-1)
+
+Package: pkger-docs
+Version: 0.7.0-1
+Architecture: all
+Depends: pkger | pkger-lite, pkger:any (>= 0.5)
+";
+
+    static CONTENTS: &str = "usr/bin/pkger    admin/pkger
+usr/share/man/man1/pkger.1.gz    doc/pkger-docs
+etc/pkger.yml    admin/pkger-config,admin/pkger-legacy
+";
+
+    #[test]
+    fn compares_versions() {
+        assert_eq!(compare_versions("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0-1", "1.0-2"), Ordering::Less);
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0~rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1:0.1", "2.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0a", "1.0+"), Ordering::Less);
+        assert_eq!(compare_versions("1.001", "1.1"), Ordering::Equal);
+    }
+
+    #[test]
+    fn simulates_publishing() {
+        let index = RepoIndex::new(PACKAGES, Some(CONTENTS.to_string()));
+        let files = vec![
+            "usr/bin/pkger".to_string(),
+            "usr/share/man/man1/pkger.1.gz".to_string(),
+            "etc/pkger.yml".to_string(),
+        ];
+
+        let control =
+            "Package: pkger\nVersion: 0.7.0-1\nArchitecture: amd64\nReplaces: pkger-legacy\n";
+        assert_eq!(
+            index.simulate(control, &files),
+            vec![
+                Problem::NotNewer {
+                    existing: "0.7.0-1".to_string()
+                },
+                Problem::FileConflict {
+                    path: "usr/share/man/man1/pkger.1.gz".to_string(),
+                    owner: "pkger-docs".to_string()
+                },
+                Problem::FileConflict {
+                    path: "etc/pkger.yml".to_string(),
+                    owner: "pkger-config".to_string()
+                },
+            ]
+        );
+
+        let control = "Package: pkger\nVersion: 0.8.0-1\nArchitecture: amd64\n";
+        assert_eq!(
+            index.simulate(control, &files[..1]),
+            vec![Problem::BrokenDependent {
+                package: "pkger-plugins".to_string(),
+                version: "0.7.0-1".to_string(),
+                relation: "pkger (= 0.7.0-1)".to_string()
+            }]
+        );
+        assert!(RepoIndex::new("", None)
+            .simulate(control, &files)
+            .is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+//! Decryption of secrets in the configuration file. Whole files encrypted with SOPS and single
+//! values encrypted with age are supported by calling the `sops` and `age` binaries.
+
+use pkger_core::{ErrContext, Error, Result};
+
+use serde_yaml::Value as YamlValue;
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::{debug, info_span, trace};
+
+/// Header of ASCII-armored values encrypted with age
+const AGE_ARMOR_HEADER: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+/// Environment variable with the path to the age identity used by SOPS
+const SOPS_AGE_KEY_FILE: &str = "SOPS_AGE_KEY_FILE";
+
+/// Decrypts the configuration loaded from `path` in place. A file encrypted with SOPS is
+/// decrypted as a whole, afterwards every string value encrypted with age is replaced with its
+/// plaintext. The age identity is read from `age_key_file` of the configuration or from the
+/// `SOPS_AGE_KEY_FILE` environment variable.
+pub fn decrypt(config: &mut YamlValue, path: &Path) -> Result<()> {
+    let span = info_span!("decrypt-config", path = %path.display());
+    let _enter = span.enter();
+
+    let key_file = config
+        .get("age_key_file")
+        .and_then(YamlValue::as_str)
+        .map(PathBuf::from)
+        .or_else(|| env::var_os(SOPS_AGE_KEY_FILE).map(PathBuf::from));
+    trace!(key_file = ?key_file);
+
+    if config.get("sops").is_some() {
+        debug!("decrypting configuration with sops");
+        *config = sops_decrypt(path, key_file.as_deref())?;
+    }
+
+    decrypt_values(config, &mut |value| {
+        let key_file = key_file.as_deref().context(
+            "found a value encrypted with age but neither `age_key_file` nor `SOPS_AGE_KEY_FILE` is set",
+        )?;
+        age_decrypt(value, key_file)
+    })
+}
+
+/// Replaces every string in `value` that is encrypted with age with the output of `decrypt`.
+fn decrypt_values<F>(value: &mut YamlValue, decrypt: &mut F) -> Result<()>
+where
+    F: FnMut(&str) -> Result<String>,
+{
+    match value {
+        YamlValue::String(s) if s.trim_start().starts_with(AGE_ARMOR_HEADER) => {
+            *s = decrypt(s)?;
+        }
+        YamlValue::Sequence(values) => {
+            for value in values {
+                decrypt_values(value, decrypt)?;
+            }
+        }
+        YamlValue::Mapping(mapping) => {
+            for (_, value) in mapping.iter_mut() {
+                decrypt_values(value, decrypt)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn sops_decrypt(path: &Path, key_file: Option<&Path>) -> Result<YamlValue> {
+    let mut cmd = Command::new("sops");
+    cmd.args(["--decrypt", "--input-type", "yaml", "--output-type", "yaml"])
+        .arg(path);
+    if let Some(key_file) = key_file {
+        cmd.env(SOPS_AGE_KEY_FILE, key_file);
+    }
+    let output = cmd
+        .output()
+        .context("failed to run `sops`, is it installed?")?;
+    if !output.status.success() {
+        return err!(
+            "failed to decrypt configuration with sops: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    serde_yaml::from_slice(&output.stdout).context("failed to parse decrypted configuration")
+}
+
+fn age_decrypt(value: &str, key_file: &Path) -> Result<String> {
+    let mut child = Command::new("age")
+        .arg("--decrypt")
+        .arg("--identity")
+        .arg(key_file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run `age`, is it installed?")?;
+    child
+        .stdin
+        .take()
+        .context("failed to open stdin of `age`")?
+        .write_all(value.as_bytes())
+        .context("failed to pass the encrypted value to `age`")?;
+    let output = child
+        .wait_with_output()
+        .context("failed to wait for `age`")?;
+    if !output.status.success() {
+        return err!(
+            "failed to decrypt value with age: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    String::from_utf8(output.stdout)
+        .map(|value| value.trim_end_matches('\n').to_string())
+        .context("decrypted value is not valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decrypts_only_encrypted_values() {
+        let encrypted = format!(
+            "{}\nYWdl\n-----END AGE ENCRYPTED FILE-----\n",
+            AGE_ARMOR_HEADER
+        );
+        let mut config: YamlValue = serde_yaml::from_str(&format!(
+            r#"
+recipes_dir: recipes
+gpg_pass: {0:?}
+publish:
+  - provider: cloudsmith
+    token: {0:?}
+"#,
+            encrypted
+        ))
+        .unwrap();
+
+        let mut decrypted = 0;
+        decrypt_values(&mut config, &mut |value| {
+            assert_eq!(value, encrypted);
+            decrypted += 1;
+            Ok("secret".to_string())
+        })
+        .unwrap();
+
+        assert_eq!(decrypted, 2);
+        assert_eq!(config["recipes_dir"].as_str(), Some("recipes"));
+        assert_eq!(config["gpg_pass"].as_str(), Some("secret"));
+        assert_eq!(config["publish"][0]["token"].as_str(), Some("secret"));
+    }
+}
@@ -0,0 +1,174 @@
+//! Summary of the jobs of a build session printed once all of them finish.
+
+use crate::job::JobResult;
+use crate::table::{Cell, IntoCell, IntoTable, Table};
+use pkger_core::recipe::BuildTarget;
+use pkger_core::units::{format_duration, format_size};
+
+use colored::Color;
+use std::fs;
+use std::time::Duration;
+
+/// Result of a job with the recipe, image and target it was run for
+pub struct JobSummary<'a> {
+    pub recipe: &'a str,
+    pub image: &'a str,
+    pub target: BuildTarget,
+    pub result: &'a JobResult,
+}
+
+impl JobSummary<'_> {
+    fn status(&self) -> &'static str {
+        match self.result {
+            JobResult::Success { .. } => "success",
+            JobResult::Failure { .. } => "failure",
+            JobResult::UpToDate { .. } => "up to date",
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        match self.result {
+            JobResult::Success { duration, .. }
+            | JobResult::Failure { duration, .. }
+            | JobResult::UpToDate { duration, .. } => *duration,
+        }
+    }
+
+    /// Returns the total size of the packages built by the job, including subpackages and debug
+    /// packages, or the size of the package kept by the job if any of them exists.
+    fn size(&self) -> Option<u64> {
+        let packages = match self.result {
+            JobResult::Success {
+                output, packages, ..
+            } if packages.is_empty() => std::slice::from_ref(output),
+            JobResult::Success { packages, .. } => packages.as_slice(),
+            JobResult::UpToDate {
+                output: Some(output),
+                ..
+            } => std::slice::from_ref(output),
+            _ => return None,
+        };
+        packages
+            .iter()
+            .filter_map(|package| fs::metadata(package).ok())
+            .map(|metadata| metadata.len())
+            .reduce(|total, size| total + size)
+    }
+}
+
+/// Renders a table with a row for every job followed by the totals, the number of jobs with each
+/// status, the `elapsed` time of the whole session and the size of all packages.
+pub fn table(jobs: &[JobSummary], elapsed: Duration) -> Table {
+    let mut rows = vec![];
+    let mut total_size = 0;
+    for job in jobs {
+        let size = job.size();
+        total_size += size.unwrap_or_default();
+        let color = match job.result {
+            JobResult::Success { .. } => Color::Green,
+            JobResult::Failure { .. } => Color::Red,
+            JobResult::UpToDate { .. } => Color::BrightBlack,
+        };
+        rows.push(vec![
+            job.recipe.cell().left().color(Color::BrightBlue),
+            job.image.cell().left().color(Color::Blue),
+            job.target.as_ref().cell().left(),
+            job.status().cell().left().color(color),
+            format_duration(job.duration()).cell().right(),
+            size.map(format_size)
+                .unwrap_or_else(|| "-".to_string())
+                .cell()
+                .right(),
+        ]);
+    }
+
+    let counts: Vec<_> = ["success", "failure", "up to date"]
+        .iter()
+        .filter_map(|status| {
+            let count = jobs.iter().filter(|job| job.status() == *status).count();
+            if count > 0 {
+                Some(format!("{} {}", count, status))
+            } else {
+                None
+            }
+        })
+        .collect();
+    rows.push(vec![
+        "total".cell().left().bold(),
+        format!("{} jobs", jobs.len()).cell().left(),
+        Cell::from(""),
+        counts.join(", ").cell().left(),
+        format_duration(elapsed).cell().right().bold(),
+        format_size(total_size).cell().right().bold(),
+    ]);
+
+    rows.into_table().with_headers(vec![
+        "Recipe".cell().bold(),
+        "Image".cell().bold(),
+        "Target".cell().bold(),
+        "Status".cell().bold(),
+        "Duration".cell().bold(),
+        "Size".cell().bold(),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_session_summary() {
+        let dir = tempdir::TempDir::new("pkger-summary").unwrap();
+        let package = |name: &str, size: usize| {
+            let path = dir.path().join(name);
+            fs::write(&path, vec![0; size]).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let output = package("pkger-0.1.0-0.x86_64.rpm", 2048);
+        let results = [
+            JobResult::Success {
+                id: "pkger-rocky".to_string(),
+                duration: Duration::from_secs(75),
+                packages: vec![
+                    output.clone(),
+                    package("pkger-debuginfo-0.1.0-0.x86_64.rpm", 1024),
+                ],
+                output,
+                conflicts: vec![],
+            },
+            JobResult::failure("pkger-debian", Duration::from_secs(3), "build failed"),
+        ];
+        let jobs = vec![
+            JobSummary {
+                recipe: "pkger",
+                image: "rocky",
+                target: BuildTarget::Rpm,
+                result: &results[0],
+            },
+            JobSummary {
+                recipe: "pkger",
+                image: "debian",
+                target: BuildTarget::Deb,
+                result: &results[1],
+            },
+        ];
+        let rendered = table(&jobs, Duration::from_secs(80)).render(false);
+
+        let rows: Vec<Vec<_>> = rendered
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["Recipe", "Image", "Target", "Status", "Duration", "Size"],
+                vec!["pkger", "rocky", "rpm", "success", "1m", "15s", "3.0", "KiB"],
+                vec!["pkger", "debian", "deb", "failure", "3.0s", "-"],
+                vec![
+                    "total", "2", "jobs", "1", "success,", "1", "failure", "1m", "20s", "3.0",
+                    "KiB"
+                ],
+            ]
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use crate::opts::GenRecipeOpts;
+use pkger_core::recipe::{self, RecipeRep};
+use pkger_core::{err, ErrContext, Error, Result};
+
+use serde_yaml::Value as YamlValue;
+use std::io::{self, Write};
+use std::path::Path;
+
+static SOURCE_TYPES: &[&str] = &["git", "http", "local", "none"];
+static LANGUAGES: &[&str] = &["rust", "go", "c", "cmake", "python", "none"];
+
+/// Answers from the interactive recipe wizard that don't have a matching field in
+/// [GenRecipeOpts](GenRecipeOpts).
+pub struct Wizard {
+    images: Vec<String>,
+    language: String,
+}
+
+/// Prints the `question` and reads a single line answer from stdin. Returns `default` if the
+/// answer is empty and fails if stdin is closed so that the wizard doesn't keep asking.
+fn prompt(question: &str, default: Option<&str>) -> Result<String> {
+    match default {
+        Some(default) if !default.is_empty() => print!("{} [{}]: ", question, default),
+        _ => print!("{}: ", question),
+    }
+    io::stdout().flush().context("failed to flush stdout")?;
+
+    let mut line = String::new();
+    let read = io::stdin()
+        .read_line(&mut line)
+        .context("failed to read input from user")?;
+    if read == 0 {
+        println!();
+        return err!("input was closed, aborting the wizard");
+    }
+    let answer = line.trim();
+
+    if answer.is_empty() {
+        Ok(default.unwrap_or_default().to_string())
+    } else {
+        Ok(answer.to_string())
+    }
+}
+
+/// Asks until one of `choices` is selected.
+fn prompt_choice(question: &str, choices: &[&str], default: &str) -> Result<String> {
+    loop {
+        let answer = prompt(
+            &format!("{} ({})", question, choices.join("/")),
+            Some(default),
+        )?;
+        if choices.contains(&answer.as_str()) {
+            return Ok(answer);
+        }
+        println!("`{}` is not one of {}", answer, choices.join(", "));
+    }
+}
+
+/// Asks for a list of values separated by commas or whitespace.
+fn prompt_list(question: &str, default: Option<&[String]>) -> Result<Vec<String>> {
+    let default = default.map(|default| default.join(", "));
+    Ok(prompt(question, default.as_deref())?
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Asks for an optional value, `-` clears the default.
+fn prompt_optional(question: &str, default: Option<&str>) -> Result<Option<String>> {
+    let answer = prompt(question, default)?;
+    if answer.is_empty() || answer == "-" {
+        Ok(None)
+    } else {
+        Ok(Some(answer))
+    }
+}
+
+/// Interactively asks the user about the recipe filling in `opts`. `recipes_dir` is used to verify
+/// that the recipe doesn't already exist and `images` are the images available in configuration.
+pub fn recipe(opts: &mut GenRecipeOpts, recipes_dir: &Path, images: &[String]) -> Result<Wizard> {
+    loop {
+        let name = prompt("Name of the recipe", opts.name.as_deref())?;
+        if let Err(e) = recipe::validate_name(&name) {
+            println!("{}", e);
+            continue;
+        }
+        if recipes_dir.join(&name).exists() {
+            println!("recipe `{}` already exists", name);
+            continue;
+        }
+        opts.name = Some(name);
+        break;
+    }
+
+    opts.version = Some(prompt(
+        "Version",
+        opts.version.as_deref().or(Some("1.0.0")),
+    )?);
+    opts.description = prompt_optional("Description", opts.description.as_deref())?;
+    opts.license = prompt_optional("License", opts.license.as_deref())?;
+
+    let default_source = if opts.git_url.is_some() {
+        "git"
+    } else if let Some(source) = &opts.source {
+        if source.starts_with("http") {
+            "http"
+        } else {
+            "local"
+        }
+    } else {
+        "none"
+    };
+    match prompt_choice("Source type", SOURCE_TYPES, default_source)?.as_str() {
+        "git" => {
+            opts.source = None;
+            opts.git_url = Some(prompt("Git repository URL", opts.git_url.as_deref())?);
+            opts.git_branch = prompt_optional("Git branch", opts.git_branch.as_deref())?;
+        }
+        "http" => {
+            opts.git_url = None;
+            opts.source = Some(prompt("URL of the source archive", opts.source.as_deref())?);
+        }
+        "local" => {
+            opts.git_url = None;
+            opts.source = Some(prompt("Path to the source", opts.source.as_deref())?);
+        }
+        _ => {
+            opts.git_url = None;
+            opts.source = None;
+        }
+    }
+
+    let images = if images.is_empty() {
+        prompt_list("Images to build on (separated by commas)", None)?
+    } else {
+        prompt_list(
+            &format!(
+                "Images to build on (separated by commas, available: {})",
+                images.join(", ")
+            ),
+            None,
+        )?
+    };
+
+    opts.build_depends = Some(prompt_list(
+        "Build dependencies (separated by commas)",
+        opts.build_depends.as_deref(),
+    )?);
+    opts.depends = Some(prompt_list(
+        "Runtime dependencies (separated by commas)",
+        opts.depends.as_deref(),
+    )?);
+
+    let language = prompt_choice("Language of the project", LANGUAGES, "none")?;
+
+    Ok(Wizard { images, language })
+}
+
+impl Wizard {
+    /// Serializes the `recipe` replacing its scripts with a commented skeleton for the chosen
+    /// language.
+    pub fn render(&self, mut recipe: RecipeRep) -> Result<String> {
+        recipe.metadata.images = self.images.clone();
+
+        let mut value = serde_yaml::to_value(&recipe).context("failed to serialize recipe")?;
+        if let Some(mapping) = value.as_mapping_mut() {
+            mapping.remove(&YamlValue::from("configure"));
+            mapping.remove(&YamlValue::from("build"));
+            mapping.remove(&YamlValue::from("install"));
+        }
+        let mut rendered = serde_yaml::to_string(&value).context("failed to serialize recipe")?;
+        if !rendered.ends_with('\n') {
+            rendered.push('\n');
+        }
+        rendered.push_str(self.scripts());
+
+        Ok(rendered)
+    }
+
+    fn scripts(&self) -> &'static str {
+        match self.language.as_str() {
+            "rust" => {
+                r#"build:
+  # Steps are executed in $PKGER_BLD_DIR that contains the fetched sources.
+  # Make sure `cargo` is available, for example by adding it to `build_depends`.
+  steps:
+    - cmd: cargo build --release
+install:
+  # Everything in $PKGER_OUT_DIR ends up in the final package.
+  steps:
+    - cmd: mkdir -p $PKGER_OUT_DIR/usr/bin
+    - cmd: install -m755 $PKGER_BLD_DIR/target/release/$RECIPE $PKGER_OUT_DIR/usr/bin/
+"#
+            }
+            "go" => {
+                r#"build:
+  # Steps are executed in $PKGER_BLD_DIR that contains the fetched sources.
+  # Make sure `go` is available, for example by adding it to `build_depends`.
+  steps:
+    - cmd: go build -o $RECIPE .
+install:
+  # Everything in $PKGER_OUT_DIR ends up in the final package.
+  steps:
+    - cmd: mkdir -p $PKGER_OUT_DIR/usr/bin
+    - cmd: install -m755 $PKGER_BLD_DIR/$RECIPE $PKGER_OUT_DIR/usr/bin/
+"#
+            }
+            "c" => {
+                r#"configure:
+  # Remove this phase if the project doesn't use a configure script.
+  steps:
+    - cmd: ./configure --prefix=/usr
+build:
+  # Steps are executed in $PKGER_BLD_DIR that contains the fetched sources.
+  steps:
+    - cmd: make
+install:
+  # Everything in $PKGER_OUT_DIR ends up in the final package.
+  steps:
+    - cmd: make DESTDIR=$PKGER_OUT_DIR install
+"#
+            }
+            "cmake" => {
+                r#"configure:
+  # Make sure `cmake` is available, for example by adding it to `build_depends`.
+  steps:
+    - cmd: cmake -S . -B build -DCMAKE_INSTALL_PREFIX=/usr -DCMAKE_BUILD_TYPE=Release
+build:
+  # Steps are executed in $PKGER_BLD_DIR that contains the fetched sources.
+  steps:
+    - cmd: cmake --build build
+install:
+  # Everything in $PKGER_OUT_DIR ends up in the final package.
+  steps:
+    - cmd: DESTDIR=$PKGER_OUT_DIR cmake --install build
+"#
+            }
+            "python" => {
+                r#"build:
+  # Steps are executed in $PKGER_BLD_DIR that contains the fetched sources.
+  # Make sure `python3` and `pip` are available, for example by adding them to `build_depends`.
+  steps:
+    - cmd: python3 -m pip wheel --no-deps -w dist .
+install:
+  # Everything in $PKGER_OUT_DIR ends up in the final package.
+  steps:
+    - cmd: python3 -m pip install --no-deps --root $PKGER_OUT_DIR --prefix /usr dist/*.whl
+"#
+            }
+            _ => {
+                r#"build:
+  # Steps are executed in $PKGER_BLD_DIR that contains the fetched sources.
+  steps:
+    - cmd: echo "building $RECIPE $RECIPE_VERSION"
+install:
+  # Everything in $PKGER_OUT_DIR ends up in the final package.
+  steps:
+    - cmd: mkdir -p $PKGER_OUT_DIR
+"#
+            }
+        }
+    }
+}
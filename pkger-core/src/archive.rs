@@ -3,15 +3,18 @@
 pub use flate2;
 pub use tar;
 
-use crate::{ErrContext, Result};
+use crate::{err, ErrContext, Error, Result};
 
+use bzip2::write::BzEncoder;
 use flate2::write::GzEncoder;
-use flate2::Compression;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::ops::RangeInclusive;
 use std::path::Path;
 use tracing::{info_span, trace};
+use xz2::write::XzEncoder;
 
 /// Unpacks a given tar archive to the path specified by `output_dir`
 pub fn unpack_tarball<T: io::Read, P: AsRef<Path>>(
@@ -29,34 +32,147 @@ pub fn unpack_tarball<T: io::Read, P: AsRef<Path>>(
             trace!(parent: &span, entry = %path.display(), "unpacking");
             let name = path.file_name().unwrap_or_default();
 
-            entry.unpack(output_dir.join(name))?;
+            let dest = output_dir.join(name);
+            entry
+                .unpack(&dest)
+                .context(format!("failed to write `{}`", dest.display()))?;
         }
     }
 
     Ok(())
 }
 
-/// Save the give tar archive as gzip encoded tar to path specified by `output_dir` with the
-/// filename set to `name`.
-pub fn save_tar_gz<T: io::Read>(
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Compression of tarballs created by the `gzip` target
+pub enum Compression {
+    #[default]
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+/// Name, tarball extension, supported levels and default level of a compression
+struct Codec {
+    name: &'static str,
+    extension: &'static str,
+    levels: RangeInclusive<u32>,
+    default_level: u32,
+}
+
+impl Compression {
+    /// All supported compressions
+    pub const ALL: [Compression; 4] = [
+        Compression::Gzip,
+        Compression::Xz,
+        Compression::Zstd,
+        Compression::Bzip2,
+    ];
+
+    fn codec(&self) -> Codec {
+        let (name, extension, levels, default_level) = match self {
+            Compression::Gzip => ("gzip", "tar.gz", 1..=9, 6),
+            Compression::Xz => ("xz", "tar.xz", 0..=9, 6),
+            Compression::Zstd => ("zstd", "tar.zst", 1..=22, 3),
+            Compression::Bzip2 => ("bzip2", "tar.bz2", 1..=9, 9),
+        };
+        Codec {
+            name,
+            extension,
+            levels,
+            default_level,
+        }
+    }
+
+    /// Returns the extension of a tarball compressed with this compression
+    pub fn extension(&self) -> &'static str {
+        self.codec().extension
+    }
+
+    /// Recognizes the compression from the extension of a tarball like `tar.zst`
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Compression::ALL
+            .into_iter()
+            .find(|compression| compression.extension() == extension)
+    }
+
+    /// Returns the range of levels supported by this compression
+    pub fn levels(&self) -> RangeInclusive<u32> {
+        self.codec().levels
+    }
+
+    /// Verifies that the `level` is supported by this compression
+    pub fn check_level(&self, level: u32) -> Result<()> {
+        let levels = self.levels();
+        if !levels.contains(&level) {
+            return err!(
+                "invalid {} compression level {}, expected a level in range {}-{}",
+                self.as_ref(),
+                level,
+                levels.start(),
+                levels.end()
+            );
+        }
+        Ok(())
+    }
+
+    fn default_level(&self) -> u32 {
+        self.codec().default_level
+    }
+}
+
+impl AsRef<str> for Compression {
+    fn as_ref(&self) -> &str {
+        self.codec().name
+    }
+}
+
+/// Save the given tar archive compressed with `compression` to path specified by `output_dir`
+/// with the filename set to `name`. Uses the default level of the compression if `level` is not
+/// specified.
+pub fn save_tar<T: io::Read>(
     archive: tar::Archive<T>,
     name: &str,
     output_dir: &Path,
+    compression: Compression,
+    level: Option<u32>,
 ) -> Result<()> {
     let path = output_dir.join(name);
-    let span = info_span!("save-tar-gz", path = %path.display());
+    let span = info_span!("save-tar", path = %path.display(), compression = compression.as_ref());
     let _enter = span.enter();
 
-    trace!(parent: &span, "creating a gzipped tarball");
+    let level = level.unwrap_or_else(|| compression.default_level());
+    compression.check_level(level)?;
+
+    trace!(parent: &span, level, "creating a compressed tarball");
     let f = File::create(path.as_path())?;
-    let mut e = GzEncoder::new(f, Compression::default());
     let mut archive = archive.into_inner();
     let mut bytes = Vec::new();
     archive.read_to_end(&mut bytes)?;
 
-    e.write_all(&bytes)?;
-
-    e.finish()?;
+    match compression {
+        Compression::Gzip => {
+            let mut e = GzEncoder::new(f, flate2::Compression::new(level));
+            e.write_all(&bytes)?;
+            e.finish()?;
+        }
+        Compression::Xz => {
+            let mut e = XzEncoder::new(f, level);
+            e.write_all(&bytes)?;
+            e.finish()?;
+        }
+        Compression::Zstd => {
+            let mut e = zstd::Encoder::new(f, level as i32)?;
+            e.write_all(&bytes)?;
+            e.finish()?;
+        }
+        Compression::Bzip2 => {
+            let mut e = BzEncoder::new(f, bzip2::Compression::new(level));
+            e.write_all(&bytes)?;
+            e.finish()?;
+        }
+    }
 
     Ok(())
 }
@@ -88,3 +204,45 @@ where
 
     archive.into_inner().context("failed to create tar archive")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn saves_compressed_tarballs() {
+        let tmp = tempdir::TempDir::new("pkger-archive").unwrap();
+        let tarball = create_tarball([("pkger.txt", &b"pkger"[..])].into_iter()).unwrap();
+
+        for compression in Compression::ALL {
+            let name = format!("pkger.{}", compression.extension());
+            save_tar(
+                tar::Archive::new(&tarball[..]),
+                &name,
+                tmp.path(),
+                compression,
+                None,
+            )
+            .unwrap();
+            assert_eq!(
+                Compression::from_extension(compression.extension()),
+                Some(compression)
+            );
+            assert!(tmp.path().join(name).exists());
+        }
+
+        assert_eq!(
+            save_tar(
+                tar::Archive::new(&tarball[..]),
+                "pkger.tar.zst",
+                tmp.path(),
+                Compression::Zstd,
+                Some(23),
+            )
+            .unwrap_err()
+            .to_string(),
+            "invalid zstd compression level 23, expected a level in range 1-22"
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Blocking work, like running programs on the host, done on a separate thread so that it doesn't
+//! stall other jobs running on the async runtime.
+
+use crate::{err, ErrContext, Error, Result};
+
+use futures::channel::oneshot;
+use std::process::{Command, Output};
+use std::thread;
+
+/// Runs `f` on a new thread and waits for its result.
+pub async fn run<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+    rx.await.context("blocking task panicked")?
+}
+
+/// Runs `cmd` to completion and returns its output, failing with its stderr if it doesn't
+/// succeed. `program` is the name used in errors.
+pub async fn output(mut cmd: Command, program: &'static str) -> Result<Output> {
+    run(move || {
+        let output = cmd
+            .output()
+            .context(format!("failed to run `{}`, is it installed?", program))?;
+        if !output.status.success() {
+            return err!(
+                "`{}` failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn runs_blocking_work_on_a_thread() {
+        let id = futures::executor::block_on(run(|| Ok(thread::current().id()))).unwrap();
+        assert_ne!(id, thread::current().id());
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo out; echo err >&2; exit 3"]);
+        let error = futures::executor::block_on(output(cmd, "sh")).unwrap_err();
+        assert_eq!(error.to_string(), "`sh` failed: err");
+    }
+}
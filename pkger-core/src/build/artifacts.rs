@@ -0,0 +1,314 @@
+//! Saving of built packages to the output directory. Packages are first downloaded to a staging
+//! directory and then moved next to the packages of previous builds according to the policy for
+//! files that already exist.
+
+use crate::{err, ErrContext, Error, Result};
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{info_span, trace, warn};
+
+/// Extensions of artifacts consisting of multiple parts that are kept together when renaming
+static COMPOUND_EXTENSIONS: &[&str] = &[
+    ".pkg.tar.zst",
+    ".pkg.tar.xz",
+    ".src.rpm",
+    ".tar.gz",
+    ".tar.xz",
+    ".tar.zst",
+    ".tar.bz2",
+];
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Action taken when a built artifact has the same name as an existing file in the output
+/// directory
+pub enum ArtifactPolicy {
+    #[default]
+    /// The existing file is replaced
+    Overwrite,
+    /// The existing file is kept and the new artifact is discarded
+    Skip,
+    /// The build fails without changing the existing file
+    Error,
+    /// The new artifact is saved with a numeric suffix like `pkger-0.1.0-0.x86_64.1.rpm`
+    Rename,
+}
+
+impl FromStr for ArtifactPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match &s.to_lowercase()[..] {
+            "overwrite" => Ok(ArtifactPolicy::Overwrite),
+            "skip" => Ok(ArtifactPolicy::Skip),
+            "error" => Ok(ArtifactPolicy::Error),
+            "rename" => Ok(ArtifactPolicy::Rename),
+            _ => err!("invalid artifact policy `{}`", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Action taken for an artifact that already existed in the output directory
+pub enum ConflictAction {
+    Overwritten,
+    Skipped,
+    /// The new artifact was saved to the path
+    Renamed(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A built artifact with the same name as an existing file with different content
+pub struct ArtifactConflict {
+    /// Path of the existing file
+    pub path: PathBuf,
+    pub action: ConflictAction,
+}
+
+impl fmt::Display for ArtifactConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.action {
+            ConflictAction::Overwritten => write!(f, "overwrote `{}`", self.path.display()),
+            ConflictAction::Skipped => write!(f, "kept existing `{}`", self.path.display()),
+            ConflictAction::Renamed(path) => write!(
+                f,
+                "saved as `{}` next to existing `{}`",
+                path.display(),
+                self.path.display()
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// An artifact moved from the staging directory
+pub struct SavedArtifact {
+    /// Path of the artifact in the staging directory
+    pub staged: PathBuf,
+    /// Path of the artifact in the output directory
+    pub path: PathBuf,
+    pub conflict: Option<ArtifactConflict>,
+}
+
+/// Returns the final path of the artifact `staged` or the path itself if it wasn't saved.
+pub fn saved_path(saved: &[SavedArtifact], staged: &Path) -> PathBuf {
+    saved
+        .iter()
+        .find(|artifact| artifact.staged == staged)
+        .map(|artifact| artifact.path.clone())
+        .unwrap_or_else(|| staged.to_path_buf())
+}
+
+/// Returns the staging directory of the job with `id` inside of `out_dir`. Staging inside of the
+/// output directory keeps it on the same file system so artifacts can be moved.
+pub fn staging_dir(out_dir: &Path, id: &str) -> PathBuf {
+    out_dir.join(format!(".{}", id))
+}
+
+/// Moves all files of `staging_dir` to `out_dir` applying the `policy` to files that already
+/// exist. Existing files with the same content as the new artifact are not conflicts and are
+/// kept. With `ArtifactPolicy::Error` no files are moved if any of them conflicts.
+pub fn save(
+    staging_dir: &Path,
+    out_dir: &Path,
+    policy: ArtifactPolicy,
+) -> Result<Vec<SavedArtifact>> {
+    let span = info_span!("save-artifacts", policy = ?policy);
+    let _enter = span.enter();
+
+    let mut staged = vec![];
+    for entry in fs::read_dir(staging_dir).context("failed to read staging directory")? {
+        let entry = entry.context("invalid dir entry")?;
+        staged.push((entry.path(), out_dir.join(entry.file_name())));
+    }
+    staged.sort();
+
+    let mut conflicting = vec![];
+    for (artifact, path) in &staged {
+        let exists = fs::symlink_metadata(path).is_ok();
+        if exists && !same_content(artifact, path) {
+            conflicting.push(path.clone());
+        }
+    }
+    if policy == ArtifactPolicy::Error && !conflicting.is_empty() {
+        let paths: Vec<_> = conflicting
+            .iter()
+            .map(|path| format!("`{}`", path.display()))
+            .collect();
+        return err!(
+            "artifacts already exist in the output directory: {}",
+            paths.join(", ")
+        );
+    }
+
+    let mut saved = vec![];
+    for (artifact, path) in staged {
+        let (dest, conflict) = if !conflicting.contains(&path) {
+            if fs::symlink_metadata(&path).is_ok() {
+                trace!(path = %path.display(), "identical artifact already exists");
+                fs::remove_file(&artifact).context("failed to remove duplicate artifact")?;
+                saved.push(SavedArtifact {
+                    staged: artifact,
+                    path,
+                    conflict: None,
+                });
+                continue;
+            }
+            (path.clone(), None)
+        } else {
+            match policy {
+                ArtifactPolicy::Overwrite | ArtifactPolicy::Error => {
+                    (path.clone(), Some(ConflictAction::Overwritten))
+                }
+                ArtifactPolicy::Skip => {
+                    fs::remove_file(&artifact).context("failed to remove skipped artifact")?;
+                    (path.clone(), Some(ConflictAction::Skipped))
+                }
+                ArtifactPolicy::Rename => {
+                    let renamed = free_path(&path);
+                    (renamed.clone(), Some(ConflictAction::Renamed(renamed)))
+                }
+            }
+        };
+        if conflict != Some(ConflictAction::Skipped) {
+            trace!(artifact = %artifact.display(), path = %dest.display(), "moving artifact");
+            fs::rename(&artifact, &dest)
+                .context(format!("failed to save artifact to `{}`", dest.display()))?;
+        }
+        let conflict = conflict.map(|action| ArtifactConflict {
+            path: path.clone(),
+            action,
+        });
+        if let Some(conflict) = &conflict {
+            warn!(artifact = %path.display(), "{}", conflict);
+        }
+        saved.push(SavedArtifact {
+            staged: artifact,
+            path: dest,
+            conflict,
+        });
+    }
+    Ok(saved)
+}
+
+/// Returns true if the files at `a` and `b` can be read and have the same content.
+fn same_content(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a_md), Ok(b_md)) if a_md.len() == b_md.len() => {
+            matches!((fs::read(a), fs::read(b)), (Ok(a), Ok(b)) if a == b)
+        }
+        _ => false,
+    }
+}
+
+/// Returns the first path with a numeric suffix before the extension of `path` like
+/// `pkger-0.1.0.1.tar.gz` that doesn't exist.
+fn free_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (stem, extension) = match COMPOUND_EXTENSIONS
+        .iter()
+        .find(|extension| name.len() > extension.len() && name.ends_with(*extension))
+    {
+        Some(extension) => name.split_at(name.len() - extension.len()),
+        None => match name.rfind('.') {
+            Some(idx) if idx > 0 => name.split_at(idx),
+            _ => (name.as_str(), ""),
+        },
+    };
+    let mut n = 1;
+    loop {
+        let candidate = path.with_file_name(format!("{}.{}{}", stem, n, extension));
+        if fs::symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn saves_artifacts_with_policy() {
+        let tmp = tempdir::TempDir::new("pkger-artifacts").unwrap();
+        let out_dir = tmp.path();
+        let staging = staging_dir(out_dir, "pkger-job");
+        let stage = |files: &[(&str, &str)]| {
+            fs::create_dir_all(&staging).unwrap();
+            for (name, content) in files {
+                fs::write(staging.join(name), content).unwrap();
+            }
+        };
+        let conflicts = |saved: &[SavedArtifact]| {
+            saved
+                .iter()
+                .filter_map(|artifact| artifact.conflict.as_ref().map(ToString::to_string))
+                .collect::<Vec<_>>()
+        };
+        fs::write(out_dir.join("pkger-0.1.0.tar.gz"), "old").unwrap();
+        fs::write(out_dir.join("RPM-GPG-KEY-pkger"), "key").unwrap();
+
+        stage(&[("pkger-0.1.0.tar.gz", "new"), ("RPM-GPG-KEY-pkger", "key")]);
+        assert!(save(&staging, out_dir, ArtifactPolicy::Error).is_err());
+        assert_eq!(
+            fs::read_to_string(out_dir.join("pkger-0.1.0.tar.gz")).unwrap(),
+            "old"
+        );
+
+        let saved = save(&staging, out_dir, ArtifactPolicy::Rename).unwrap();
+        let renamed = out_dir.join("pkger-0.1.0.1.tar.gz");
+        assert_eq!(
+            saved_path(&saved, &staging.join("pkger-0.1.0.tar.gz")),
+            renamed
+        );
+        assert_eq!(fs::read_to_string(&renamed).unwrap(), "new");
+        assert_eq!(
+            conflicts(&saved),
+            vec![format!(
+                "saved as `{}` next to existing `{}`",
+                renamed.display(),
+                out_dir.join("pkger-0.1.0.tar.gz").display()
+            )]
+        );
+
+        stage(&[("pkger-0.1.0.tar.gz", "newer")]);
+        let saved = save(&staging, out_dir, ArtifactPolicy::Skip).unwrap();
+        assert_eq!(
+            saved[0].conflict.as_ref().unwrap().action,
+            ConflictAction::Skipped
+        );
+        assert_eq!(
+            fs::read_to_string(out_dir.join("pkger-0.1.0.tar.gz")).unwrap(),
+            "old"
+        );
+
+        stage(&[("pkger-0.1.0.tar.gz", "newer"), ("pkger.deb", "deb")]);
+        let saved = save(&staging, out_dir, ArtifactPolicy::Overwrite).unwrap();
+        assert_eq!(
+            conflicts(&saved),
+            vec![format!(
+                "overwrote `{}`",
+                out_dir.join("pkger-0.1.0.tar.gz").display()
+            )]
+        );
+        assert_eq!(
+            fs::read_to_string(out_dir.join("pkger-0.1.0.tar.gz")).unwrap(),
+            "newer"
+        );
+        assert_eq!(fs::read_dir(&staging).unwrap().count(), 0);
+
+        assert_eq!(
+            free_path(&out_dir.join("pkger-0.1.0-0.src.rpm")),
+            out_dir.join("pkger-0.1.0-0.1.src.rpm")
+        );
+        assert_eq!(free_path(&out_dir.join("README")), out_dir.join("README.1"));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::build::container::Context;
+use crate::build::package;
+use crate::container::ExecOpts;
+use crate::recipe::{Binaries, RpathPolicy};
+use crate::{err, ErrContext, Error, Result};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, info_span, trace, Instrument};
+
+/// Strips ELF binaries in the output directory and applies the RPATH policy and `patchelf`
+/// adjustments of the recipe. When `keep_debug` is enabled the debug information is saved to
+/// `/usr/lib/debug` in a separate directory that is downloaded to `output_dir`. If the debug
+/// information should be packaged instead, returns the path of the directory in the container.
+pub async fn process(ctx: &Context<'_>, output_dir: &Path) -> Result<Option<PathBuf>> {
+    let binaries = if let Some(binaries) = &ctx.build.recipe.metadata.binaries {
+        binaries
+    } else {
+        return Ok(None);
+    };
+    let debug_package =
+        binaries.debug_package && package::supports_debug_package(ctx.build.target.build_target());
+    let keep_debug = binaries.keep_debug || debug_package;
+    let span = info_span!("process-binaries");
+    async move {
+        let exclude = exclude_matcher(binaries)?;
+        let files = find_elf_files(ctx)
+            .await?
+            .into_iter()
+            .filter(|file| {
+                let excluded = exclude
+                    .matched_path_or_any_parents(file, false)
+                    .is_ignore();
+                if excluded {
+                    debug!(file = %file, "excluded from processing");
+                }
+                !excluded
+            })
+            .collect::<Vec<_>>();
+        trace!(files = ?files);
+
+        let debug_dir = ctx
+            .build
+            .container_tmp_dir
+            .join(format!("{}-debug", package_name(ctx)));
+
+        for file in &files {
+            apply_rpath_policy(ctx, binaries.rpath, file).await?;
+
+            if binaries.strip {
+                let cmd = if keep_debug {
+                    let debug_file = debug_dir.join(format!("usr/lib/debug/{}.debug", file));
+                    let parent = debug_file.parent().unwrap_or(&debug_dir);
+                    format!(
+                        "mkdir -p {0} && objcopy --only-keep-debug {2} {1} && strip --strip-unneeded {2} && objcopy --add-gnu-debuglink={1} {2}",
+                        parent.display(),
+                        debug_file.display(),
+                        file
+                    )
+                } else {
+                    format!("strip --strip-unneeded {}", file)
+                };
+                info!(file = %file, "stripping binary");
+                exec_in_out_dir(ctx, &cmd)
+                    .await
+                    .context(format!("failed to strip `{}`", file))?;
+            }
+        }
+
+        for patchelf in &binaries.patchelf {
+            info!(path = %patchelf.path, args = %patchelf.args, "running patchelf");
+            exec_in_out_dir(
+                ctx,
+                &format!("patchelf {} {}", patchelf.args, patchelf.path),
+            )
+            .await
+            .context(format!("failed to run patchelf on `{}`", patchelf.path))?;
+        }
+
+        if !binaries.strip || !keep_debug || files.is_empty() {
+            return Ok(None);
+        }
+        if debug_package {
+            return Ok(Some(debug_dir));
+        }
+
+        info!(output_dir = %output_dir.display(), "downloading debug information");
+        let archive = ctx
+            .container
+            .copy_from(&debug_dir)
+            .await
+            .context("failed to download debug information")?;
+        tar::Archive::new(&archive[..])
+            .unpack(output_dir)
+            .context("failed to unpack debug information")?;
+
+        Ok(None)
+    }
+    .instrument(span)
+    .await
+}
+
+/// Returns the name of the package without the extension used for the directory with debug
+/// information.
+fn package_name(ctx: &Context<'_>) -> String {
+    let metadata = &ctx.build.recipe.metadata;
+    format!(
+        "{}-{}-{}",
+        metadata.name,
+        metadata.version,
+        metadata.release()
+    )
+}
+
+fn exclude_matcher(binaries: &Binaries) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in &binaries.exclude {
+        builder
+            .add_line(None, pattern)
+            .context(format!("invalid exclude pattern `{}`", pattern))?;
+    }
+    builder.build().context("failed to build exclude patterns")
+}
+
+/// Returns paths of all ELF files in the output directory relative to it.
+async fn find_elf_files(ctx: &Context<'_>) -> Result<Vec<String>> {
+    exec_in_out_dir(
+        ctx,
+        r#"find . -type f -exec sh -c 'head -c 4 "$1" | grep -q ELF && echo "$1"' _ {} \;"#,
+    )
+    .await
+    .map(|out| {
+        out.split('\n')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.trim_start_matches("./").to_string())
+            .collect()
+    })
+    .context("failed to find binaries")
+}
+
+async fn apply_rpath_policy(ctx: &Context<'_>, policy: RpathPolicy, file: &str) -> Result<()> {
+    if policy == RpathPolicy::Keep {
+        return Ok(());
+    }
+
+    let dynamic = exec_in_out_dir(ctx, &format!("readelf -d {}", file))
+        .await
+        .context(format!("failed to read dynamic section of `{}`", file))?;
+    let rpaths = parse_rpaths(&dynamic);
+    if rpaths.is_empty() {
+        return Ok(());
+    }
+    trace!(file = %file, rpaths = ?rpaths);
+
+    match policy {
+        RpathPolicy::Check => {
+            let invalid = rpaths
+                .iter()
+                .filter(|rpath| !rpath.starts_with("$ORIGIN"))
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            if !invalid.is_empty() {
+                return err!(
+                    "binary `{}` has RPATH entries not relative to $ORIGIN: {}",
+                    file,
+                    invalid.join(", ")
+                );
+            }
+        }
+        RpathPolicy::Clear => {
+            info!(file = %file, rpaths = ?rpaths, "removing RPATH");
+            exec_in_out_dir(ctx, &format!("patchelf --remove-rpath {}", file))
+                .await
+                .context(format!("failed to remove RPATH of `{}`", file))?;
+        }
+        RpathPolicy::Keep => {}
+    }
+
+    Ok(())
+}
+
+async fn exec_in_out_dir(ctx: &Context<'_>, cmd: &str) -> Result<String> {
+    ctx.checked_exec_with(
+        &ExecOpts::default()
+            .cmd(cmd)
+            .working_dir(&ctx.build.container_out_dir)
+            .build(),
+        true,
+    )
+    .await
+    .map(|out| out.stdout.join(""))
+}
+
+/// Returns all RPATH and RUNPATH entries from the output of `readelf -d`.
+fn parse_rpaths(dynamic: &str) -> Vec<String> {
+    dynamic
+        .lines()
+        .filter(|line| line.contains("(RPATH)") || line.contains("(RUNPATH)"))
+        .filter_map(|line| {
+            let start = line.find('[')?;
+            let end = line.rfind(']')?;
+            line.get(start + 1..end)
+        })
+        .flat_map(|paths| paths.split(':'))
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_rpaths() {
+        let dynamic = r#"
+Dynamic section at offset 0x2dc8 contains 28 entries:
+  Tag        Type                         Name/Value
+ 0x0000000000000001 (NEEDED)             Shared library: [libc.so.6]
+ 0x000000000000000f (RPATH)              Library rpath: [/home/build/lib]
+ 0x000000000000001d (RUNPATH)            Library runpath: [$ORIGIN/../lib:/usr/local/lib]
+"#;
+        assert_eq!(
+            parse_rpaths(dynamic),
+            vec!["/home/build/lib", "$ORIGIN/../lib", "/usr/local/lib"]
+        );
+        assert!(
+            parse_rpaths(" 0x0000000000000001 (NEEDED) Shared library: [libc.so.6]").is_empty()
+        );
+    }
+
+    #[test]
+    fn matches_excluded_files() {
+        let binaries = Binaries {
+            exclude: vec!["usr/lib/firmware/".to_string(), "*.ko".to_string()],
+            ..Default::default()
+        };
+        let exclude = exclude_matcher(&binaries).unwrap();
+        let is_excluded = |path: &str| exclude.matched_path_or_any_parents(path, false).is_ignore();
+
+        assert!(is_excluded("usr/lib/firmware/blob.bin"));
+        assert!(is_excluded("usr/lib/modules/driver.ko"));
+        assert!(!is_excluded("usr/bin/pkger"));
+    }
+}
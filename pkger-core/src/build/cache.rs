@@ -0,0 +1,140 @@
+use crate::blocking;
+use crate::build::container::Context;
+use crate::build::snapshot::{self, SNAPSHOT_PART_SIZE};
+use crate::{ErrContext, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, info_span, trace, warn, Instrument};
+
+/// Name of the directory in the system cache directory where pkger keeps its files.
+pub static DEFAULT_CACHE_DIR: &str = "pkger";
+
+/// Name of the parts of a snapshot in the directory of the snapshot.
+static SNAPSHOT_FILE: &str = "cache.tar";
+
+/// Returns the name of the snapshot directory for the given container directory.
+fn snapshot_name(dir: &Path) -> String {
+    dir.to_string_lossy().trim_matches('/').replace('/', "_")
+}
+
+/// Returns the directory on the host where snapshots of cache directories of this build are kept.
+fn snapshots_dir(ctx: &Context<'_>) -> PathBuf {
+    ctx.build
+        .cache_dir
+        .join("dirs")
+        .join(&ctx.build.recipe.metadata.name)
+        .join(ctx.build.target.image())
+}
+
+/// Restores previously saved snapshots of `cache_dirs` of the recipe into the container.
+pub async fn restore(ctx: &Context<'_>) -> Result<()> {
+    let span = info_span!("restore-cache-dirs");
+    async move {
+        if ctx.build.sandbox().is_some() {
+            trace!("cache directories are not shared with sandboxed builds, skipping");
+            return Ok(());
+        }
+        let snapshots_dir = snapshots_dir(ctx);
+
+        for dir in &ctx.build.recipe.metadata.cache_dirs {
+            let dir = Path::new(dir);
+            let snapshot = snapshots_dir.join(snapshot_name(dir));
+            if !snapshot.join(snapshot::part_name(SNAPSHOT_FILE, 0)).exists() {
+                trace!(dir = %dir.display(), "no snapshot found, skipping");
+                continue;
+            }
+            let parent = match dir.parent() {
+                Some(parent) if dir.is_absolute() => parent,
+                _ => {
+                    warn!(dir = %dir.display(), "only absolute paths are allowed in cache_dirs");
+                    continue;
+                }
+            };
+
+            info!(dir = %dir.display(), snapshot = %snapshot.display(), "restoring cache directory");
+            ctx.create_dirs(&[parent]).await?;
+            for index in 0.. {
+                let part = snapshot.join(snapshot::part_name(SNAPSHOT_FILE, index));
+                if !part.exists() {
+                    break;
+                }
+                let archive = blocking::run(move || {
+                    fs::read(&part).context("failed to read cache directory snapshot")
+                })
+                .await?;
+                ctx.container
+                    .extract_archive(archive, parent)
+                    .await
+                    .context("failed to extract cache directory snapshot")?;
+            }
+        }
+
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
+
+/// Saves snapshots of `cache_dirs` of the recipe from the container so that they can be restored
+/// in the next build.
+pub async fn save(ctx: &Context<'_>) -> Result<()> {
+    let span = info_span!("save-cache-dirs");
+    async move {
+        if ctx.build.sandbox().is_some() {
+            trace!("cache directories are not shared with sandboxed builds, skipping");
+            return Ok(());
+        }
+        let snapshots_dir = snapshots_dir(ctx);
+
+        for dir in &ctx.build.recipe.metadata.cache_dirs {
+            let dir = Path::new(dir);
+            if !dir.is_absolute() {
+                warn!(dir = %dir.display(), "only absolute paths are allowed in cache_dirs");
+                continue;
+            }
+
+            let name = snapshot_name(dir);
+            let snapshot = snapshots_dir.join(&name);
+            // the snapshot is written to a temporary directory first so that a failed download or
+            // an interrupted build doesn't replace the previous snapshot with an incomplete one
+            let tmp_dir = snapshots_dir.join(format!("{}.tmp", name));
+            if tmp_dir.exists() {
+                fs::remove_dir_all(&tmp_dir)
+                    .context("failed to remove incomplete cache directory snapshot")?;
+            }
+            fs::create_dir_all(&tmp_dir)
+                .context("failed to create directory for cache snapshots")?;
+
+            let download = tmp_dir.join(SNAPSHOT_FILE);
+            if let Err(e) = ctx.container.copy_from_file(dir, &download).await {
+                warn!(dir = %dir.display(), reason = %format!("{:?}", e), "failed to snapshot cache directory");
+                fs::remove_dir_all(&tmp_dir)
+                    .context("failed to remove incomplete cache directory snapshot")?;
+                continue;
+            }
+
+            info!(dir = %dir.display(), snapshot = %snapshot.display(), "saving cache directory");
+            {
+                let tmp_dir = tmp_dir.clone();
+                blocking::run(move || {
+                    let archive = fs::File::open(&download)
+                        .context("failed to open cache directory snapshot")?;
+                    snapshot::split(archive, &tmp_dir, SNAPSHOT_FILE, SNAPSHOT_PART_SIZE)?;
+                    fs::remove_file(&download)
+                        .context("failed to remove downloaded cache directory snapshot")
+                })
+                .await?;
+            }
+            if snapshot.exists() {
+                fs::remove_dir_all(&snapshot)
+                    .context("failed to remove previous cache directory snapshot")?;
+            }
+            fs::rename(&tmp_dir, &snapshot).context("failed to save cache directory snapshot")?;
+        }
+
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
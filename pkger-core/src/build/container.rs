@@ -1,15 +1,22 @@
 use crate::build;
-use crate::container::{fix_name, DockerContainer, ExecOpts, Output};
+use crate::build::info::BuildInfo;
+use crate::container::{fix_name, DockerContainer, ExecOpts, Output, DEFAULT_SHELL};
 use crate::docker::{api::ContainerCreateOpts, ExecContainerOpts};
 use crate::image::ImageState;
+use crate::security;
 use crate::ssh;
+use crate::text;
 use crate::{err, ErrContext, Error, Result};
 
 use crate::recipe::Env;
+use std::borrow::Cow;
 use std::path::Path;
-use tracing::{info_span, trace, Instrument};
+use tracing::{info_span, trace, warn, Instrument};
 
 pub static SESSION_LABEL_KEY: &str = "pkger.session";
+/// Directories that stay writable in containers with a read-only root filesystem, the build
+/// directories live in `/tmp` and packages are built in the home directory of root.
+static WRITABLE_DIRS: &[&str] = &["/tmp", "/var/tmp", "/root"];
 
 macro_rules! _exec {
     ($cmd: expr) => {
@@ -37,10 +44,12 @@ macro_rules! exec {
 
 // https://github.com/rust-lang/rust-clippy/issues/7271
 #[allow(clippy::needless_lifetimes)]
-/// Creates and starts a container from the given ImageState
+/// Creates and starts a container from the given ImageState. Containers that cache dependencies
+/// of the image need a `writable_rootfs` even if the security settings make it read-only.
 pub async fn spawn<'ctx>(
     ctx: &'ctx build::Context,
     image_state: &ImageState,
+    writable_rootfs: bool,
 ) -> Result<Context<'ctx>> {
     let span = info_span!("init-container-ctx");
     async move {
@@ -48,9 +57,14 @@ pub async fn spawn<'ctx>(
 
         let mut volumes = Vec::new();
 
-        let mut env = ctx.recipe.env.clone();
+        // variables of the recipe take precedence over the ones forwarded from the host
+        let mut env = forwarded_env(ctx);
+        for (key, value) in ctx.recipe.env.iter() {
+            env.insert(key, value);
+        }
         env.insert("PKGER_BLD_DIR", ctx.container_bld_dir.to_string_lossy());
         env.insert("PKGER_OUT_DIR", ctx.container_out_dir.to_string_lossy());
+        env.insert("PKGER_PREFIX", &ctx.recipe.metadata.prefix);
         env.insert("PKGER_OS", image_state.os.name());
         env.insert("PKGER_OS_VERSION", image_state.os.version());
         env.insert("RECIPE", &ctx.recipe.metadata.name);
@@ -72,38 +86,150 @@ pub async fn spawn<'ctx>(
 
         trace!(env = ?env);
 
-        let opts = ContainerCreateOpts::builder(&image_state.id)
+        let image_target = ctx.target.image_target();
+        let entrypoint = image_target
+            .entrypoint
+            .clone()
+            .unwrap_or_else(|| vec![DEFAULT_SHELL.to_string(), "-c".to_string()]);
+        let cmd = image_target
+            .cmd
+            .clone()
+            .unwrap_or_else(|| vec!["sleep infinity".to_string()]);
+        trace!(entrypoint = ?entrypoint, cmd = ?cmd);
+
+        let security = ctx.security().resolve(apparmor_enabled(ctx).await);
+        let mut read_only_rootfs = security.read_only_rootfs == Some(true) && !writable_rootfs;
+        if read_only_rootfs && !ctx.pkger_deps.is_empty() {
+            warn!("keeping the root filesystem writable to install packages built by pkger");
+            read_only_rootfs = false;
+        }
+        if read_only_rootfs {
+            volumes.extend(WRITABLE_DIRS.iter().map(|dir| dir.to_string()));
+        }
+
+        let mut builder = ContainerCreateOpts::builder(&image_state.id)
             .name(fix_name(&ctx.id))
-            .cmd(["sleep infinity"])
-            .entrypoint(["/bin/sh", "-c"])
+            .cmd(cmd)
+            .entrypoint(entrypoint)
             .labels([(SESSION_LABEL_KEY, ctx.session_id.to_string())])
             .volumes(volumes)
             .env(env.clone().kv_vec())
-            .working_dir(ctx.container_bld_dir.to_string_lossy())
-            .build();
+            .working_dir(ctx.container_bld_dir.to_string_lossy());
+        if let Some(signal) = &image_target.stop_signal {
+            builder = builder.stop_signal(signal);
+        }
+        let network = ctx.network();
+        if let Some(mode) = &network.mode {
+            builder = builder.network_mode(mode);
+        }
+        if !network.dns.is_empty() {
+            builder = builder.dns(&network.dns);
+        }
+        if !network.dns_search.is_empty() {
+            builder = builder.dns_search(&network.dns_search);
+        }
+        if !network.extra_hosts.is_empty() {
+            builder = builder.extra_hosts(&network.extra_hosts);
+        }
+        let security_options = security.security_options()?;
+        if !security_options.is_empty() {
+            trace!(security_options = ?security_options);
+            builder = builder.security_options(security_options);
+        }
+        if let Some(caps) = &security.cap_drop {
+            builder = builder.cap_drop(caps);
+        }
+        if let Some(caps) = &security.cap_add {
+            builder = builder.capabilities(caps);
+        }
+        if read_only_rootfs {
+            builder = builder.readonly_rootfs(true);
+        }
+        let opts = builder.build();
 
         let mut ctx = Context::new(ctx, opts);
+        if let Some(signal) = &image_target.stop_signal {
+            ctx.container.set_stop_signal(signal);
+        }
         ctx.set_env(env);
-        ctx.container.spawn(&ctx.opts).await.map(|_| ctx)
+        ctx.container.spawn(&ctx.opts).await?;
+        ctx.container.healthcheck().await.context(format!(
+            "image `{}` is not suitable for builds",
+            image_state.image
+        ))?;
+
+        // package managers can't be used with a read-only root filesystem so they don't need to
+        // be configured
+        if let Some(script) = ctx
+            .build
+            .network()
+            .ipv6_script()
+            .filter(|_| !read_only_rootfs)
+        {
+            trace!(script = %script, "configuring network");
+            ctx.checked_exec(&ExecOpts::default().cmd(&script).user("root").build())
+                .await
+                .context("failed to configure network of the container")?;
+        }
+
+        Ok(ctx)
     }
     .instrument(span)
     .await
 }
 
+/// Returns whether the Docker daemon confines containers with AppArmor. Daemons that can't be
+/// inspected are assumed to not support it.
+async fn apparmor_enabled(ctx: &build::Context) -> bool {
+    match ctx.docker.info().await {
+        Ok(info) => security::apparmor_enabled(&info.security_options),
+        Err(e) => {
+            warn!(reason = %e, "failed to check if the Docker daemon supports AppArmor");
+            false
+        }
+    }
+}
+
+/// Returns the variables of the host forwarded to the container of the build.
+pub(crate) fn forwarded_env(ctx: &build::Context) -> Env {
+    let patterns = pass_env_patterns(&ctx.pass_env, &ctx.recipe.pass_env, ctx.sandbox().is_some());
+    Env::passthrough(&patterns, std::env::vars_os())
+}
+
+/// Returns the patterns of names of variables of the host forwarded to the container, the ones
+/// from the configuration followed by the ones from the recipe. Sandboxed builds don't forward any
+/// variables so that untrusted recipes can't read secrets of the host.
+fn pass_env_patterns<'a>(
+    config: &'a [String],
+    recipe: &'a [String],
+    sandboxed: bool,
+) -> Vec<&'a String> {
+    if sandboxed {
+        return vec![];
+    }
+    config.iter().chain(recipe).collect()
+}
+
 pub struct Context<'job> {
     pub container: DockerContainer<'job>,
     pub opts: ContainerCreateOpts,
     pub build: &'job build::Context,
     pub vars: Env,
+    pub build_info: BuildInfo,
 }
 
 impl<'job> Context<'job> {
     pub fn new(build: &'job build::Context, opts: ContainerCreateOpts) -> Context<'job> {
+        let mut container = DockerContainer::new(&build.docker);
+        if let Some(host) = &build.docker_host {
+            container.set_host(host);
+        }
         Context {
-            container: DockerContainer::new(&build.docker),
+            container,
             opts,
             build,
             vars: Env::new(),
+            build_info: BuildInfo::default(),
         }
     }
 
@@ -111,10 +237,28 @@ impl<'job> Context<'job> {
         self.vars = env;
     }
 
+    /// Sets the build info of the build making its variables available in templates and scripts.
+    pub fn set_build_info(&mut self, build_info: BuildInfo) {
+        for (key, value) in build_info.vars() {
+            self.vars.insert(key, value);
+        }
+        self.build_info = build_info;
+    }
+
     pub async fn checked_exec(&self, opts: &ExecContainerOpts) -> Result<Output<String>> {
+        self.checked_exec_with(opts, self.build.quiet).await
+    }
+
+    /// Same as [checked_exec](Context::checked_exec) but allows overriding the `quiet` setting of
+    /// the build.
+    pub async fn checked_exec_with(
+        &self,
+        opts: &ExecContainerOpts,
+        quiet: bool,
+    ) -> Result<Output<String>> {
         let span = info_span!("checked-exec");
         async move {
-            let out = self.container.exec(opts, self.build.quiet).await?;
+            let out = self.container.exec(opts, quiet).await?;
             if out.exit_code != 0 {
                 err!(
                     "command failed with exit code {}\nError:\n{}",
@@ -150,6 +294,45 @@ impl<'job> Context<'job> {
         .await
     }
 
+    /// Uploads text files to the `destination` directory in the container. Unless disabled with
+    /// `normalize_text` in the recipe, each file has to be valid UTF-8 and has its Windows line
+    /// endings converted to Unix ones.
+    pub async fn upload_text_files<E, P>(
+        &self,
+        files: Vec<(E, &[u8])>,
+        destination: P,
+    ) -> Result<()>
+    where
+        E: AsRef<Path>,
+        P: AsRef<Path>,
+    {
+        let span = info_span!("upload-text-files");
+        async move {
+            let normalize = self.build.recipe.metadata.normalize_text;
+            let files = files
+                .into_iter()
+                .map(|(path, data)| {
+                    if normalize {
+                        trace!(file = %path.as_ref().display(), "normalizing");
+                        text::normalize(&path.as_ref().to_string_lossy(), data)
+                            .map(|data| (path, data))
+                    } else {
+                        Ok((path, Cow::Borrowed(data)))
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            self.container
+                .upload_files(
+                    files.iter().map(|(path, data)| (path, data.as_ref())),
+                    destination,
+                )
+                .await
+        }
+        .instrument(span)
+        .await
+    }
+
     pub async fn create_dirs<P: AsRef<Path>>(&self, dirs: &[P]) -> Result<()> {
         let span = info_span!("create-dirs");
         async move {
@@ -172,3 +355,17 @@ impl<'job> Context<'job> {
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn sandboxed_builds_forward_no_variables() {
+        let config = vec!["CI".to_string()];
+        let recipe = vec!["*".to_string()];
+        assert_eq!(pass_env_patterns(&config, &recipe, false), vec!["CI", "*"]);
+        assert!(pass_env_patterns(&config, &recipe, true).is_empty());
+    }
+}
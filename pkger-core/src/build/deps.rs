@@ -1,21 +1,142 @@
 use crate::build::container::Context;
+use crate::container::ExecOpts;
 use crate::image::{Image, ImageState};
-use crate::recipe::{BuildTarget, Recipe};
+use crate::recipe::{pkger_recipe, BuildTarget, Recipe, RpathPolicy};
+use crate::{err, ErrContext, Error, Result};
 
+use sha2::{Digest, Sha256};
 use std::collections::HashSet;
+use std::fs;
+use tracing::{info, info_span, Instrument};
 
-pub fn recipe<'ctx>(ctx: &Context<'ctx>, state: &ImageState) -> HashSet<&'ctx str> {
-    if let Some(deps) = &ctx.build.recipe.metadata.build_depends {
-        let mut _deps = deps.resolve_names(&state.image);
-        let simple = Image::simple(*ctx.build.target.build_target()).1;
-        if state.image != simple {
+/// Returns a canonical hash of a set of dependencies that doesn't depend on their order.
+pub fn hash<'d>(deps: impl IntoIterator<Item = &'d str>) -> String {
+    let mut deps: Vec<_> = deps.into_iter().collect();
+    deps.sort_unstable();
+    deps.dedup();
+    let mut hasher = Sha256::new();
+    for dep in deps {
+        hasher.update(dep.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Returns a concise description of the changes from the `old` to the `new` set of dependencies
+/// like `added: cmake; removed: make`.
+pub fn diff(old: &HashSet<String>, new: &HashSet<&str>) -> String {
+    let mut added: Vec<_> = new
+        .iter()
+        .filter(|dep| !old.contains(**dep))
+        .copied()
+        .collect();
+    let mut removed: Vec<_> = old
+        .iter()
+        .map(String::as_str)
+        .filter(|dep| !new.contains(dep))
+        .collect();
+    added.sort_unstable();
+    removed.sort_unstable();
+
+    let mut changes = vec![];
+    if !added.is_empty() {
+        changes.push(format!("added: {}", added.join(", ")));
+    }
+    if !removed.is_empty() {
+        changes.push(format!("removed: {}", removed.join(", ")));
+    }
+    changes.join("; ")
+}
+
+/// Returns the dependencies of the `recipe` installed in the cached image of the `image`.
+/// Packages built by pkger are excluded as they are installed before each build instead.
+pub fn recipe<'r>(recipe: &'r Recipe, target: BuildTarget, image: &str) -> HashSet<&'r str> {
+    if let Some(deps) = &recipe.metadata.build_depends {
+        let mut _deps = deps.resolve_names(image);
+        let simple = Image::simple(target).1;
+        if image != simple {
             _deps.extend(deps.resolve_names(simple));
+            _deps.retain(|dep| pkger_recipe(dep).is_none());
             return _deps;
         }
     }
     HashSet::new()
 }
 
+/// Returns all dependencies installed in the cached image of the `image`, the default ones of
+/// the `target` and the ones of the `recipe`. Both the cached image and the check whether it is
+/// still up to date use this set.
+pub fn cached<'r>(
+    recipe: &'r Recipe,
+    target: BuildTarget,
+    image: &str,
+    enable_gpg: bool,
+) -> HashSet<&'r str> {
+    let mut deps: HashSet<&str> = default(&target, recipe, enable_gpg).into_iter().collect();
+    deps.extend(self::recipe(recipe, target, image));
+    deps
+}
+
+/// Returns names of recipes listed as `pkger:<recipe>` in `build_depends` of the `recipe` for
+/// the `image`. Their packages built for the same image are installed before running scripts.
+pub fn pkger_recipes<'r>(recipe: &'r Recipe, image: &str, target: BuildTarget) -> HashSet<&'r str> {
+    let deps = if let Some(deps) = &recipe.metadata.build_depends {
+        deps
+    } else {
+        return HashSet::new();
+    };
+    let mut names = deps.resolve_names(image);
+    names.extend(deps.resolve_names(Image::simple(target).1));
+    names.into_iter().filter_map(pkger_recipe).collect()
+}
+
+/// Uploads packages built by pkger that the recipe depends on to the container and installs
+/// them with the package manager of the image.
+pub async fn install_pkger_deps(ctx: &Context<'_>, state: &ImageState) -> Result<()> {
+    if ctx.build.pkger_deps.is_empty() {
+        return Ok(());
+    }
+    let span = info_span!("install-pkger-deps");
+    async move {
+        let dir = ctx.build.container_tmp_dir.join("pkger-deps");
+        ctx.create_dirs(&[dir.as_path()]).await?;
+
+        let mut names = vec![];
+        let mut files = vec![];
+        for package in &ctx.build.pkger_deps {
+            let name = match package.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => return err!("invalid package path `{}`", package.display()),
+            };
+            info!(package = %package.display(), "installing package built by pkger");
+            files.push((
+                name.clone(),
+                fs::read(package).context(format!("failed to read `{}`", package.display()))?,
+            ));
+            names.push(name);
+        }
+        ctx.container
+            .upload_files(
+                files.iter().map(|(name, data)| (name, data.as_slice())),
+                &dir,
+            )
+            .await
+            .context("failed to upload packages built by pkger")?;
+
+        let pkg_mngr = state.os.package_manager();
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&pkg_mngr.install_local_cmd(&dir, &names))
+                .build(),
+        )
+        .await
+        .map(|_| ())
+        .context("failed to install packages built by pkger")
+    }
+    .instrument(span)
+    .await
+}
+
 pub fn default(target: &BuildTarget, recipe: &Recipe, enable_gpg: bool) -> HashSet<&'static str> {
     let mut deps = HashSet::new();
     deps.insert("tar");
@@ -23,6 +144,15 @@ pub fn default(target: &BuildTarget, recipe: &Recipe, enable_gpg: bool) -> HashS
         BuildTarget::Rpm => {
             deps.insert("rpm-build");
             deps.insert("util-linux"); // for setarch
+            if recipe
+                .metadata
+                .rpm
+                .as_ref()
+                .map(|rpm| rpm.deltarpm)
+                .unwrap_or_default()
+            {
+                deps.insert("deltarpm");
+            }
 
             if enable_gpg {
                 deps.insert("gnupg2");
@@ -48,15 +178,23 @@ pub fn default(target: &BuildTarget, recipe: &Recipe, enable_gpg: bool) -> HashS
             deps.insert("sudo");
             deps.insert("bash");
         }
+        BuildTarget::Msi => {
+            deps.insert("wixl");
+        }
+        BuildTarget::Xbps => {
+            deps.insert("xbps");
+        }
     }
     if recipe.metadata.git.is_some() {
         deps.insert("git");
-    } else if let Some(src) = &recipe.metadata.source {
-        if src.starts_with("http") {
-            deps.insert("curl");
-        }
-        if src.ends_with(".zip") {
-            deps.insert("zip");
+    } else {
+        for src in &recipe.metadata.source {
+            if src.source().starts_with("http") {
+                deps.insert("curl");
+            }
+            if src.unpack() && src.source().ends_with(".zip") {
+                deps.insert("zip");
+            }
         }
     }
 
@@ -64,5 +202,86 @@ pub fn default(target: &BuildTarget, recipe: &Recipe, enable_gpg: bool) -> HashS
         deps.insert("patch");
     }
 
+    if !recipe.metadata.man_pages.is_empty() {
+        deps.insert("gzip");
+    }
+
+    if let Some(binaries) = &recipe.metadata.binaries {
+        if binaries.strip || binaries.rpath != RpathPolicy::Keep {
+            deps.insert("binutils");
+        }
+        if binaries.rpath == RpathPolicy::Clear || !binaries.patchelf.is_empty() {
+            deps.insert("patchelf");
+        }
+    }
+
     deps
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn hashes_and_diffs_deps() {
+        assert_eq!(
+            hash(["make", "gcc", "git"]),
+            hash(["git", "make", "gcc", "git"])
+        );
+        assert_ne!(hash(["make", "gcc"]), hash(["make", "gcc", "git"]));
+        assert_ne!(hash(["ab", "c"]), hash(["a", "bc"]));
+
+        let old: HashSet<String> = ["gcc", "make", "git"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let new: HashSet<&str> = ["gcc", "cmake", "git", "ninja"].into_iter().collect();
+        assert_eq!(diff(&old, &new), "added: cmake, ninja; removed: make");
+        assert_eq!(
+            diff(&old, &["gcc", "git"].into_iter().collect()),
+            "removed: make"
+        );
+        assert_eq!(
+            diff(&old, &["gcc", "git", "make"].into_iter().collect()),
+            ""
+        );
+    }
+
+    #[test]
+    fn cached_deps_exclude_pkger_packages() {
+        let rep = crate::recipe::RecipeRep::from_yaml_bytes(
+            br#"
+metadata:
+  name: pkger
+  version: 0.1.0
+  description: pkger
+  license: MIT
+  build_depends:
+    all: [gcc]
+    pkger-rpm: [rpm-devel]
+    rocky: [cmake, "pkger:libpkger"]
+build:
+  steps: []
+"#,
+        )
+        .unwrap();
+        let recipe = Recipe::new(rep, std::path::PathBuf::new()).unwrap();
+
+        let mut deps: Vec<_> = cached(&recipe, BuildTarget::Rpm, "rocky", false)
+            .into_iter()
+            .collect();
+        deps.sort_unstable();
+        assert_eq!(
+            deps,
+            vec![
+                "cmake",
+                "gcc",
+                "rpm-build",
+                "rpm-devel",
+                "tar",
+                "util-linux"
+            ]
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use crate::build::container::Context;
+use crate::container::ExecOpts;
+use crate::recipe::BuildTarget;
+use crate::shell;
+use crate::{ErrContext, Result};
+
+use std::path::{Path, PathBuf};
+use tracing::{info, info_span, trace, warn, Instrument};
+
+/// Installs license files, documentation, man pages and shell completions of the recipe from the
+/// build directory to the output directory following the conventions of the build target.
+pub async fn install(ctx: &Context<'_>) -> Result<()> {
+    let span = info_span!("install-docs");
+    async move {
+        install_license_and_doc_files(ctx).await?;
+        install_man_pages(ctx).await?;
+        install_completions(ctx).await
+    }
+    .instrument(span)
+    .await
+}
+
+/// Installs `license_files` and `doc_files` of the recipe. DEB packages get a single `copyright`
+/// file in the documentation directory with all licenses, other targets get the files in
+/// `<prefix>/share/licenses/<name>/`.
+async fn install_license_and_doc_files(ctx: &Context<'_>) -> Result<()> {
+    let metadata = &ctx.build.recipe.metadata;
+    if metadata.license_files.is_empty() && metadata.doc_files.is_empty() {
+        trace!("no license or doc files to install");
+        return Ok(());
+    }
+
+    let is_deb = matches!(ctx.build.target.build_target(), BuildTarget::Deb);
+    let doc_dir = if is_deb {
+        format!(
+            "{}/share/doc/{}",
+            metadata.prefix,
+            metadata.name.replace('_', "-")
+        )
+    } else {
+        metadata.doc_dir()
+    };
+    let doc_dir = out_path(ctx, &doc_dir);
+
+    if !metadata.license_files.is_empty() {
+        let cmd = if is_deb {
+            deb_copyright_cmd(&metadata.license_files, &doc_dir)
+        } else {
+            copy_cmd(
+                &metadata.license_files,
+                &out_path(ctx, &metadata.license_dir()),
+            )
+        };
+        info!(files = ?metadata.license_files, "installing license files");
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&cmd)
+                .working_dir(&ctx.build.container_bld_dir)
+                .build(),
+        )
+        .await
+        .context("failed to install license files")?;
+    }
+
+    if !metadata.doc_files.is_empty() {
+        info!(files = ?metadata.doc_files, "installing doc files");
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&copy_cmd(&metadata.doc_files, &doc_dir))
+                .working_dir(&ctx.build.container_bld_dir)
+                .build(),
+        )
+        .await
+        .context("failed to install doc files")?;
+    }
+
+    Ok(())
+}
+
+/// Compresses `man_pages` of the recipe with `gzip -9n` and installs them to
+/// `<prefix>/share/man/man<section>/`. Pages that are already compressed are copied as is.
+async fn install_man_pages(ctx: &Context<'_>) -> Result<()> {
+    for page in &ctx.build.recipe.metadata.man_pages {
+        let section = if let Some(section) = man_section(page) {
+            section
+        } else {
+            warn!(page = %page, "man page has no section extension like `.1`, skipping");
+            continue;
+        };
+        let file_name = Path::new(page)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| page.to_string());
+        let man_dir = out_path(
+            ctx,
+            &format!(
+                "{}/share/man/man{}",
+                ctx.build.recipe.metadata.prefix, section
+            ),
+        );
+
+        let cmd = if page.ends_with(".gz") {
+            format!(
+                "mkdir -p {0} && cp -v {1} {0}/",
+                quote_path(&man_dir),
+                shell::quote(page)
+            )
+        } else {
+            format!(
+                "mkdir -p {} && gzip -9nc {} > {}",
+                quote_path(&man_dir),
+                shell::quote(page),
+                quote_path(&man_dir.join(format!("{}.gz", file_name)))
+            )
+        };
+
+        info!(page = %page, section = %section, "installing man page");
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&cmd)
+                .working_dir(&ctx.build.container_bld_dir)
+                .build(),
+        )
+        .await
+        .context("failed to install man page")?;
+    }
+
+    Ok(())
+}
+
+/// Installs `completions` of the recipe to the completion directories of each shell. The scripts
+/// are named after the recipe and always installed under `/usr` where shells look for them.
+async fn install_completions(ctx: &Context<'_>) -> Result<()> {
+    let metadata = &ctx.build.recipe.metadata;
+    let completions = if let Some(completions) = &metadata.completions {
+        completions
+    } else {
+        return Ok(());
+    };
+
+    let zsh_dir = if matches!(ctx.build.target.build_target(), BuildTarget::Deb) {
+        "/usr/share/zsh/vendor-completions"
+    } else {
+        "/usr/share/zsh/site-functions"
+    };
+    let scripts = [
+        (
+            "bash",
+            &completions.bash,
+            format!("/usr/share/bash-completion/completions/{}", metadata.name),
+        ),
+        (
+            "zsh",
+            &completions.zsh,
+            format!("{}/_{}", zsh_dir, metadata.name),
+        ),
+        (
+            "fish",
+            &completions.fish,
+            format!(
+                "/usr/share/fish/vendor_completions.d/{}.fish",
+                metadata.name
+            ),
+        ),
+    ];
+
+    for (shell, script, path) in scripts {
+        let script = if let Some(script) = script {
+            script
+        } else {
+            continue;
+        };
+
+        info!(shell = %shell, script = %script, path = %path, "installing completions");
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "install -Dm644 {} {}",
+                    shell::quote(script),
+                    quote_path(&out_path(ctx, &path))
+                ))
+                .working_dir(&ctx.build.container_bld_dir)
+                .build(),
+        )
+        .await
+        .context("failed to install completions")?;
+    }
+
+    Ok(())
+}
+
+/// Returns the command that copies `files` relative to the build directory to `dir`.
+fn copy_cmd(files: &[String], dir: &Path) -> String {
+    format!(
+        "mkdir -p {0} && cp -rv {1} {0}/",
+        quote_path(dir),
+        quote_all(files)
+    )
+}
+
+/// Returns the command that writes the `copyright` file of a DEB package to `dir`. A single
+/// license file is copied as is, multiple ones are joined with the name of each file above its
+/// content so that every license is kept.
+fn deb_copyright_cmd(files: &[String], dir: &Path) -> String {
+    let content = match files {
+        [file] => format!("cat {}", shell::quote(file)),
+        files => format!(
+            "{{ {}; }}",
+            files
+                .iter()
+                .map(|file| format!(
+                    "echo {}; echo; cat {}",
+                    shell::quote(&format!("{}:", file)),
+                    shell::quote(file)
+                ))
+                .collect::<Vec<_>>()
+                .join("; echo; ")
+        ),
+    };
+    format!(
+        "mkdir -p {} && {} > {}",
+        quote_path(dir),
+        content,
+        quote_path(&dir.join("copyright"))
+    )
+}
+
+/// Quotes each of the `files` so that paths with spaces are passed as single words.
+fn quote_all(files: &[String]) -> String {
+    files
+        .iter()
+        .map(|file| shell::quote(file))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes the `path` of a directory or file in the container.
+fn quote_path(path: &Path) -> String {
+    shell::quote(&path.to_string_lossy())
+}
+
+/// Returns the section of the man `page` based on its extension, for example `1` for `pkger.1`
+/// or `3` for `Foo::Bar.3pm.gz`.
+fn man_section(page: &str) -> Option<char> {
+    let page = page.strip_suffix(".gz").unwrap_or(page);
+    let (_, extension) = page.rsplit_once('.')?;
+    extension.chars().next().filter(|c| c.is_ascii_digit())
+}
+
+/// Returns the absolute `path` of the package inside of the output directory of the container.
+fn out_path(ctx: &Context<'_>, path: &str) -> PathBuf {
+    ctx.build
+        .container_out_dir
+        .join(Path::new(path).strip_prefix("/").unwrap_or(Path::new(path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn finds_man_section() {
+        assert_eq!(man_section("doc/pkger.1"), Some('1'));
+        assert_eq!(man_section("Foo::Bar.3pm.gz"), Some('3'));
+        assert_eq!(man_section("pkger.conf.5"), Some('5'));
+        assert_eq!(man_section("README.md"), None);
+        assert_eq!(man_section("pkger"), None);
+    }
+
+    #[test]
+    fn quotes_paths_of_license_and_doc_files() {
+        let dir = Path::new("/tmp/out/usr/share/doc/my pkg");
+        let files = vec!["LICENSE".to_string(), "docs/User Guide.md".to_string()];
+        assert_eq!(
+            copy_cmd(&files, dir),
+            "mkdir -p '/tmp/out/usr/share/doc/my pkg' && cp -rv 'LICENSE' 'docs/User Guide.md' \
+             '/tmp/out/usr/share/doc/my pkg'/"
+        );
+        assert_eq!(
+            deb_copyright_cmd(&files[..1], dir),
+            "mkdir -p '/tmp/out/usr/share/doc/my pkg' && cat 'LICENSE' \
+             > '/tmp/out/usr/share/doc/my pkg/copyright'"
+        );
+        assert_eq!(
+            deb_copyright_cmd(&files, dir),
+            "mkdir -p '/tmp/out/usr/share/doc/my pkg' && { echo 'LICENSE:'; echo; cat 'LICENSE'; \
+             echo; echo 'docs/User Guide.md:'; echo; cat 'docs/User Guide.md'; } \
+             > '/tmp/out/usr/share/doc/my pkg/copyright'"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use crate::build::container::Context;
+use crate::container::ExecOpts;
+use crate::recipe::{BuildTarget, FileAttributes, Symlink};
+use crate::shell;
+use crate::{ErrContext, Result};
+
+use tracing::{info, info_span, trace, warn, Instrument};
+
+/// Creates the symbolic links declared in the recipe in the output directory in the container.
+/// Existing files at the paths of the links are replaced.
+pub async fn create_symlinks(ctx: &Context<'_>) -> Result<()> {
+    let symlinks = &ctx.build.recipe.metadata.symlinks;
+    if symlinks.is_empty() {
+        return Ok(());
+    }
+    let span = info_span!("create-symlinks");
+    async move {
+        info!(symlinks = %symlinks.len(), "creating symlinks");
+        let script = symlinks_script(symlinks);
+        trace!(script = %script);
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&script)
+                .working_dir(&ctx.build.container_out_dir)
+                .build(),
+        )
+        .await
+        .map(|_| ())
+        .context("failed to create symlinks")
+    }
+    .instrument(span)
+    .await
+}
+
+/// Applies the modes of the `files` section of the recipe to the output directory in the container
+/// so that they are packaged with the files. DEB and RPM packages set the owners and groups when
+/// installed as the accounts usually don't exist in the build container, other targets apply
+/// owners and groups given as numeric ids in the container.
+pub async fn apply(ctx: &Context<'_>) -> Result<()> {
+    let files = &ctx.build.recipe.metadata.files;
+    if files.is_empty() {
+        return Ok(());
+    }
+    let set_ownership = !matches!(
+        ctx.build.target.build_target(),
+        BuildTarget::Deb | BuildTarget::Rpm
+    );
+    let span = info_span!("apply-file-attributes");
+    async move {
+        if set_ownership {
+            for entry in files
+                .iter()
+                .filter(|entry| entry.ownership().is_some() && !entry.has_numeric_ownership())
+            {
+                warn!(path = %entry.path, "owners and groups of files have to be numeric ids for this target, skipping");
+            }
+        }
+        let script = apply_script(files, set_ownership);
+        if script.is_empty() {
+            return Ok(());
+        }
+        info!(entries = %files.len(), "applying file attributes");
+        trace!(script = %script);
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&script)
+                .working_dir(&ctx.build.container_out_dir)
+                .build(),
+        )
+        .await
+        .map(|_| ())
+        .context("failed to apply owners and modes of files")
+    }
+    .instrument(span)
+    .await
+}
+
+/// Returns the shell commands that apply the modes of `entries` to their exact paths in the output
+/// directory in order. With `set_ownership` the numeric owners and groups of entries are applied
+/// to the paths and everything below them first, after owning all files by root so that files not
+/// listed don't keep the ids they had in the build. Modes come last as changing the owner clears
+/// setuid and setgid bits.
+fn apply_script(entries: &[FileAttributes], set_ownership: bool) -> String {
+    let path = |entry: &FileAttributes| shell::quote(&format!(".{}", entry.path));
+    let mut commands = vec![];
+    if set_ownership {
+        let owners: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.has_numeric_ownership())
+            .filter_map(|entry| {
+                entry
+                    .ownership()
+                    .map(|ownership| format!("chown -R {} {}", ownership, path(entry)))
+            })
+            .collect();
+        if !owners.is_empty() {
+            commands.push("chown -R 0:0 .".to_string());
+            commands.extend(owners);
+        }
+    }
+    for entry in entries {
+        if let Some(mode) = &entry.mode {
+            commands.push(format!("chmod {} {}", mode, path(entry)));
+        }
+    }
+    commands.join(" && ")
+}
+
+/// Returns the shell commands that create `symlinks` in the output directory with targets
+/// relative to the directories of the links. Existing files and links at the paths are replaced
+/// while directories fail the script so that a mistyped link can't delete a whole directory.
+fn symlinks_script(symlinks: &[Symlink]) -> String {
+    symlinks
+        .iter()
+        .map(|symlink| {
+            let link = shell::quote(&format!(".{}", symlink.link));
+            let dir = symlink
+                .link
+                .rsplit_once('/')
+                .map(|(dir, _)| format!(".{}", dir))
+                .unwrap_or_else(|| ".".to_string());
+            format!(
+                "mkdir -p {dir} && {{ [ -L {link} ] || [ ! -d {link} ] || {{ echo {error} >&2; false; }}; }} \
+                 && ln -sfn {target} {link}",
+                dir = shell::quote(&dir),
+                link = link,
+                error = shell::quote(&format!("symlink path {} is a directory", symlink.link)),
+                target = shell::quote(&symlink.relative_target()),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" && ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn renders_apply_script() {
+        let entry = |path: &str, owner: Option<&str>, group: Option<&str>, mode: Option<&str>| {
+            FileAttributes {
+                path: path.to_string(),
+                owner: owner.map(String::from),
+                group: group.map(String::from),
+                mode: mode.map(String::from),
+            }
+        };
+        let entries = [
+            entry("/etc/pkger", Some("pkger"), Some("pkger"), Some("0750")),
+            entry("/var/lib/pkger", Some("1000"), Some("4"), None),
+            entry("/usr/bin/pkger's helper", None, None, Some("4755")),
+        ];
+        assert_eq!(
+            apply_script(&entries, true),
+            "chown -R 0:0 . && chown -R 1000:4 './var/lib/pkger' && chmod 0750 './etc/pkger' \
+             && chmod 4755 './usr/bin/pkger'\\''s helper'"
+        );
+        assert_eq!(
+            apply_script(&entries, false),
+            "chmod 0750 './etc/pkger' && chmod 4755 './usr/bin/pkger'\\''s helper'"
+        );
+        assert_eq!(apply_script(&entries[1..2], false), "");
+    }
+
+    #[test]
+    fn renders_symlinks_script() {
+        let symlink = |link: &str, target: &str| Symlink {
+            link: link.to_string(),
+            target: target.to_string(),
+        };
+        assert_eq!(
+            symlinks_script(&[
+                symlink("/usr/bin/pkger", "/opt/pkger/bin/pkger"),
+                symlink("/libpkger.so", "libpkger.so.1"),
+            ]),
+            "mkdir -p './usr/bin' && { [ -L './usr/bin/pkger' ] || [ ! -d './usr/bin/pkger' ] \
+             || { echo 'symlink path /usr/bin/pkger is a directory' >&2; false; }; } \
+             && ln -sfn '../../opt/pkger/bin/pkger' './usr/bin/pkger' \
+             && mkdir -p '.' && { [ -L './libpkger.so' ] || [ ! -d './libpkger.so' ] \
+             || { echo 'symlink path /libpkger.so is a directory' >&2; false; }; } \
+             && ln -sfn 'libpkger.so.1' './libpkger.so'"
+        );
+    }
+}
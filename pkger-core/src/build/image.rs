@@ -19,32 +19,43 @@ use tracing::{debug, info, info_span, trace, warn, Instrument};
 pub static CACHED: &str = "cached";
 pub static LATEST: &str = "latest";
 
+/// Returns the tag of an image specific to the given `project` so that images of different
+/// projects don't overwrite each other.
+pub fn project_tag(tag: &str, project: Option<&str>) -> String {
+    match project {
+        Some(project) => format!("{}-{}", tag, project),
+        None => tag.to_string(),
+    }
+}
+
 pub async fn build(ctx: &mut Context) -> Result<ImageState> {
     let span = info_span!("image-build");
     async move {
-        let mut deps = if let Some(deps) = &ctx.recipe.metadata.build_depends {
-            deps.resolve_names(ctx.target.image())
-        } else {
-            Default::default()
-        };
-        deps.extend(deps::default(
-            ctx.target.build_target(),
+        let deps = deps::cached(
             &ctx.recipe,
+            *ctx.target.build_target(),
+            ctx.target.image(),
             ctx.gpg_key.is_some(),
-        ));
+        );
         trace!(resolved_deps = ?deps);
 
+        let latest = project_tag(LATEST, ctx.project.as_deref());
         let state =
             find_cached_state(&ctx.image.path, &ctx.target, &ctx.image_state, ctx.simple).await;
 
         if let Some(state) = state {
-            let state_deps = state
-                .deps
-                .iter()
-                .map(|s| s.as_str())
-                .collect::<HashSet<_>>();
-            if deps != state_deps {
-                info!(old = ?state.deps, new = ?deps, "dependencies changed");
+            // states saved by previous versions have no hash of dependencies
+            let deps_changed = match &state.deps_hash {
+                Some(hash) => hash != &deps::hash(deps.iter().copied()),
+                None => {
+                    state.deps.len() != deps.len()
+                        || !deps.iter().all(|dep| state.deps.contains(*dep))
+                }
+            };
+            if deps_changed {
+                info!(changes = %deps::diff(&state.deps, &deps), "dependencies changed");
+            } else if state.recipe_hash.as_ref() != Some(&ctx.recipe.hash) {
+                info!("recipe changed");
             } else {
                 trace!("unchanged");
 
@@ -52,16 +63,35 @@ pub async fn build(ctx: &mut Context) -> Result<ImageState> {
                     trace!("image state exists in docker, reusing");
                     return Ok(state);
                 } else {
-                    warn!("found cached state but image doesn't exist in docker")
+                    warn!("found cached state but image doesn't exist in docker, repairing it");
+                    // the stale state is dropped so that it is only rebuilt once even if this
+                    // build fails
+                    ctx.image_state.write().await.remove(&ctx.target);
+                    if let Some(state) = find_base_image(ctx, &latest).await? {
+                        return Ok(state);
+                    }
                 }
             }
         }
 
         debug!(image = %ctx.target.image(), "building from scratch");
         let images = ctx.docker.images();
-        let opts = BuildOpts::builder(&ctx.image.path)
-            .tag(&format!("{}:{}", &ctx.target.image(), LATEST))
-            .build();
+        let mut opts =
+            BuildOpts::builder(&ctx.image.path).tag(&format!("{}:{}", &ctx.target.image(), latest));
+        // variants are built from the same directory with different build args
+        let build_args = &ctx.target.image_target().build_args;
+        if !build_args.is_empty() {
+            trace!(build_args = ?build_args);
+            opts = opts.buildargs(build_args);
+        }
+        let network = ctx.network();
+        if let Some(mode) = &network.mode {
+            opts = opts.network_mode(mode);
+        }
+        if !network.extra_hosts.is_empty() {
+            opts = opts.extra_hosts(&network.extra_hosts);
+        }
+        let opts = opts.build();
 
         let mut stream = images.build(&opts);
 
@@ -83,7 +113,7 @@ pub async fn build(ctx: &mut Context) -> Result<ImageState> {
                     let state = ImageState::new(
                         &aux.id,
                         &ctx.target,
-                        LATEST,
+                        &latest,
                         &SystemTime::now(),
                         &ctx.docker,
                         &Default::default(),
@@ -106,6 +136,34 @@ pub async fn build(ctx: &mut Context) -> Result<ImageState> {
     .await
 }
 
+/// Returns the state of the image built from the directory of the image if it still exists in
+/// docker so that only the cached image with dependencies has to be built again.
+async fn find_base_image(ctx: &Context, latest: &str) -> Result<Option<ImageState>> {
+    let tag = format!("{}:{}", ctx.target.image(), latest);
+    let details = match ctx.docker.images().get(&tag).inspect().await {
+        Ok(details) => details,
+        Err(e) => {
+            trace!(image = %tag, reason = %e, "base image not found");
+            return Ok(None);
+        }
+    };
+    let state = ImageState::new(
+        &details.id,
+        &ctx.target,
+        latest,
+        &SystemTime::now(),
+        &ctx.docker,
+        &Default::default(),
+        ctx.simple,
+    )
+    .await?;
+    info!(image = %tag, id = %state.id, "reusing existing base image");
+
+    let mut image_state = ctx.image_state.write().await;
+    (*image_state).update(ctx.target.clone(), state.clone());
+    Ok(Some(state))
+}
+
 pub async fn create_cache(
     ctx: &container::Context<'_>,
     docker: &Docker,
@@ -127,15 +185,23 @@ pub async fn create_cache(
 
         let deps_joined = deps.iter().map(|s| s.to_string()).collect::<Vec<_>>();
 
+        // package managers are configured once as their configuration persists in the image
+        let network = ctx.build.network();
+        let setup = network
+            .ipv6_script()
+            .map(|script| format!("RUN {}\n", script))
+            .unwrap_or_default();
+
         #[rustfmt::skip]
             let dockerfile = format!(
 r#"FROM {}
 ENV DEBIAN_FRONTEND noninteractive
-RUN {} {}
+{}RUN {}
 RUN {} {}
 RUN {} {} {}"#,
                 tag,
-                pkg_mngr_name, pkg_mngr.clean_cache().join(" "),
+                setup,
+                pkg_mngr.clean_cache_cmd(),
                 pkg_mngr_name, pkg_mngr.update_repos_args().join(" "),
                 pkg_mngr_name, pkg_mngr.install_args().join(" "), deps_joined.join(" ")
             );
@@ -156,9 +222,15 @@ RUN {} {} {}"#,
         fs::write(temp_path.join("Dockerfile"), dockerfile)?;
 
         let images = docker.images();
-        let opts = BuildOpts::builder(&temp_path)
-            .tag(format!("{}:{}", state.image, CACHED))
-            .build();
+        let cached = project_tag(CACHED, ctx.build.project.as_deref());
+        let mut opts = BuildOpts::builder(&temp_path).tag(format!("{}:{}", state.image, cached));
+        if let Some(mode) = &network.mode {
+            opts = opts.network_mode(mode);
+        }
+        if !network.extra_hosts.is_empty() {
+            opts = opts.extra_hosts(&network.extra_hosts);
+        }
+        let opts = opts.build();
 
         let mut stream = images.build(&opts);
 
@@ -180,7 +252,7 @@ RUN {} {} {}"#,
                     return ImageState::new(
                         &aux.id,
                         &ctx.build.target,
-                        CACHED,
+                        &cached,
                         &SystemTime::now(),
                         docker,
                         deps,
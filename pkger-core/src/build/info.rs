@@ -0,0 +1,285 @@
+use crate::build::container::Context;
+use crate::container::ExecOpts;
+use crate::recipe::BuildTarget;
+use crate::{ErrContext, Result};
+
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tracing::{debug, info, info_span, trace, Instrument};
+
+/// Version of pkger recorded in packages
+pub const PKGER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Origin of a package exposed to the scripts of the recipe and optionally recorded in the package
+pub struct BuildInfo {
+    /// Commit of the git repository of the source
+    pub commit: Option<String>,
+    /// Branch of the git repository of the source
+    pub branch: Option<String>,
+    /// Whether tracked files of the source had uncommitted changes
+    pub dirty: bool,
+    /// URL of the git repository defined in the recipe
+    pub repository: Option<String>,
+    /// Time of the build in the RFC 3339 format
+    pub build_time: String,
+    /// Major and minor version of `rpm` in the image, only detected for RPM builds
+    pub rpm_version: Option<(u32, u32)>,
+}
+
+/// The first version of RPM that accepts the `VCS` tag
+const RPM_VCS_MIN_VERSION: (u32, u32) = (4, 15);
+
+impl BuildInfo {
+    /// Returns the commit with a `-dirty` suffix if the source had uncommitted changes.
+    pub fn describe_commit(&self) -> Option<String> {
+        self.commit.as_ref().map(|commit| {
+            if self.dirty {
+                format!("{}-dirty", commit)
+            } else {
+                commit.to_string()
+            }
+        })
+    }
+
+    /// Returns the variables available in scripts and templates of the recipe.
+    pub fn vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![("PKGER_BUILD_TIME", self.build_time.clone())];
+        if let Some(commit) = &self.commit {
+            vars.push(("PKGER_GIT_COMMIT", commit.clone()));
+            vars.push(("PKGER_GIT_DIRTY", self.dirty.to_string()));
+        }
+        if let Some(branch) = &self.branch {
+            vars.push(("PKGER_GIT_BRANCH", branch.clone()));
+        }
+        vars
+    }
+
+    /// Returns the location of the source in the format of the `VCS` tag of RPM. Returns `None`
+    /// if the `rpm` of the image is older than 4.15 and would reject the tag.
+    pub fn rpm_vcs(&self) -> Option<String> {
+        if !matches!(self.rpm_version, Some(version) if version >= RPM_VCS_MIN_VERSION) {
+            return None;
+        }
+        let commit = self.describe_commit()?;
+        Some(match &self.repository {
+            Some(repository) => format!("git+{}#{}", repository, commit),
+            None => commit,
+        })
+    }
+
+    /// Returns user-defined fields of the DEB control file.
+    pub fn deb_fields(&self) -> Vec<String> {
+        let mut fields = vec![];
+        if let Some(commit) = self.describe_commit() {
+            fields.push(format!("Pkger-Commit: {}", commit));
+        }
+        fields.push(format!("Pkger-Version: {}", PKGER_VERSION));
+        fields.push(format!("Pkger-Build-Time: {}", self.build_time));
+        fields
+    }
+
+    /// Renders the content of the `BUILD_INFO` file as `key=value` lines.
+    pub fn render(&self, name: &str, version: &str, release: &str, image: &str) -> String {
+        let mut lines = vec![
+            format!("name={}", name),
+            format!("version={}", version),
+            format!("release={}", release),
+        ];
+        if let Some(commit) = &self.commit {
+            lines.push(format!("commit={}", commit));
+            lines.push(format!("dirty={}", self.dirty));
+        }
+        if let Some(branch) = &self.branch {
+            lines.push(format!("branch={}", branch));
+        }
+        if let Some(repository) = &self.repository {
+            lines.push(format!("repository={}", repository));
+        }
+        lines.push(format!("image={}", image));
+        lines.push(format!("pkger_version={}", PKGER_VERSION));
+        lines.push(format!("build_time={}", self.build_time));
+
+        let mut rendered = lines.join("\n");
+        rendered.push('\n');
+        rendered
+    }
+}
+
+/// Detects the commit, branch and dirty state of the git repository in the build directory after
+/// the source was fetched. Sources that are not git repositories or images without `git` only
+/// get the build time. For RPM builds the version of `rpm` is detected as well.
+pub async fn detect(ctx: &Context<'_>) -> BuildInfo {
+    let span = info_span!("detect-build-info");
+    async move {
+        let mut build_info = BuildInfo {
+            repository: ctx
+                .build
+                .recipe
+                .metadata
+                .git
+                .as_ref()
+                .map(|git| git.url().to_string()),
+            build_time: format_utc(SystemTime::now()),
+            ..Default::default()
+        };
+
+        let out = ctx
+            .checked_exec_with(
+                &ExecOpts::default()
+                    .cmd("git rev-parse HEAD && git rev-parse --abbrev-ref HEAD && git status --porcelain --untracked-files=no | wc -l")
+                    .working_dir(&ctx.build.container_bld_dir)
+                    .build(),
+                true,
+            )
+            .await;
+        match out {
+            Ok(out) => {
+                let stdout = out.stdout.join("");
+                let mut lines = stdout.lines().map(str::trim);
+                build_info.commit = lines.next().map(str::to_string);
+                build_info.branch = lines
+                    .next()
+                    .filter(|branch| *branch != "HEAD")
+                    .map(str::to_string);
+                build_info.dirty = lines.next().map(|n| n != "0").unwrap_or_default();
+                info!(commit = ?build_info.commit, branch = ?build_info.branch, dirty = %build_info.dirty, "detected source revision");
+            }
+            Err(e) => debug!(reason = %e, "source is not a git repository"),
+        }
+
+        if matches!(ctx.build.target.build_target(), BuildTarget::Rpm) {
+            let out = ctx
+                .checked_exec_with(&ExecOpts::default().cmd("rpm --version").build(), true)
+                .await;
+            match out {
+                Ok(out) => {
+                    build_info.rpm_version = parse_rpm_version(&out.stdout.join(""));
+                    debug!(rpm_version = ?build_info.rpm_version, "detected rpm version");
+                }
+                Err(e) => debug!(reason = %e, "failed to detect rpm version"),
+            }
+        }
+
+        build_info
+    }
+    .instrument(span)
+    .await
+}
+
+/// Writes the `BUILD_INFO` file to `<prefix>/share/<name>/` of the package if enabled in the
+/// recipe.
+pub async fn install(ctx: &Context<'_>) -> Result<()> {
+    let metadata = &ctx.build.recipe.metadata;
+    if !metadata.build_info {
+        return Ok(());
+    }
+    let span = info_span!("install-build-info");
+    async move {
+        let dir = ctx
+            .build
+            .container_out_dir
+            .join(metadata.prefix.trim_start_matches('/'))
+            .join("share")
+            .join(&metadata.name);
+        let content = ctx.build_info.render(
+            &metadata.name,
+            &metadata.version,
+            metadata.release(),
+            ctx.build.target.image(),
+        );
+        trace!(content = %content);
+
+        info!(dir = %dir.display(), "installing BUILD_INFO");
+        ctx.create_dirs(&[&dir]).await?;
+        ctx.container
+            .upload_files(
+                vec![(PathBuf::from("BUILD_INFO"), content.as_bytes())],
+                &dir,
+            )
+            .await
+            .context("failed to install BUILD_INFO")
+    }
+    .instrument(span)
+    .await
+}
+
+/// Formats the `time` in the RFC 3339 format in UTC like `2024-05-01T12:30:00Z`.
+fn format_utc(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+/// Parses the major and minor version from the output of `rpm --version` like
+/// `RPM version 4.14.3`.
+fn parse_rpm_version(out: &str) -> Option<(u32, u32)> {
+    let version = out.split_whitespace().last()?;
+    let mut parts = version.split('.').map(str::parse::<u32>);
+    Some((parts.next()?.ok()?, parts.next()?.ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_utc_time() {
+        let at = |secs| format_utc(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_714_566_645), "2024-05-01T12:30:45Z");
+    }
+
+    #[test]
+    fn renders_build_info() {
+        let build_info = BuildInfo {
+            commit: Some("0ab32f".to_string()),
+            branch: Some("master".to_string()),
+            dirty: true,
+            repository: Some("https://github.com/vv9k/pkger.git".to_string()),
+            build_time: "2024-05-01T12:30:45Z".to_string(),
+            rpm_version: Some((4, 16)),
+        };
+
+        assert_eq!(
+            build_info.rpm_vcs().unwrap(),
+            "git+https://github.com/vv9k/pkger.git#0ab32f-dirty"
+        );
+        assert_eq!(
+            build_info.render("pkger", "0.1.0", "1", "debian10"),
+            format!(
+                "name=pkger
+version=0.1.0
+release=1
+commit=0ab32f
+dirty=true
+branch=master
+repository=https://github.com/vv9k/pkger.git
+image=debian10
+pkger_version={}
+build_time=2024-05-01T12:30:45Z
+",
+                PKGER_VERSION
+            )
+        );
+        assert_eq!(BuildInfo::default().rpm_vcs(), None);
+
+        let el8 = BuildInfo {
+            rpm_version: Some((4, 14)),
+            ..build_info
+        };
+        assert_eq!(el8.rpm_vcs(), None);
+    }
+
+    #[test]
+    fn parses_rpm_version() {
+        assert_eq!(parse_rpm_version("RPM version 4.14.3\n"), Some((4, 14)));
+        assert_eq!(parse_rpm_version("RPM version 4.18.0"), Some((4, 18)));
+        assert_eq!(parse_rpm_version("RPM version 5"), None);
+        assert_eq!(parse_rpm_version(""), None);
+    }
+}
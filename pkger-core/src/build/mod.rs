@@ -1,25 +1,47 @@
 #[macro_use]
 pub mod container;
+pub mod artifacts;
+pub mod binaries;
+pub mod cache;
 pub mod deps;
+pub mod docs;
+pub mod files;
 pub mod image;
+pub mod info;
 pub mod package;
 pub mod patches;
+pub mod progress;
 pub mod remote;
+pub mod sandbox;
 pub mod scripts;
+pub mod snapshot;
+pub mod sources;
+pub mod store;
+pub mod subpackages;
+pub mod systemd;
+pub mod users;
 
+use crate::build::artifacts::{ArtifactConflict, ArtifactPolicy};
+use crate::build::progress::{Event, Phase, PhaseTimer, ProgressSender};
+use crate::build::sandbox::Sandbox;
+use crate::build::store::OutputLayout;
 use crate::container::ExecOpts;
 use crate::docker::Docker;
 use crate::gpg::GpgKey;
-use crate::image::{Image, ImageState, ImagesState};
-use crate::recipe::{ImageTarget, Recipe, RecipeTarget};
+use crate::image::{BuildRecord, Image, ImageState, ImagesState};
+use crate::network::NetworkConfig;
+use crate::recipe::{ImageTarget, Recipe, RecipeTarget, Source};
+use crate::security::SecurityConfig;
 use crate::ssh::SshConfig;
-use crate::{ErrContext, Result};
+use crate::template;
+use crate::units::format_size;
+use crate::{err, ErrContext, Result};
 
 use async_rwlock::RwLock;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 use tracing::{info, info_span, trace, warn, Instrument};
 use uuid::Uuid;
@@ -32,16 +54,44 @@ pub struct Context {
     recipe: Arc<Recipe>,
     image: Image,
     docker: Docker,
+    docker_host: Option<String>,
     container_bld_dir: PathBuf,
     container_out_dir: PathBuf,
     container_tmp_dir: PathBuf,
     out_dir: PathBuf,
+    cache_dir: PathBuf,
     target: RecipeTarget,
     image_state: Arc<RwLock<ImagesState>>,
     simple: bool,
     gpg_key: Option<GpgKey>,
     ssh: Option<SshConfig>,
     quiet: bool,
+    commit_image: Option<String>,
+    progress: Option<ProgressSender>,
+    project: Option<String>,
+    skip_up_to_date: bool,
+    phase_timer: Mutex<PhaseTimer>,
+    output_layout: OutputLayout,
+    artifact_policy: ArtifactPolicy,
+    source_cache: bool,
+    pkger_deps: Vec<PathBuf>,
+    pass_env: Vec<String>,
+    network: NetworkConfig,
+    security: SecurityConfig,
+    sandbox: Option<Sandbox>,
+    resolved_source: Option<Option<String>>,
+}
+
+#[derive(Debug)]
+/// Outcome of a successful build
+pub enum Outcome {
+    /// Packages were built and saved to the paths, the main package first followed by
+    /// subpackages and debug packages. Contains the artifacts that had the same name as existing
+    /// files of the output directory and the actions taken for them.
+    Built(Vec<PathBuf>, Vec<ArtifactConflict>),
+    /// The build was skipped because the recipe, its source and the image didn't change since the
+    /// last successful build. Contains the path to the previously built package if known.
+    UpToDate(Option<PathBuf>),
 }
 
 impl Context {
@@ -53,11 +103,13 @@ impl Context {
         docker: Docker,
         target: ImageTarget,
         out_dir: &Path,
+        cache_dir: &Path,
         image_state: Arc<RwLock<ImagesState>>,
         simple: bool,
         gpg_key: Option<GpgKey>,
         ssh: Option<SshConfig>,
         quiet: bool,
+        commit_image: Option<String>,
     ) -> Self {
         let timestamp = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -86,16 +138,32 @@ impl Context {
             recipe,
             image,
             docker,
+            docker_host: None,
             container_bld_dir,
             container_out_dir,
             container_tmp_dir,
             out_dir: out_dir.to_path_buf(),
+            cache_dir: cache_dir.to_path_buf(),
             target,
             image_state,
             simple,
             gpg_key,
             ssh,
             quiet,
+            commit_image,
+            progress: None,
+            project: None,
+            skip_up_to_date: false,
+            phase_timer: Mutex::new(PhaseTimer::default()),
+            output_layout: OutputLayout::default(),
+            artifact_policy: ArtifactPolicy::default(),
+            source_cache: false,
+            pkger_deps: vec![],
+            pass_env: vec![],
+            network: NetworkConfig::default(),
+            security: SecurityConfig::default(),
+            sandbox: None,
+            resolved_source: None,
         }
     }
 
@@ -103,13 +171,223 @@ impl Context {
         self.id.as_str()
     }
 
+    /// Sets the sender to which progress events of this build will be sent.
+    pub fn set_progress(&mut self, sender: ProgressSender) {
+        self.progress = Some(sender);
+    }
+
+    /// Sets the project this build belongs to. Tags of images built for this build will be
+    /// suffixed with the name of the project.
+    pub fn set_project(&mut self, project: impl Into<String>) {
+        self.project = Some(project.into());
+    }
+
+    /// Enables skipping the build if the recipe, its source and the image didn't change since the
+    /// last successful build.
+    pub fn set_skip_up_to_date(&mut self, skip: bool) {
+        self.skip_up_to_date = skip;
+    }
+
+    /// Sets the layout of packages in the output directory.
+    pub fn set_output_layout(&mut self, layout: OutputLayout) {
+        self.output_layout = layout;
+    }
+
+    /// Sets the action taken when built artifacts have the same names as existing files in the
+    /// output directory.
+    pub fn set_artifact_policy(&mut self, policy: ArtifactPolicy) {
+        self.artifact_policy = policy;
+    }
+
+    /// Enables caching of sources downloaded over HTTP(S) in the cache directory on the host.
+    pub fn set_source_cache(&mut self, enabled: bool) {
+        self.source_cache = enabled;
+    }
+
+    /// Sets the packages built by pkger from other recipes that are installed in the container
+    /// before running scripts.
+    pub fn set_pkger_deps(&mut self, packages: Vec<PathBuf>) {
+        self.pkger_deps = packages;
+    }
+
+    /// Sets the names of environment variables of the host forwarded to the container in addition
+    /// to the ones from the recipe.
+    pub fn set_pass_env(&mut self, pass_env: Vec<String>) {
+        self.pass_env = pass_env;
+    }
+
+    /// Sets the DNS settings and extra hosts of build containers and cache images.
+    pub fn set_network(&mut self, network: NetworkConfig) {
+        self.network = network;
+    }
+
+    /// Returns the network settings with the ones of the image applied on top.
+    pub fn network(&self) -> NetworkConfig {
+        match &self.target.image_target().network {
+            Some(image) => self.network.merge(image),
+            None => self.network.clone(),
+        }
+    }
+
+    /// Sets the seccomp and AppArmor profiles and other confinement of build containers.
+    pub fn set_security(&mut self, security: SecurityConfig) {
+        self.security = security;
+    }
+
+    /// Returns the confinement of build containers with the settings of the image applied on top.
+    /// Sandboxed builds always use the hardened settings.
+    pub fn security(&self) -> SecurityConfig {
+        let security = match &self.target.image_target().security {
+            Some(image) => self.security.merge(image),
+            None => self.security.clone(),
+        };
+        if self.sandbox.is_some() {
+            security.sandboxed()
+        } else {
+            security
+        }
+    }
+
+    /// Sets the URI of the Docker daemon used by commands that run the `docker` CLI so that they
+    /// talk to the same daemon as the connection of this build.
+    pub fn set_docker_host(&mut self, host: impl Into<String>) {
+        self.docker_host = Some(host.into());
+    }
+
+    /// Enables the restrictions of builds of untrusted recipes.
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        self.sandbox = Some(sandbox);
+    }
+
+    pub fn sandbox(&self) -> Option<&Sandbox> {
+        self.sandbox.as_ref()
+    }
+
+    /// Returns the source of the recipe resolved at the start of the build or `None` if it
+    /// couldn't be resolved.
+    pub(crate) fn resolved_source(&self) -> Option<&Option<String>> {
+        self.resolved_source.as_ref()
+    }
+
+    /// Returns the source of the recipe in a form that is recorded with successful builds. Local
+    /// directories are not tracked as their content can change without changing the path and
+    /// branches of git sources are resolved to the commit they point to.
+    async fn source_ref(&self) -> Result<Option<String>> {
+        let metadata = &self.recipe.metadata;
+        let source = if metadata.source.is_empty() {
+            None
+        } else {
+            Some(
+                metadata
+                    .source
+                    .iter()
+                    .map(Source::source)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )
+        };
+        let git = match &metadata.git {
+            Some(_) if self.sandbox.is_some() => {
+                return err!("git sources of sandboxed builds aren't resolved on the host")
+            }
+            Some(git) => Some(format!(
+                "{}#{}",
+                git.url(),
+                remote::resolve_commit(git.url(), git.branch()).await?
+            )),
+            None => None,
+        };
+        Ok(match (source, git) {
+            (Some(source), Some(git)) => Some(format!("{} {}", source, git)),
+            (source, git) => source.or(git),
+        })
+    }
+
+    /// Whether the source has to be resolved, which needs a `git ls-remote` on the host for git
+    /// sources. It's only used to skip up to date builds and to restore snapshots of cached steps,
+    /// while unresolved sources are recorded so that the next build isn't skipped.
+    fn needs_source_ref(&self) -> bool {
+        let recipe = &self.recipe;
+        let has_cached_steps = recipe
+            .configure_script
+            .iter()
+            .flat_map(|script| &script.steps)
+            .chain(&recipe.build_script.steps)
+            .chain(
+                recipe
+                    .install_script
+                    .iter()
+                    .flat_map(|script| &script.steps),
+            )
+            .any(|step| step.is_cached());
+        recipe.metadata.git.is_none() || self.skip_up_to_date || has_cached_steps
+    }
+
+    /// Returns the record of the last successful build if the build with `image_state` would use
+    /// the same recipe, `source` and image and its package still exists.
+    async fn up_to_date_build(
+        &self,
+        image_state: &ImageState,
+        source: &Option<String>,
+    ) -> Option<BuildRecord> {
+        if self
+            .recipe
+            .metadata
+            .source
+            .iter()
+            .any(|source| Path::new(source.source()).is_dir())
+        {
+            trace!("source is a local directory, can't verify if it changed");
+            return None;
+        }
+        let state = self.image_state.read().await;
+        state
+            .last_build(self.target.recipe(), self.target.image())
+            .filter(|record| {
+                record.recipe_hash == self.recipe.hash
+                    && record.image_id == image_state.id
+                    && &record.source == source
+                    && matches!(&record.output, Some(output) if output.exists())
+            })
+            .cloned()
+    }
+
+    fn report_phase(&self, phase: Phase) {
+        if let Ok(mut timer) = self.phase_timer.lock() {
+            timer.start(phase);
+        }
+        if let Some(sender) = &self.progress {
+            let _ = sender.unbounded_send(Event::Phase {
+                id: self.id.clone(),
+                phase,
+                percent: phase.percent(),
+            });
+        }
+    }
+
+    /// Returns the directory to which packages built on `image` are saved.
+    pub fn package_dir(&self, image: &ImageState) -> PathBuf {
+        self.output_layout.package_dir(
+            &self.out_dir,
+            &self.recipe.metadata.name,
+            &self.recipe.metadata.version,
+            &image.image,
+        )
+    }
+
     async fn create_out_dir(&self, image: &ImageState) -> Result<PathBuf> {
         let span = info_span!("create-out-dir");
         async move {
-            let out_dir = self.out_dir.join(&image.image);
+            let out_dir = self.package_dir(image);
 
             if out_dir.exists() {
                 trace!(dir = %out_dir.display(), "already exists, skipping");
+                // links of other policies are kept so that new artifacts are compared with them
+                if self.output_layout == OutputLayout::Store
+                    && self.artifact_policy == ArtifactPolicy::Overwrite
+                {
+                    store::remove_links(&out_dir)?;
+                }
                 Ok(out_dir)
             } else {
                 trace!(dir = %out_dir.display(), "creating directory");
@@ -123,33 +401,60 @@ impl Context {
     }
 }
 
-pub async fn run(ctx: &mut Context) -> Result<PathBuf> {
+pub async fn run(ctx: &mut Context) -> Result<Outcome> {
     let span = info_span!("build", recipe = %ctx.recipe.metadata.name, image = %ctx.target.image(), target = %ctx.target.build_target().as_ref());
-    async move {
+    let result = async {
         info!(id = %ctx.id, "running job" );
+        if let Some(sender) = &ctx.progress {
+            let _ = sender.unbounded_send(Event::Started {
+                id: ctx.id.clone(),
+                recipe: ctx.recipe.metadata.name.clone(),
+                image: ctx.target.image().to_string(),
+                target: ctx.target.build_target().as_ref().to_string(),
+            });
+        }
+
+        if ctx.sandbox.is_some() {
+            sandbox::check_recipe(&ctx.recipe)?;
+        }
+
+        ctx.report_phase(Phase::BuildImage);
         let image_state = image::build(ctx).await.context("failed to build image")?;
+        let cached = image::project_tag(image::CACHED, ctx.project.as_deref());
 
-        let out_dir = ctx.create_out_dir(&image_state).await?;
+        let source = if ctx.needs_source_ref() {
+            match ctx.source_ref().await {
+                Ok(source) => Some(source),
+                Err(e) => {
+                    warn!(reason = %format!("{:?}", e), "failed to resolve the source of the recipe, assuming it changed");
+                    None
+                }
+            }
+        } else {
+            trace!("not resolving the git source of the recipe");
+            None
+        };
+        ctx.resolved_source = source.clone();
+        if ctx.skip_up_to_date && image_state.tag == cached {
+            if let Some(source) = &source {
+                if let Some(record) = ctx.up_to_date_build(&image_state, source).await {
+                    info!("recipe, source and image unchanged since last successful build, skipping");
+                    return Ok(Outcome::UpToDate(record.output));
+                }
+            }
+        }
 
-        let mut container_ctx = container::spawn(ctx, &image_state).await?;
+        let out_dir = ctx.create_out_dir(&image_state).await?;
 
-        let image_state = if image_state.tag != image::CACHED {
-            let mut deps = deps::default(
-                ctx.target.build_target(),
-                &ctx.recipe,
-                ctx.gpg_key.is_some(),
-            );
-            deps.extend(deps::recipe(&container_ctx, &image_state));
-            let new_state =
-                image::create_cache(&container_ctx, &ctx.docker, &image_state, &deps).await?;
-            info!(id = %new_state.id, image = %new_state.image, "successfully cached image");
+        let mut container_ctx =
+            container::spawn(ctx, &image_state, image_state.tag != cached).await?;
 
-            trace!("saving image state");
-            let mut state = ctx.image_state.write().await;
-            (*state).update(ctx.target.clone(), new_state.clone());
+        let image_state = if image_state.tag != cached {
+            ctx.report_phase(Phase::CacheImage);
+            let new_state = cache_image(ctx, &container_ctx, &image_state).await?;
 
             container_ctx.container.remove().await?;
-            container_ctx = container::spawn(ctx, &new_state).await?;
+            container_ctx = container::spawn(ctx, &new_state, false).await?;
 
             new_state
         } else {
@@ -163,28 +468,215 @@ pub async fn run(ctx: &mut Context) -> Result<PathBuf> {
         ];
 
         container_ctx.create_dirs(&dirs[..]).await?;
+        deps::install_pkger_deps(&container_ctx, &image_state).await?;
 
-        remote::fetch_source(&container_ctx).await?;
+        ctx.report_phase(Phase::FetchSource);
+        // cache directories, sources and patches are written to separate locations so they can be
+        // fetched at the same time
+        let (_, _, patches) = futures::try_join!(
+            cache::restore(&container_ctx),
+            remote::fetch_source(&container_ctx),
+            async {
+                match &ctx.recipe.metadata.patches {
+                    Some(patches) => patches::collect(&container_ctx, patches).await.map(Some),
+                    None => Ok(None),
+                }
+            }
+        )?;
 
-        if let Some(patches) = &ctx.recipe.metadata.patches {
-            let patches = patches::collect(&container_ctx, patches).await?;
+        if let Some(sandbox) = &ctx.sandbox {
+            sandbox::disconnect_network(&container_ctx).await?;
+            sandbox::check_output_size(&container_ctx, sandbox).await?;
+        }
+
+        let build_info = info::detect(&container_ctx).await;
+        container_ctx.set_build_info(build_info);
+
+        if let Some(patches) = patches {
+            ctx.report_phase(Phase::ApplyPatches);
             patches::apply(&container_ctx, patches).await?;
         }
 
-        scripts::run(&container_ctx).await?;
+        ctx.report_phase(Phase::RunScripts);
+        scripts::run(&container_ctx, &image_state).await?;
+        docs::install(&container_ctx).await?;
+        systemd::install(&container_ctx).await?;
+        users::install(&container_ctx).await?;
+        info::install(&container_ctx).await?;
 
         exclude_paths(&container_ctx).await?;
+        let debug_dir = binaries::process(&container_ctx, out_dir.as_path()).await?;
+        // restored step snapshots and documentation copied from the build directory aren't
+        // covered by the checks after each step
+        if let Some(sandbox) = &ctx.sandbox {
+            sandbox::check_output_size(&container_ctx, sandbox).await?;
+        }
+        files::create_symlinks(&container_ctx).await?;
+        files::apply(&container_ctx).await?;
+        let subpackage_dirs = subpackages::split(&container_ctx).await?;
 
-        let package = package::build(&container_ctx, &image_state, out_dir.as_path()).await?;
+        ctx.report_phase(Phase::Package);
+        let staging_dir = artifacts::staging_dir(&out_dir, &ctx.id);
+        fs::create_dir_all(&staging_dir).context("failed to create staging directory")?;
+        let saved = build_packages(&container_ctx, &image_state, debug_dir, &subpackage_dirs, &staging_dir)
+            .await
+            .and_then(|packages| {
+                artifacts::save(&staging_dir, &out_dir, ctx.artifact_policy)
+                    .map(|saved| (packages, saved))
+            });
+        if let Err(e) = fs::remove_dir_all(&staging_dir) {
+            warn!(dir = %staging_dir.display(), reason = %e, "failed to remove staging directory");
+        }
+        let (packages, saved) = saved?;
+        let packages: Vec<_> = packages
+            .iter()
+            .map(|package| artifacts::saved_path(&saved, package))
+            .collect();
+        let conflicts: Vec<_> = saved
+            .into_iter()
+            .filter_map(|artifact| artifact.conflict)
+            .collect();
+        if ctx.output_layout == OutputLayout::Store {
+            for package in &packages {
+                let stored = store::store_package(&ctx.out_dir, package)?;
+                info!(path = %stored.display(), "saved package to the store");
+            }
+        }
+
+        cache::save(&container_ctx).await?;
+
+        if let Some(tag) = &ctx.commit_image {
+            let tag = template::render(tag, container_ctx.vars.inner());
+            let id = container_ctx.container.commit(&tag).await?;
+            info!(id = %id, tag = %tag, "committed build container as image");
+        }
 
         container_ctx.container.remove().await?;
 
-        Ok(package)
+        let steps = ctx
+            .phase_timer
+            .lock()
+            .map(|mut timer| timer.finish())
+            .unwrap_or_default();
+        info!(
+            deps = %image_state.deps.len(),
+            deps_size = %image_state.deps_size.map(format_size).unwrap_or_else(|| "unknown".to_string()),
+            slowest_steps = %steps.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+            "build profile"
+        );
+
+        ctx.image_state.write().await.record_build(
+            ctx.target.clone(),
+            BuildRecord {
+                recipe_hash: ctx.recipe.hash.clone(),
+                image_id: image_state.id.clone(),
+                timestamp: SystemTime::now(),
+                // unresolved sources are not recorded so that the next build isn't skipped
+                source: source.unwrap_or_default(),
+                output: packages.first().cloned(),
+                packages: packages.iter().skip(1).cloned().collect(),
+                steps,
+                packaging: ctx
+                    .recipe
+                    .preview_packaging(*ctx.target.build_target(), ctx.target.image()),
+            },
+        );
+
+        Ok(Outcome::Built(packages, conflicts))
+    }
+    .instrument(span)
+    .await;
+
+    if !matches!(result, Ok(Outcome::UpToDate(_))) {
+        ctx.image_state.write().await.record_result(
+            ctx.target.clone(),
+            ctx.session_id.to_string(),
+            result.is_ok(),
+        );
+    }
+
+    result
+}
+
+/// Builds the package, its debug package and subpackages saving them to `output_dir`. Returns the
+/// paths of the packages starting with the main package.
+async fn build_packages(
+    ctx: &container::Context<'_>,
+    image_state: &ImageState,
+    debug_dir: Option<PathBuf>,
+    subpackage_dirs: &[PathBuf],
+    output_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut packages = vec![package::build(ctx, image_state, output_dir).await?];
+    if let Some(debug_dir) = debug_dir {
+        packages.extend(package::build_debug(ctx, image_state, &debug_dir, output_dir).await?);
+    }
+    for (subpackage, dir) in ctx
+        .build
+        .recipe
+        .metadata
+        .subpackages
+        .iter()
+        .zip(subpackage_dirs)
+    {
+        packages.extend(
+            package::build_subpackage(ctx, image_state, subpackage, dir, output_dir).await?,
+        );
+    }
+    Ok(packages)
+}
+
+/// Builds the image of the job and caches dependencies of the recipe in it without fetching the
+/// source or running any scripts. Returns the state of the cached image.
+pub async fn warmup(ctx: &mut Context) -> Result<ImageState> {
+    let span =
+        info_span!("warmup", recipe = %ctx.recipe.metadata.name, image = %ctx.target.image());
+    async move {
+        let image_state = image::build(ctx).await.context("failed to build image")?;
+        if image_state.tag == image::project_tag(image::CACHED, ctx.project.as_deref()) {
+            info!(id = %image_state.id, "image already cached");
+            return Ok(image_state);
+        }
+
+        let container_ctx = container::spawn(ctx, &image_state, true).await?;
+        let result = cache_image(ctx, &container_ctx, &image_state).await;
+        container_ctx.container.remove().await?;
+        result
     }
     .instrument(span)
     .await
 }
 
+/// Installs default dependencies and dependencies of the recipe in the container and commits it as
+/// the cached image of the job.
+async fn cache_image(
+    ctx: &Context,
+    container_ctx: &container::Context<'_>,
+    image_state: &ImageState,
+) -> Result<ImageState> {
+    let deps = deps::cached(
+        &ctx.recipe,
+        *ctx.target.build_target(),
+        &image_state.image,
+        ctx.gpg_key.is_some(),
+    );
+    let mut new_state = image::create_cache(container_ctx, &ctx.docker, image_state, &deps).await?;
+    new_state.recipe_hash = Some(ctx.recipe.hash.clone());
+    new_state.deps_size = Some(
+        new_state
+            .details
+            .size
+            .saturating_sub(image_state.details.size),
+    );
+    info!(id = %new_state.id, image = %new_state.image, "successfully cached image");
+
+    trace!("saving image state");
+    let mut state = ctx.image_state.write().await;
+    (*state).update(ctx.target.clone(), new_state.clone());
+
+    Ok(new_state)
+}
+
 pub async fn exclude_paths(ctx: &container::Context<'_>) -> Result<()> {
     let span = info_span!("exclude-paths");
     async move {
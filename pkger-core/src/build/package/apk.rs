@@ -10,7 +10,7 @@ pub fn package_name(ctx: &Context<'_>, extension: bool) -> String {
     format!(
         "{}-{}-r{}{}",
         &ctx.build.recipe.metadata.name,
-        &ctx.build.recipe.metadata.version,
+        ctx.build.recipe.metadata.apk_version(),
         &ctx.build.recipe.metadata.release(),
         if extension { ".apk" } else { "" },
     )
@@ -71,14 +71,12 @@ pub(crate) async fn build(
             .render();
         debug!(APKBUILD = %apkbuild);
 
-        ctx.container
-            .upload_files(
-                vec![("APKBUILD".to_string(), apkbuild.as_bytes())],
-                &bld_dir,
-                ctx.build.quiet,
-            )
-            .await
-            .context("failed to upload APKBUILD to container")?;
+        ctx.upload_text_files(
+            vec![("APKBUILD".to_string(), apkbuild.as_bytes())],
+            &bld_dir,
+        )
+        .await
+        .context("failed to upload APKBUILD to container")?;
 
         trace!("create build user");
 
@@ -112,7 +110,7 @@ pub(crate) async fn build(
                 info!("uploading signing key");
                 trace!(key_location = %key_location.display());
                 ctx.container
-                    .upload_files([(SIGNING_KEY, key.as_slice())], &abuild_dir, false)
+                    .upload_files([(SIGNING_KEY, key.as_slice())], &abuild_dir)
                     .await
                     .context("failed to upload signing key")?;
                 ctx.checked_exec(&exec!(&format!("chmod 600 {}", key_path.display())))
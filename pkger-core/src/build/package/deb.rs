@@ -1,9 +1,13 @@
+use crate::archive::flate2::{write::GzEncoder, Compression};
 use crate::build::container::Context;
-use crate::build::package::sign::{import_gpg_key, upload_gpg_key};
+use crate::build::package::sign::{export_public_key, import_gpg_key, upload_gpg_key};
 use crate::container::ExecOpts;
 use crate::image::ImageState;
-use crate::{ErrContext, Result};
+use crate::recipe::{ChangelogDate, ChangelogEntry, DebInfo, Metadata, Subpackage};
+use crate::{err, ErrContext, Error, Result};
 
+use std::convert::TryFrom;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use tracing::{debug, info, info_span, trace, Instrument};
 
@@ -57,70 +61,83 @@ pub async fn build(
         let control = _span.in_scope(|| {
             ctx.build
                 .recipe
-                .as_deb_control(&image_state.image, size)
+                .as_deb_control(&image_state.image, size, &ctx.build_info)
                 .render()
         });
         debug!(control = %control);
 
         // Upload install scripts
+        let scripts = ctx.build.recipe.metadata.deb_scripts();
+        if !scripts.is_empty() {
+            let scripts_paths: String = scripts
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            ctx.upload_text_files(
+                scripts
+                    .iter()
+                    .map(|(name, script)| (format!("./{}", name), script.as_bytes()))
+                    .collect(),
+                &deb_dir,
+            )
+            .await
+            .context("failed to upload install scripts to container")?;
+
+            ctx.checked_exec(
+                &ExecOpts::default()
+                    .cmd(&format!("chmod 0755 {}", scripts_paths))
+                    .working_dir(&deb_dir)
+                    .build(),
+            )
+            .await
+            .context("failed to change ownership of build scripts")?;
+        }
+
         if let Some(deb) = &ctx.build.recipe.metadata.deb {
-            let mut scripts = vec![];
-            if let Some(postinst) = &deb.postinst_script {
-                scripts.push(("./postinst", postinst.as_bytes()));
+            // triggers is a plain control file and must not be executable
+            if let Some(triggers) = &deb.triggers {
+                ctx.upload_text_files(vec![("./triggers", triggers.as_bytes())], &deb_dir)
+                    .await
+                    .context("failed to upload triggers file to container")?;
             }
-            if !scripts.is_empty() {
-                let scripts_paths: String = scripts
-                    .iter()
-                    .map(|s| s.0.trim_start_matches("./"))
-                    .collect::<Vec<_>>()
-                    .join(" ");
 
-                ctx.container
-                    .upload_files(scripts, &deb_dir, ctx.build.quiet)
+            if let Some(conffiles) = deb.conffiles.as_ref().filter(|files| !files.is_empty()) {
+                let conffiles = format!("{}\n", conffiles.join("\n"));
+                ctx.upload_text_files(vec![("./conffiles", conffiles.as_bytes())], &deb_dir)
                     .await
-                    .context("failed to upload install scripts to container")?;
-
-                ctx.checked_exec(
-                    &ExecOpts::default()
-                        .cmd(&format!("chmod 0755 {}", scripts_paths))
-                        .working_dir(&deb_dir)
-                        .build(),
-                )
-                .await
-                .context("failed to change ownership of build scripts")?;
+                    .context("failed to upload conffiles to container")?;
             }
         }
 
-        ctx.container
-            .upload_files(
-                vec![("./control", control.as_bytes())],
-                &deb_dir,
-                ctx.build.quiet,
-            )
+        ctx.upload_text_files(vec![("./control", control.as_bytes())], &deb_dir)
             .await
             .context("failed to upload control file to container")?;
 
         trace!("copy source files to build dir");
+        // keep modes set by the recipe
+        let cp_opts = if ctx.build.recipe.metadata.files.is_empty() {
+            "-rv"
+        } else {
+            "-av"
+        };
         ctx.checked_exec(
             &ExecOpts::default()
-                .cmd(&format!("cp -rv . {}", base_dir.display()))
+                .cmd(&format!("cp {} . {}", cp_opts, base_dir.display()))
                 .working_dir(&ctx.build.container_out_dir)
                 .build(),
         )
         .await
         .context("failed to copy source files to build directory")?;
 
-        let dpkg_deb_opts = if image_state.os.version().parse::<u8>().unwrap_or_default() < 10 {
-            "--build"
-        } else {
-            "--build --root-owner-group"
-        };
+        install_changelog(ctx, &base_dir).await?;
 
         ctx.checked_exec(
             &ExecOpts::default()
                 .cmd(&format!(
                     "dpkg-deb {} {}",
-                    dpkg_deb_opts,
+                    dpkg_deb_opts(image_state, &ctx.build.recipe.metadata),
                     base_dir.display()
                 ))
                 .build(),
@@ -133,6 +150,11 @@ pub async fn build(
 
         sign_package(ctx, &package_file).await?;
 
+        if let Some(gpg_key) = &ctx.build.gpg_key {
+            let key = export_public_key(ctx, gpg_key, output_dir).await?;
+            info!(key = %key.display(), "exported public key");
+        }
+
         ctx.container
             .download_files(&package_file, output_dir)
             .await
@@ -143,6 +165,256 @@ pub async fn build(
     .await
 }
 
+/// Installs the Debian changelog of the package compressed to
+/// `/usr/share/doc/<name>/changelog.Debian.gz` in `base_dir` if the recipe has one.
+async fn install_changelog(ctx: &Context<'_>, base_dir: &Path) -> Result<()> {
+    let recipe = &ctx.build.recipe;
+    let range = recipe
+        .metadata
+        .deb
+        .as_ref()
+        .and_then(|deb| deb.changelog.as_ref())
+        .and_then(|changelog| changelog.git.as_deref());
+    let git_entries = match range {
+        Some(range) => Some(vec![git_changelog_entry(ctx, range).await?]),
+        None => None,
+    };
+    let changelog = match recipe.render_deb_changelog(git_entries.as_deref()) {
+        Some(changelog) => changelog,
+        None => return Ok(()),
+    };
+    trace!(changelog = %changelog);
+
+    let mut encoder = GzEncoder::new(vec![], Compression::best());
+    encoder.write_all(changelog.as_bytes())?;
+    let compressed = encoder.finish()?;
+
+    let dir = base_dir
+        .join("usr/share/doc")
+        .join(recipe.metadata.name.replace('_', "-"));
+    info!(dir = %dir.display(), "installing Debian changelog");
+    ctx.create_dirs(&[&dir]).await?;
+    ctx.container
+        .upload_files(
+            vec![(PathBuf::from("changelog.Debian.gz"), compressed.as_slice())],
+            &dir,
+        )
+        .await
+        .context("failed to install Debian changelog")
+}
+
+/// Returns a changelog entry of the built version listing subjects of the commits in the `range`
+/// of revisions of the git repository in the build directory, dated by the newest commit.
+async fn git_changelog_entry(ctx: &Context<'_>, range: &str) -> Result<ChangelogEntry> {
+    let out = ctx
+        .checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "git log --no-merges --date=short --format='%cd %s' {}",
+                    range
+                ))
+                .working_dir(&ctx.build.container_bld_dir)
+                .build(),
+        )
+        .await
+        .context("failed to read git log of the source for the Debian changelog")?;
+    parse_git_log(&out.stdout.join(""), range)
+}
+
+/// Parses lines of `git log` in the `<date> <subject>` format, newest first.
+fn parse_git_log(log: &str, range: &str) -> Result<ChangelogEntry> {
+    let mut date = None;
+    let mut changes = vec![];
+    for line in log.lines().filter(|line| !line.trim().is_empty()) {
+        let (commit_date, subject) = line.split_once(' ').unwrap_or((line, ""));
+        if date.is_none() {
+            date = Some(ChangelogDate::try_from(commit_date.to_string())?);
+        }
+        changes.push(subject.trim().to_string());
+    }
+    match date {
+        Some(date) => Ok(ChangelogEntry {
+            date,
+            version: None,
+            author: None,
+            changes,
+        }),
+        None => err!(
+            "no commits found in range `{}` for the Debian changelog",
+            range
+        ),
+    }
+}
+
+/// Returns the options of `dpkg-deb`. Files are owned by root in the package, owners set by the
+/// recipe are applied by `postinst`.
+fn dpkg_deb_opts(image_state: &ImageState, metadata: &Metadata) -> String {
+    let mut opts = if image_state.os.version().parse::<u8>().unwrap_or_default() < 10 {
+        "--build".to_string()
+    } else {
+        "--build --root-owner-group".to_string()
+    };
+    if let Some(deb) = &metadata.deb {
+        opts.push_str(&compression_opts(deb));
+    }
+    opts
+}
+
+/// Returns the `-Z` and `-z` options of `dpkg-deb` selecting the compression of the package
+/// configured in the recipe
+fn compression_opts(deb: &DebInfo) -> String {
+    let mut opts = String::new();
+    if let Some(compression) = deb.compression {
+        opts.push_str(" -Z");
+        opts.push_str(compression.as_ref());
+    }
+    if let Some(level) = deb.compression_level {
+        opts.push_str(&format!(" -z{}", level));
+    }
+    opts
+}
+
+/// Creates a `-dbgsym` package from the debug information saved to `debug_dir` and saves it to
+/// `output_dir`
+pub(crate) async fn build_dbgsym(
+    ctx: &Context<'_>,
+    image_state: &ImageState,
+    debug_dir: &Path,
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let metadata = &ctx.build.recipe.metadata;
+    let package_name = format!(
+        "{}-dbgsym-{}-{}.{}",
+        metadata.name.replace('_', "-"),
+        metadata.version,
+        metadata.release(),
+        metadata.arch.deb_name()
+    );
+
+    let span = info_span!("DEB-dbgsym", package = %package_name);
+    let _span = span.clone();
+    async move {
+        info!("building DEB debug symbols package");
+
+        let debbld_dir = PathBuf::from("/root/debbuild");
+        let base_dir = debbld_dir.join(&package_name);
+        let deb_dir = base_dir.join("DEBIAN");
+        ctx.create_dirs(&[deb_dir.as_path()])
+            .await
+            .context("failed to create dirs")?;
+
+        let control = _span.in_scope(|| ctx.build.recipe.as_deb_dbgsym_control().render());
+        debug!(control = %control);
+        ctx.upload_text_files(vec![("./control", control.as_bytes())], &deb_dir)
+            .await
+            .context("failed to upload control file to container")?;
+
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "cp -a {}/. {} && dpkg-deb {} {}",
+                    debug_dir.display(),
+                    base_dir.display(),
+                    dpkg_deb_opts(image_state, &ctx.build.recipe.metadata),
+                    base_dir.display()
+                ))
+                .build(),
+        )
+        .await
+        .context("failed to build debug symbols package")?;
+
+        let deb_name = [&package_name, ".deb"].join("");
+        let package_file = debbld_dir.join(&deb_name);
+
+        sign_package(ctx, &package_file).await?;
+
+        ctx.container
+            .download_files(&package_file, output_dir)
+            .await
+            .map(|_| output_dir.join(deb_name))
+            .context("failed to download debug symbols package")
+    }
+    .instrument(span)
+    .await
+}
+
+/// Creates the `subpackage` from the files moved to `dir` and saves it to `output_dir`
+pub(crate) async fn build_subpackage(
+    ctx: &Context<'_>,
+    image_state: &ImageState,
+    subpackage: &Subpackage,
+    dir: &Path,
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let metadata = &ctx.build.recipe.metadata;
+    let package_name = format!(
+        "{}-{}-{}.{}",
+        subpackage.name.replace('_', "-"),
+        metadata.version,
+        metadata.release(),
+        metadata.arch.deb_name()
+    );
+
+    let span = info_span!("DEB-subpackage", package = %package_name);
+    let _span = span.clone();
+    async move {
+        info!("building DEB subpackage");
+
+        let debbld_dir = PathBuf::from("/root/debbuild");
+        let base_dir = debbld_dir.join(&package_name);
+        let deb_dir = base_dir.join("DEBIAN");
+        ctx.create_dirs(&[deb_dir.as_path()])
+            .await
+            .context("failed to create dirs")?;
+
+        let size_out = ctx
+            .checked_exec(&ExecOpts::default().cmd("du -s .").working_dir(dir).build())
+            .await
+            .context("failed to check size of subpackage files")?
+            .stdout
+            .join("");
+        let size = size_out.split_ascii_whitespace().next();
+
+        let control = _span.in_scope(|| {
+            ctx.build
+                .recipe
+                .as_deb_subpackage_control(subpackage, &image_state.image, size)
+                .render()
+        });
+        debug!(control = %control);
+        ctx.upload_text_files(vec![("./control", control.as_bytes())], &deb_dir)
+            .await
+            .context("failed to upload control file to container")?;
+
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "cp -a {}/. {} && dpkg-deb {} {}",
+                    dir.display(),
+                    base_dir.display(),
+                    dpkg_deb_opts(image_state, metadata),
+                    base_dir.display()
+                ))
+                .build(),
+        )
+        .await
+        .context("failed to build subpackage")?;
+
+        let deb_name = [&package_name, ".deb"].join("");
+        let package_file = debbld_dir.join(&deb_name);
+
+        sign_package(ctx, &package_file).await?;
+
+        ctx.container
+            .download_files(&package_file, output_dir)
+            .await
+            .map(|_| output_dir.join(deb_name))
+            .context("failed to download subpackage")
+    }
+    .instrument(span)
+    .await
+}
+
 pub(crate) async fn sign_package(ctx: &Context<'_>, package: &Path) -> Result<()> {
     let span = info_span!("sign", package = %package.display());
     async move {
@@ -200,3 +472,45 @@ pub(crate) async fn sign_package(ctx: &Context<'_>, package: &Path) -> Result<()
     .instrument(span)
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::DebRep;
+    use pretty_assertions::assert_eq;
+    use std::convert::TryFrom;
+
+    fn deb_info(yaml: &str) -> crate::Result<DebInfo> {
+        DebInfo::try_from(serde_yaml::from_str::<DebRep>(yaml).unwrap())
+    }
+
+    #[test]
+    fn parses_git_log() {
+        let entry = parse_git_log(
+            "2024-05-01 Fix a crash on startup\n2024-04-20 Add IPv6 support\n",
+            "v1.0.0..v1.1.0",
+        )
+        .unwrap();
+        assert_eq!(entry.date.to_string(), "2024-05-01");
+        assert_eq!(
+            entry.changes,
+            vec!["Fix a crash on startup", "Add IPv6 support"]
+        );
+        assert!(parse_git_log("", "v1.0.0..v1.1.0").is_err());
+    }
+
+    #[test]
+    fn builds_compression_opts() {
+        assert_eq!(compression_opts(&deb_info("{}").unwrap()), "");
+        assert_eq!(
+            compression_opts(&deb_info("compression: zstd\ncompression_level: 19").unwrap()),
+            " -Zzstd -z19"
+        );
+        assert_eq!(
+            compression_opts(&deb_info("compression_level: 9").unwrap()),
+            " -z9"
+        );
+        assert!(deb_info("compression: xz\ncompression_level: 19").is_err());
+        assert!(deb_info("compression: bzip2").is_err());
+    }
+}
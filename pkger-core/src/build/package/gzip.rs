@@ -1,4 +1,4 @@
-use crate::archive::{save_tar_gz, tar};
+use crate::archive::{save_tar, tar};
 use crate::build::container::Context;
 use crate::{ErrContext, Result};
 
@@ -6,21 +6,31 @@ use std::path::{Path, PathBuf};
 use tracing::{info, info_span, Instrument};
 
 pub fn package_name(ctx: &Context<'_>, extension: bool) -> String {
+    let metadata = &ctx.build.recipe.metadata;
+    let compression = metadata.gzip.as_ref().map(|gzip| gzip.compression);
     format!(
-        "{}-{}.{}",
-        &ctx.build.recipe.metadata.name,
-        &ctx.build.recipe.metadata.version,
-        if extension { ".tar.gz" } else { "" },
+        "{}-{}{}",
+        &metadata.name,
+        &metadata.version,
+        if extension {
+            format!(".{}", compression.unwrap_or_default().extension())
+        } else {
+            String::new()
+        },
     )
 }
 
-/// Creates a final GZIP package and saves it to `output_dir` returning the path of the final
-/// archive as String.
+/// Creates a final compressed tarball and saves it to `output_dir` returning the path of the
+/// final archive.
 pub async fn build(ctx: &Context<'_>, output_dir: &Path) -> Result<PathBuf> {
     let span = info_span!("GZIP");
     let cloned_span = span.clone();
     async move {
-        info!("building GZIP package");
+        let gzip = ctx.build.recipe.metadata.gzip.clone().unwrap_or_default();
+        info!(
+            compression = gzip.compression.as_ref(),
+            "building GZIP package"
+        );
         let package = ctx
             .container
             .copy_from(&ctx.build.container_out_dir)
@@ -31,8 +41,17 @@ pub async fn build(ctx: &Context<'_>, output_dir: &Path) -> Result<PathBuf> {
 
         cloned_span
             .in_scope(|| {
-                save_tar_gz(archive, &archive_name, output_dir)
-                    .context("failed to save package as tar.gz")
+                save_tar(
+                    archive,
+                    &archive_name,
+                    output_dir,
+                    gzip.compression,
+                    gzip.level,
+                )
+                .context(format!(
+                    "failed to save package as {}",
+                    gzip.compression.extension()
+                ))
             })
             .map(|_| output_dir.join(archive_name))
     }
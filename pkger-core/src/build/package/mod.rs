@@ -2,15 +2,17 @@ use std::path::{Path, PathBuf};
 
 use crate::build::container::Context;
 use crate::image::ImageState;
-use crate::recipe::BuildTarget;
+use crate::recipe::{BuildTarget, Subpackage};
 use crate::Result;
 
 pub mod apk;
 pub mod deb;
 pub mod gzip;
+pub mod msi;
 pub mod pkg;
 pub mod rpm;
 mod sign;
+pub mod xbps;
 
 pub async fn build(
     ctx: &Context<'_>,
@@ -23,5 +25,56 @@ pub async fn build(
         BuildTarget::Deb => deb::build(ctx, image_state, output_dir).await,
         BuildTarget::Pkg => pkg::build(ctx, image_state, output_dir).await,
         BuildTarget::Apk => apk::build(ctx, image_state, output_dir).await,
+        BuildTarget::Msi => msi::build(ctx, output_dir).await,
+        BuildTarget::Xbps => xbps::build(ctx, image_state, output_dir).await,
+    }
+}
+
+/// Returns `true` if the debug information of binaries can be packaged separately for `target`.
+pub fn supports_debug_package(target: &BuildTarget) -> bool {
+    matches!(target, BuildTarget::Deb | BuildTarget::Rpm)
+}
+
+/// Creates a package with the debug information saved to `debug_dir` in the container and saves
+/// it to `output_dir`. Returns `None` if the target doesn't support debug packages.
+pub async fn build_debug(
+    ctx: &Context<'_>,
+    image_state: &ImageState,
+    debug_dir: &Path,
+    output_dir: &Path,
+) -> Result<Option<PathBuf>> {
+    match ctx.build.target.build_target() {
+        BuildTarget::Deb => deb::build_dbgsym(ctx, image_state, debug_dir, output_dir)
+            .await
+            .map(Some),
+        BuildTarget::Rpm => rpm::build_debuginfo(ctx, debug_dir, output_dir)
+            .await
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Returns `true` if files of the recipe can be split into subpackages for `target`.
+pub fn supports_subpackages(target: &BuildTarget) -> bool {
+    matches!(target, BuildTarget::Deb | BuildTarget::Rpm)
+}
+
+/// Creates the `subpackage` from the files moved to `dir` in the container and saves it to
+/// `output_dir`. Returns `None` if the target doesn't support subpackages.
+pub async fn build_subpackage(
+    ctx: &Context<'_>,
+    image_state: &ImageState,
+    subpackage: &Subpackage,
+    dir: &Path,
+    output_dir: &Path,
+) -> Result<Option<PathBuf>> {
+    match ctx.build.target.build_target() {
+        BuildTarget::Deb => deb::build_subpackage(ctx, image_state, subpackage, dir, output_dir)
+            .await
+            .map(Some),
+        BuildTarget::Rpm => rpm::build_subpackage(ctx, image_state, subpackage, dir, output_dir)
+            .await
+            .map(Some),
+        _ => Ok(None),
     }
 }
@@ -0,0 +1,214 @@
+use crate::build::container::Context;
+use crate::container::ExecOpts;
+use crate::recipe::{BuildArch, Recipe};
+use crate::{err, ErrContext, Error, Result};
+
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, info_span, Instrument};
+
+/// Id of the component group with the files of the package generated by `wixl-heat`
+const COMPONENT_GROUP: &str = "ProductFiles";
+
+pub fn package_name(ctx: &Context<'_>, extension: bool) -> String {
+    let metadata = &ctx.build.recipe.metadata;
+    format!(
+        "{}-{}-{}.{}{}",
+        &metadata.name,
+        &metadata.version,
+        metadata.release(),
+        wixl_arch(&metadata.arch)
+            .map(|(arch, _)| arch)
+            .unwrap_or_else(|_| metadata.arch.as_ref()),
+        if extension { ".msi" } else { "" },
+    )
+}
+
+/// Returns the architecture passed to `wixl` and the id of the program files directory for
+/// the `arch` of the recipe.
+fn wixl_arch(arch: &BuildArch) -> Result<(&'static str, &'static str)> {
+    match arch {
+        BuildArch::x86_64 => Ok(("x64", "ProgramFiles64Folder")),
+        BuildArch::x86 | BuildArch::All => Ok(("x86", "ProgramFilesFolder")),
+        arch => err!(
+            "MSI packages can't be built for `{}`, only `x86_64`, `x86` and `noarch` are supported",
+            arch
+        ),
+    }
+}
+
+/// Converts the `version` of the recipe to the `major.minor.build` format of MSI taking the
+/// leading digits of each part, so `1.2.3~git20240501` becomes `1.2.3`.
+fn msi_version(version: &str) -> Result<String> {
+    const LIMITS: [u32; 3] = [255, 255, 65535];
+    let mut parts = vec![];
+    for (part, limit) in version.split('.').zip(LIMITS) {
+        let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+        if digits.is_empty() {
+            break;
+        }
+        match digits.parse::<u32>() {
+            Ok(n) if n <= limit => parts.push(n.to_string()),
+            _ => return err!("version `{}` is out of the range supported by MSI", version),
+        }
+        if digits.len() != part.len() {
+            break;
+        }
+    }
+    if parts.is_empty() {
+        return err!(
+            "version `{}` doesn't start with a number required by MSI",
+            version
+        );
+    }
+    Ok(parts.join("."))
+}
+
+/// Derives a stable upgrade code from the `name` of the package so that all versions of it are
+/// recognized as the same product.
+fn upgrade_code(name: &str) -> String {
+    let digest = Sha256::digest(format!("pkger-msi:{}", name).as_bytes());
+    let hex: String = digest[..16].iter().map(|b| format!("{:02X}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders the WiX source of the product. Files are added by `wixl-heat` during the build to
+/// the `ProductFiles` component group installed to `Program Files\<name>`.
+pub fn render_wxs(recipe: &Recipe) -> Result<String> {
+    let metadata = &recipe.metadata;
+    let msi = metadata.msi.as_ref();
+    let (_, program_files) = wixl_arch(&metadata.arch)?;
+    let manufacturer = msi
+        .and_then(|msi| msi.manufacturer.as_deref())
+        .or(metadata.maintainer.as_deref())
+        .unwrap_or(&metadata.name);
+    let upgrade_code = msi
+        .and_then(|msi| msi.upgrade_code.clone())
+        .unwrap_or_else(|| upgrade_code(&metadata.name));
+
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<Wix xmlns="http://schemas.microsoft.com/wix/2006/wi">
+  <Product Id="*" Name="{name}" Version="{version}" Manufacturer="{manufacturer}" Language="1033" UpgradeCode="{upgrade_code}">
+    <Package InstallerVersion="200" Compressed="yes" InstallScope="perMachine" Description="{description}"/>
+    <Media Id="1" Cabinet="product.cab" EmbedCab="yes"/>
+    <Directory Id="TARGETDIR" Name="SourceDir">
+      <Directory Id="{program_files}">
+        <Directory Id="INSTALLDIR" Name="{name}"/>
+      </Directory>
+    </Directory>
+    <Feature Id="Complete" Level="1">
+      <ComponentGroupRef Id="{component_group}"/>
+    </Feature>
+  </Product>
+</Wix>
+"#,
+        name = escape_xml(&metadata.name),
+        version = msi_version(&metadata.version)?,
+        manufacturer = escape_xml(manufacturer),
+        upgrade_code = upgrade_code,
+        description = escape_xml(&metadata.description),
+        program_files = program_files,
+        component_group = COMPONENT_GROUP,
+    ))
+}
+
+/// Creates a final MSI package from the files installed under the prefix with `wixl` and saves
+/// it to `output_dir`
+pub(crate) async fn build(ctx: &Context<'_>, output_dir: &Path) -> Result<PathBuf> {
+    let package_name = package_name(ctx, false);
+
+    let span = info_span!("MSI", package = %package_name);
+    let cloned_span = span.clone();
+    async move {
+        info!("building MSI package");
+        let recipe = &ctx.build.recipe;
+        let (arch, _) = wixl_arch(&recipe.metadata.arch)?;
+
+        let wxs = cloned_span.in_scope(|| render_wxs(recipe))?;
+        debug!(wxs = %wxs);
+
+        let msibld_dir = PathBuf::from("/root/msibuild");
+        ctx.create_dirs(&[msibld_dir.as_path()])
+            .await
+            .context("failed to create dirs")?;
+        ctx.upload_text_files(
+            vec![("product.wxs".to_string(), wxs.as_bytes())],
+            &msibld_dir,
+        )
+        .await
+        .context("failed to upload WiX source to container")?;
+
+        let msi_name = [&package_name, ".msi"].join("");
+        let msi = msibld_dir.join(&msi_name);
+        let prefix_dir = ctx
+            .build
+            .container_out_dir
+            .join(recipe.metadata.prefix.trim_start_matches('/'));
+
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    r#"src={prefix}; [ -d "$src" ] || src={out}; cd "$src" && find . -type f | sed 's|^\./||' | wixl-heat --var var.SourceDir --component-group {group} --directory-ref INSTALLDIR > {bld}/files.wxs && wixl --arch {arch} -D SourceDir="$src" -o {msi} {bld}/product.wxs {bld}/files.wxs"#,
+                    prefix = prefix_dir.display(),
+                    out = ctx.build.container_out_dir.display(),
+                    group = COMPONENT_GROUP,
+                    bld = msibld_dir.display(),
+                    arch = arch,
+                    msi = msi.display(),
+                ))
+                .build(),
+        )
+        .await
+        .context("failed to build msi package")?;
+
+        ctx.container
+            .download_files(&msi, output_dir)
+            .await
+            .map(|_| output_dir.join(msi_name))
+            .context("failed to download finished package")
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn converts_versions() {
+        assert_eq!(msi_version("1.2.3").unwrap(), "1.2.3");
+        assert_eq!(msi_version("1.2.3.4").unwrap(), "1.2.3");
+        assert_eq!(msi_version("0.7~git20240501").unwrap(), "0.7");
+        assert_eq!(
+            msi_version("2024.1").unwrap_err().to_string(),
+            "version `2024.1` is out of the range supported by MSI"
+        );
+        assert!(msi_version("v1.0").is_err());
+    }
+
+    #[test]
+    fn derives_stable_upgrade_codes() {
+        let code = upgrade_code("pkger");
+        assert_eq!(code, upgrade_code("pkger"));
+        assert_ne!(code, upgrade_code("pkger-dev"));
+        assert_eq!(code.len(), 36);
+        assert_eq!(code.matches('-').count(), 4);
+    }
+}
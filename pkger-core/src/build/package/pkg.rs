@@ -10,7 +10,7 @@ pub fn package_name(ctx: &Context<'_>, extension: bool) -> String {
     format!(
         "{}-{}-{}-{}{}",
         &ctx.build.recipe.metadata.name,
-        &ctx.build.recipe.metadata.version,
+        ctx.build.recipe.metadata.pkg_version(),
         &ctx.build.recipe.metadata.release(),
         ctx.build.recipe.metadata.arch.pkg_name(),
         if extension { ".pkg" } else { "" },
@@ -87,14 +87,37 @@ pub(crate) async fn build(
             .render();
         debug!(PKGBUILD = %pkgbuild);
 
-        ctx.container
-            .upload_files(
-                vec![("PKGBUILD".to_string(), pkgbuild.as_bytes())],
-                &bld_dir,
-                ctx.build.quiet,
-            )
-            .await
-            .context("failed to upload PKGBUILD to container")?;
+        ctx.upload_text_files(
+            vec![("PKGBUILD".to_string(), pkgbuild.as_bytes())],
+            &bld_dir,
+        )
+        .await
+        .context("failed to upload PKGBUILD to container")?;
+
+        let metadata = &ctx.build.recipe.metadata;
+        if let Some(pkg) = &metadata.pkg {
+            let install_file = pkg.install_file(&metadata.name);
+            if let (Some(install_file), Some(install)) = (&install_file, pkg.render_install()) {
+                debug!(install = %install);
+                ctx.upload_text_files(vec![(install_file.as_str(), install.as_bytes())], &bld_dir)
+                    .await
+                    .context("failed to upload install file to container")?;
+            } else if let (Some(install_file), Some(path)) = (&install_file, &pkg.install) {
+                trace!(path = %path, "copy install file");
+                ctx.checked_exec(
+                    &ExecOpts::default()
+                        .cmd(&format!(
+                            "cp {} {}",
+                            path,
+                            bld_dir.join(install_file).display()
+                        ))
+                        .working_dir(&ctx.build.container_out_dir)
+                        .build(),
+                )
+                .await
+                .context("failed to copy install file")?;
+            }
+        }
 
         trace!("create build user");
         ctx.script_exec([
@@ -121,7 +144,18 @@ pub(crate) async fn build(
         ])
         .await?;
 
-        let pkg = format!("{}.pkg.tar.zst", package_name);
+        // makepkg includes the epoch in the name of the package
+        let pkg = match &metadata.epoch {
+            Some(epoch) => format!(
+                "{}-{}:{}-{}-{}.pkg.tar.zst",
+                metadata.name,
+                epoch,
+                metadata.pkg_version(),
+                metadata.release(),
+                metadata.arch.pkg_name()
+            ),
+            None => format!("{}.pkg.tar.zst", package_name),
+        };
         let pkg_path = bld_dir.join(&pkg);
 
         ctx.container
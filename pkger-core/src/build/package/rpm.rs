@@ -1,12 +1,14 @@
 use crate::build::container::Context;
-use crate::build::package::sign::{import_gpg_key, upload_gpg_key};
+use crate::build::package::sign::{export_public_key, import_gpg_key, upload_gpg_key};
 use crate::container::ExecOpts;
 use crate::image::ImageState;
-use crate::recipe::BuildArch;
-use crate::{ErrContext, Result};
+use crate::recipe::{BuildArch, Subpackage};
+use crate::{err, ErrContext, Error, Result};
 
+use std::fs;
 use std::path::{Path, PathBuf};
-use tracing::{debug, info, info_span, trace, Instrument};
+use std::time::SystemTime;
+use tracing::{debug, info, info_span, trace, warn, Instrument};
 
 pub fn package_name(ctx: &Context<'_>, extension: bool) -> String {
     format!(
@@ -101,20 +103,19 @@ pub(crate) async fn build(
         trace!(source_files = ?files);
 
         let spec = cloned_span.in_scope(|| {
-            recipe
-                .as_rpm_spec(&[source_tar], &files[..], &image_state.image)
-                .render()
-        });
+            recipe.render_rpm_spec(
+                &[source_tar],
+                &files[..],
+                &image_state.image,
+                &ctx.build_info,
+                &ctx.vars,
+            )
+        })?;
 
         let spec_file = [&recipe.metadata.name, ".spec"].join("");
         debug!(spec_file = %spec_file, spec = %spec);
 
-        ctx.container
-            .upload_files(
-                vec![(["./", &spec_file].join(""), spec.as_bytes())],
-                &specs,
-                ctx.build.quiet,
-            )
+        ctx.upload_text_files(vec![(["./", &spec_file].join(""), spec.as_bytes())], &specs)
             .await
             .context("failed to upload spec file to container")?;
 
@@ -155,7 +156,25 @@ pub(crate) async fn build(
         .await
         .context("failed to copy source rpm to final directory")?;
 
-        sign_package(ctx, &arch_dir.join(rpm_name)).await?;
+        sign_package(ctx, &arch_dir.join(&rpm_name)).await?;
+
+        if recipe
+            .metadata
+            .rpm
+            .as_ref()
+            .map(|rpm| rpm.deltarpm)
+            .unwrap_or_default()
+        {
+            let package_dir = ctx.build.package_dir(image_state);
+            if let Err(e) = build_delta(ctx, &arch_dir, &rpm_name, &package_dir).await {
+                warn!(reason = %format!("{:?}", e), "failed to generate deltarpm");
+            }
+        }
+
+        if let Some(gpg_key) = &ctx.build.gpg_key {
+            let key = export_public_key(ctx, gpg_key, output_dir).await?;
+            info!(key = %key.display(), "exported public key");
+        }
 
         ctx.container
             .download_files(&arch_dir, output_dir)
@@ -167,6 +186,272 @@ pub(crate) async fn build(
     .await
 }
 
+/// Creates a `-debuginfo` package from the debug information saved to `debug_dir` and saves it to
+/// `output_dir`
+pub(crate) async fn build_debuginfo(
+    ctx: &Context<'_>,
+    debug_dir: &Path,
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let metadata = &ctx.build.recipe.metadata;
+    let arch = metadata.arch.rpm_name();
+    let rpm_name = format!(
+        "{}-debuginfo-{}-{}.{}.rpm",
+        metadata.name,
+        metadata.version,
+        metadata.release(),
+        arch
+    );
+
+    let span = info_span!("RPM-debuginfo", package = %rpm_name);
+    let cloned_span = span.clone();
+    async move {
+        info!("building RPM debuginfo package");
+
+        let base_path = PathBuf::from("/root/rpmbuild");
+        let specs = base_path.join("SPECS");
+        let arch_dir = base_path.join("RPMS").join(arch);
+        ctx.create_dirs(&[specs.as_path(), arch_dir.as_path()])
+            .await
+            .context("failed to create directories")?;
+
+        let spec = cloned_span.in_scope(|| {
+            ctx.build
+                .recipe
+                .as_rpm_debuginfo_spec(&debug_dir.to_string_lossy())
+                .render()
+        });
+        let spec_file = format!("{}-debuginfo.spec", metadata.name);
+        debug!(spec_file = %spec_file, spec = %spec);
+        ctx.upload_text_files(vec![(["./", &spec_file].join(""), spec.as_bytes())], &specs)
+            .await
+            .context("failed to upload spec file to container")?;
+
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "rpmbuild -bb --target {} {}",
+                    arch,
+                    specs.join(spec_file).display()
+                ))
+                .build(),
+        )
+        .await
+        .context("failed to build debuginfo package")?;
+
+        let package = arch_dir.join(&rpm_name);
+        sign_package(ctx, &package).await?;
+
+        ctx.container
+            .download_files(&package, output_dir)
+            .await
+            .map(|_| output_dir.join(rpm_name))
+            .context("failed to download debuginfo package")
+    }
+    .instrument(span)
+    .await
+}
+
+/// Creates the `subpackage` from the files moved to `dir` and saves it to `output_dir`
+pub(crate) async fn build_subpackage(
+    ctx: &Context<'_>,
+    image_state: &ImageState,
+    subpackage: &Subpackage,
+    dir: &Path,
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let metadata = &ctx.build.recipe.metadata;
+    let arch = metadata.arch.rpm_name();
+    let rpm_name = format!(
+        "{}-{}-{}.{}.rpm",
+        subpackage.name,
+        metadata.version,
+        metadata.release(),
+        arch
+    );
+
+    let span = info_span!("RPM-subpackage", package = %rpm_name);
+    let cloned_span = span.clone();
+    async move {
+        info!("building RPM subpackage");
+
+        let base_path = PathBuf::from("/root/rpmbuild");
+        let specs = base_path.join("SPECS");
+        let arch_dir = base_path.join("RPMS").join(arch);
+        ctx.create_dirs(&[specs.as_path(), arch_dir.as_path()])
+            .await
+            .context("failed to create directories")?;
+
+        let files = ctx
+            .checked_exec(
+                &ExecOpts::default()
+                    .cmd(r#"find . -type f -o -type l -name "*""#)
+                    .working_dir(dir)
+                    .build(),
+            )
+            .await
+            .map(|out| {
+                out.stdout
+                    .join("")
+                    .split('\n')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.trim_start_matches('.').to_string())
+                    .collect::<Vec<_>>()
+            })
+            .context("failed to find subpackage files")?;
+
+        let spec = cloned_span.in_scope(|| {
+            ctx.build
+                .recipe
+                .as_rpm_subpackage_spec(
+                    subpackage,
+                    &dir.to_string_lossy(),
+                    &files,
+                    &image_state.image,
+                )
+                .render()
+        });
+        let spec_file = format!("{}.spec", subpackage.name);
+        debug!(spec_file = %spec_file, spec = %spec);
+        ctx.upload_text_files(vec![(["./", &spec_file].join(""), spec.as_bytes())], &specs)
+            .await
+            .context("failed to upload spec file to container")?;
+
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "rpmbuild -bb --target {} {}",
+                    arch,
+                    specs.join(spec_file).display()
+                ))
+                .build(),
+        )
+        .await
+        .context("failed to build subpackage")?;
+
+        let package = arch_dir.join(&rpm_name);
+        sign_package(ctx, &package).await?;
+
+        ctx.container
+            .download_files(&package, output_dir)
+            .await
+            .map(|_| output_dir.join(rpm_name))
+            .context("failed to download subpackage")
+    }
+    .instrument(span)
+    .await
+}
+
+/// Returns `<version>-<release>` of the RPM `file_name` if it is a package of `name` built for
+/// `arch`.
+fn rpm_version<'f>(file_name: &'f str, name: &str, arch: &str) -> Option<&'f str> {
+    let version = file_name
+        .strip_prefix(name)?
+        .strip_prefix('-')?
+        .strip_suffix(".rpm")?
+        .strip_suffix(arch)?
+        .strip_suffix('.')?;
+    // versions and releases of RPMs can't contain `-`, more dashes mean a different package
+    if version.matches('-').count() == 1 {
+        Some(version)
+    } else {
+        None
+    }
+}
+
+/// Finds the most recently modified RPM of the same package and architecture in `output_dir`
+/// other than `current`. Returns its path with `<version>-<release>` of the package.
+fn previous_rpm(
+    output_dir: &Path,
+    name: &str,
+    arch: &str,
+    current: &str,
+) -> Option<(PathBuf, String)> {
+    let mut previous: Option<(SystemTime, PathBuf, String)> = None;
+    for entry in fs::read_dir(output_dir).ok()?.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name == current {
+            continue;
+        }
+        let version = match rpm_version(&file_name, name, arch) {
+            Some(version) => version.to_string(),
+            None => continue,
+        };
+        let modified = entry
+            .metadata()
+            .and_then(|md| md.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        if previous
+            .as_ref()
+            .map(|(time, _, _)| modified > *time)
+            .unwrap_or(true)
+        {
+            previous = Some((modified, entry.path(), version));
+        }
+    }
+    previous.map(|(_, path, version)| (path, version))
+}
+
+/// Generates a deltarpm between the previous RPM of the package found in `package_dir` and the
+/// new `rpm_name` in `arch_dir`. The delta is saved to `arch_dir` so that it's downloaded together
+/// with the package.
+async fn build_delta(
+    ctx: &Context<'_>,
+    arch_dir: &Path,
+    rpm_name: &str,
+    package_dir: &Path,
+) -> Result<()> {
+    let metadata = &ctx.build.recipe.metadata;
+    let arch = metadata.arch.rpm_name();
+    let (previous, old_version) =
+        if let Some(previous) = previous_rpm(package_dir, &metadata.name, arch, rpm_name) {
+            previous
+        } else {
+            info!("no previous RPM found, skipping deltarpm");
+            return Ok(());
+        };
+    let span = info_span!("deltarpm", previous = %previous.display());
+    async move {
+        let old_name = match previous.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return err!("invalid package path `{}`", previous.display()),
+        };
+        let dir = ctx.build.container_tmp_dir.join("deltarpm");
+        ctx.create_dirs(&[dir.as_path()]).await?;
+        let data =
+            fs::read(&previous).context(format!("failed to read `{}`", previous.display()))?;
+        ctx.container
+            .upload_files(vec![(old_name.as_str(), data.as_slice())], &dir)
+            .await
+            .context("failed to upload previous RPM")?;
+
+        let delta_name = format!(
+            "{}-{}_{}-{}.{}.drpm",
+            metadata.name,
+            old_version,
+            metadata.version,
+            metadata.release(),
+            arch
+        );
+        info!(delta = %delta_name, "generating deltarpm");
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "makedeltarpm {} {} {}",
+                    dir.join(&old_name).display(),
+                    arch_dir.join(rpm_name).display(),
+                    arch_dir.join(&delta_name).display()
+                ))
+                .build(),
+        )
+        .await
+        .map(|_| ())
+        .context("failed to run makedeltarpm")
+    }
+    .instrument(span)
+    .await
+}
+
 pub(crate) async fn sign_package(ctx: &Context<'_>, package: &Path) -> Result<()> {
     let span = info_span!("sign", package = %package.display());
     async move {
@@ -200,7 +485,6 @@ pub(crate) async fn sign_package(ctx: &Context<'_>, package: &Path) -> Result<()
             ("./.rpmmacros", macros.as_bytes()),
         ],
         "/root/",
-        ctx.build.quiet,
         ).await.context("failed to upload rpm macros")?;
 
 
@@ -239,3 +523,47 @@ pub(crate) async fn sign_package(ctx: &Context<'_>, package: &Path) -> Result<()
     .instrument(span)
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn finds_previous_rpms() {
+        assert_eq!(
+            rpm_version("pkger-0.7.0-1.x86_64.rpm", "pkger", "x86_64"),
+            Some("0.7.0-1")
+        );
+        assert_eq!(
+            rpm_version("pkger-cli-0.7.0-1.x86_64.rpm", "pkger", "x86_64"),
+            None
+        );
+        assert_eq!(
+            rpm_version("pkger-0.7.0-1.src.rpm", "pkger", "x86_64"),
+            None
+        );
+        assert_eq!(
+            rpm_version("pkger-0.7.0-1.noarch.rpm", "pkger", "x86_64"),
+            None
+        );
+
+        let tmp = tempdir::TempDir::new("pkger-deltarpm").unwrap();
+        let dir = tmp.path();
+        for name in [
+            "pkger-0.6.0-0.x86_64.rpm",
+            "pkger-0.6.0-0.src.rpm",
+            "pkger-0.7.0-0.x86_64.rpm",
+        ] {
+            fs::write(dir.join(name), b"rpm").unwrap();
+        }
+        assert_eq!(
+            previous_rpm(dir, "pkger", "x86_64", "pkger-0.7.0-0.x86_64.rpm"),
+            Some((dir.join("pkger-0.6.0-0.x86_64.rpm"), "0.6.0-0".to_string()))
+        );
+        assert_eq!(
+            previous_rpm(dir, "pkger", "aarch64", "pkger-0.7.0-0.aarch64.rpm"),
+            None
+        );
+    }
+}
@@ -23,11 +23,7 @@ pub(crate) async fn upload_gpg_key(
         .context("failed reading the gpg key")?;
 
     ctx.container
-        .upload_files(
-            vec![("./GPG-SIGN-KEY", key.as_slice())],
-            &destination,
-            ctx.build.quiet,
-        )
+        .upload_files(vec![("./GPG-SIGN-KEY", key.as_slice())], &destination)
         .instrument(span)
         .await
         .map(|_| destination.join("GPG-SIGN-KEY"))
@@ -46,3 +42,36 @@ pub(crate) async fn import_gpg_key(ctx: &Context<'_>, gpg_key: &GpgKey, path: &P
     .await
     .map(|_| ())
 }
+
+/// Exports the ASCII-armored public part of the imported `gpg_key` to `output_dir` so that
+/// consumers of the packages can verify the signatures. Returns the path to the exported key.
+pub(crate) async fn export_public_key(
+    ctx: &Context<'_>,
+    gpg_key: &GpgKey,
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let file = gpg_key.public_key_file();
+    let span = info_span!("export-public-key", file = %file);
+    async move {
+        let key_path = ctx.build.container_tmp_dir.join(&file);
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    r#"gpg --armor --export '{}' > {}"#,
+                    gpg_key.name(),
+                    key_path.display()
+                ))
+                .build(),
+        )
+        .await
+        .context("failed to export public key")?;
+
+        ctx.container
+            .download_files(&key_path, output_dir)
+            .await
+            .map(|_| output_dir.join(file))
+            .context("failed to download public key")
+    }
+    .instrument(span)
+    .await
+}
@@ -0,0 +1,235 @@
+use crate::build::container::Context;
+use crate::container::ExecOpts;
+use crate::image::ImageState;
+use crate::recipe::Recipe;
+use crate::shell;
+use crate::{err, ErrContext, Error, Result};
+
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, info_span, Instrument};
+
+pub fn package_name(ctx: &Context<'_>, extension: bool) -> String {
+    let metadata = &ctx.build.recipe.metadata;
+    format!(
+        "{}-{}_{}.{}{}",
+        &metadata.name,
+        &metadata.version,
+        metadata.release(),
+        metadata.arch.xbps_name(),
+        if extension { ".xbps" } else { "" },
+    )
+}
+
+/// Returns the `pkgver` of the package in the `<name>-<version>_<revision>` form used by xbps.
+fn pkgver(recipe: &Recipe) -> Result<String> {
+    let metadata = &recipe.metadata;
+    if metadata.version.contains(['-', '_']) {
+        return err!(
+            "version `{}` can't contain `-` or `_` in xbps packages",
+            metadata.version
+        );
+    }
+    Ok(format!(
+        "{}-{}_{}",
+        metadata.name,
+        metadata.version,
+        metadata.release()
+    ))
+}
+
+/// Converts a dependency to a package pattern required by xbps, names without a version
+/// constraint match any version.
+fn pattern(dependency: &str) -> String {
+    if dependency.contains(['<', '>', '=']) {
+        dependency.to_string()
+    } else {
+        format!("{}>=0", dependency)
+    }
+}
+
+fn sorted<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut names: Vec<_> = names.into_iter().collect();
+    names.sort_unstable();
+    names
+}
+
+/// Returns the properties of the package as pairs of the `xbps-create` option and the value.
+pub fn properties(recipe: &Recipe, image: &str) -> Result<Vec<(&'static str, String)>> {
+    let metadata = &recipe.metadata;
+    let version = format!("{}_{}", metadata.version, metadata.release());
+    let mut props = vec![
+        ("--pkgver", pkgver(recipe)?),
+        ("--architecture", metadata.arch.xbps_name().to_string()),
+        ("--desc", metadata.description.clone()),
+        ("--license", metadata.license.clone()),
+    ];
+    if let Some(url) = &metadata.url {
+        props.push(("--homepage", url.clone()));
+    }
+    if let Some(maintainer) = &metadata.maintainer {
+        props.push(("--maintainer", maintainer.clone()));
+    }
+    if let Some(depends) = &metadata.depends {
+        let depends = sorted(depends.resolve_names(image));
+        if !depends.is_empty() {
+            let depends: Vec<_> = depends.into_iter().map(pattern).collect();
+            props.push(("--dependencies", depends.join(" ")));
+        }
+    }
+    if let Some(conflicts) = &metadata.conflicts {
+        let conflicts = sorted(conflicts.resolve_names(image));
+        if !conflicts.is_empty() {
+            let conflicts: Vec<_> = conflicts.into_iter().map(pattern).collect();
+            props.push(("--conflicts", conflicts.join(" ")));
+        }
+    }
+    let mut provides = metadata
+        .provides
+        .as_ref()
+        .map(|provides| sorted(provides.resolve_names(image)))
+        .unwrap_or_default();
+    provides.extend(metadata.renamed_from.iter().map(String::as_str));
+    if !provides.is_empty() {
+        let provides: Vec<_> = provides
+            .into_iter()
+            .map(|name| format!("{}-{}", name, version))
+            .collect();
+        props.push(("--provides", provides.join(" ")));
+    }
+    let mut replaces: Vec<_> = metadata
+        .replaces
+        .as_ref()
+        .map(|replaces| sorted(replaces.resolve_names(image)))
+        .unwrap_or_default()
+        .into_iter()
+        .map(pattern)
+        .collect();
+    replaces.extend(
+        metadata
+            .renamed_from
+            .iter()
+            .map(|old| format!("{}<{}", old, version)),
+    );
+    if !replaces.is_empty() {
+        props.push(("--replaces", replaces.join(" ")));
+    }
+    Ok(props)
+}
+
+/// Renders the properties of the package in a readable form.
+pub fn render_properties(recipe: &Recipe, image: &str) -> Result<String> {
+    Ok(properties(recipe, image)?
+        .into_iter()
+        .map(|(option, value)| format!("{}: {}\n", option.trim_start_matches('-'), value))
+        .collect())
+}
+
+/// Creates a final XBPS package with `xbps-create` from the output directory and saves it to
+/// `output_dir`
+pub(crate) async fn build(
+    ctx: &Context<'_>,
+    image_state: &ImageState,
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let package_name = package_name(ctx, false);
+
+    let span = info_span!("XBPS", package = %package_name);
+    let cloned_span = span.clone();
+    async move {
+        info!("building XBPS package");
+        let props = cloned_span.in_scope(|| properties(&ctx.build.recipe, &image_state.image))?;
+        debug!(properties = ?props);
+
+        let xbpsbld_dir = PathBuf::from("/root/xbpsbuild");
+        ctx.create_dirs(&[xbpsbld_dir.as_path()])
+            .await
+            .context("failed to create dirs")?;
+
+        let args: Vec<_> = props
+            .iter()
+            .map(|(option, value)| format!("{} {}", option, shell::quote(value)))
+            .collect();
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "xbps-create {} {}",
+                    args.join(" "),
+                    ctx.build.container_out_dir.display()
+                ))
+                .working_dir(&xbpsbld_dir)
+                .build(),
+        )
+        .await
+        .context("failed to build xbps package")?;
+
+        let xbps_name = [&package_name, ".xbps"].join("");
+        ctx.container
+            .download_files(&xbpsbld_dir.join(&xbps_name), output_dir)
+            .await
+            .map(|_| output_dir.join(xbps_name))
+            .context("failed to download finished package")
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::{Dependencies, RecipeRep};
+    use pretty_assertions::assert_eq;
+    use std::convert::TryFrom;
+
+    const TEST_RECIPE: &[u8] = include_bytes!("../../../../example/recipes/test-suite/recipe.yml");
+
+    #[test]
+    fn renders_properties() {
+        let rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let metadata = &mut recipe.metadata;
+        metadata.name = "pkger".to_string();
+        metadata.version = "1.2.0".to_string();
+        metadata.release = Some("1".to_string());
+        metadata.description = "Package building tool".to_string();
+        metadata.license = "MIT".to_string();
+        metadata.url = None;
+        metadata.maintainer = None;
+        metadata.arch = "x86_64".into();
+        metadata.depends = Some(
+            Dependencies::try_from(
+                serde_yaml::from_str::<serde_yaml::Value>("[curl, libc>=2.30]").unwrap(),
+            )
+            .unwrap(),
+        );
+        metadata.conflicts = Some(
+            Dependencies::try_from(
+                serde_yaml::from_str::<serde_yaml::Value>("[pkger-git]").unwrap(),
+            )
+            .unwrap(),
+        );
+        metadata.provides = None;
+        metadata.replaces = Some(
+            Dependencies::try_from(
+                serde_yaml::from_str::<serde_yaml::Value>("[pkger-legacy]").unwrap(),
+            )
+            .unwrap(),
+        );
+        metadata.renamed_from = vec!["pkger-old".to_string()];
+
+        assert_eq!(
+            render_properties(&recipe, "void").unwrap(),
+            "pkgver: pkger-1.2.0_1
+architecture: x86_64
+desc: Package building tool
+license: MIT
+dependencies: curl>=0 libc>=2.30
+conflicts: pkger-git>=0
+provides: pkger-old-1.2.0_1
+replaces: pkger-legacy>=0 pkger-old<1.2.0_1
+"
+        );
+
+        recipe.metadata.version = "1.2.0-rc1".to_string();
+        assert!(properties(&recipe, "void").is_err());
+    }
+}
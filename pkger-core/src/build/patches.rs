@@ -86,20 +86,9 @@ pub async fn collect(
 
         let to_copy = to_copy.iter().map(PathBuf::as_path).collect::<Vec<_>>();
 
-        let patches_archive = ctx.build.container_tmp_dir.join("patches.tar");
-        remote::fetch_fs_source(ctx, &to_copy, &patches_archive).await?;
-
-        ctx.checked_exec(
-            &ExecOpts::default()
-                .cmd(&format!(
-                    "tar xf {} -C {}",
-                    patches_archive.display(),
-                    patch_dir.display()
-                ))
-                .build(),
-        )
-        .await
-        .map(|_| out)
+        remote::fetch_fs_source(ctx, &to_copy, &patch_dir)
+            .await
+            .map(|_| out)
     }
     .instrument(span)
     .await
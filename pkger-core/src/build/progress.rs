@@ -0,0 +1,170 @@
+use crate::units::format_duration;
+
+use futures::channel::mpsc::UnboundedSender;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub type ProgressSender = UnboundedSender<Event>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// A phase of a build job
+pub enum Phase {
+    BuildImage,
+    CacheImage,
+    FetchSource,
+    ApplyPatches,
+    RunScripts,
+    Package,
+}
+
+impl Phase {
+    /// Rough estimate of how much of the job is finished when this phase starts
+    pub fn percent(&self) -> u8 {
+        match self {
+            Phase::BuildImage => 0,
+            Phase::CacheImage => 15,
+            Phase::FetchSource => 30,
+            Phase::ApplyPatches => 40,
+            Phase::RunScripts => 45,
+            Phase::Package => 85,
+        }
+    }
+}
+
+impl AsRef<str> for Phase {
+    fn as_ref(&self) -> &str {
+        match self {
+            Phase::BuildImage => "build_image",
+            Phase::CacheImage => "cache_image",
+            Phase::FetchSource => "fetch_source",
+            Phase::ApplyPatches => "apply_patches",
+            Phase::RunScripts => "run_scripts",
+            Phase::Package => "package",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+/// Time spent in a phase of a build job
+pub struct PhaseDuration {
+    pub phase: Phase,
+    /// Duration in seconds
+    pub duration: f32,
+}
+
+impl fmt::Display for PhaseDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.phase.as_ref(),
+            format_duration(Duration::from_secs_f32(self.duration))
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+/// Measures how long each phase of a build job takes
+pub struct PhaseTimer {
+    current: Option<(Phase, Instant)>,
+    durations: Vec<PhaseDuration>,
+}
+
+impl PhaseTimer {
+    /// Finishes the current phase and starts measuring `phase`.
+    pub fn start(&mut self, phase: Phase) {
+        self.stop();
+        self.current = Some((phase, Instant::now()));
+    }
+
+    /// Finishes the current phase and returns durations of all phases, the slowest first.
+    pub fn finish(&mut self) -> Vec<PhaseDuration> {
+        self.stop();
+        let mut durations = std::mem::take(&mut self.durations);
+        durations.sort_by(|a, b| b.duration.total_cmp(&a.duration));
+        durations
+    }
+
+    fn stop(&mut self) {
+        if let Some((phase, start)) = self.current.take() {
+            self.durations.push(PhaseDuration {
+                phase,
+                duration: start.elapsed().as_secs_f32(),
+            });
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Success,
+    UpToDate,
+    Failure,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+/// Progress event of a build job
+pub enum Event {
+    Started {
+        id: String,
+        recipe: String,
+        image: String,
+        target: String,
+    },
+    Phase {
+        id: String,
+        phase: Phase,
+        percent: u8,
+    },
+    Finished {
+        id: String,
+        status: Status,
+        duration: f32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        /// Artifacts that had the same names as existing files and the actions taken for them
+        conflicts: Vec<String>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn sorts_phase_durations() {
+        let mut timer = PhaseTimer {
+            current: None,
+            durations: vec![
+                PhaseDuration {
+                    phase: Phase::FetchSource,
+                    duration: 2.,
+                },
+                PhaseDuration {
+                    phase: Phase::RunScripts,
+                    duration: 30.21,
+                },
+            ],
+        };
+        timer.start(Phase::Package);
+
+        let durations = timer.finish();
+        assert_eq!(
+            durations
+                .iter()
+                .map(|duration| duration.phase)
+                .collect::<Vec<_>>(),
+            vec![Phase::RunScripts, Phase::FetchSource, Phase::Package]
+        );
+        assert_eq!(durations[0].to_string(), "run_scripts 30.2s");
+        assert!(timer.finish().is_empty());
+    }
+}
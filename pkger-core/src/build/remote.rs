@@ -1,13 +1,19 @@
-use crate::archive::create_tarball;
+use crate::archive::{create_tarball, tar};
+use crate::blocking;
 use crate::build::container::Context;
+use crate::build::{sandbox, sources};
 use crate::container::ExecOpts;
-use crate::recipe::GitSource;
+use crate::recipe::{ArchiveKind, GitSource, PeerSource, Source};
+use crate::shell;
 use crate::template;
-use crate::Result;
+use crate::{err, ErrContext, Error, Result};
 
+use futures::future::try_join_all;
+use ignore::WalkBuilder;
 use std::fs;
 use std::path::{Path, PathBuf};
-use tracing::{debug, info, info_span, Instrument};
+use std::process::{Command, Stdio};
+use tracing::{debug, info, info_span, warn, Instrument};
 
 pub async fn fetch_git_source(ctx: &Context<'_>, repo: &GitSource) -> Result<()> {
     let span = info_span!("clone-git");
@@ -28,6 +34,36 @@ pub async fn fetch_git_source(ctx: &Context<'_>, repo: &GitSource) -> Result<()>
         .await
 }
 
+/// Returns the commit that the `branch` of the git repository at `url` points to. Branches and
+/// tags are resolved on the host with `git ls-remote`, full commit hashes are returned as they are.
+/// Git never prompts for credentials so that a build can't hang on a private repository.
+pub async fn resolve_commit(url: &str, branch: &str) -> Result<String> {
+    if is_commit(branch) {
+        return Ok(branch.to_string());
+    }
+    let mut cmd = Command::new("git");
+    cmd.args(["ls-remote", "--exit-code", "--", url, branch])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_SSH_COMMAND", "ssh -o BatchMode=yes")
+        .stdin(Stdio::null());
+    let output = blocking::output(cmd, "git").await?;
+    match parse_ls_remote(&String::from_utf8_lossy(&output.stdout)) {
+        Some(commit) => Ok(commit.to_string()),
+        None => err!("failed to resolve `{}` of `{}`", branch, url),
+    }
+}
+
+fn is_commit(rev: &str) -> bool {
+    rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Returns the commit of the first reference listed by `git ls-remote`.
+fn parse_ls_remote(out: &str) -> Option<&str> {
+    out.lines()
+        .filter_map(|line| line.split_ascii_whitespace().next())
+        .find(|commit| is_commit(commit))
+}
+
 pub async fn fetch_http_source(ctx: &Context<'_>, source: &str, dest: &Path) -> Result<()> {
     let span = info_span!("download-http");
     async move {
@@ -45,10 +81,14 @@ pub async fn fetch_http_source(ctx: &Context<'_>, source: &str, dest: &Path) ->
     .await
 }
 
+/// Copies `files` to the `dest` directory in the container.
 pub async fn fetch_fs_source(ctx: &Context<'_>, files: &[&Path], dest: &Path) -> Result<()> {
     let span = info_span!("copy-files-into");
     let mut entries = Vec::new();
     for f in files {
+        if ctx.build.sandbox().is_some() {
+            sandbox::check_path(f, &ctx.build.recipe.recipe_dir)?;
+        }
         debug!(parent: &span, entry = %f.display(), "adding");
         let filename = f
             .file_name()
@@ -60,12 +100,52 @@ pub async fn fetch_fs_source(ctx: &Context<'_>, files: &[&Path], dest: &Path) ->
     let archive = span.in_scope(|| create_tarball(entries.iter().map(|(p, b)| (p, &b[..]))))?;
 
     ctx.container
-        .inner()
-        .copy_file_into(dest, &archive)
+        .extract_archive(archive, dest)
         .instrument(span.clone())
-        .await?;
+        .await
+}
+
+/// Name of the file that lists paths to skip when using a local directory as source.
+pub static IGNORE_FILE: &str = ".pkgerignore";
+
+/// Copies the contents of a local directory to `dest` in the container skipping all paths matched
+/// by patterns from `.pkgerignore` files.
+pub async fn fetch_dir_source(ctx: &Context<'_>, dir: &Path, dest: &Path) -> Result<()> {
+    let span = info_span!("copy-dir-into", dir = %dir.display());
+    let cloned_span = span.clone();
+    async move {
+        if let Some(sandbox) = ctx.build.sandbox() {
+            sandbox.check_source_dir(dir, &ctx.build.recipe.recipe_dir)?;
+        }
+        info!(destination = %dest.display(), "copying local directory");
+        let archive = cloned_span.in_scope(|| -> Result<Vec<u8>> {
+            let mut builder = tar::Builder::new(Vec::new());
+            let walker = WalkBuilder::new(dir)
+                .standard_filters(false)
+                .add_custom_ignore_filename(IGNORE_FILE)
+                .build();
+            for entry in walker {
+                let entry = entry.context("failed to read directory entry")?;
+                let path = entry.path();
+                if !entry.file_type().map(|ty| ty.is_file()).unwrap_or(false) {
+                    continue;
+                }
+                let name = path.strip_prefix(dir).unwrap_or(path);
+                debug!(entry = %name.display(), "adding");
+                builder
+                    .append_path_with_name(path, name)
+                    .context("failed to add entry to archive")?;
+            }
+            builder.into_inner().context("failed to create tar archive")
+        })?;
 
-    Ok(())
+        ctx.container
+            .extract_archive(archive, dest)
+            .await
+            .context("failed to copy local directory to container")
+    }
+    .instrument(span)
+    .await
 }
 
 pub async fn fetch_source(ctx: &Context<'_>) -> Result<()> {
@@ -73,40 +153,252 @@ pub async fn fetch_source(ctx: &Context<'_>) -> Result<()> {
     async move {
         if let Some(repo) = &ctx.build.recipe.metadata.git {
             fetch_git_source(ctx, repo).await?;
-        } else if let Some(source) = &ctx.build.recipe.metadata.source {
-            let source = template::render(source, ctx.vars.inner());
-            if source.starts_with("http") {
-                fetch_http_source(ctx, source.as_str(), &ctx.build.container_tmp_dir).await?;
-            } else {
-                let src_path = PathBuf::from(source);
-                fetch_fs_source(ctx, &[src_path.as_path()], &ctx.build.container_tmp_dir).await?;
+        } else {
+            let sources = &ctx.build.recipe.metadata.source;
+            let fetched = try_join_all(
+                sources
+                    .iter()
+                    .enumerate()
+                    .map(|(i, source)| fetch_single_source(ctx, source, i)),
+            )
+            .await?;
+            // sources are moved to the shared build directory one after another in the order of
+            // the recipe so that archives with overlapping paths don't race
+            for (source, fetched) in sources.iter().zip(fetched) {
+                match fetched {
+                    Fetched::Dir(path) => {
+                        fetch_dir_source(ctx, &path, &ctx.build.container_bld_dir).await?
+                    }
+                    Fetched::File { file_name, tmp_dir } => {
+                        ctx.checked_exec(
+                            &ExecOpts::default()
+                                .cmd(&unpack_cmd(
+                                    source,
+                                    &file_name,
+                                    &ctx.build.container_bld_dir,
+                                ))
+                                .working_dir(&tmp_dir)
+                                .build(),
+                        )
+                        .await?;
+                    }
+                }
             }
+        }
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
+
+/// A fetched source that still has to be moved to the build directory.
+enum Fetched {
+    /// A local directory that is copied as is
+    Dir(PathBuf),
+    /// A file in the temporary directory of the source that is extracted if it is an archive that
+    /// should be unpacked, otherwise it is copied untouched
+    File { file_name: String, tmp_dir: PathBuf },
+}
+
+/// Fetches the `source` to a temporary directory. Sources are fetched concurrently so each of
+/// them gets its own temporary directory based on its `index`.
+async fn fetch_single_source(ctx: &Context<'_>, source: &Source, index: usize) -> Result<Fetched> {
+    let location = template::render(source.source(), ctx.vars.inner());
+    let tmp_dir = ctx
+        .build
+        .container_tmp_dir
+        .join(format!("source-{}", index));
+    ctx.create_dirs(&[tmp_dir.as_path()]).await?;
+
+    let file_name = if let Some(peer) = PeerSource::parse(&location) {
+        sources::fetch_peer(ctx, peer, &location, source.sha256(), &tmp_dir).await?
+    } else if location.starts_with("http") {
+        fetch_http_mirrors(ctx, source, &location, &tmp_dir).await?
+    } else {
+        let src_path = PathBuf::from(&location);
+        if src_path.is_dir() {
+            return Ok(Fetched::Dir(src_path));
+        }
+        fetch_fs_source(ctx, &[src_path.as_path()], &tmp_dir).await?;
+        let file_name = src_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if let Some(sha256) = source.sha256() {
+            verify_checksum(ctx, &file_name, sha256, &tmp_dir).await?;
+        }
+        file_name
+    };
+
+    if source.strip_components() > 0
+        && ArchiveKind::from_file_name(&file_name) == Some(ArchiveKind::Zip)
+    {
+        warn!(source = %location, "strip_components is not supported for zip archives");
+    }
+
+    Ok(Fetched::File { file_name, tmp_dir })
+}
+
+/// Returns the name of the file downloaded from `url`, the last segment of its path without the
+/// query and fragment.
+fn url_file_name(url: &str) -> Result<String> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path.rsplit('/').next().unwrap_or_default() {
+        "" | "." | ".." => err!("failed to determine the file name of source `{}`", url),
+        file_name => Ok(file_name.to_string()),
+    }
+}
+
+/// Downloads the source from `location` or from its mirrors in order until one of them succeeds
+/// and has the expected checksum to `dest`. Returns the name of the downloaded file.
+async fn fetch_http_mirrors(
+    ctx: &Context<'_>,
+    source: &Source,
+    location: &str,
+    dest: &Path,
+) -> Result<String> {
+    let file_name = url_file_name(location)?;
+    let mirrors = source
+        .mirrors()
+        .iter()
+        .map(|mirror| template::render(mirror, ctx.vars.inner()))
+        .collect::<Vec<_>>();
+
+    for url in std::iter::once(location).chain(mirrors.iter().map(String::as_str)) {
+        info!(url = %url, file = %file_name, "fetching");
+        // sandboxed builds don't use the shared cache so they can't plant files under trusted URLs
+        let result = if ctx.build.source_cache && ctx.build.sandbox().is_none() {
+            sources::fetch(ctx, url, &file_name, source.sha256(), dest).await
+        } else {
             ctx.checked_exec(
                 &ExecOpts::default()
                     .cmd(&format!(
-                        r#"
-                        for file in *;
-                        do
-                            if [[ $file =~ (.*[.]tar.*|.*[.](tgz|tbz|txz|tlz|tsz|taz|tz)) ]]
-                            then
-                                tar xvf $file -C {0}
-                            elif [[ $file == *.zip ]]
-                            then
-                                unzip $file -d {0}
-                            else
-                                cp -v $file {0}
-                            fi
-                        done"#,
-                        ctx.build.container_bld_dir.display(),
+                        "curl -fL -o {} {}",
+                        shell::quote(&file_name),
+                        shell::quote(url)
                     ))
-                    .working_dir(&ctx.build.container_tmp_dir)
-                    .shell("/bin/bash")
+                    .working_dir(dest)
                     .build(),
             )
-            .await?;
+            .await
+            .map(|_| ())
+        };
+        let result = match (result, source.sha256()) {
+            (Ok(_), Some(sha256)) => verify_checksum(ctx, &file_name, sha256, dest).await,
+            (result, _) => result,
+        };
+        match result {
+            Ok(_) => return Ok(file_name),
+            Err(e) => {
+                warn!(url = %url, reason = %format!("{:?}", e), "failed to fetch source");
+                ctx.checked_exec(
+                    &ExecOpts::default()
+                        .cmd(&format!("rm -f {}", shell::quote(&file_name)))
+                        .working_dir(dest)
+                        .build(),
+                )
+                .await?;
+            }
         }
-        Ok(())
     }
-    .instrument(span)
+
+    err!(
+        "failed to fetch source `{}` from {} location(s)",
+        location,
+        mirrors.len() + 1
+    )
+}
+
+/// Verifies that the SHA256 checksum of the fetched `file_name` in `dir` matches `sha256`.
+async fn verify_checksum(
+    ctx: &Context<'_>,
+    file_name: &str,
+    sha256: &str,
+    dir: &Path,
+) -> Result<()> {
+    ctx.checked_exec(
+        &ExecOpts::default()
+            .cmd(&format!(
+                "echo {} | sha256sum -c -",
+                shell::quote(&format!("{}  {}", sha256, file_name))
+            ))
+            .working_dir(dir)
+            .build(),
+    )
     .await
+    .map(|_| ())
+    .context(format!("checksum of `{}` doesn't match", file_name))
+}
+
+/// Returns the command that extracts or copies the fetched `file_name` to `dest`.
+fn unpack_cmd(source: &Source, file_name: &str, dest: &Path) -> String {
+    let file = shell::quote(file_name);
+    match ArchiveKind::from_file_name(file_name).filter(|_| source.unpack()) {
+        Some(ArchiveKind::Tar) if source.strip_components() > 0 => format!(
+            "tar xvf {} -C {} --strip-components={}",
+            file,
+            dest.display(),
+            source.strip_components()
+        ),
+        Some(ArchiveKind::Tar) => format!("tar xvf {} -C {}", file, dest.display()),
+        Some(ArchiveKind::Zip) => format!("unzip {} -d {}", file, dest.display()),
+        None => format!("cp -v {} {}", file, dest.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn renders_unpack_commands() {
+        let dest = Path::new("/tmp/bld");
+        assert_eq!(
+            unpack_cmd(&Source::from("pkger.tar.gz"), "pkger.tar.gz", dest),
+            "tar xvf 'pkger.tar.gz' -C /tmp/bld"
+        );
+        assert_eq!(
+            unpack_cmd(&Source::new("pkger.tgz", true, 1), "pkger.tgz", dest),
+            "tar xvf 'pkger.tgz' -C /tmp/bld --strip-components=1"
+        );
+        assert_eq!(
+            unpack_cmd(&Source::from("pkger.zip"), "pkger.zip", dest),
+            "unzip 'pkger.zip' -d /tmp/bld"
+        );
+        assert_eq!(
+            unpack_cmd(&Source::new("blob.tar.xz", false, 0), "blob.tar.xz", dest),
+            "cp -v 'blob.tar.xz' /tmp/bld"
+        );
+        assert_eq!(
+            unpack_cmd(&Source::from("firmware.bin"), "firmware.bin", dest),
+            "cp -v 'firmware.bin' /tmp/bld"
+        );
+    }
+
+    #[test]
+    fn derives_file_names_from_urls() {
+        assert_eq!(
+            url_file_name("https://host/pkger-0.1.tar.gz?raw=true#top").unwrap(),
+            "pkger-0.1.tar.gz"
+        );
+        assert_eq!(
+            url_file_name("https://host/dl/pkger.zip").unwrap(),
+            "pkger.zip"
+        );
+        assert!(url_file_name("https://host/dl/").is_err());
+        assert!(url_file_name("https://host/..?x=/a").is_err());
+    }
+
+    #[test]
+    fn parses_commits_of_remote_refs() {
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(
+            parse_ls_remote(&format!("{}\trefs/heads/master\n", commit)),
+            Some(commit)
+        );
+        assert_eq!(parse_ls_remote(""), None);
+        assert!(is_commit(commit));
+        assert!(!is_commit("master"));
+    }
 }
@@ -0,0 +1,275 @@
+//! Restrictions of builds of untrusted recipes. Sandboxed builds run in hardened containers, can
+//! only read local files from the directory of the recipe, lose network access once sources are
+//! fetched and fail as soon as the build and output directories grow over a limit.
+
+use crate::build::container::Context;
+use crate::container::ExecOpts;
+use crate::network::DEFAULT_MODE;
+use crate::recipe::Recipe;
+use crate::units::format_size;
+use crate::{err, ErrContext, Error, Result};
+
+use std::path::{Path, PathBuf};
+use tracing::{debug, info_span, Instrument};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Restrictions applied to builds of untrusted recipes
+pub struct Sandbox {
+    /// Maximum size of the build and output directories together in bytes
+    pub max_output_size: u64,
+    /// Directory passed by the user with `--source-path` that replaces the sources of the recipe
+    pub source_path: Option<PathBuf>,
+}
+
+impl Sandbox {
+    /// Verifies that the local directory `dir` used as a source can be copied to the container.
+    /// The directory passed with `--source-path` comes from the user and not from the recipe so
+    /// it is always allowed, every other directory has to be inside of `recipe_dir`.
+    pub fn check_source_dir(&self, dir: &Path, recipe_dir: &Path) -> Result<()> {
+        if let Some(source_path) = &self.source_path {
+            if let (Ok(dir), Ok(source_path)) = (dir.canonicalize(), source_path.canonicalize()) {
+                if dir == source_path {
+                    return Ok(());
+                }
+            }
+        }
+        check_path(dir, recipe_dir)
+    }
+}
+
+/// Verifies that the local file or directory at `path` is inside of `recipe_dir` so that
+/// sandboxed recipes can't copy other files of the host to the container.
+pub fn check_path(path: &Path, recipe_dir: &Path) -> Result<()> {
+    let resolve = |path: &Path| {
+        path.canonicalize()
+            .context(format!("failed to resolve `{}`", path.display()))
+    };
+    if !resolve(path)?.starts_with(resolve(recipe_dir)?) {
+        return err!(
+            "sandboxed builds can only use local files from the recipe directory, `{}` is outside of it",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Verifies that a sandboxed `recipe` only reads files of the host from its directory, like
+/// includes, the RPM changelog file and spec template or answers of steps, and that it has no
+/// interactive steps that would attach the terminal of the user.
+pub fn check_recipe(recipe: &Recipe) -> Result<()> {
+    let dir = &recipe.recipe_dir;
+    for include in &recipe.includes {
+        check_path(&dir.join(include), dir).context("invalid include")?;
+    }
+    if let Some(rpm) = &recipe.metadata.rpm {
+        if let Some(file) = &rpm.changelog_file {
+            check_path(&dir.join(file), dir).context("invalid RPM changelog file")?;
+        }
+        if let Some(template) = &rpm.spec_template {
+            check_path(&dir.join(template), dir).context("invalid RPM spec template")?;
+        }
+    }
+
+    let steps = recipe
+        .configure_script
+        .iter()
+        .flat_map(|script| &script.steps)
+        .chain(&recipe.build_script.steps)
+        .chain(
+            recipe
+                .install_script
+                .iter()
+                .flat_map(|script| &script.steps),
+        );
+    for step in steps {
+        if let Some(answers) = &step.answers {
+            check_path(&dir.join(answers), dir).context("invalid answers file")?;
+        }
+        if step.is_interactive() {
+            return err!(
+                "sandboxed builds can't run interactive step `{}`",
+                step.label()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Disconnects the build container from its network once the sources are fetched.
+pub async fn disconnect_network(ctx: &Context<'_>) -> Result<()> {
+    let mode = ctx.build.network().mode;
+    match mode.as_deref().unwrap_or(DEFAULT_MODE) {
+        "none" => Ok(()),
+        "host" => err!("sandboxed builds can't use the host network"),
+        network => ctx.container.disconnect_network(network).await,
+    }
+}
+
+/// Fails the build if the size of the build and output directories is over the limit of the
+/// `sandbox`. Called after fetching sources and after every step of the scripts so that a build
+/// can't fill the disk of the host before it is packaged.
+pub async fn check_output_size(ctx: &Context<'_>, sandbox: &Sandbox) -> Result<()> {
+    let span = info_span!("check-output-size");
+    async move {
+        let out = ctx
+            .checked_exec(
+                &ExecOpts::default()
+                    .cmd(&format!(
+                        "du -skc {} {}",
+                        ctx.build.container_bld_dir.display(),
+                        ctx.build.container_out_dir.display()
+                    ))
+                    .build(),
+            )
+            .await
+            .context("failed to check size of the build and output directories")?
+            .stdout
+            .join("");
+        let size = parse_du_size(&out)?;
+        debug!(size = %format_size(size), limit = %format_size(sandbox.max_output_size));
+        if size > sandbox.max_output_size {
+            return err!(
+                "build and output directories are {} which is over the limit of {}",
+                format_size(size),
+                format_size(sandbox.max_output_size)
+            );
+        }
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
+
+/// Returns the size in bytes from the total on the last line of the output of `du -skc`.
+fn parse_du_size(out: &str) -> Result<u64> {
+    out.lines()
+        .last()
+        .and_then(|total| total.split_ascii_whitespace().next())
+        .and_then(|kib| kib.parse::<u64>().ok())
+        .map(|kib| kib * 1024)
+        .ok_or_else(|| anyhow!("invalid output of du `{}`", out.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::RecipeRep;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use tempdir::TempDir;
+
+    const TEST_RECIPE: &[u8] = include_bytes!("../../../example/recipes/test-suite/recipe.yml");
+
+    /// Returns the result of checking the test recipe modified by `edit` in a recipe directory
+    /// next to a `secret` file that sandboxed recipes must not read.
+    fn check_in_recipe_dir(edit: impl FnOnce(&mut RecipeRep)) -> Result<()> {
+        let tmp = TempDir::new("pkger-sandbox").unwrap();
+        let recipe_dir = tmp.path().join("recipe");
+        fs::create_dir_all(&recipe_dir).unwrap();
+        fs::write(recipe_dir.join("allowed"), "").unwrap();
+        fs::write(tmp.path().join("secret"), "").unwrap();
+
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        edit(&mut rep);
+        check_recipe(&Recipe::new(rep, recipe_dir).unwrap())
+    }
+
+    #[test]
+    fn restricts_sandboxed_builds() {
+        assert_eq!(
+            parse_du_size("1024\t/tmp/pkger-build\n1024\t/tmp/pkger-out\n2048\ttotal\n").unwrap(),
+            2 * 1024 * 1024
+        );
+        assert!(parse_du_size("du: cannot access").is_err());
+
+        let tmp = TempDir::new("pkger-sandbox").unwrap();
+        let recipe_dir = tmp.path().join("recipe");
+        fs::create_dir_all(recipe_dir.join("patches")).unwrap();
+        fs::write(recipe_dir.join("patches/fix.patch"), "").unwrap();
+        fs::write(tmp.path().join("secret"), "").unwrap();
+
+        check_path(&recipe_dir.join("patches/fix.patch"), &recipe_dir).unwrap();
+        check_path(&recipe_dir, &recipe_dir).unwrap();
+        assert!(check_path(&recipe_dir.join("../secret"), &recipe_dir).is_err());
+        assert!(check_path(&tmp.path().join("secret"), &recipe_dir).is_err());
+    }
+
+    #[test]
+    fn allows_source_path_of_the_user() {
+        let tmp = TempDir::new("pkger-sandbox").unwrap();
+        let recipe_dir = tmp.path().join("recipe");
+        let checkout = tmp.path().join("checkout");
+        fs::create_dir_all(recipe_dir.join("src")).unwrap();
+        fs::create_dir_all(&checkout).unwrap();
+
+        let mut sandbox = Sandbox {
+            max_output_size: 1024,
+            source_path: None,
+        };
+        sandbox
+            .check_source_dir(&recipe_dir.join("src"), &recipe_dir)
+            .unwrap();
+        assert!(sandbox.check_source_dir(&checkout, &recipe_dir).is_err());
+
+        sandbox.source_path = Some(recipe_dir.join("../checkout"));
+        sandbox.check_source_dir(&checkout, &recipe_dir).unwrap();
+        assert!(sandbox
+            .check_source_dir(&tmp.path().join("recipe/.."), &recipe_dir)
+            .is_err());
+
+        // paths that don't exist never match the source path
+        sandbox.source_path = Some(tmp.path().join("missing"));
+        assert!(sandbox
+            .check_source_dir(&tmp.path().join("missing"), &recipe_dir)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_includes_outside_of_recipe_dir() {
+        check_in_recipe_dir(|rep| rep.include = vec!["allowed".into()]).unwrap();
+        assert!(check_in_recipe_dir(|rep| rep.include = vec!["../secret".into()]).is_err());
+    }
+
+    #[test]
+    fn rejects_rpm_changelog_file_outside_of_recipe_dir() {
+        check_in_recipe_dir(|rep| {
+            rep.metadata.rpm = Some(serde_yaml::from_str("changelog_file: allowed").unwrap())
+        })
+        .unwrap();
+        assert!(check_in_recipe_dir(|rep| {
+            rep.metadata.rpm = Some(serde_yaml::from_str("changelog_file: ../secret").unwrap())
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_rpm_spec_template_outside_of_recipe_dir() {
+        check_in_recipe_dir(|rep| {
+            rep.metadata.rpm = Some(serde_yaml::from_str("spec_template: allowed").unwrap())
+        })
+        .unwrap();
+        assert!(check_in_recipe_dir(|rep| {
+            rep.metadata.rpm = Some(serde_yaml::from_str("spec_template: ../secret").unwrap())
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_answers_outside_of_recipe_dir_and_interactive_steps() {
+        let error = check_in_recipe_dir(|rep| {
+            rep.build.steps[0].answers = Some("../secret".into());
+            rep.build.steps[0].interactive = Some(true);
+        })
+        .unwrap_err();
+        assert_eq!(error.to_string(), "invalid answers file");
+
+        let error = check_in_recipe_dir(|rep| {
+            rep.build.steps[0].answers = Some("allowed".into());
+            rep.build.steps[0].interactive = Some(true);
+        })
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("sandboxed builds can't run interactive step"));
+    }
+}
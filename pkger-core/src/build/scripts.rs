@@ -1,14 +1,21 @@
-use crate::build::container::Context;
-use crate::container::ExecOpts;
+use crate::build::container::{forwarded_env, Context};
+use crate::build::{sandbox, snapshot};
+use crate::container::{ExecOpts, Output, DEFAULT_SHELL};
+use crate::image::ImageState;
+use crate::recipe::{Command, Env};
 use crate::template;
-use crate::{Error, Result};
+use crate::{err, ErrContext, Error, Result};
 
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::fs;
 use std::path::PathBuf;
-use tracing::{debug, info, info_span, trace, Instrument};
+use tracing::{debug, info, info_span, trace, warn, Instrument};
 
 macro_rules! run_script {
-    ($phase:literal, $script:expr, $dir:expr,  $ctx:ident) => {{
+    ($phase:literal, $script:expr, $dir:expr, $ctx:ident, $hasher:ident) => {{
         let _span = info_span!($phase);
+        let hasher = &mut $hasher;
         async move {
             trace!(script = ?$script);
             info!(concat!("executing ", $phase, " scripts"));
@@ -24,31 +31,77 @@ macro_rules! run_script {
                 opts = opts.working_dir($dir);
             }
 
-            if let Some(shell) = &$script.shell {
+            // the shell of the phase takes precedence over the shell of the recipe
+            let shell = $script.shell.as_deref().or($ctx.build.recipe.shell.as_deref());
+            if let Some(shell) = shell {
                 trace!(shell = %shell);
-                opts = opts.shell(shell.as_str());
+                opts = opts.shell(shell);
             }
+            hasher.update(format!("{}\n{:?}\n{:?}\n", $phase, shell, $script.working_dir));
 
-            for cmd in &$script.steps {
+            for (i, cmd) in $script.steps.iter().enumerate() {
                 if let Some(images) = &cmd.images {
                     trace!(images = ?images, "only execute on");
                     if !images.contains(&$ctx.build.target.image().to_owned()) {
                         trace!(image = %$ctx.build.target.image(), "not found in images");
                         if !cmd.has_target_specified() {
-                            debug!(command = %cmd.cmd, "skipping, excluded by image filter");
+                            debug!(command = %cmd.label(), "skipping, excluded by image filter");
                             continue;
                         }
                     }
                 }
 
                 if !cmd.should_run_on($ctx.build.target.build_target()) {
-                    debug!(command = %cmd.cmd, "skipping, shouldn't run on target");
+                    debug!(command = %cmd.label(), "skipping, shouldn't run on target");
                     continue;
                 }
 
-                debug!(command = %cmd.cmd, "running");
-                $ctx.checked_exec(&opts.clone().cmd(&cmd.cmd).build())
-                    .await?;
+                hasher.update(serde_yaml::to_string(cmd).context("failed to serialize step")?);
+                let key = format!("{:x}", hasher.clone().finalize());
+                if cmd.is_cached() && snapshot::restore($ctx, $phase, i, &key).await? {
+                    info!(command = %cmd.label(), "step unchanged, skipping");
+                    continue;
+                }
+
+                debug!(command = %cmd.label(), "running");
+                let command = match cmd.script_file(shell.unwrap_or(DEFAULT_SHELL)) {
+                    Some(file) => upload_script($ctx, &format!("{}-step-{}", $phase, i), &file).await?,
+                    None => cmd.cmd.clone(),
+                };
+                let mut cmd_opts = opts.clone().cmd(&command);
+                // variables of the build info are only known after the container was created
+                let mut _env = $ctx
+                    .build_info
+                    .vars()
+                    .into_iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect::<Vec<_>>();
+                if let Some(env) = &cmd.env {
+                    _env.extend(
+                        Env::from(Some(env.clone()))
+                            .iter()
+                            .map(|(k, v)| format!("{}={}", k, template::render(v, $ctx.vars.inner()))),
+                    );
+                }
+                trace!(env = ?_env);
+                cmd_opts = cmd_opts.env(&_env);
+                let out = if cmd.is_interactive() {
+                    run_interactive($ctx, cmd, &command, cmd_opts).await?
+                } else {
+                    $ctx.container.exec(&cmd_opts.build(), $ctx.build.quiet)
+                        .await?
+                };
+                verify_output(cmd, &out)
+                    .context(format!(concat!($phase, " step `{}` failed"), cmd.label()))?;
+                if let Some(sandbox) = $ctx.build.sandbox() {
+                    sandbox::check_output_size($ctx, sandbox).await?;
+                }
+
+                if cmd.is_cached() {
+                    if let Err(e) = snapshot::save($ctx, $phase, i, &key).await {
+                        warn!(command = %cmd.label(), reason = %format!("{:?}", e), "failed to save step snapshot");
+                    }
+                }
             }
 
             Ok::<_, Error>(())
@@ -58,25 +111,138 @@ macro_rules! run_script {
     }};
 }
 
-pub async fn run(ctx: &Context<'_>) -> Result<()> {
+/// Uploads the `file` of a script step as an executable named `name` to the temporary directory
+/// of the build and returns its path.
+async fn upload_script(ctx: &Context<'_>, name: &str, file: &str) -> Result<String> {
+    let dir = &ctx.build.container_tmp_dir;
+    ctx.upload_text_files(vec![(format!("./{}", name), file.as_bytes())], dir)
+        .await
+        .context("failed to upload script of the step")?;
+    let path = dir.join(name).display().to_string();
+    ctx.checked_exec(
+        &ExecOpts::default()
+            .cmd(&format!("chmod 0755 {}", path))
+            .build(),
+    )
+    .await
+    .context("failed to make the script of the step executable")?;
+    Ok(path)
+}
+
+/// Runs an interactive step. If the step has an answers file it is uploaded to the container and
+/// passed as stdin of the command, otherwise the terminal of the user is attached.
+async fn run_interactive(
+    ctx: &Context<'_>,
+    cmd: &Command,
+    command: &str,
+    opts: ExecOpts<'_>,
+) -> Result<Output<String>> {
+    let span = info_span!("interactive", command = %cmd.label());
+    async move {
+        if let Some(answers) = &cmd.answers {
+            let answers = ctx.build.recipe.recipe_dir.join(answers);
+            trace!(answers = %answers.display(), "using answers file");
+            let content = fs::read(&answers).context("failed to read answers file")?;
+            let name = answers
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "answers".to_string());
+            ctx.upload_text_files(
+                vec![(format!("./{}", name), &content[..])],
+                &ctx.build.container_tmp_dir,
+            )
+            .await
+            .context("failed to upload answers file")?;
+
+            // a subshell so that the answers are the stdin of every command of the step
+            let command = format!(
+                "(\n{}\n) < {}",
+                command,
+                ctx.build.container_tmp_dir.join(name).display()
+            );
+            ctx.container
+                .exec(&opts.cmd(&command).tty(true).build(), false)
+                .await
+        } else {
+            info!("attaching terminal");
+            ctx.container.exec_interactive(&opts).await
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+/// Verifies that the exit code of a step is one of the expected exit codes (by default only `0`)
+/// and that the output matches the `expect_output` regex if one was provided.
+fn verify_output(cmd: &Command, out: &Output<String>) -> Result<()> {
+    let exit_codes = cmd.expect_exit_codes.as_deref().unwrap_or(&[0]);
+    if !exit_codes.contains(&out.exit_code) {
+        return err!(
+            "command failed with exit code {}\nError:\n{}",
+            out.exit_code,
+            out.stderr.join("\n")
+        );
+    }
+
+    if let Some(expected) = &cmd.expect_output {
+        let re = Regex::new(expected).context("invalid `expect_output` regex")?;
+        let output = format!("{}{}", out.stdout.join(""), out.stderr.join(""));
+        if !re.is_match(&output) {
+            return err!("output of the command doesn't match `{}`", expected);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn run(ctx: &Context<'_>, image_state: &ImageState) -> Result<()> {
     let span = info_span!("exec-scripts");
     async move {
+        // keys of step snapshots cover the inputs of the recipe, the image, the resolved source,
+        // variables forwarded from the host and all previous steps
+        let mut hasher = Sha256::new();
+        hasher.update(&ctx.build.recipe.inputs_hash);
+        hasher.update(&image_state.id);
+        hasher.update(ctx.build.target.build_target().as_ref());
+        if let Some(Some(source)) = ctx.build.resolved_source() {
+            hasher.update(source);
+        }
+        let forwarded = forwarded_env(ctx.build);
+        let mut forwarded: Vec<_> = forwarded.iter().collect();
+        forwarded.sort();
+        for (key, value) in forwarded {
+            hasher.update(format!("{}={}\n", key, value));
+        }
+
         if let Some(config_script) = &ctx.build.recipe.configure_script {
             run_script!(
                 "configure",
                 config_script,
                 &ctx.build.container_bld_dir,
-                ctx
+                ctx,
+                hasher
             );
         } else {
             info!("no configure steps to run");
         }
 
         let build_script = &ctx.build.recipe.build_script;
-        run_script!("build", build_script, &ctx.build.container_bld_dir, ctx);
+        run_script!(
+            "build",
+            build_script,
+            &ctx.build.container_bld_dir,
+            ctx,
+            hasher
+        );
 
         if let Some(install_script) = &ctx.build.recipe.install_script {
-            run_script!("install", install_script, &ctx.build.container_out_dir, ctx);
+            run_script!(
+                "install",
+                install_script,
+                &ctx.build.container_out_dir,
+                ctx,
+                hasher
+            );
         } else {
             info!("no install steps to run");
         }
@@ -0,0 +1,313 @@
+//! Snapshots of the build and output directories taken after steps marked with `cache: true`.
+//! Each snapshot is keyed by a hash of the step, all steps before it and the inputs of the
+//! recipe so that it is only restored when the step would produce the same result.
+
+use crate::build::container::Context;
+use crate::container::ExecOpts;
+use crate::{blocking, ErrContext, Result};
+
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, info_span, trace, Instrument};
+
+/// Names of the snapshot files of the build and output directories.
+static BUILD_SNAPSHOT: &str = "build.tar";
+static OUT_SNAPSHOT: &str = "out.tar";
+/// Size of the entries of a part of a snapshot after which a new part is started.
+pub(crate) const SNAPSHOT_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Returns the directory on the host where step snapshots of this build are kept.
+fn snapshots_dir(ctx: &Context<'_>) -> PathBuf {
+    ctx.build
+        .cache_dir
+        .join("steps")
+        .join(&ctx.build.recipe.metadata.name)
+        .join(ctx.build.target.image())
+}
+
+/// Returns the prefix of the names of all snapshots of the step at `index` of the `phase`.
+fn step_prefix(phase: &str, index: usize) -> String {
+    format!("{}-{}-", phase, index)
+}
+
+/// Returns the name of the part at `index` of the snapshot `file`.
+pub(crate) fn part_name(file: &str, index: usize) -> String {
+    format!("{}.{}", file, index)
+}
+
+/// Strips the first component from the paths of all entries of the `archive`. Archives
+/// downloaded from the container contain the directory itself while directories of each build
+/// have a different name. The entries are written to parts like with [`split`].
+pub(crate) fn strip_root(archive: impl Read, dir: &Path, file: &str, part_size: u64) -> Result<()> {
+    fn strip(path: &Path) -> PathBuf {
+        path.components()
+            .skip_while(|c| matches!(c, Component::CurDir | Component::RootDir))
+            .skip(1)
+            .collect()
+    }
+    write_parts(archive, dir, file, part_size, strip)
+}
+
+/// Writes the entries of the `archive` to parts of the snapshot `file` in `dir` that hold about
+/// `part_size` bytes each so that restoring the snapshot never needs more than one part in memory.
+pub(crate) fn split(archive: impl Read, dir: &Path, file: &str, part_size: u64) -> Result<()> {
+    write_parts(archive, dir, file, part_size, Path::to_path_buf)
+}
+
+fn write_parts(
+    archive: impl Read,
+    dir: &Path,
+    file: &str,
+    part_size: u64,
+    map_path: impl Fn(&Path) -> PathBuf,
+) -> Result<()> {
+    fn create_part(dir: &Path, file: &str, index: usize) -> Result<tar::Builder<fs::File>> {
+        fs::File::create(dir.join(part_name(file, index)))
+            .map(tar::Builder::new)
+            .context("failed to create snapshot archive")
+    }
+
+    let mut parts = 1;
+    let mut size = 0;
+    let mut builder = create_part(dir, file, 0)?;
+    let mut input = tar::Archive::new(archive);
+    for entry in input.entries().context("invalid snapshot archive")? {
+        let mut entry = entry.context("invalid snapshot archive entry")?;
+        let path = map_path(&entry.path().context("invalid path")?);
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+        if size >= part_size {
+            builder
+                .finish()
+                .context("failed to finish snapshot archive")?;
+            builder = create_part(dir, file, parts)?;
+            parts += 1;
+            size = 0;
+        }
+        let mut header = entry.header().clone();
+        size += header.entry_size().unwrap_or_default();
+        if header.entry_type() == tar::EntryType::Link {
+            let target = entry
+                .link_name()
+                .context("invalid link name")?
+                .map(|target| map_path(&target))
+                .unwrap_or_default();
+            builder.append_link(&mut header, &path, &target)
+        } else {
+            builder.append_data(&mut header, &path, &mut entry)
+        }
+        .context("failed to add entry to snapshot archive")?;
+    }
+    builder
+        .finish()
+        .context("failed to finish snapshot archive")
+}
+
+/// Restores the snapshot of the step at `index` of the `phase` with the given `key`. Returns
+/// `false` if there is no such snapshot, the build is sandboxed or the source of the recipe
+/// couldn't be resolved, in which case it's unknown whether the snapshot was taken with the same
+/// source.
+pub async fn restore(ctx: &Context<'_>, phase: &str, index: usize, key: &str) -> Result<bool> {
+    let dir = snapshots_dir(ctx).join(format!("{}{}", step_prefix(phase, index), key));
+    let span = info_span!("restore-step-snapshot", snapshot = %dir.display());
+    async move {
+        if ctx.build.sandbox().is_some() {
+            trace!("step snapshots are not shared with sandboxed builds, not restoring");
+            return Ok(false);
+        }
+        if ctx.build.resolved_source().is_none() {
+            trace!("source of the recipe is unknown, not restoring");
+            return Ok(false);
+        }
+        let exists = [BUILD_SNAPSHOT, OUT_SNAPSHOT]
+            .iter()
+            .all(|file| dir.join(part_name(file, 0)).exists());
+        if !exists {
+            trace!("no snapshot found");
+            return Ok(false);
+        }
+
+        for (file, container_dir) in [
+            (BUILD_SNAPSHOT, &ctx.build.container_bld_dir),
+            (OUT_SNAPSHOT, &ctx.build.container_out_dir),
+        ] {
+            ctx.checked_exec(
+                &ExecOpts::default()
+                    .cmd("find . -mindepth 1 -delete")
+                    .working_dir(container_dir)
+                    .build(),
+            )
+            .await
+            .context("failed to clean directory before restoring step snapshot")?;
+            for index in 0.. {
+                let part = dir.join(part_name(file, index));
+                if !part.exists() {
+                    break;
+                }
+                let archive = fs::read(&part).context("failed to read step snapshot")?;
+                ctx.container
+                    .extract_archive(archive, container_dir)
+                    .await
+                    .context("failed to extract step snapshot")?;
+            }
+        }
+
+        info!("restored step snapshot");
+        Ok(true)
+    }
+    .instrument(span)
+    .await
+}
+
+/// Saves a snapshot of the build and output directories after the step at `index` of the
+/// `phase` under the given `key`. Older snapshots of the same step are removed. Nothing is saved
+/// in sandboxed builds.
+pub async fn save(ctx: &Context<'_>, phase: &str, index: usize, key: &str) -> Result<()> {
+    let snapshots_dir = snapshots_dir(ctx);
+    let prefix = step_prefix(phase, index);
+    let dir = snapshots_dir.join(format!("{}{}", prefix, key));
+    let span = info_span!("save-step-snapshot", snapshot = %dir.display());
+    async move {
+        if ctx.build.sandbox().is_some() {
+            trace!("step snapshots are not shared with sandboxed builds, not saving");
+            return Ok(());
+        }
+        if snapshots_dir.exists() {
+            for entry in fs::read_dir(&snapshots_dir).context("failed to read snapshots dir")? {
+                let path = entry?.path();
+                let is_stale = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().starts_with(&prefix))
+                    .unwrap_or_default();
+                if is_stale {
+                    trace!(snapshot = %path.display(), "removing stale snapshot");
+                    fs::remove_dir_all(&path).context("failed to remove stale step snapshot")?;
+                }
+            }
+        }
+
+        // the snapshot is written to a temporary directory first so that an interrupted build
+        // doesn't leave an incomplete snapshot behind
+        let tmp_dir = dir.with_extension("tmp");
+        fs::create_dir_all(&tmp_dir).context("failed to create step snapshot directory")?;
+        for (file, container_dir) in [
+            (BUILD_SNAPSHOT, &ctx.build.container_bld_dir),
+            (OUT_SNAPSHOT, &ctx.build.container_out_dir),
+        ] {
+            let download = tmp_dir.join(file);
+            ctx.container
+                .copy_from_file(container_dir, &download)
+                .await
+                .context("failed to download directory for step snapshot")?;
+            let tmp_dir = tmp_dir.clone();
+            blocking::run(move || {
+                let archive = fs::File::open(&download).context("failed to open step snapshot")?;
+                strip_root(archive, &tmp_dir, file, SNAPSHOT_PART_SIZE)?;
+                fs::remove_file(&download).context("failed to remove downloaded step snapshot")
+            })
+            .await?;
+        }
+        fs::rename(&tmp_dir, &dir).context("failed to save step snapshot")?;
+
+        info!("saved step snapshot");
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn strips_root_of_snapshots() {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        builder
+            .append_data(&mut header.clone(), "pkger-build-1/", &[][..])
+            .unwrap();
+        builder
+            .append_data(&mut header, "pkger-build-1/target/", &[][..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        builder
+            .append_data(&mut header, "pkger-build-1/target/bin", &b"bin"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "pkger-build-1/bin", "pkger-build-1/target/bin")
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let dir = tempdir::TempDir::new("pkger-snapshot").unwrap();
+        strip_root(&archive[..], dir.path(), BUILD_SNAPSHOT, 1).unwrap();
+        let mut parts = vec![];
+        let mut entries = vec![];
+        for index in 0..2 {
+            let part = fs::read(dir.path().join(part_name(BUILD_SNAPSHOT, index))).unwrap();
+            let mut archive = tar::Archive::new(&part[..]);
+            let part_entries: Vec<_> = archive
+                .entries()
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    (
+                        entry.path().unwrap().to_string_lossy().to_string(),
+                        entry
+                            .link_name()
+                            .unwrap()
+                            .map(|link| link.to_string_lossy().to_string()),
+                    )
+                })
+                .collect();
+            parts.push(part_entries.len());
+            entries.extend(part_entries);
+        }
+        // a new part is only started once the entries of the current one reach the part size
+        assert_eq!(parts, vec![2, 1]);
+        assert!(!dir.path().join(part_name(BUILD_SNAPSHOT, 2)).exists());
+        assert_eq!(
+            entries,
+            vec![
+                ("target".to_string(), None),
+                ("target/bin".to_string(), None),
+                ("bin".to_string(), Some("target/bin".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_snapshots_without_changing_paths() {
+        let mut builder = tar::Builder::new(vec![]);
+        for path in [".cargo/a", ".cargo/b"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(1);
+            builder.append_data(&mut header, path, &b"x"[..]).unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+
+        let dir = tempdir::TempDir::new("pkger-snapshot").unwrap();
+        split(&archive[..], dir.path(), "cache.tar", 1).unwrap();
+        let paths: Vec<_> = (0..2)
+            .flat_map(|index| {
+                let part = fs::read(dir.path().join(part_name("cache.tar", index))).unwrap();
+                tar::Archive::new(&part[..])
+                    .entries()
+                    .unwrap()
+                    .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(paths, vec![".cargo/a", ".cargo/b"]);
+        assert!(!dir.path().join(part_name("cache.tar", 2)).exists());
+    }
+}
@@ -0,0 +1,504 @@
+//! Host-side cache of sources downloaded over HTTP(S). Downloaded files are kept in the cache
+//! directory together with a lockfile that records their checksums and the `ETag` and
+//! `Last-Modified` validators sent by the upstream server. Subsequent builds send conditional
+//! requests and reuse the cached file when the server responds with `304 Not Modified`.
+//!
+//! Sources distributed over torrents or IPFS are always fetched on the host to the same cache
+//! before being uploaded to the container.
+
+use crate::archive::create_tarball;
+use crate::build::container::Context;
+use crate::container::ExecOpts;
+use crate::recipe::PeerSource;
+use crate::shell;
+use crate::{blocking, err, ErrContext, Error, Result};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use tracing::{debug, info, info_span, trace, warn, Instrument};
+
+/// Name of the lockfile in the sources cache directory
+pub const LOCK_FILE: &str = "sources.lock";
+
+/// Serializes updates of the lockfile by concurrently fetched sources.
+static LOCK_UPDATE: Mutex<()> = Mutex::new(());
+
+/// Size of the parts cached sources are uploaded to the container in so that large files are
+/// never held in memory as a whole.
+const UPLOAD_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LockedSource {
+    /// Name of the cached file in the sources cache directory
+    pub file: String,
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SourcesLock {
+    /// Cached sources by their URL
+    #[serde(default)]
+    pub sources: BTreeMap<String, LockedSource>,
+}
+
+impl SourcesLock {
+    /// Loads the lockfile from `path` returning an empty one if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read(path).context("failed to read sources lockfile")?;
+        serde_yaml::from_slice(&content).context("failed to parse sources lockfile")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content =
+            serde_yaml::to_string(self).context("failed to serialize sources lockfile")?;
+        fs::write(path, content).context("failed to save sources lockfile")
+    }
+}
+
+/// Returns the directory on the host where downloaded sources are cached.
+pub fn sources_dir(cache_dir: &Path) -> PathBuf {
+    cache_dir.join("sources")
+}
+
+/// Returns the name of the cached file prefixed with a hash of the `url` so that files with the
+/// same name from different locations don't overwrite each other.
+fn cache_file_name(url: &str, file_name: &str) -> String {
+    let digest = Sha256::digest(url.as_bytes());
+    let hash: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}", hash, file_name)
+}
+
+/// Returns the original name of the `file` named with [cache_file_name].
+fn original_file_name(file: &str) -> &str {
+    file.split_once('-').map(|(_, name)| name).unwrap_or(file)
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).context("failed to open cached source")?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).context("failed to hash cached source")?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Returns `curl` arguments that make the request conditional on the validators of `locked`.
+fn conditional_args(locked: &LockedSource) -> String {
+    let mut args = String::new();
+    if let Some(etag) = &locked.etag {
+        args.push_str(&format!(
+            " -H {}",
+            shell::quote(&format!("If-None-Match: {}", etag))
+        ));
+    }
+    if let Some(last_modified) = &locked.last_modified {
+        args.push_str(&format!(
+            " -H {}",
+            shell::quote(&format!("If-Modified-Since: {}", last_modified))
+        ));
+    }
+    args
+}
+
+/// Parses the `ETag` and `Last-Modified` headers of the final response from headers dumped by
+/// `curl -D`, which contain the headers of every redirect that was followed.
+fn parse_validators(headers: &str) -> (Option<String>, Option<String>) {
+    let last_response = headers
+        .split("\r\n\r\n")
+        .flat_map(|block| block.split("\n\n"))
+        .filter(|block| !block.trim().is_empty())
+        .last()
+        .unwrap_or_default();
+
+    let mut etag = None;
+    let mut last_modified = None;
+    for line in last_response.lines() {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("etag") {
+                etag = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("last-modified") {
+                last_modified = Some(value.to_string());
+            }
+        }
+    }
+    (etag, last_modified)
+}
+
+/// Fetches the source from `url` to `dest` in the container as `file_name` using the host-side
+/// cache. A cached copy is revalidated with a conditional request and also used when the
+/// request fails. A cached copy that doesn't match the expected `sha256` is ignored.
+pub async fn fetch(
+    ctx: &Context<'_>,
+    url: &str,
+    file_name: &str,
+    sha256: Option<&str>,
+    dest: &Path,
+) -> Result<()> {
+    let span = info_span!("cached-download", url = %url);
+    async move {
+        let dir = sources_dir(&ctx.build.cache_dir);
+        let locked = cached(&dir, url, sha256)?;
+        trace!(locked = ?locked);
+
+        if let Some(locked) = &locked {
+            upload(ctx, &dir, &locked.file, file_name, dest).await?;
+        }
+
+        let new_file = format!("{}.new", file_name);
+        let headers_file = format!("{}.headers", file_name);
+        let result = ctx
+            .checked_exec_with(
+                &ExecOpts::default()
+                    .cmd(&format!(
+                        "curl -fsSL -o {} -D {} -w '%{{http_code}}'{} {}",
+                        shell::quote(&new_file),
+                        shell::quote(&headers_file),
+                        locked.as_ref().map(conditional_args).unwrap_or_default(),
+                        shell::quote(url)
+                    ))
+                    .working_dir(dest)
+                    .build(),
+                true,
+            )
+            .await;
+
+        let status = match (result, &locked) {
+            (Ok(out), _) => out.stdout.join("").trim().to_string(),
+            (Err(e), Some(_)) => {
+                warn!(reason = %format!("{:?}", e), "failed to revalidate source, using cached copy");
+                return Ok(());
+            }
+            (Err(e), None) => return Err(e),
+        };
+
+        if status == "304" && locked.is_some() {
+            info!("source not modified, using cached copy");
+            return ctx
+                .checked_exec(
+                    &ExecOpts::default()
+                        .cmd(&format!(
+                            "rm -f {} {}",
+                            shell::quote(&new_file),
+                            shell::quote(&headers_file)
+                        ))
+                        .working_dir(dest)
+                        .build(),
+                )
+                .await
+                .map(|_| ());
+        }
+
+        info!(status = %status, "downloaded source");
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!(
+                    "mv -f {} {}",
+                    shell::quote(&new_file),
+                    shell::quote(file_name)
+                ))
+                .working_dir(dest)
+                .build(),
+        )
+        .await?;
+
+        if let Err(e) = save(ctx, url, file_name, dest).await {
+            warn!(reason = %format!("{:?}", e), "failed to cache source");
+        }
+
+        ctx.checked_exec(
+            &ExecOpts::default()
+                .cmd(&format!("rm -f {}", shell::quote(&headers_file)))
+                .working_dir(dest)
+                .build(),
+        )
+        .await
+        .map(|_| ())
+    }
+    .instrument(span)
+    .await
+}
+
+/// Copies the downloaded `file_name` from `dest` in the container to the sources cache and
+/// records its validators in the lockfile.
+async fn save(ctx: &Context<'_>, url: &str, file_name: &str, dest: &Path) -> Result<()> {
+    let dir = sources_dir(&ctx.build.cache_dir);
+    let tmp = tempdir::TempDir::new("pkger-source").context("failed to create temporary dir")?;
+    ctx.container
+        .download_files(&dest.join(file_name), tmp.path())
+        .await?;
+    ctx.container
+        .download_files(&dest.join(format!("{}.headers", file_name)), tmp.path())
+        .await?;
+
+    let headers = fs::read_to_string(tmp.path().join(format!("{}.headers", file_name)))
+        .context("failed to read response headers")?;
+    let (etag, last_modified) = parse_validators(&headers);
+
+    fs::create_dir_all(&dir).context("failed to create sources cache directory")?;
+    let file = cache_file_name(url, file_name);
+    fs::copy(tmp.path().join(file_name), dir.join(&file)).context("failed to cache source")?;
+    let locked = LockedSource {
+        sha256: sha256_file(&dir.join(&file))?,
+        file,
+        etag,
+        last_modified,
+    };
+    debug!(locked = ?locked, "caching source");
+    record(&dir, url, locked)
+}
+
+/// Records the cached source of `location` in the lockfile in `dir`.
+fn record(dir: &Path, location: &str, locked: LockedSource) -> Result<()> {
+    let _guard = LOCK_UPDATE.lock().unwrap_or_else(|e| e.into_inner());
+    let lock_path = dir.join(LOCK_FILE);
+    let mut lock = SourcesLock::load(&lock_path)?;
+    lock.sources.insert(location.to_string(), locked);
+    lock.save(&lock_path)
+}
+
+/// Returns the cached source of `location` in `dir` if its file exists and matches `sha256`.
+fn cached(dir: &Path, location: &str, sha256: Option<&str>) -> Result<Option<LockedSource>> {
+    Ok(SourcesLock::load(&dir.join(LOCK_FILE))?
+        .sources
+        .remove(location)
+        .filter(|locked| dir.join(&locked.file).exists())
+        .filter(|locked| sha256.map(|sha256| sha256 == locked.sha256).unwrap_or(true)))
+}
+
+/// Uploads the cached `file` from `dir` to `dest` in the container as `file_name`. Files larger
+/// than [UPLOAD_PART_SIZE] are read and uploaded in parts that are joined in the container.
+async fn upload(
+    ctx: &Context<'_>,
+    dir: &Path,
+    file: &str,
+    file_name: &str,
+    dest: &Path,
+) -> Result<()> {
+    debug!(file = %file, "uploading cached source");
+    let mut source = fs::File::open(dir.join(file)).context("failed to open cached source")?;
+    let mut parts = vec![];
+    loop {
+        let (returned, part) = blocking::run(move || {
+            let mut part = vec![];
+            (&mut source)
+                .take(UPLOAD_PART_SIZE)
+                .read_to_end(&mut part)
+                .context("failed to read cached source")?;
+            Ok((source, part))
+        })
+        .await?;
+        source = returned;
+        let last = (part.len() as u64) < UPLOAD_PART_SIZE;
+        if last && parts.is_empty() {
+            return upload_part(ctx, file_name, &part, dest).await;
+        }
+        let name = format!("{}.part{}", file_name, parts.len());
+        upload_part(ctx, &name, &part, dest).await?;
+        parts.push(shell::quote(&name));
+        if last {
+            break;
+        }
+    }
+
+    trace!(parts = %parts.len(), "joining uploaded parts");
+    ctx.checked_exec(
+        &ExecOpts::default()
+            .cmd(&format!(
+                "cat {0} > {1} && rm -f {0}",
+                parts.join(" "),
+                shell::quote(file_name)
+            ))
+            .working_dir(dest)
+            .build(),
+    )
+    .await
+    .map(|_| ())
+    .context("failed to join uploaded parts of cached source")
+}
+
+async fn upload_part(ctx: &Context<'_>, name: &str, content: &[u8], dest: &Path) -> Result<()> {
+    let archive = create_tarball(std::iter::once((format!("./{}", name), content)))?;
+    ctx.container
+        .extract_archive(archive, dest)
+        .await
+        .context("failed to upload cached source")
+}
+
+/// Downloads the `peer` source on the host to `dir` and returns the name of the downloaded file.
+/// The downloading programs run on a separate thread.
+/// Makes sure a file name derived from a peer source stays inside the download directory.
+fn check_file_name(file_name: &str) -> Result<()> {
+    if file_name.is_empty() || file_name == "." || file_name == ".." || file_name.contains('/') {
+        return err!("invalid file name `{}` of the peer source", file_name);
+    }
+    Ok(())
+}
+
+async fn download_peer(peer: PeerSource<'_>, dir: &Path) -> Result<String> {
+    match peer {
+        PeerSource::Ipfs(path) => {
+            let file_name = path
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or(path)
+                .to_string();
+            check_file_name(&file_name)?;
+            let mut cmd = Command::new("ipfs");
+            cmd.arg("get")
+                .arg("--output")
+                .arg(dir.join(&file_name))
+                .arg("--")
+                .arg(format!("/ipfs/{}", path));
+            trace!(command = ?cmd, "running");
+            blocking::output(cmd, "ipfs").await?;
+            Ok(file_name)
+        }
+        PeerSource::Torrent(torrent) => {
+            let mut cmd = Command::new("aria2c");
+            cmd.args([
+                "--seed-time=0",
+                "--follow-torrent=mem",
+                "--bt-save-metadata=false",
+                "--summary-interval=0",
+                "--console-log-level=warn",
+            ])
+            .arg("--dir")
+            .arg(dir)
+            .arg("--")
+            .arg(torrent);
+            trace!(command = ?cmd, "running");
+            blocking::output(cmd, "aria2c").await?;
+
+            let mut entries = fs::read_dir(dir)
+                .context("failed to read downloaded torrent")?
+                .collect::<std::io::Result<Vec<_>>>()
+                .context("failed to read downloaded torrent")?;
+            match entries.pop() {
+                Some(entry) if entries.is_empty() && entry.path().is_file() => {
+                    let file_name = entry.file_name().to_string_lossy().to_string();
+                    check_file_name(&file_name)?;
+                    Ok(file_name)
+                }
+                _ => err!("only torrents with a single file are supported as sources"),
+            }
+        }
+    }
+}
+
+/// Fetches the `peer` source from `location` to `dest` in the container. The file is downloaded
+/// on the host to the sources cache unless a cached copy with the expected `sha256` exists,
+/// which is mandatory for these sources. Returns the name of the fetched file.
+pub async fn fetch_peer(
+    ctx: &Context<'_>,
+    peer: PeerSource<'_>,
+    location: &str,
+    sha256: Option<&str>,
+    dest: &Path,
+) -> Result<String> {
+    let span = info_span!("peer-download", location = %location);
+    async move {
+        if ctx.build.sandbox().is_some() {
+            return err!("torrent and IPFS sources can't be used in sandboxed builds");
+        }
+        let sha256 = sha256.context("torrent and IPFS sources require a `sha256` checksum")?;
+        let dir = sources_dir(&ctx.build.cache_dir);
+
+        let file = if let Some(locked) = cached(&dir, location, Some(sha256))? {
+            info!("using cached source");
+            locked.file
+        } else {
+            info!("downloading source on the host");
+            fs::create_dir_all(&dir).context("failed to create sources cache directory")?;
+            let tmp = tempdir::TempDir::new_in(&dir, "download")
+                .context("failed to create temporary dir")?;
+            let file_name = download_peer(peer, tmp.path()).await?;
+
+            let downloaded = tmp.path().join(&file_name);
+            let actual = {
+                let downloaded = downloaded.clone();
+                blocking::run(move || sha256_file(&downloaded)).await?
+            };
+            if actual != sha256 {
+                return err!(
+                    "checksum of `{}` doesn't match, expected `{}` found `{}`",
+                    file_name,
+                    sha256,
+                    actual
+                );
+            }
+
+            let file = cache_file_name(location, &file_name);
+            fs::rename(&downloaded, dir.join(&file)).context("failed to cache source")?;
+            record(
+                &dir,
+                location,
+                LockedSource {
+                    file: file.clone(),
+                    sha256: actual,
+                    etag: None,
+                    last_modified: None,
+                },
+            )?;
+            file
+        };
+
+        let file_name = original_file_name(&file).to_string();
+        upload(ctx, &dir, &file, &file_name, dest).await?;
+        Ok(file_name)
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_validators_of_final_response() {
+        let headers = "HTTP/1.1 302 Found\r\nLocation: https://mirror/pkger.tar.gz\r\nETag: \"redirect\"\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 7\r\netag: \"abc\"\r\nLast-Modified: Wed, 01 May 2024 10:00:00 GMT\r\n\r\n";
+        assert_eq!(
+            parse_validators(headers),
+            (
+                Some("\"abc\"".to_string()),
+                Some("Wed, 01 May 2024 10:00:00 GMT".to_string())
+            )
+        );
+        assert_eq!(parse_validators("HTTP/1.1 200 OK\n\n"), (None, None));
+
+        let locked = LockedSource {
+            file: cache_file_name("https://host/pkger.tar.gz", "pkger.tar.gz"),
+            sha256: String::new(),
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+        };
+        assert!(locked.file.ends_with("-pkger.tar.gz"));
+        assert_eq!(original_file_name(&locked.file), "pkger.tar.gz");
+        assert_ne!(
+            locked.file,
+            cache_file_name("https://mirror/pkger.tar.gz", "pkger.tar.gz")
+        );
+        assert_eq!(conditional_args(&locked), r#" -H 'If-None-Match: "abc"'"#);
+    }
+    #[test]
+    fn rejects_file_names_outside_of_download_dir() {
+        assert!(check_file_name("model.tar.gz").is_ok());
+        for name in ["", ".", "..", "a/../b"] {
+            assert!(check_file_name(name).is_err());
+        }
+    }
+}
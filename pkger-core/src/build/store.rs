@@ -0,0 +1,153 @@
+use crate::{ErrContext, Result};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, info_span, trace};
+
+/// Name of the directory in the output directory that contains content-addressed packages
+pub const STORE_DIR: &str = "store";
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Layout of packages in the output directory
+pub enum OutputLayout {
+    #[default]
+    /// Packages are saved to `<output_dir>/<image>/`
+    Flat,
+    /// Packages are saved to `<output_dir>/store/<sha256>/` and linked from
+    /// `<output_dir>/<recipe>/<version>/<image>/`
+    Store,
+}
+
+impl OutputLayout {
+    /// Returns the directory to which packages of `recipe` in `version` built on `image` are
+    /// saved.
+    pub fn package_dir(
+        &self,
+        output_dir: &Path,
+        recipe: &str,
+        version: &str,
+        image: &str,
+    ) -> PathBuf {
+        match self {
+            OutputLayout::Flat => output_dir.join(image),
+            OutputLayout::Store => output_dir.join(recipe).join(version).join(image),
+        }
+    }
+}
+
+/// Moves the `package` to `<output_dir>/store/<sha256>/` and replaces it with a relative symlink
+/// to the stored file. If the store already contains a package with the same content and name the
+/// new file is removed instead so identical packages are only kept once. Returns the path of the
+/// stored package.
+pub fn store_package(output_dir: &Path, package: &Path) -> Result<PathBuf> {
+    let span = info_span!("store-package", package = %package.display());
+    let _enter = span.enter();
+
+    let file_name = package
+        .file_name()
+        .context("package path has no file name")?;
+    let link_dir = package.parent().unwrap_or_else(|| Path::new(""));
+
+    let digest = sha256_file(package)?;
+    let store_dir = output_dir.join(STORE_DIR).join(&digest);
+    let stored = store_dir.join(file_name);
+
+    if stored.exists() {
+        info!(path = %stored.display(), "identical package already stored");
+        fs::remove_file(package).context("failed to remove duplicate package")?;
+    } else {
+        trace!(path = %stored.display(), "moving package to the store");
+        fs::create_dir_all(&store_dir).context("failed to create store directory")?;
+        fs::rename(package, &stored).context("failed to move package to the store")?;
+    }
+
+    let target = relative_store_path(output_dir, link_dir, &digest).join(file_name);
+    trace!(link = %package.display(), target = %target.display(), "linking package");
+    std::os::unix::fs::symlink(&target, package).context("failed to link stored package")?;
+
+    Ok(stored)
+}
+
+/// Removes symlinks to stored packages from the package directory `dir` so that files of a new
+/// build are not written through them to the store.
+pub fn remove_links(dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir).context("failed to read package directory")? {
+        let path = entry.context("invalid dir entry")?.path();
+        if fs::symlink_metadata(&path)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or_default()
+        {
+            trace!(link = %path.display(), "removing link");
+            fs::remove_file(&path).context("failed to remove link")?;
+        }
+    }
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).context("failed to open package")?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).context("failed to hash package")?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Returns the path of the store directory of the package with `digest` relative to `link_dir`
+/// located inside of `output_dir`.
+fn relative_store_path(output_dir: &Path, link_dir: &Path, digest: &str) -> PathBuf {
+    let depth = link_dir
+        .strip_prefix(output_dir)
+        .map(|dir| {
+            dir.components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .count()
+        })
+        .unwrap_or_default();
+    let mut path = PathBuf::new();
+    for _ in 0..depth {
+        path.push("..");
+    }
+    path.join(STORE_DIR).join(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn stores_identical_packages_once() {
+        let tmp = tempdir::TempDir::new("pkger-store").unwrap();
+        let output_dir = tmp.path();
+        let layout = OutputLayout::Store;
+
+        let mut stored = vec![];
+        for image in ["debian10", "debian11"] {
+            let dir = layout.package_dir(output_dir, "pkger", "0.1.0", image);
+            fs::create_dir_all(&dir).unwrap();
+            let package = dir.join("pkger-0.1.0-0.noarch.rpm");
+            fs::write(&package, b"noarch").unwrap();
+
+            stored.push(store_package(output_dir, &package).unwrap());
+            assert!(fs::symlink_metadata(&package)
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert_eq!(fs::read(&package).unwrap(), b"noarch");
+        }
+
+        assert_eq!(stored[0], stored[1]);
+        assert_eq!(
+            fs::read_link(output_dir.join("pkger/0.1.0/debian10/pkger-0.1.0-0.noarch.rpm"))
+                .unwrap(),
+            Path::new("../../..")
+                .join(STORE_DIR)
+                .join(stored[0].parent().unwrap().file_name().unwrap())
+                .join("pkger-0.1.0-0.noarch.rpm")
+        );
+        assert_eq!(fs::read_dir(output_dir.join(STORE_DIR)).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::build::container::Context;
+use crate::build::package;
+use crate::container::ExecOpts;
+use crate::recipe::Subpackage;
+use crate::shell;
+use crate::{ErrContext, Result};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+use tracing::{info, info_span, trace, warn, Instrument};
+
+/// Moves files of the output directory matching patterns of each subpackage of the recipe to a
+/// separate directory in the container so that they are not included in the main package. A file
+/// matching patterns of multiple subpackages is moved to the first one. Returns the directories
+/// in the same order as the subpackages or no directories if the target doesn't support them.
+pub async fn split(ctx: &Context<'_>) -> Result<Vec<PathBuf>> {
+    let subpackages = &ctx.build.recipe.metadata.subpackages;
+    if subpackages.is_empty() {
+        return Ok(vec![]);
+    }
+    let target = ctx.build.target.build_target();
+    if !package::supports_subpackages(target) {
+        warn!(target = ?target, "subpackages are not supported by the target, skipping");
+        return Ok(vec![]);
+    }
+    let span = info_span!("split-subpackages");
+    async move {
+        let matchers = subpackages
+            .iter()
+            .map(files_matcher)
+            .collect::<Result<Vec<_>>>()?;
+        let files = find_files(ctx).await?;
+        trace!(files = ?files);
+
+        let mut dirs = vec![];
+        for (subpackage, files) in subpackages.iter().zip(assign(&files, &matchers)) {
+            let dir = ctx
+                .build
+                .container_tmp_dir
+                .join(format!("{}-subpackage", subpackage.name));
+            info!(subpackage = %subpackage.name, files = %files.len(), "moving files to subpackage");
+            move_files(ctx, &files, &dir)
+                .await
+                .context(format!(
+                    "failed to move files to subpackage `{}`",
+                    subpackage.name
+                ))?;
+            dirs.push(dir);
+        }
+
+        Ok(dirs)
+    }
+    .instrument(span)
+    .await
+}
+
+fn files_matcher(subpackage: &Subpackage) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in &subpackage.files {
+        builder.add_line(None, pattern).context(format!(
+            "invalid file pattern `{}` of subpackage `{}`",
+            pattern, subpackage.name
+        ))?;
+    }
+    builder.build().context("failed to build file patterns")
+}
+
+/// Returns the `files` matched by each of the `matchers`. Every file is only assigned to the first
+/// matcher that matches it.
+fn assign<'f>(files: &'f [String], matchers: &[Gitignore]) -> Vec<Vec<&'f str>> {
+    let mut assigned = vec![vec![]; matchers.len()];
+    for file in files {
+        if let Some(i) = matchers
+            .iter()
+            .position(|matcher| matcher.matched_path_or_any_parents(file, false).is_ignore())
+        {
+            assigned[i].push(file.as_str());
+        }
+    }
+    assigned
+}
+
+/// Returns paths of all files and symlinks in the output directory relative to it.
+async fn find_files(ctx: &Context<'_>) -> Result<Vec<String>> {
+    ctx.checked_exec(
+        &ExecOpts::default()
+            .cmd("find . -type f -o -type l")
+            .working_dir(&ctx.build.container_out_dir)
+            .build(),
+    )
+    .await
+    .map(|out| {
+        out.stdout
+            .join("")
+            .split('\n')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.trim_start_matches("./").to_string())
+            .collect()
+    })
+    .context("failed to find package files")
+}
+
+/// Moves the `files` relative to the output directory to the same paths in `dir` and removes
+/// directories of the output directory left empty. The files are uploaded as a list next to `dir`
+/// so that the command doesn't grow with the number of files.
+async fn move_files(ctx: &Context<'_>, files: &[&str], dir: &Path) -> Result<()> {
+    let list = dir.with_extension("files");
+    let mut content = files.join("\n");
+    content.push('\n');
+    ctx.container
+        .upload_files(
+            vec![(list.file_name().unwrap_or_default(), content.as_bytes())],
+            list.parent().unwrap_or_else(|| Path::new("/")),
+        )
+        .await
+        .context("failed to upload the list of files")?;
+
+    ctx.checked_exec(
+        &ExecOpts::default()
+            .cmd(&move_script(dir, &list))
+            .working_dir(&ctx.build.container_out_dir)
+            .build(),
+    )
+    .await
+    .map(|_| ())
+}
+
+/// Returns a script moving every file listed in the `list` file to the same path in `dir`.
+fn move_script(dir: &Path, list: &Path) -> String {
+    let dir = shell::quote(&dir.to_string_lossy());
+    format!(
+        r#"set -e
+mkdir -p {dir}
+while IFS= read -r file; do
+    parent=$(dirname "$file")
+    mkdir -p {dir}/"$parent"
+    mv "$file" {dir}/"$parent"/
+    if [ "$parent" != . ]; then rmdir -p --ignore-fail-on-non-empty "$parent"; fi
+done < {list}"#,
+        dir = dir,
+        list = shell::quote(&list.to_string_lossy())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn assigns_files_to_subpackages() {
+        let subpackage = |name: &str, files: &[&str]| Subpackage {
+            name: name.to_string(),
+            description: None,
+            files: files.iter().map(|file| file.to_string()).collect(),
+            depends: None,
+        };
+        let matchers = [
+            subpackage("pkger-devel", &["usr/include/", "*.a", "usr/lib/*.so"]),
+            subpackage("pkger-doc", &["usr/share/doc/", "*.h"]),
+        ]
+        .iter()
+        .map(files_matcher)
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+        let files = [
+            "usr/bin/pkger",
+            "usr/include/pkger/pkger.h",
+            "usr/lib/libpkger.a",
+            "usr/lib/libpkger.so",
+            "usr/lib/libpkger.so.1",
+            "usr/share/doc/pkger/README.md",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            assign(&files, &matchers),
+            vec![
+                vec![
+                    "usr/include/pkger/pkger.h",
+                    "usr/lib/libpkger.a",
+                    "usr/lib/libpkger.so"
+                ],
+                vec!["usr/share/doc/pkger/README.md"],
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_listed_files() {
+        assert_eq!(
+            move_script(
+                Path::new("/tmp/pkger-tmp/pkger-devel-subpackage"),
+                Path::new("/tmp/pkger-tmp/pkger-devel-subpackage.files")
+            ),
+            r#"set -e
+mkdir -p '/tmp/pkger-tmp/pkger-devel-subpackage'
+while IFS= read -r file; do
+    parent=$(dirname "$file")
+    mkdir -p '/tmp/pkger-tmp/pkger-devel-subpackage'/"$parent"
+    mv "$file" '/tmp/pkger-tmp/pkger-devel-subpackage'/"$parent"/
+    if [ "$parent" != . ]; then rmdir -p --ignore-fail-on-non-empty "$parent"; fi
+done < '/tmp/pkger-tmp/pkger-devel-subpackage.files'"#
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use crate::build::container::Context;
+use crate::container::ExecOpts;
+use crate::recipe::BuildTarget;
+use crate::{shell, ErrContext, Result};
+
+use std::path::Path;
+use tracing::{info, info_span, warn, Instrument};
+
+/// Installs the systemd unit files of the recipe from the build directory to the directory of
+/// units of the build target in the output directory.
+pub async fn install(ctx: &Context<'_>) -> Result<()> {
+    let metadata = &ctx.build.recipe.metadata;
+    let systemd = if let Some(systemd) = &metadata.systemd {
+        systemd
+    } else {
+        return Ok(());
+    };
+    let target = ctx.build.target.build_target();
+    if matches!(target, BuildTarget::Msi) {
+        warn!("systemd units are not supported by the MSI target, skipping");
+        return Ok(());
+    }
+    let span = info_span!("install-systemd-units");
+    async move {
+        let unit_dir = metadata.systemd_unit_dir(target);
+        let out_dir = ctx.build.container_out_dir.join(
+            Path::new(unit_dir)
+                .strip_prefix("/")
+                .unwrap_or_else(|_| Path::new(unit_dir)),
+        );
+        for (unit, name) in systemd.units.iter().zip(systemd.unit_names()) {
+            info!(unit = %unit, dir = %unit_dir, "installing systemd unit");
+            ctx.checked_exec(
+                &ExecOpts::default()
+                    .cmd(&format!(
+                        "install -Dm644 {} {}",
+                        shell::quote(unit),
+                        shell::quote(&out_dir.join(name).to_string_lossy())
+                    ))
+                    .working_dir(&ctx.build.container_bld_dir)
+                    .build(),
+            )
+            .await
+            .context(format!("failed to install systemd unit `{}`", unit))?;
+        }
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
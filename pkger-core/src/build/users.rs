@@ -0,0 +1,43 @@
+use crate::build::container::Context;
+use crate::recipe::BuildTarget;
+use crate::{ErrContext, Result};
+
+use std::path::PathBuf;
+use tracing::{info, info_span, trace, warn, Instrument};
+
+/// Directory of `sysusers.d` configuration files installed by packages
+static SYSUSERS_DIR: &str = "usr/lib/sysusers.d";
+
+/// Installs a `sysusers.d` configuration creating the system users and groups of the recipe to
+/// the output directory so that they're also created on systems using `systemd-sysusers`.
+pub async fn install(ctx: &Context<'_>) -> Result<()> {
+    let metadata = &ctx.build.recipe.metadata;
+    if !metadata.has_accounts() {
+        return Ok(());
+    }
+    if matches!(ctx.build.target.build_target(), BuildTarget::Msi) {
+        warn!("system users and groups are not supported by the MSI target, skipping");
+        return Ok(());
+    }
+    let span = info_span!("install-sysusers");
+    async move {
+        let dir = ctx.build.container_out_dir.join(SYSUSERS_DIR);
+        let conf = metadata.sysusers_conf();
+        trace!(conf = %conf);
+
+        info!(dir = %dir.display(), "installing sysusers.d configuration");
+        ctx.create_dirs(&[&dir]).await?;
+        ctx.container
+            .upload_files(
+                vec![(
+                    PathBuf::from(format!("{}.conf", metadata.name)),
+                    conf.as_bytes(),
+                )],
+                &dir,
+            )
+            .await
+            .context("failed to install sysusers.d configuration")
+    }
+    .instrument(span)
+    .await
+}
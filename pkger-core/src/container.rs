@@ -1,22 +1,27 @@
 use crate::archive::{create_tarball, unpack_tarball};
-use crate::{ErrContext, Result};
+use crate::{blocking, docker, err, ErrContext, Error, Result};
 
 use docker_api::{
     api::{
-        ContainerCreateOpts, ContainerPruneFilter, ContainerPruneOpts, ContainersPruneInfo,
-        ExecContainerOpts, LogsOpts, RmContainerOpts,
+        ContainerCommitOpts, ContainerCreateOpts, ContainerDisconnectionOpts, ContainerPruneFilter,
+        ContainerPruneOpts, ContainersPruneInfo, ExecContainerOpts, LogsOpts, RmContainerOpts,
     },
     conn::TtyChunk,
     Container, Docker, Exec,
 };
 use futures::{StreamExt, TryStreamExt};
+use std::fs;
+use std::io::Write;
 use std::path::Path;
+use std::process;
 use std::str;
-use tracing::{error, info, info_span, trace, Instrument};
+use tracing::{error, info, info_span, trace, warn, Instrument};
 
 /// Length of significant characters of a container ID.
 static CONTAINER_ID_LEN: usize = 12;
-static DEFAULT_SHELL: &str = "/bin/sh";
+pub static DEFAULT_SHELL: &str = "/bin/sh";
+/// Shells linked as the default shell when it is missing in the container.
+static FALLBACK_SHELLS: &[&str] = &["/bin/bash", "/bin/ash", "/bin/dash", "/usr/bin/bash"];
 
 fn truncate(id: &str) -> &str {
     if id.len() > CONTAINER_ID_LEN {
@@ -28,10 +33,11 @@ fn truncate(id: &str) -> &str {
 
 /// Removes invalid characters from the given name.
 ///
-/// According to the error message allowed characters are [a-zA-Z0-9_.-].
+/// According to the error message allowed characters are [a-zA-Z0-9][a-zA-Z0-9_.-].
 pub fn fix_name(name: &str) -> String {
     name.chars()
-        .filter(|&c| c.is_alphanumeric() || c == '-' || c == '.' || c == '-')
+        .filter(|&c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+        .skip_while(|c| !c.is_ascii_alphanumeric())
         .collect()
 }
 
@@ -118,6 +124,12 @@ impl<'opts> ExecOpts<'opts> {
         self
     }
 
+    /// Sets additional environment variables in the form of `KEY=VALUE` for this exec.
+    pub fn env(mut self, env: &'opts [String]) -> Self {
+        self.env = Some(env);
+        self
+    }
+
     pub fn build(self) -> ExecContainerOpts {
         let mut builder = ExecContainerOpts::builder();
 
@@ -150,6 +162,8 @@ impl<'opts> ExecOpts<'opts> {
 pub struct DockerContainer<'job> {
     container: Container<'job>,
     docker: &'job Docker,
+    stop_signal: Option<String>,
+    host: Option<String>,
 }
 
 impl<'job> DockerContainer<'job> {
@@ -157,9 +171,23 @@ impl<'job> DockerContainer<'job> {
         Self {
             container: docker.containers().get(""),
             docker,
+            stop_signal: None,
+            host: None,
         }
     }
 
+    /// Sets the URI of the Docker daemon passed to the `docker` CLI when running interactive
+    /// commands. By default the CLI uses its own configuration.
+    pub fn set_host(&mut self, host: impl Into<String>) {
+        self.host = Some(host.into());
+    }
+
+    /// Sets the signal sent to the container when it is removed. By default the container is
+    /// killed with `SIGKILL`.
+    pub fn set_stop_signal(&mut self, signal: impl Into<String>) {
+        self.stop_signal = Some(signal.into());
+    }
+
     pub fn inner(&self) -> &Container<'job> {
         &self.container
     }
@@ -185,18 +213,96 @@ impl<'job> DockerContainer<'job> {
         .await
     }
 
+    /// Verifies that the container is still running and that the default shell used by all
+    /// commands exists. If the default shell is missing the first available fallback shell is
+    /// linked in its place.
+    pub async fn healthcheck(&self) -> Result<()> {
+        let span = info_span!("container-healthcheck", id = %self.id());
+        async move {
+            let details = self
+                .container
+                .inspect()
+                .await
+                .context("failed to inspect container")?;
+            if !details.state.running {
+                let logs = self.logs(true, true).await.unwrap_or_default();
+                return err!(
+                    "container exited right after start with code {} ({})\n{}",
+                    details.state.exit_code,
+                    details.state.status,
+                    String::from_utf8_lossy(&logs.stdout).trim()
+                );
+            }
+
+            if self.has_shell(DEFAULT_SHELL).await {
+                trace!(shell = %DEFAULT_SHELL, "default shell available");
+                return Ok(());
+            }
+
+            for shell in FALLBACK_SHELLS {
+                if !self.has_shell(shell).await {
+                    continue;
+                }
+                warn!(shell = %shell, "default shell `{}` is missing, linking fallback", DEFAULT_SHELL);
+                let link = format!("ln -sf {} {}", shell, DEFAULT_SHELL);
+                let out = self
+                    .exec(
+                        &ExecContainerOpts::builder()
+                            .cmd(vec![shell, "-c", link.as_str()])
+                            .attach_stdout(true)
+                            .attach_stderr(true)
+                            .build(),
+                        true,
+                    )
+                    .await?;
+                if out.exit_code == 0 {
+                    return Ok(());
+                }
+                return err!(
+                    "failed to link `{}` as `{}`: {}",
+                    shell,
+                    DEFAULT_SHELL,
+                    out.stderr.join("").trim()
+                );
+            }
+
+            err!(
+                "no shell found in the container, tried: {}, {}",
+                DEFAULT_SHELL,
+                FALLBACK_SHELLS.join(", ")
+            )
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Checks whether `shell` can be executed in the container.
+    async fn has_shell(&self, shell: &str) -> bool {
+        self.exec(
+            &ExecContainerOpts::builder()
+                .cmd(vec![shell, "-c", "true"])
+                .attach_stdout(true)
+                .attach_stderr(true)
+                .build(),
+            true,
+        )
+        .await
+        .map(|out| out.exit_code == 0)
+        .unwrap_or(false)
+    }
+
     pub async fn remove(&self) -> Result<()> {
         let span = info_span!("container-remove");
         async move {
             info!(id = %self.id(), "stopping container");
             self.container
-                .kill(None)
+                .kill(self.stop_signal.as_deref())
                 .await
                 .context("failed to stop container")?;
 
             info!(id = %self.id(), "deleting container");
             self.container
-                .remove(&RmContainerOpts::builder().force(true).build())
+                .remove(&RmContainerOpts::builder().force(true).volumes(true).build())
                 .await
                 .context("failed to delete container")?;
 
@@ -206,6 +312,92 @@ impl<'job> DockerContainer<'job> {
         .await
     }
 
+    /// Commits the current state of the container as an image with the given `tag` returning the
+    /// id of the new image. The tag can contain a repository like `repo/name:tag`.
+    pub async fn commit(&self, tag: &str) -> Result<String> {
+        let span = info_span!("container-commit", id = %self.id(), tag = %tag);
+        async move {
+            let (repo, tag) = match tag.rsplit_once(':') {
+                Some((repo, tag)) if !tag.contains('/') => (repo, tag),
+                _ => (tag, "latest"),
+            };
+            info!(repo = %repo, tag = %tag, "committing container");
+            self.container
+                .commit(&ContainerCommitOpts::builder().repo(repo).tag(tag).build())
+                .await
+                .context("failed to commit container")
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Runs the command interactively using the `docker` CLI with the TTY and stdin attached to
+    /// the terminal of the current process. The CLI runs on a separate thread until the command
+    /// finishes.
+    pub async fn exec_interactive(&self, opts: &ExecOpts<'_>) -> Result<Output<String>> {
+        let span = info_span!("container-exec-interactive", id = %self.id());
+        async move {
+            let mut cmd = docker::cli(self.host.as_deref());
+            cmd.arg("exec").arg("--interactive").arg("--tty");
+            if opts.privileged {
+                cmd.arg("--privileged");
+            }
+            if let Some(user) = opts.user {
+                cmd.arg("--user").arg(user);
+            }
+            if let Some(working_dir) = opts.working_dir {
+                cmd.arg("--workdir").arg(working_dir);
+            }
+            if let Some(env) = opts.env {
+                env.iter().for_each(|var| {
+                    cmd.arg("--env").arg(var);
+                });
+            }
+            cmd.arg(self.container.id())
+                .args([opts.shell, "-c", opts.cmd])
+                .stdin(process::Stdio::inherit())
+                .stdout(process::Stdio::inherit())
+                .stderr(process::Stdio::inherit());
+
+            trace!(command = ?cmd, "running");
+            let status =
+                blocking::run(move || cmd.status().context("failed to run `docker exec`")).await?;
+
+            Ok(Output {
+                exit_code: status.code().unwrap_or(-1) as u64,
+                ..Default::default()
+            })
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Disconnects the container from the `network` so that it has no network access for the
+    /// rest of its lifetime.
+    pub async fn disconnect_network(&self, network: &str) -> Result<()> {
+        let span = info_span!("container-disconnect-network", id = %self.id(), network = %network);
+        async move {
+            self.docker
+                .networks()
+                .get(network)
+                .disconnect(
+                    &ContainerDisconnectionOpts::builder(self.container.id())
+                        .force(true)
+                        .build(),
+                )
+                .await
+                .context(format!(
+                    "failed to disconnect container from network `{}`",
+                    network
+                ))?;
+
+            info!("disconnected container from network");
+            Ok(())
+        }
+        .instrument(span)
+        .await
+    }
+
     pub async fn exec<'cmd>(
         &self,
         opts: &ExecContainerOpts,
@@ -288,6 +480,28 @@ impl<'job> DockerContainer<'job> {
         .await
     }
 
+    /// Downloads `path` from the container as a tar archive to the file `dest` on the host. The
+    /// archive is written as it's received so it's never held in memory as a whole.
+    pub async fn copy_from_file(&self, path: &Path, dest: &Path) -> Result<()> {
+        let span = info_span!("copy-from-file", path = %path.display(), dest = %dest.display());
+        async move {
+            trace!("copying");
+            let mut file = fs::File::create(dest).context("failed to create archive file")?;
+            let mut stream = Box::pin(self.inner().copy_from(path));
+            while let Some(chunk) = stream
+                .try_next()
+                .await
+                .context("failed to copy from container")?
+            {
+                file.write_all(&chunk)
+                    .context("failed to write archive file")?;
+            }
+            Ok(())
+        }
+        .instrument(span)
+        .await
+    }
+
     pub async fn download_files(&self, source: &Path, dest: &Path) -> Result<()> {
         let span = info_span!("container-download-files", id = %self.id(), source = %source.display(), destination = %dest.display());
         let cloned_span = span.clone();
@@ -304,12 +518,21 @@ impl<'job> DockerContainer<'job> {
         .await
     }
 
-    pub async fn upload_files<'files, F, E, P>(
-        &self,
-        files: F,
-        destination: P,
-        quiet: bool,
-    ) -> Result<()>
+    /// Extracts the tar `archive` to the `destination` directory using the archive API of
+    /// Docker. The daemon unpacks the archive so the image doesn't need a compatible `tar` binary.
+    /// The destination directory has to exist in the container.
+    pub async fn extract_archive(&self, archive: Vec<u8>, destination: &Path) -> Result<()> {
+        trace!(destination = %destination.display(), size = archive.len(), "extract archive");
+        self.inner()
+            .copy_to(destination, archive.into())
+            .await
+            .context(format!(
+                "failed to extract archive to `{}`",
+                destination.display()
+            ))
+    }
+
+    pub async fn upload_files<'files, F, E, P>(&self, files: F, destination: P) -> Result<()>
     where
         F: IntoIterator<Item = (E, &'files [u8])>,
         E: AsRef<Path>,
@@ -318,28 +541,13 @@ impl<'job> DockerContainer<'job> {
         let span = info_span!("upload-files");
         let cloned_span = span.clone();
         async move {
-            let destination = destination.as_ref();
             let tar = cloned_span
                 .in_scope(|| create_tarball(files.into_iter()))
                 .context("failed creating a tarball with files")?;
-            let tar_path = destination.join("archive.tgz");
 
-            self.inner()
-                .copy_file_into(&tar_path, &tar)
+            self.extract_archive(tar, destination.as_ref())
                 .await
-                .context("failed to copy archive with files to container")?;
-
-            trace!("extract archive with files");
-            self.exec(
-                &ExecOpts::default()
-                    .cmd(&format!("tar -xf {}", tar_path.display()))
-                    .working_dir(destination)
-                    .build(),
-                quiet,
-            )
-            .await
-            .map(|_| ())
-            .context("failed to extract archive with with files")
+                .context("failed to upload files to container")
         }
         .instrument(span)
         .await
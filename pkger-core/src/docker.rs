@@ -3,12 +3,14 @@ use crate::Result;
 pub use docker_api::*;
 
 use std::path::PathBuf;
+use std::process::Command;
 
 static RUN_DOCKER_SOCK: &str = "/run/docker.sock";
 static VAR_RUN_DOCKER_SOCK: &str = "/var/run/docker.sock";
 
 pub struct DockerConnectionPool {
     connector: Docker,
+    host: String,
 }
 
 #[cfg(unix)]
@@ -22,6 +24,7 @@ impl Default for DockerConnectionPool {
 
         Self {
             connector: Docker::unix(socket_path),
+            host: format!("unix://{}", socket_path),
         }
     }
 }
@@ -31,6 +34,7 @@ impl Default for DockerConnectionPool {
     fn default() -> Self {
         Self {
             connector: Docker::tcp("127.0.0.1:8080").expect("valid host address"),
+            host: "tcp://127.0.0.1:8080".to_string(),
         }
     }
 }
@@ -44,10 +48,32 @@ impl DockerConnectionPool {
 
         Ok(Self {
             connector: Docker::new(&uri)?,
+            host: uri,
         })
     }
 
     pub fn connect(&self) -> Docker {
         self.connector.clone()
     }
+
+    /// Returns the URI of the Docker daemon like `unix:///run/docker.sock`.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns a `docker` CLI command that talks to the same daemon as the connections of this
+    /// pool.
+    pub fn cli(&self) -> Command {
+        cli(Some(&self.host))
+    }
+}
+
+/// Returns a `docker` CLI command that talks to the daemon at `host` or to the default daemon of
+/// the CLI if `None`.
+pub fn cli(host: Option<&str>) -> Command {
+    let mut cmd = Command::new("docker");
+    if let Some(host) = host {
+        cmd.arg("--host").arg(host);
+    }
+    cmd
 }
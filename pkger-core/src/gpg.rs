@@ -2,6 +2,9 @@ use crate::{err, Error, Result};
 
 use std::path::{Path, PathBuf};
 
+/// Extension of the exported ASCII-armored public key.
+pub static PUBLIC_KEY_EXTENSION: &str = "asc";
+
 #[derive(Clone, Debug)]
 pub struct GpgKey {
     path: PathBuf,
@@ -34,4 +37,26 @@ impl GpgKey {
     pub fn path(&self) -> &PathBuf {
         &self.path
     }
+
+    /// Returns the name of the file that the public key is exported to. The name is derived from
+    /// the name of the key, for example `Pkger Key <pkger@example.com>` becomes
+    /// `pkger-key-pkger-example-com.asc`.
+    pub fn public_key_file(&self) -> String {
+        let mut name = String::new();
+        for c in self.name.chars() {
+            if c.is_ascii_alphanumeric() {
+                name.push(c.to_ascii_lowercase());
+            } else if !name.is_empty() && !name.ends_with('-') {
+                name.push('-');
+            }
+        }
+        format!("{}.{}", name.trim_end_matches('-'), PUBLIC_KEY_EXTENSION)
+    }
+}
+
+/// Returns true if the `path` is an exported public key.
+pub fn is_public_key(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext == PUBLIC_KEY_EXTENSION)
+        .unwrap_or(false)
 }
@@ -3,15 +3,55 @@ pub mod state;
 
 use anyhow::Context;
 pub use os::find;
-pub use state::{ImageState, ImagesState};
+pub use state::{BuildRecord, BuildStats, BuiltImage, ImageState, ImagesState};
 
-use crate::recipe::BuildTarget;
+use crate::recipe::{BuildArch, BuildTarget};
 use crate::{err, Error, Result};
 
 use std::convert::AsRef;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+/// Maximum length of the name of an image.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Verifies that `name` can be used as a name of an image. As the name is also used as the name of
+/// the image in Docker, allowed characters are lowercase ASCII letters, digits, `-`, `_` and `.`
+/// and the name has to start with a letter or a digit.
+pub fn validate_name(name: &str) -> Result<()> {
+    let is_valid_char =
+        |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.');
+
+    if name.is_empty() {
+        return err!("image name can't be empty");
+    }
+    if name.len() > MAX_NAME_LEN {
+        return err!(
+            "image name `{}` is too long, the maximum length is {} characters",
+            name,
+            MAX_NAME_LEN
+        );
+    }
+    if !name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        || !name.chars().all(is_valid_char)
+    {
+        let suggestion: String = name
+            .trim()
+            .to_ascii_lowercase()
+            .chars()
+            .map(|c| if is_valid_char(c) { c } else { '-' })
+            .skip_while(|c| !c.is_ascii_alphanumeric())
+            .collect();
+        return err!(
+            "invalid image name `{}` - only lowercase ASCII letters, digits, `-`, `_` and `.` are allowed and the name has to start with a letter or a digit, for example `{}`",
+            name,
+            suggestion
+        );
+    }
+
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 /// A representation of an image on the filesystem
 pub struct Image {
@@ -31,6 +71,18 @@ impl Image {
             BuildTarget::Pkg => ("archlinux", "pkger-pkg"),
             BuildTarget::Gzip => ("debian:latest", "pkger-gzip"),
             BuildTarget::Apk => ("alpine:latest", "pkger-apk"),
+            BuildTarget::Msi => ("debian:latest", "pkger-msi"),
+            BuildTarget::Xbps => ("ghcr.io/void-linux/void-glibc:latest", "pkger-xbps"),
+        }
+    }
+
+    /// Returns true if the default image of the simple `target` is published for the `host`
+    /// architecture. Other images are pulled in the architecture of the Docker daemon.
+    pub fn simple_supports(target: BuildTarget, host: &BuildArch) -> bool {
+        match target {
+            // the official Arch Linux image is only available for x86_64
+            BuildTarget::Pkg => *host == BuildArch::x86_64,
+            _ => true,
         }
     }
 
@@ -72,11 +124,10 @@ impl Image {
         if !path.join("Dockerfile").exists() {
             return err!("Dockerfile missing from image `{}`", path.display());
         }
-        Ok(Image {
-            // we can unwrap here because we know the Dockerfile exists
-            name: path.file_name().unwrap().to_string_lossy().to_string(),
-            path,
-        })
+        // we can unwrap here because we know the Dockerfile exists
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        validate_name(&name)?;
+        Ok(Image { name, path })
     }
 
     pub fn load_dockerfile(&self) -> Result<String> {
@@ -1,19 +1,34 @@
+use crate::build::deps;
+use crate::build::progress::PhaseDuration;
 use crate::image::find;
 
 use crate::docker::{image::ImageDetails, Docker};
 use crate::recipe::{Os, RecipeTarget};
-use crate::{ErrContext, Result};
+use crate::{err, ErrContext, Error, Result};
 
 use std::collections::{HashMap, HashSet};
 use std::convert::AsRef;
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info, info_span, trace, Instrument};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, info_span, trace, warn, Instrument};
 
 pub static DEFAULT_STATE_FILE: &str = ".pkger.state";
+/// Header of state files that are followed by the SHA-256 checksum of the serialized state.
+/// Files without it are loaded as plain CBOR saved by previous versions.
+const STATE_MAGIC: &[u8] = b"PKGERST1";
+/// Number of previous versions of the state file kept as `<file>.1`, `<file>.2`... to recover
+/// from a corrupted state
+const STATE_BACKUPS: usize = 3;
+
+/// Returns the name of the state file of the given project.
+pub fn project_state_file(project: &str) -> String {
+    format!(".pkger-{}.state", project)
+}
 
 #[derive(Deserialize, Clone, Debug, Serialize)]
 /// Saved state of an image that contains all the metadata of the image
@@ -25,7 +40,16 @@ pub struct ImageState {
     pub timestamp: SystemTime,
     pub details: ImageDetails,
     pub deps: HashSet<String>,
+    #[serde(default)]
+    /// Canonical hash of `deps` used to detect changes of dependencies
+    pub deps_hash: Option<String>,
     pub simple: bool,
+    #[serde(default)]
+    /// Hash of the recipe this image was cached for
+    pub recipe_hash: Option<String>,
+    #[serde(default)]
+    /// Size in bytes added to the base image by installing dependencies
+    pub deps_size: Option<u64>,
 }
 
 impl PartialEq for ImageState {
@@ -37,6 +61,7 @@ impl PartialEq for ImageState {
             && self.timestamp == other.timestamp
             && self.deps == other.deps
             && self.simple == other.simple
+            && self.recipe_hash == other.recipe_hash
     }
 }
 
@@ -78,7 +103,10 @@ impl ImageState {
                 timestamp: *timestamp,
                 details,
                 deps: deps.iter().map(|s| s.to_string()).collect(),
+                deps_hash: Some(deps::hash(deps.iter().copied())),
                 simple,
+                recipe_hash: None,
+                deps_size: None,
             })
         }
         .instrument(span)
@@ -99,11 +127,105 @@ impl ImageState {
 
 //####################################################################################################
 
+#[derive(Deserialize, Clone, Debug, Serialize)]
+/// Record of the last successful build of a recipe on an image
+pub struct BuildRecord {
+    /// Hash of the recipe at the time of the build
+    pub recipe_hash: String,
+    /// ID of the image used for the build
+    pub image_id: String,
+    pub timestamp: SystemTime,
+    #[serde(default)]
+    /// Source of the recipe at the time of the build like the URL of an archive or a git
+    /// repository with a branch
+    pub source: Option<String>,
+    #[serde(default)]
+    /// Path to the built package
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    /// Paths to the subpackages and debug packages built together with the package
+    pub packages: Vec<PathBuf>,
+    #[serde(default)]
+    /// Durations of phases of the build, the slowest first
+    pub steps: Vec<PhaseDuration>,
+    #[serde(default)]
+    /// Preview of the packaging metadata like the spec file or the control file used in the build
+    pub packaging: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, Serialize)]
+/// Statistics of all finished builds of a recipe on an image
+pub struct BuildStats {
+    /// Number of finished builds
+    pub runs: u64,
+    /// Number of failed builds
+    pub failures: u64,
+    /// ID of the session in which the last build failed. Cleared after a successful build.
+    pub failed_session: Option<String>,
+}
+
+impl BuildStats {
+    /// Returns the percentage of failed builds.
+    pub fn failure_rate(&self) -> f32 {
+        if self.runs == 0 {
+            0.
+        } else {
+            self.failures as f32 / self.runs as f32 * 100.
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Serialize)]
+/// An image built by pkger, either from a Dockerfile of an image or a cached one
+pub struct BuiltImage {
+    pub id: String,
+    pub image: String,
+    pub tag: String,
+    pub timestamp: SystemTime,
+    #[serde(default)]
+    /// Imported from the state of a previous version. Such images are never reused by builds so
+    /// they are always superseded.
+    pub imported: bool,
+}
+
+#[derive(Deserialize)]
+/// State saved by the legacy single-crate pkger with images keyed only by their name
+struct LegacyImagesState {
+    images: HashMap<String, LegacyImageState>,
+}
+
+#[derive(Deserialize)]
+/// State saved by previous versions in a single file shared by all projects
+struct PreviousImagesState {
+    images: HashMap<RecipeTarget, LegacyImageState>,
+}
+
+#[derive(Deserialize)]
+/// Image of a state saved by a previous version, other fields are not needed to import it
+struct LegacyImageState {
+    id: String,
+    image: String,
+    tag: String,
+    timestamp: SystemTime,
+}
+
 #[derive(Deserialize, Debug, Serialize)]
 pub struct ImagesState {
     /// Contains historical build data of images. Each key-value pair contains an image name and
     /// [ImageState](ImageState) struct representing the state of the image.
     pub images: HashMap<RecipeTarget, ImageState>,
+    #[serde(default)]
+    /// Last successful builds of each recipe on each image
+    pub builds: HashMap<RecipeTarget, BuildRecord>,
+    #[serde(default)]
+    /// Statistics of finished builds of each recipe on each image
+    pub stats: HashMap<RecipeTarget, BuildStats>,
+    #[serde(default)]
+    /// ID of the last session that ran any builds
+    last_session: Option<String>,
+    #[serde(default)]
+    /// All images built by pkger that were not removed yet, the oldest first
+    built_images: Vec<BuiltImage>,
     /// Path to a file containing image state
     path: PathBuf,
     #[serde(skip_serializing)]
@@ -121,28 +243,87 @@ impl ImagesState {
     pub fn new<P: Into<PathBuf>>(path: P) -> Self {
         Self {
             images: HashMap::new(),
+            builds: HashMap::new(),
+            stats: HashMap::new(),
+            last_session: None,
+            built_images: Vec::new(),
             path: path.into(),
             has_changed: false,
         }
     }
 
     /// Tries to initialize images state from the given path, if the path doesn't exist creates
-    /// a new ImagesState.
+    /// a new ImagesState. If the state file is corrupted the newest valid backup is loaded
+    /// instead.
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
         let state_file = path.as_ref();
-        if !state_file.exists() {
+        if !state_file.exists() && !backup_path(state_file, 1).exists() {
             debug!("state file doesn't exist");
             return Ok(ImagesState::new(state_file));
         }
         debug!("loading state");
-        let contents =
-            fs::read(state_file).context("failed to read images state file from the filesystem")?;
-        let state =
-            serde_cbor::from_slice(&contents).context("failed to deserialize images state")?;
+        let error = match Self::load_file(state_file) {
+            Ok(state) => return Ok(state),
+            Err(e) => e,
+        };
+        for n in 1..=STATE_BACKUPS {
+            let backup = backup_path(state_file, n);
+            if !backup.exists() {
+                break;
+            }
+            match Self::load_file(&backup) {
+                Ok(mut state) => {
+                    warn!(reason = %format!("{:?}", error), backup = %backup.display(), "images state is corrupted, recovered it from a backup");
+                    state.has_changed = true;
+                    return Ok(state);
+                }
+                Err(e) => {
+                    debug!(backup = %backup.display(), reason = %format!("{:?}", e), "invalid backup")
+                }
+            }
+        }
+        Err(error)
+    }
 
+    /// Loads the state from `path` like `load`. If neither the state file nor any of its backups
+    /// exist yet, images of the state file at `previous` shared by all projects in previous
+    /// versions are imported into a new state so that they can be pruned once they are rebuilt
+    /// for the project.
+    pub fn load_or_import<P: AsRef<Path>>(path: P, previous: &Path) -> Result<Self> {
+        let state_file = path.as_ref();
+        if state_file.exists() || backup_path(state_file, 1).exists() || !previous.exists() {
+            return Self::load(state_file);
+        }
+        let mut state = Self::new(state_file);
+        let imported = state.import_previous(previous).context(format!(
+            "failed to import images state of a previous version from `{}`",
+            previous.display()
+        ))?;
+        warn!(previous = %previous.display(), images = %imported, "imported images state of a previous version, images are rebuilt for this project, run `pkger prune-images` to remove the old ones");
         Ok(state)
     }
 
+    fn load_file(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read(path).context("failed to read images state file from the filesystem")?;
+        Self::decode(&contents)
+    }
+
+    /// Deserializes the state verifying its checksum.
+    fn decode(contents: &[u8]) -> Result<Self> {
+        serde_cbor::from_slice(payload(contents)?).context("failed to deserialize images state")
+    }
+
+    /// Serializes the state prefixed with a header and the checksum of the serialized data.
+    fn encode(&self) -> Result<Vec<u8>> {
+        let payload = serde_cbor::to_vec(&self).context("failed to serialize image state")?;
+        let mut contents = Vec::with_capacity(STATE_MAGIC.len() + 32 + payload.len());
+        contents.extend_from_slice(STATE_MAGIC);
+        contents.extend_from_slice(&Sha256::digest(&payload));
+        contents.extend_from_slice(&payload);
+        Ok(contents)
+    }
+
     /// Updates the target image with a new state.
     pub fn update(&mut self, target: RecipeTarget, state: ImageState) {
         if let Some(old_state) = self.images.get(&target) {
@@ -150,15 +331,262 @@ impl ImagesState {
                 self.has_changed = true
             }
         }
+        self.record_image(&state);
         self.images.insert(target, state);
     }
 
-    /// Saves the images state to the filesystem.
+    /// Remembers the image of `state` as built by pkger so that it can be pruned once it is
+    /// superseded.
+    fn record_image(&mut self, state: &ImageState) {
+        if self.built_images.iter().any(|image| image.id == state.id) {
+            return;
+        }
+        self.built_images.push(BuiltImage {
+            id: state.id.clone(),
+            image: state.image.clone(),
+            tag: state.tag.clone(),
+            timestamp: state.timestamp,
+            imported: false,
+        });
+        self.has_changed = true;
+    }
+
+    /// Returns images built by pkger that were superseded by newer builds of the same image and
+    /// tag and are not used by any recipe, together with all images imported from previous
+    /// versions. `keep` most recent superseded images of each image and tag are not returned.
+    pub fn superseded_images(&self, keep: usize) -> Vec<&BuiltImage> {
+        let mut groups: HashMap<(&str, &str), Vec<&BuiltImage>> = HashMap::new();
+        for image in self.built_images.iter().filter(|image| !image.imported) {
+            groups
+                .entry((image.image.as_str(), image.tag.as_str()))
+                .or_default()
+                .push(image);
+        }
+
+        let mut superseded = groups
+            .into_values()
+            .flat_map(|mut images| {
+                images.sort_by_key(|image| std::cmp::Reverse(image.timestamp));
+                images
+                    .into_iter()
+                    .skip(1)
+                    .filter(|image| !self.images.values().any(|state| state.id == image.id))
+                    .skip(keep)
+            })
+            .chain(self.built_images.iter().filter(|image| image.imported))
+            .collect::<Vec<_>>();
+        superseded.sort_by_key(|image| image.timestamp);
+        superseded
+    }
+
+    /// Imports images from the state file at `path` shared by all projects in previous versions.
+    /// Images of previous versions have no hash of the recipe and are tagged for no project so
+    /// they are not reused by builds, they are only recorded as built so that they can be pruned.
+    /// Returns the number of images that were not recorded yet.
+    pub fn import_previous(&mut self, path: &Path) -> Result<usize> {
+        let contents = fs::read(path).context("failed to read images state")?;
+        let previous: PreviousImagesState = serde_cbor::from_slice(payload(&contents)?)
+            .context("failed to deserialize images state")?;
+        Ok(self.record_imported(previous.images.into_values()))
+    }
+
+    /// Records `images` of a previous version as built, the oldest first. Returns the number of
+    /// images that were not recorded yet.
+    fn record_imported(&mut self, images: impl IntoIterator<Item = LegacyImageState>) -> usize {
+        let mut images: Vec<_> = images.into_iter().collect();
+        images.sort_by_key(|image| image.timestamp);
+
+        let mut imported = 0;
+        for image in images {
+            if self.built_images.iter().any(|built| built.id == image.id) {
+                continue;
+            }
+            trace!(id = %image.id, image = %image.image, tag = %image.tag, "importing image");
+            self.built_images.push(BuiltImage {
+                id: image.id,
+                image: image.image,
+                tag: image.tag,
+                timestamp: image.timestamp,
+                imported: true,
+            });
+            imported += 1;
+        }
+        if imported > 0 {
+            self.has_changed = true;
+        }
+        imported
+    }
+
+    /// Imports images from the state file at `path` saved by the legacy single-crate pkger or
+    /// shared by all projects in previous versions of pkger-cli, the format is detected from the
+    /// keys of the images. Legacy images are not tied to any recipe so they are not reused by
+    /// builds, they are only recorded as built so that they can be pruned. Returns the number of
+    /// images that were not recorded yet.
+    pub fn import_legacy(&mut self, path: &Path) -> Result<usize> {
+        let contents = fs::read(path).context("failed to read legacy images state")?;
+        if contents.starts_with(STATE_MAGIC) {
+            return err!("`{}` is not a legacy images state", path.display());
+        }
+        if let Ok(legacy) = serde_cbor::from_slice::<LegacyImagesState>(&contents) {
+            return Ok(self.record_imported(legacy.images.into_values()));
+        }
+        trace!("not a state of the single-crate pkger, importing state shared by projects");
+        let previous: PreviousImagesState = serde_cbor::from_slice(&contents)
+            .context("failed to deserialize legacy images state")?;
+        Ok(self.record_imported(previous.images.into_values()))
+    }
+
+    /// Forgets the image with `id` after it was removed from Docker.
+    pub fn forget_image(&mut self, id: &str) {
+        let len = self.built_images.len();
+        self.built_images.retain(|image| image.id != id);
+        if self.built_images.len() != len {
+            self.has_changed = true;
+        }
+    }
+
+    /// Removes the state of the `target` image, for example because its image doesn't exist in
+    /// Docker anymore, so that it is built again.
+    pub fn remove(&mut self, target: &RecipeTarget) -> Option<ImageState> {
+        let state = self.images.remove(target);
+        if state.is_some() {
+            self.has_changed = true;
+        }
+        state
+    }
+
+    /// Returns IDs of all images referenced by the state.
+    pub fn image_ids(&self) -> HashSet<&str> {
+        self.images
+            .values()
+            .map(|state| state.id.as_str())
+            .chain(self.built_images.iter().map(|image| image.id.as_str()))
+            .collect()
+    }
+
+    /// Reconciles the state with the images that exist in Docker. States of images whose ID is
+    /// not in `existing` are removed and such images are forgotten. Returns the targets whose
+    /// state was removed with the removed states.
+    pub fn reconcile(&mut self, existing: &HashSet<String>) -> Vec<(RecipeTarget, ImageState)> {
+        let missing: Vec<_> = self
+            .images
+            .iter()
+            .filter(|(_, state)| !existing.contains(&state.id))
+            .map(|(target, _)| target.clone())
+            .collect();
+        let removed = missing
+            .into_iter()
+            .filter_map(|target| self.remove(&target).map(|state| (target, state)))
+            .collect();
+        let len = self.built_images.len();
+        self.built_images
+            .retain(|image| existing.contains(&image.id));
+        if self.built_images.len() != len {
+            self.has_changed = true;
+        }
+        removed
+    }
+
+    /// Records a successful build of the target.
+    pub fn record_build(&mut self, target: RecipeTarget, record: BuildRecord) {
+        self.builds.insert(target, record);
+        self.has_changed = true;
+    }
+
+    /// Records the result of a finished build of the target in the session with ID `session`.
+    pub fn record_result(&mut self, target: RecipeTarget, session: String, success: bool) {
+        let stats = self.stats.entry(target).or_default();
+        stats.runs += 1;
+        if success {
+            stats.failed_session = None;
+        } else {
+            stats.failures += 1;
+            stats.failed_session = Some(session);
+        }
+        self.has_changed = true;
+    }
+
+    /// Returns statistics of finished builds of `recipe` on `image`.
+    pub fn stats(&self, recipe: &str, image: &str) -> Option<&BuildStats> {
+        self.stats
+            .iter()
+            .find(|(target, _)| target.recipe() == recipe && target.image() == image)
+            .map(|(_, stats)| stats)
+    }
+
+    /// Marks `session` as the last session that ran any builds.
+    pub fn set_last_session(&mut self, session: String) {
+        if self.last_session.as_ref() != Some(&session) {
+            self.last_session = Some(session);
+            self.has_changed = true;
+        }
+    }
+
+    /// Returns targets that failed during the last session that ran any builds.
+    pub fn failed_in_last_session(&self) -> Vec<&RecipeTarget> {
+        self.stats
+            .iter()
+            .filter(|(_, stats)| {
+                stats.failed_session.is_some() && stats.failed_session == self.last_session
+            })
+            .map(|(target, _)| target)
+            .collect()
+    }
+
+    /// Returns the last successful build of `recipe` on `image`.
+    pub fn last_build(&self, recipe: &str, image: &str) -> Option<&BuildRecord> {
+        self.builds
+            .iter()
+            .find(|(target, _)| target.recipe() == recipe && target.image() == image)
+            .map(|(_, record)| record)
+    }
+
+    /// Returns the target and the state of the image used by the last successful build that
+    /// produced the package at `output`, either the main package or one of the other packages.
+    pub fn find_build(&self, output: &Path) -> Option<(&RecipeTarget, &ImageState)> {
+        self.builds
+            .iter()
+            .find(|(_, record)| {
+                record.output.as_deref() == Some(output)
+                    || record.packages.iter().any(|package| package == output)
+            })
+            .and_then(|(target, _)| self.images.get(target).map(|state| (target, state)))
+    }
+
+    /// Returns `Some(true)` if `hash` differs from the hash of `recipe` recorded during the last
+    /// successful build on `image`, `None` if the recipe was never built on that image.
+    pub fn recipe_changed(&self, recipe: &str, image: &str, hash: &str) -> Option<bool> {
+        self.last_build(recipe, image)
+            .map(|record| record.recipe_hash != hash)
+    }
+
+    /// Saves the images state to the filesystem. The state is written to a temporary file that
+    /// replaces the state file after the previous versions are rotated to backups.
     pub fn save(&self) -> Result<()> {
         trace!("saving images state");
-        serde_cbor::to_vec(&self)
-            .context("failed to serialize image state")
-            .and_then(|d| fs::write(&self.path, d).context("failed to save state file"))
+        let contents = self.encode()?;
+
+        let mut tmp_name = self.path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+        let mut file =
+            fs::File::create(&tmp_path).context("failed to create temporary state file")?;
+        file.write_all(&contents)
+            .and_then(|_| file.sync_all())
+            .context("failed to save state file")?;
+
+        if self.path.exists() {
+            for n in (1..STATE_BACKUPS).rev() {
+                let backup = backup_path(&self.path, n);
+                if backup.exists() {
+                    fs::rename(&backup, backup_path(&self.path, n + 1))
+                        .context("failed to rotate state backups")?;
+                }
+            }
+            fs::rename(&self.path, backup_path(&self.path, 1))
+                .context("failed to back up state file")?;
+        }
+        fs::rename(&tmp_path, &self.path).context("failed to save state file")
     }
 
     /// Returns the location from which this state was initialized.
@@ -166,9 +594,13 @@ impl ImagesState {
         &self.path
     }
 
-    /// Clears the state to contain no images.
+    /// Clears the state to contain no images. Images built by pkger are still remembered so that
+    /// they can be pruned later.
     pub fn clear(&mut self) {
         self.images.clear();
+        self.builds.clear();
+        self.stats.clear();
+        self.last_session = None;
     }
 
     /// Returns true if the state was updated.
@@ -176,3 +608,257 @@ impl ImagesState {
         self.has_changed
     }
 }
+
+/// Returns the serialized state from the `contents` of a state file verifying its checksum. Files
+/// saved by previous versions contain only the serialized state.
+fn payload(contents: &[u8]) -> Result<&[u8]> {
+    match contents.strip_prefix(STATE_MAGIC) {
+        Some(rest) => {
+            if rest.len() < 32 {
+                return err!("images state file is truncated");
+            }
+            let (checksum, payload) = rest.split_at(32);
+            if Sha256::digest(payload)[..] != *checksum {
+                return err!("checksum of the images state file doesn't match its content");
+            }
+            Ok(payload)
+        }
+        None => Ok(contents),
+    }
+}
+
+/// Returns the path of the `n`-th backup of the state file, `1` being the newest.
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::{BuildTarget, ImageTarget};
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    fn built_image(id: &str, image: &str, tag: &str, secs: u64) -> BuiltImage {
+        BuiltImage {
+            id: id.to_string(),
+            image: image.to_string(),
+            tag: tag.to_string(),
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            imported: false,
+        }
+    }
+
+    fn imported_image(id: &str, image: &str, tag: &str, secs: u64) -> BuiltImage {
+        BuiltImage {
+            imported: true,
+            ..built_image(id, image, tag, secs)
+        }
+    }
+
+    /// Writes a state in the layout saved in `<cache_dir>/.pkger.state` by the previous release,
+    /// before states were kept per project. Details of images are skipped on import so they are
+    /// left out.
+    fn write_previous_state(path: &Path) {
+        #[derive(Serialize)]
+        struct PreviousImageState {
+            id: String,
+            image: String,
+            tag: String,
+            os: Os,
+            timestamp: SystemTime,
+            deps: HashSet<String>,
+            simple: bool,
+        }
+        #[derive(Serialize)]
+        struct Previous {
+            images: HashMap<RecipeTarget, PreviousImageState>,
+            path: PathBuf,
+        }
+
+        let image_state = |id: &str, image: &str, secs: u64| PreviousImageState {
+            id: id.to_string(),
+            image: image.to_string(),
+            tag: "cached".to_string(),
+            os: Os::new("rocky", Some("9")).unwrap(),
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            deps: HashSet::from(["gcc".to_string()]),
+            simple: false,
+        };
+        let target = |recipe: &str, image: &str| {
+            RecipeTarget::new(
+                recipe.to_string(),
+                ImageTarget::new(image, BuildTarget::Rpm, None::<&str>),
+            )
+        };
+        let previous = Previous {
+            images: HashMap::from([
+                (target("test", "rocky"), image_state("2", "rocky", 2)),
+                (target("test", "centos"), image_state("1", "centos", 1)),
+            ]),
+            path: path.to_path_buf(),
+        };
+        fs::write(path, serde_cbor::to_vec(&previous).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn finds_superseded_images() {
+        let mut state = ImagesState::new("state");
+        state.built_images = vec![
+            built_image("1", "rocky", "latest", 1),
+            built_image("2", "rocky", "cached", 2),
+            built_image("3", "rocky", "latest", 3),
+            built_image("4", "rocky", "latest", 4),
+            built_image("5", "debian", "latest", 5),
+        ];
+
+        let ids = |images: Vec<&BuiltImage>| {
+            images
+                .into_iter()
+                .map(|image| image.id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(state.superseded_images(0)), vec!["1", "3"]);
+        assert_eq!(ids(state.superseded_images(1)), vec!["1"]);
+        assert!(state.superseded_images(2).is_empty());
+
+        state.forget_image("1");
+        assert_eq!(ids(state.superseded_images(0)), vec!["3"]);
+        assert!(state.has_changed());
+
+        let existing: HashSet<String> = ["2", "4", "5"].iter().map(|id| id.to_string()).collect();
+        let mut all_ids: Vec<_> = state.image_ids().into_iter().collect();
+        all_ids.sort_unstable();
+        assert_eq!(all_ids, vec!["2", "3", "4", "5"]);
+        assert!(state.reconcile(&existing).is_empty());
+        assert!(state.superseded_images(0).is_empty());
+        assert_eq!(state.image_ids().len(), 3);
+    }
+
+    #[test]
+    fn recovers_corrupted_state_from_backups() {
+        let tmp = tempdir::TempDir::new("pkger-state").unwrap();
+        let path = tmp.path().join(".pkger.state");
+
+        let mut state = ImagesState::new(&path);
+        for (id, secs) in [("1", 1), ("2", 2), ("3", 3), ("4", 4), ("5", 5)] {
+            state
+                .built_images
+                .push(built_image(id, "rocky", "latest", secs));
+            state.save().unwrap();
+        }
+        assert!(backup_path(&path, STATE_BACKUPS).exists());
+        assert!(!backup_path(&path, STATE_BACKUPS + 1).exists());
+        assert!(!path.with_extension("state.tmp").exists());
+        assert_eq!(ImagesState::load(&path).unwrap().built_images.len(), 5);
+
+        let mut contents = fs::read(&path).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 0xff;
+        fs::write(&path, &contents).unwrap();
+        assert_eq!(
+            ImagesState::load_file(&path).unwrap_err().to_string(),
+            "checksum of the images state file doesn't match its content"
+        );
+        let recovered = ImagesState::load(&path).unwrap();
+        assert_eq!(recovered.built_images.len(), 4);
+        assert!(recovered.has_changed());
+
+        // state files saved by previous versions contain only the serialized state
+        fs::write(&path, serde_cbor::to_vec(&state).unwrap()).unwrap();
+        assert_eq!(ImagesState::load(&path).unwrap().built_images.len(), 5);
+    }
+
+    #[test]
+    fn imports_legacy_state() {
+        #[derive(Serialize)]
+        struct Legacy {
+            images: HashMap<String, BuiltImage>,
+            path: PathBuf,
+        }
+
+        let tmp = tempdir::TempDir::new("pkger-state").unwrap();
+        let path = tmp.path().join(".pkger.state");
+        let legacy = Legacy {
+            images: HashMap::from([
+                ("rocky".to_string(), built_image("2", "rocky", "latest", 2)),
+                (
+                    "debian".to_string(),
+                    built_image("1", "debian", "latest", 1),
+                ),
+            ]),
+            path: path.clone(),
+        };
+        fs::write(&path, serde_cbor::to_vec(&legacy).unwrap()).unwrap();
+        assert!(ImagesState::load_file(&path).is_err());
+
+        let mut state = ImagesState::new(tmp.path().join(".pkger-default.state"));
+        state.built_images = vec![built_image("2", "rocky", "latest", 2)];
+        assert_eq!(state.import_legacy(&path).unwrap(), 1);
+        assert_eq!(
+            state.built_images,
+            vec![
+                built_image("2", "rocky", "latest", 2),
+                imported_image("1", "debian", "latest", 1)
+            ]
+        );
+        assert!(state.has_changed());
+        assert_eq!(state.import_legacy(&path).unwrap(), 0);
+
+        state.save().unwrap();
+        assert!(state
+            .import_legacy(&tmp.path().join(".pkger-default.state"))
+            .is_err());
+    }
+
+    #[test]
+    fn imports_state_shared_by_projects() {
+        let tmp = tempdir::TempDir::new("pkger-state").unwrap();
+        let previous_path = tmp.path().join(DEFAULT_STATE_FILE);
+        write_previous_state(&previous_path);
+
+        let path = tmp.path().join(project_state_file("default"));
+        let state = ImagesState::load_or_import(&path, &previous_path).unwrap();
+        assert_eq!(state.locations(), path.as_path());
+        assert!(state.images.is_empty());
+        assert_eq!(
+            state.built_images,
+            vec![
+                imported_image("1", "centos", "cached", 1),
+                imported_image("2", "rocky", "cached", 2)
+            ]
+        );
+        assert_eq!(state.superseded_images(1).len(), 2);
+        assert!(state.has_changed());
+        state.save().unwrap();
+
+        // the state of the project is loaded once it exists
+        fs::write(&previous_path, b"invalid").unwrap();
+        let reloaded = ImagesState::load_or_import(&path, &previous_path).unwrap();
+        assert_eq!(reloaded.built_images, state.built_images);
+        assert!(!reloaded.has_changed());
+    }
+
+    #[test]
+    fn migrates_state_of_previous_release() {
+        let tmp = tempdir::TempDir::new("pkger-state").unwrap();
+        let previous_path = tmp.path().join(DEFAULT_STATE_FILE);
+        write_previous_state(&previous_path);
+
+        let mut state = ImagesState::new(tmp.path().join(project_state_file("default")));
+        assert_eq!(state.import_legacy(&previous_path).unwrap(), 2);
+        assert_eq!(
+            state.built_images,
+            vec![
+                imported_image("1", "centos", "cached", 1),
+                imported_image("2", "rocky", "cached", 2)
+            ]
+        );
+        assert_eq!(state.import_legacy(&previous_path).unwrap(), 0);
+
+        fs::write(&previous_path, b"invalid").unwrap();
+        assert!(state.import_legacy(&previous_path).is_err());
+    }
+}
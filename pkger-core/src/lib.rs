@@ -2,15 +2,22 @@
 extern crate anyhow;
 
 pub mod archive;
+pub mod blocking;
 pub mod build;
 pub mod container;
 pub mod docker;
 pub mod gpg;
 pub mod image;
+pub mod network;
 pub mod oneshot;
 pub mod recipe;
+pub mod render;
+pub mod security;
+pub mod shell;
 pub mod ssh;
 pub mod template;
+pub mod text;
+pub mod units;
 
 pub use anyhow::{anyhow, Context as ErrContext, Error, Result};
 
@@ -0,0 +1,206 @@
+use crate::{err, Error, Result};
+
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+#[derive(Clone, Default, Deserialize, Debug, Serialize, Eq, PartialEq, Hash)]
+/// Network mode, DNS settings and additional entries of `/etc/hosts` of build containers
+pub struct NetworkConfig {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Network mode of build containers and image builds like `bridge`, `host` or the name of a
+    /// user defined network. Defaults to the default network of docker
+    pub mode: Option<String>,
+    #[serde(default)]
+    /// Configures APT, DNF and YUM to only resolve addresses of mirrors to IPv6 addresses, for
+    /// networks that have no IPv4 connectivity
+    pub ipv6_only: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Addresses of DNS servers
+    pub dns: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Search domains used to resolve names that are not fully qualified
+    pub dns_search: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Entries of `/etc/hosts` in the `hostname:address` format
+    pub extra_hosts: Vec<String>,
+}
+
+/// Network that containers are attached to when no network mode is configured.
+pub static DEFAULT_MODE: &str = "bridge";
+
+/// Configuration file of APT created in IPv6 only mode.
+static APT_IPV6_CONF: &str = "/etc/apt/apt.conf.d/99pkger-ipv6";
+
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && !domain.starts_with(['-', '.'])
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
+}
+
+impl NetworkConfig {
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none()
+            && !self.ipv6_only
+            && self.dns.is_empty()
+            && self.dns_search.is_empty()
+            && self.extra_hosts.is_empty()
+    }
+
+    /// Verifies that all addresses and names are valid.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(mode) = &self.mode {
+            let is_valid = !mode.is_empty()
+                && mode
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+            if !is_valid {
+                return err!("invalid network mode `{}`", mode);
+            }
+        }
+        for dns in &self.dns {
+            if dns.parse::<IpAddr>().is_err() {
+                return err!("invalid address of a DNS server `{}`", dns);
+            }
+        }
+        for domain in &self.dns_search {
+            if !is_valid_domain(domain) {
+                return err!("invalid DNS search domain `{}`", domain);
+            }
+        }
+        for host in &self.extra_hosts {
+            let (name, address) = Self::split_host(host)?;
+            if !is_valid_domain(name) || address.parse::<IpAddr>().is_err() {
+                return err!("invalid extra host `{}`, expected `hostname:address`", host);
+            }
+        }
+        Ok(())
+    }
+
+    fn split_host(host: &str) -> Result<(&str, &str)> {
+        host.split_once(':')
+            .ok_or_else(|| anyhow!("invalid extra host `{}`, expected `hostname:address`", host))
+    }
+
+    /// Returns the configuration with settings of `other`, like the ones of an image, applied on
+    /// top of this one. DNS servers and search domains of `other` replace the ones of this
+    /// configuration when set, extra hosts of both are kept. The network mode of `other` wins if
+    /// set and IPv6 only mode is enabled if either of them enables it.
+    pub fn merge(&self, other: &NetworkConfig) -> NetworkConfig {
+        let pick = |this: &Vec<String>, other: &Vec<String>| {
+            if other.is_empty() {
+                this.clone()
+            } else {
+                other.clone()
+            }
+        };
+        NetworkConfig {
+            mode: other.mode.clone().or_else(|| self.mode.clone()),
+            ipv6_only: self.ipv6_only || other.ipv6_only,
+            dns: pick(&self.dns, &other.dns),
+            dns_search: pick(&self.dns_search, &other.dns_search),
+            extra_hosts: self
+                .extra_hosts
+                .iter()
+                .chain(&other.extra_hosts)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Returns a shell command that configures APT, DNF and YUM to resolve addresses of mirrors
+    /// to IPv6 only if IPv6 only mode is enabled. DNS servers, search domains and extra hosts are
+    /// passed to docker instead.
+    pub fn ipv6_script(&self) -> Option<String> {
+        if !self.ipv6_only {
+            return None;
+        }
+
+        Some(format!(
+            "if [ -d /etc/apt/apt.conf.d ]; then echo 'Acquire::ForceIPv6 \"true\";' > {}; fi && \
+             for conf in /etc/dnf/dnf.conf /etc/yum.conf; do if [ -f $conf ] && ! grep -q '^ip_resolve' $conf; then sed -i '/^\\[main\\]/a ip_resolve=6' $conf; fi; done",
+            APT_IPV6_CONF
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn merges_network_configs() {
+        let config = NetworkConfig {
+            dns: vec!["10.0.0.2".into()],
+            extra_hosts: vec!["git.corp:10.0.0.5".into()],
+            ..Default::default()
+        };
+        let image = NetworkConfig {
+            dns_search: vec!["corp.example.com".into()],
+            extra_hosts: vec!["mirror.corp:fd00::1".into()],
+            ..Default::default()
+        };
+        let network = config.merge(&image);
+        network.validate().unwrap();
+
+        assert_eq!(
+            network,
+            NetworkConfig {
+                dns: vec!["10.0.0.2".into()],
+                dns_search: vec!["corp.example.com".into()],
+                extra_hosts: vec!["git.corp:10.0.0.5".into(), "mirror.corp:fd00::1".into()],
+                ..Default::default()
+            }
+        );
+        // DNS settings and hosts are passed to docker and don't need a script
+        assert_eq!(network.ipv6_script(), None);
+
+        let invalid = |dns: &str, search: &str, host: &str| NetworkConfig {
+            dns: vec![dns.into()],
+            dns_search: vec![search.into()],
+            extra_hosts: vec![host.into()],
+            ..Default::default()
+        };
+        assert!(invalid("10.0.0.256", "corp", "a:10.0.0.1")
+            .validate()
+            .is_err());
+        assert!(invalid("10.0.0.2", "corp'; rm", "a:10.0.0.1")
+            .validate()
+            .is_err());
+        assert!(invalid("10.0.0.2", "corp", "10.0.0.1").validate().is_err());
+    }
+
+    #[test]
+    fn configures_ipv6_only_networks() {
+        let config = NetworkConfig {
+            mode: Some("pkger-v6".into()),
+            ..Default::default()
+        };
+        let image = NetworkConfig {
+            ipv6_only: true,
+            ..Default::default()
+        };
+        let network = config.merge(&image);
+        network.validate().unwrap();
+        assert_eq!(network.mode.as_deref(), Some("pkger-v6"));
+
+        let script = network.ipv6_script().unwrap();
+        assert!(script.starts_with(
+            "if [ -d /etc/apt/apt.conf.d ]; then echo 'Acquire::ForceIPv6 \"true\";' > /etc/apt/apt.conf.d/99pkger-ipv6; fi && "
+        ));
+        assert!(script.ends_with("then sed -i '/^\\[main\\]/a ip_resolve=6' $conf; fi; done"));
+        assert_eq!(config.ipv6_script(), None);
+
+        let invalid = NetworkConfig {
+            mode: Some("bridge; rm".into()),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
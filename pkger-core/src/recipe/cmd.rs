@@ -1,6 +1,9 @@
 use crate::recipe::BuildTarget;
+use crate::{err, Error, Result};
 
 use serde::{Deserialize, Serialize};
+use serde_yaml::Mapping;
+use std::path::PathBuf;
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
 /// Wrapper type for steps parsed from a recipe. Can be either a simple string or a map specifying
@@ -12,7 +15,12 @@ use serde::{Deserialize, Serialize};
 /// { cmd = "echo 123", images = ["centos8", "debian10"] }
 ///
 /// { cmd = "echo 321", rpm = true } # execute only when building rpm target
+///
+/// { cmd = "echo $FOO", env = { FOO = "bar" } } # set additional environment variables for this step
+///
+/// { python = "print(123)" } # execute a script with an interpreter other than the shell
 pub struct Command {
+    #[serde(default)]
     pub cmd: String,
     pub images: Option<Vec<String>>,
     pub rpm: Option<bool>,
@@ -20,6 +28,27 @@ pub struct Command {
     pub pkg: Option<bool>,
     pub gzip: Option<bool>,
     pub apk: Option<bool>,
+    pub msi: Option<bool>,
+    pub xbps: Option<bool>,
+    pub env: Option<Mapping>,
+    /// Allocate a TTY and attach stdin so that the step can answer interactive prompts
+    pub interactive: Option<bool>,
+    /// File with answers passed as stdin to an interactive step instead of the terminal
+    pub answers: Option<PathBuf>,
+    /// Regex that the output of the step has to match for it to succeed
+    pub expect_output: Option<String>,
+    /// Exit codes that are considered successful, defaults to `[0]`
+    pub expect_exit_codes: Option<Vec<u64>>,
+    /// Content of a script executed instead of `cmd`. The script is uploaded to the container and
+    /// executed with the `interpreter`
+    pub script: Option<String>,
+    /// Interpreter of the `script`, defaults to the shell of the phase
+    pub interpreter: Option<String>,
+    /// Content of a Python script executed with `python3` instead of `cmd`
+    pub python: Option<String>,
+    /// Snapshot the build and output directories after this step and restore them instead of
+    /// running the step when neither the step nor its inputs changed
+    pub cache: Option<bool>,
 }
 
 impl From<&str> for Command {
@@ -32,13 +61,99 @@ impl From<&str> for Command {
             pkg: None,
             gzip: None,
             apk: None,
+            msi: None,
+            xbps: None,
+            env: None,
+            interactive: None,
+            answers: None,
+            expect_output: None,
+            expect_exit_codes: None,
+            script: None,
+            interpreter: None,
+            python: None,
+            cache: None,
         }
     }
 }
 
 impl Command {
+    /// Verifies that exactly one of `cmd`, `script` or `python` is set.
+    pub fn validate(&self) -> Result<()> {
+        let set = [
+            !self.cmd.is_empty(),
+            self.script.is_some(),
+            self.python.is_some(),
+        ]
+        .iter()
+        .filter(|set| **set)
+        .count();
+        if set != 1 {
+            return err!(
+                "step `{}` has to set exactly one of `cmd`, `script` or `python`",
+                self.label()
+            );
+        }
+        if self.interpreter.is_some() && self.script.is_none() {
+            return err!("`interpreter` of step `{}` requires `script`", self.label());
+        }
+        Ok(())
+    }
+
+    /// Returns the interpreter and the content of a step that runs a script instead of a command.
+    /// The interpreter is `None` when the script should run with the shell of the phase.
+    pub fn script(&self) -> Option<(Option<&str>, &str)> {
+        if let Some(python) = &self.python {
+            Some((Some("python3"), python))
+        } else {
+            self.script
+                .as_deref()
+                .map(|script| (self.interpreter.as_deref(), script))
+        }
+    }
+
+    /// Returns the content of the file of a script step starting with a shebang of the
+    /// interpreter, `shell` is used when the step doesn't specify one. A shebang already present
+    /// in the script is kept unless the interpreter was specified.
+    pub fn script_file(&self, shell: &str) -> Option<String> {
+        let (interpreter, script) = self.script()?;
+        if interpreter.is_none() && script.starts_with("#!") {
+            return Some(script.to_string());
+        }
+        let interpreter = interpreter.unwrap_or(shell);
+        let shebang = if interpreter.starts_with('/') {
+            interpreter.to_string()
+        } else {
+            format!("/usr/bin/env {}", interpreter)
+        };
+        Some(format!("#!{}\n{}", shebang, script))
+    }
+
+    /// Returns the command of this step or the first line of its script, used to identify the
+    /// step in logs and errors.
+    pub fn label(&self) -> &str {
+        match self.script() {
+            Some((_, script)) => script
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty() && !line.starts_with("#!"))
+                .unwrap_or_default(),
+            None => &self.cmd,
+        }
+    }
+
+    pub fn is_cached(&self) -> bool {
+        self.cache.unwrap_or_default()
+    }
+    pub fn is_interactive(&self) -> bool {
+        self.interactive.unwrap_or_default()
+    }
     pub fn has_target_specified(&self) -> bool {
-        self.rpm.is_some() || self.deb.is_some() || self.pkg.is_some() || self.gzip.is_some()
+        self.rpm.is_some()
+            || self.deb.is_some()
+            || self.pkg.is_some()
+            || self.gzip.is_some()
+            || self.msi.is_some()
+            || self.xbps.is_some()
     }
     pub fn should_run_on(&self, target: &BuildTarget) -> bool {
         if !self.has_target_specified() {
@@ -50,6 +165,8 @@ impl Command {
             BuildTarget::Pkg => self.pkg,
             BuildTarget::Gzip => self.gzip,
             BuildTarget::Apk => self.apk,
+            BuildTarget::Msi => self.msi,
+            BuildTarget::Xbps => self.xbps,
         }
         .unwrap_or_default()
     }
@@ -82,4 +199,45 @@ mod tests {
         assert!(cmd.should_run_on(&BuildTarget::Deb));
         assert!(cmd.should_run_on(&BuildTarget::Apk));
     }
+
+    #[test]
+    fn parses_step_env() {
+        let cmd: Command = serde_yaml::from_str("cmd: echo $FOO\nenv:\n  FOO: bar\n").unwrap();
+        let env = crate::recipe::Env::from(cmd.env);
+        assert_eq!(env.inner().get("FOO"), Some(&"bar".to_string()));
+    }
+
+    #[test]
+    fn parses_script_steps() {
+        let cmd: Command =
+            serde_yaml::from_str("python: |\n  import os\n  print(os.getcwd())\n").unwrap();
+        assert!(cmd.validate().is_ok());
+        assert_eq!(cmd.label(), "import os");
+        assert_eq!(
+            cmd.script_file("/bin/sh").unwrap(),
+            "#!/usr/bin/env python3\nimport os\nprint(os.getcwd())\n"
+        );
+
+        let cmd: Command = serde_yaml::from_str("script: \"#!/bin/bash\\necho 1\"").unwrap();
+        assert_eq!(cmd.script_file("fish").unwrap(), "#!/bin/bash\necho 1");
+        let cmd: Command = serde_yaml::from_str("script: echo 1\ninterpreter: /bin/bash").unwrap();
+        assert_eq!(cmd.script_file("fish").unwrap(), "#!/bin/bash\necho 1");
+        let cmd: Command = serde_yaml::from_str("script: echo 1").unwrap();
+        assert_eq!(
+            cmd.script_file("fish").unwrap(),
+            "#!/usr/bin/env fish\necho 1"
+        );
+
+        assert!(Command::from("echo 1").validate().is_ok());
+        assert!(
+            serde_yaml::from_str::<Command>("cmd: echo 1\npython: print(1)")
+                .unwrap()
+                .validate()
+                .is_err()
+        );
+        assert!(serde_yaml::from_str::<Command>("images: [debian]")
+            .unwrap()
+            .validate()
+            .is_err());
+    }
 }
@@ -1,5 +1,6 @@
 use serde_yaml::Mapping;
 use std::collections::HashMap;
+use std::ffi::OsString;
 
 #[derive(Clone, Default, Debug, PartialEq)]
 pub struct Env(HashMap<String, String>);
@@ -61,6 +62,34 @@ impl Env {
     pub fn inner(&self) -> &HashMap<String, String> {
         &self.0
     }
+
+    /// Returns the variables from `vars`, like the environment of the host, whose names match one
+    /// of the `patterns`. A pattern ending with `*` matches all names starting with the rest of
+    /// the pattern. Variables with names or values that are not valid UTF-8 are skipped and `vars`
+    /// are not iterated at all without patterns.
+    pub fn passthrough<P, I>(patterns: &[P], vars: I) -> Self
+    where
+        P: AsRef<str>,
+        I: IntoIterator<Item = (OsString, OsString)>,
+    {
+        if patterns.is_empty() {
+            return Env::new();
+        }
+        let matches = |name: &str| {
+            patterns.iter().any(|pattern| {
+                let pattern = pattern.as_ref();
+                match pattern.strip_suffix('*') {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => name == pattern,
+                }
+            })
+        };
+        Env(vars
+            .into_iter()
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+            .filter(|(k, _)| matches(k))
+            .collect())
+    }
 }
 
 #[cfg(test)]
@@ -83,4 +112,42 @@ mod tests {
         env.remove("second");
         assert!(env.is_empty());
     }
+
+    #[test]
+    fn passes_matching_vars() {
+        let vars = [
+            ("CARGO_NET_RETRY", "5"),
+            ("CARGO_NET_OFFLINE", "false"),
+            ("CARGO_HOME", "/root/.cargo"),
+            ("GOPROXY", "https://proxy.golang.org"),
+            ("GOPROXY_EXTRA", "1"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (OsString::from(k), OsString::from(v)));
+
+        let env = Env::passthrough(&["CARGO_NET_*", "GOPROXY"], vars);
+
+        let mut names: Vec<_> = env.inner().keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            vec!["CARGO_NET_OFFLINE", "CARGO_NET_RETRY", "GOPROXY"]
+        );
+        assert!(Env::passthrough::<&str, _>(&[], std::env::vars_os()).is_empty());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            let vars = vec![
+                (
+                    OsString::from("GOPROXY"),
+                    OsString::from_vec(vec![0xff, 0xfe]),
+                ),
+                (OsString::from("GOFLAGS"), OsString::from("-mod=vendor")),
+            ];
+            let env = Env::passthrough(&["GO*"], vars);
+            assert_eq!(env.inner().len(), 1);
+            assert_eq!(env.inner()["GOFLAGS"], "-mod=vendor");
+        }
+    }
 }
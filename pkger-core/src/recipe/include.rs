@@ -0,0 +1,132 @@
+use crate::recipe::schema;
+use crate::{err, ErrContext, Error, Result};
+
+use serde_yaml::Value as YamlValue;
+use std::fs;
+use std::path::Path;
+use tracing::{info_span, trace};
+
+/// Name of the top level recipe field containing a list of files to include.
+pub static INCLUDE_KEY: &str = "include";
+
+/// Merges `fragment` into `base`. Values from `base` take precedence over the ones from
+/// `fragment`, mappings are merged recursively and sequences are joined with entries of the
+/// fragment going first.
+pub fn merge(base: &mut YamlValue, fragment: YamlValue) {
+    match (base, fragment) {
+        (YamlValue::Mapping(base), YamlValue::Mapping(fragment)) => {
+            for (key, value) in fragment {
+                if let Some(base_value) = base.get_mut(&key) {
+                    merge(base_value, value);
+                } else {
+                    base.insert(key, value);
+                }
+            }
+        }
+        (YamlValue::Sequence(base), YamlValue::Sequence(mut fragment)) => {
+            fragment.append(base);
+            *base = fragment;
+        }
+        (base, fragment) => {
+            if base.is_null() {
+                *base = fragment;
+            }
+        }
+    }
+}
+
+/// Loads all files listed in the `include` field of the recipe and merges them into the recipe.
+/// Relative paths are resolved from `recipe_dir`. The `include` field is replaced with a list of
+/// resolved paths.
+pub fn resolve(recipe: &mut YamlValue, recipe_dir: &Path) -> Result<()> {
+    let span = info_span!("resolve-includes", recipe_dir = %recipe_dir.display());
+    let _enter = span.enter();
+
+    let key = YamlValue::from(INCLUDE_KEY);
+    let includes = match recipe.as_mapping_mut().and_then(|m| m.remove(&key)) {
+        Some(YamlValue::Sequence(includes)) => includes,
+        Some(YamlValue::String(include)) => vec![YamlValue::String(include)],
+        Some(YamlValue::Null) | None => return Ok(()),
+        Some(_) => return err!("`include` must be a path or a list of paths"),
+    };
+
+    let mut resolved = Vec::new();
+    for include in includes {
+        let include = include
+            .as_str()
+            .map(|s| recipe_dir.join(s))
+            .context("`include` entries must be paths")?;
+        trace!(path = %include.display(), "including");
+
+        let data = fs::read(&include).context(format!(
+            "failed to read included file `{}`",
+            include.display()
+        ))?;
+        let mut fragment: YamlValue = serde_yaml::from_slice(&data).context(format!(
+            "failed to parse included file `{}`",
+            include.display()
+        ))?;
+        schema::migrate(&mut fragment).context(format!(
+            "failed to migrate included file `{}`",
+            include.display()
+        ))?;
+        if let Some(fragment) = fragment.as_mapping_mut() {
+            fragment.remove(&key);
+            fragment.remove(&YamlValue::from(schema::SCHEMA_VERSION_KEY));
+        }
+
+        merge(recipe, fragment);
+        resolved.push(YamlValue::from(include.to_string_lossy().to_string()));
+    }
+
+    if let Some(recipe) = recipe.as_mapping_mut() {
+        recipe.insert(key, YamlValue::Sequence(resolved));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn merges_fragment() {
+        let mut base: YamlValue = serde_yaml::from_str(
+            r#"
+metadata:
+  name: test
+  build_depends:
+    all: [ gcc ]
+"#,
+        )
+        .unwrap();
+        let fragment: YamlValue = serde_yaml::from_str(
+            r#"
+metadata:
+  name: overridden
+  license: MIT
+  build_depends:
+    all: [ make, curl ]
+    pkger-rpm: [ rpm-build ]
+"#,
+        )
+        .unwrap();
+        let expected: YamlValue = serde_yaml::from_str(
+            r#"
+metadata:
+  name: test
+  build_depends:
+    all: [ make, curl, gcc ]
+    pkger-rpm: [ rpm-build ]
+  license: MIT
+"#,
+        )
+        .unwrap();
+
+        merge(&mut base, fragment);
+
+        assert_eq!(base, expected);
+    }
+}
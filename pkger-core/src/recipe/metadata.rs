@@ -1,24 +1,43 @@
+mod alternatives;
 mod arch;
+mod changelog;
 mod deps;
+mod files;
 mod git;
 mod image;
 mod os;
 mod patches;
+mod scripts;
+mod source;
+mod systemd;
 mod target;
+mod users;
 
+pub use alternatives::Alternative;
 pub use arch::BuildArch;
-pub use deps::Dependencies;
+pub use changelog::{ChangelogDate, ChangelogEntry};
+pub use deps::{pkger_recipe, Dependencies};
+pub use files::{FileAttributes, Symlink};
 pub use git::GitSource;
 pub use image::{deserialize_images, ImageTarget};
 pub use os::{Distro, Os, PackageManager};
 pub use patches::{Patch, Patches};
+use scripts::Placement;
+pub use scripts::Scriptlet;
+pub use source::{parse_sources, ArchiveKind, PeerSource, Source};
+pub use systemd::Systemd;
 pub use target::BuildTarget;
+pub use users::{SystemGroup, SystemUser};
 
-use crate::{Error, Result};
+use crate::archive::Compression;
+use crate::{err, Error, Result};
 
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value as YamlValue;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
+use std::path::PathBuf;
+use tracing::warn;
 
 macro_rules! if_let_some_ty {
     ($from:expr, $ty:tt) => {
@@ -57,9 +76,10 @@ pub struct MetadataRep {
     pub url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub arch: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    /// http/https or file system source pointing to a tar.gz or tar.xz package
-    pub source: Option<String>,
+    #[serde(default = "null")]
+    #[serde(skip_serializing_if = "YamlValue::is_null")]
+    /// http/https or file system sources pointing to archives, files or directories
+    pub source: YamlValue,
     #[serde(default = "null")]
     #[serde(skip_serializing_if = "YamlValue::is_null")]
     /// Git repository as source
@@ -70,6 +90,43 @@ pub struct MetadataRep {
     #[serde(skip_serializing_if = "Option::is_none")]
     /// Directories to exclude when creating the package
     pub exclude: Option<Vec<String>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Directories inside of the container that will be saved after a successful build and
+    /// restored before the next build of this recipe on the same image
+    pub cache_dirs: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// License files relative to the build directory that will be installed to the license
+    /// directory of the package
+    pub license_files: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Documentation files relative to the build directory that will be installed to the
+    /// documentation directory of the package
+    pub doc_files: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Man pages relative to the build directory that will be compressed and installed to the
+    /// directory of their section
+    pub man_pages: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Shell completion scripts relative to the build directory
+    pub completions: Option<Completions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Systemd unit files relative to the build directory installed and enabled by the package
+    pub systemd: Option<Systemd>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Processing of ELF binaries like stripping and RPATH policies applied before packaging
+    pub binaries: Option<Binaries>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Whether generated and uploaded text files like control files, specs or install scripts
+    /// should be validated as UTF-8 and have their line endings converted to Unix ones
+    pub normalize_text: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Whether a `BUILD_INFO` file and package fields with the source commit, pkger version and
+    /// build time should be added to the package
+    pub build_info: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub group: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -79,6 +136,9 @@ pub struct MetadataRep {
     #[serde(skip_serializing_if = "Option::is_none")]
     /// Used to force the package to be seen as newer than any previous version with a lower epoch
     pub epoch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Installation prefix of the package available as `$PKGER_PREFIX`. Defaults to `/usr`
+    pub prefix: Option<String>,
 
     #[serde(default = "null")]
     #[serde(skip_serializing_if = "YamlValue::is_null")]
@@ -92,6 +152,46 @@ pub struct MetadataRep {
     #[serde(default = "null")]
     #[serde(skip_serializing_if = "YamlValue::is_null")]
     pub provides: YamlValue,
+    #[serde(default = "null")]
+    #[serde(alias = "obsoletes")]
+    #[serde(skip_serializing_if = "YamlValue::is_null")]
+    /// Packages replaced by this package, rendered as `Replaces` of DEB, `Obsoletes` of RPM and
+    /// `replaces` of other targets
+    pub replaces: YamlValue,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Previous names of this package. Emits fields that make upgrades from packages with the old
+    /// names replace them with this package.
+    pub renamed_from: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Releases of the package with their changes rendered to the changelog of the package
+    pub changelog: Vec<ChangelogEntry>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Additional DEB or RPM packages built from files of the output directory like `-devel` or
+    /// `-doc` packages
+    pub subpackages: Vec<SubpackageRep>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Owners, groups and modes of paths in the output directory applied before packaging
+    pub files: Vec<FileAttributes>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    /// Symbolic links created in the output directory mapping paths of links to their targets
+    pub symlinks: BTreeMap<String, String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// System users created when the package is installed
+    pub users: Vec<SystemUser>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// System groups created when the package is installed
+    pub groups: Vec<SystemGroup>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Paths of the package registered with `update-alternatives`
+    pub alternatives: Vec<Alternative>,
 
     #[serde(default = "null")]
     #[serde(skip_serializing_if = "YamlValue::is_null")]
@@ -114,6 +214,129 @@ pub struct MetadataRep {
     #[serde(skip_serializing_if = "Option::is_none")]
     // Only APK
     pub apk: Option<ApkRep>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    // Only MSI
+    pub msi: Option<MsiRep>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    // Only GZIP
+    pub gzip: Option<GzipRep>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    // Only ebuild
+    pub ebuild: Option<EbuildRep>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+/// Shell completion scripts installed to the completion directories of each shell
+pub struct Completions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zsh: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fish: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+/// Processing of ELF binaries in the output directory
+pub struct Binaries {
+    #[serde(default)]
+    /// Strip debug information and unneeded symbols from binaries
+    pub strip: bool,
+    #[serde(default)]
+    /// Save the debug information of stripped binaries to separate `.debug` files
+    pub keep_debug: bool,
+    #[serde(default)]
+    /// Package the debug information of stripped binaries as a `-dbgsym` DEB or a `-debuginfo`
+    /// RPM built alongside the main package
+    pub debug_package: bool,
+    #[serde(default)]
+    pub rpath: RpathPolicy,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Adjustments made with `patchelf` after applying the RPATH policy
+    pub patchelf: Vec<Patchelf>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Patterns of files relative to the output directory that won't be stripped or checked
+    pub exclude: Vec<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+/// Additional package built from a part of the files of the recipe
+pub struct SubpackageRep {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Defaults to the description of the recipe
+    pub description: Option<String>,
+    /// Patterns of files relative to the output directory that are moved to this package
+    pub files: Vec<String>,
+    #[serde(default = "null")]
+    #[serde(skip_serializing_if = "YamlValue::is_null")]
+    pub depends: YamlValue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subpackage {
+    pub name: String,
+    pub description: Option<String>,
+    /// Patterns of files relative to the output directory that are moved to this package
+    pub files: Vec<String>,
+    pub depends: Option<Dependencies>,
+}
+
+/// Verifies that names of `subpackages` of the package `name` are valid and unique and that each
+/// of them has some files.
+fn validate_subpackages(name: &str, subpackages: Vec<SubpackageRep>) -> Result<Vec<Subpackage>> {
+    let mut validated: Vec<Subpackage> = vec![];
+    for rep in subpackages {
+        let is_valid = !rep.name.is_empty()
+            && rep
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.' | '_'));
+        if !is_valid {
+            return err!("invalid subpackage name `{}`", rep.name);
+        }
+        if rep.name == name || validated.iter().any(|sub| sub.name == rep.name) {
+            return err!("duplicate package name `{}` in subpackages", rep.name);
+        }
+        if rep.files.is_empty() {
+            return err!("subpackage `{}` has no files", rep.name);
+        }
+        validated.push(Subpackage {
+            name: rep.name,
+            description: rep.description,
+            files: rep.files,
+            depends: Dependencies::try_from(rep.depends).ok(),
+        });
+    }
+    Ok(validated)
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// What to do with RPATH and RUNPATH entries of binaries
+pub enum RpathPolicy {
+    #[default]
+    /// Leave the entries as they are
+    Keep,
+    /// Fail the build if any entry is not relative to `$ORIGIN`
+    Check,
+    /// Remove all entries
+    Clear,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+/// Runs `patchelf` with `args` on the file at `path` relative to the output directory
+pub struct Patchelf {
+    pub path: String,
+    pub args: String,
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
@@ -130,6 +353,18 @@ pub struct PkgRep {
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub optdepends: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_install_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_install_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_upgrade_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_upgrade_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_remove_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_remove_script: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -142,17 +377,79 @@ pub struct PkgInfo {
     pub replaces: Option<Dependencies>,
     /// Optional dependencies needed for full functionality of the package
     pub optdepends: Vec<String>,
+    /// Bodies of the functions of the generated `.INSTALL` file by the name of the function like
+    /// `post_install`
+    pub install_functions: Vec<(&'static str, String)>,
+}
+
+impl PkgInfo {
+    /// Returns the name of the install file of the PKGBUILD of package `name` if the package has
+    /// one, either from the output directory or generated from the install functions.
+    pub fn install_file(&self, name: &str) -> Option<String> {
+        if self.install.is_some() || !self.install_functions.is_empty() {
+            Some(format!("{}.install", name))
+        } else {
+            None
+        }
+    }
+
+    /// Renders the install file with the install functions of the recipe or returns `None` if
+    /// the recipe doesn't define any.
+    pub fn render_install(&self) -> Option<String> {
+        if self.install_functions.is_empty() {
+            return None;
+        }
+        let functions: Vec<_> = self
+            .install_functions
+            .iter()
+            .map(|(name, body)| {
+                let body: String = body
+                    .lines()
+                    .map(|line| {
+                        if line.trim().is_empty() {
+                            "\n".to_string()
+                        } else {
+                            format!("    {}\n", line)
+                        }
+                    })
+                    .collect();
+                format!("{}() {{\n{}}}\n", name, body)
+            })
+            .collect();
+        Some(functions.join("\n"))
+    }
 }
 
 impl TryFrom<PkgRep> for PkgInfo {
     type Error = Error;
 
     fn try_from(rep: PkgRep) -> Result<Self> {
+        let install_functions: Vec<_> = [
+            ("pre_install", rep.pre_install_script),
+            ("post_install", rep.post_install_script),
+            ("pre_upgrade", rep.pre_upgrade_script),
+            ("post_upgrade", rep.post_upgrade_script),
+            ("pre_remove", rep.pre_remove_script),
+            ("post_remove", rep.post_remove_script),
+        ]
+        .into_iter()
+        .filter_map(|(name, script)| {
+            script
+                .filter(|script| !script.trim().is_empty())
+                .map(|script| (name, script))
+        })
+        .collect();
+        if rep.install.is_some() && !install_functions.is_empty() {
+            return err!(
+                "`install` of pkg can't be combined with install functions like `post_install_script`"
+            );
+        }
         Ok(Self {
             install: rep.install,
             backup: rep.backup,
             replaces: Dependencies::try_from(rep.replaces).ok(),
             optdepends: rep.optdepends,
+            install_functions,
         })
     }
 }
@@ -185,8 +482,81 @@ pub struct DebRep {
     #[serde(skip_serializing_if = "YamlValue::is_null")]
     pub enhances: YamlValue,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preinst_script: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub postinst_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prerm_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postrm_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub triggers: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conffiles: Option<Vec<String>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Compression of the package, one of `gzip`, `xz` or `zstd`
+    pub compression: Option<Compression>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Compression level, defaults to the default level of `dpkg-deb`
+    pub compression_level: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Settings of the `changelog.Debian.gz` file of the package
+    pub changelog: Option<DebChangelog>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+/// Settings of the Debian changelog installed to `/usr/share/doc/<name>/changelog.Debian.gz`
+pub struct DebChangelog {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Distribution of the entries, defaults to `unstable`
+    pub distribution: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Urgency of the entries, defaults to `medium`
+    pub urgency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Range of revisions of the git repository of the source like `v1.0.0..v1.1.0`. When set the
+    /// changelog has a single entry for the built version listing the commits in the range
+    /// instead of the `changelog` of the recipe
+    pub git: Option<String>,
+}
+
+impl DebChangelog {
+    pub fn distribution(&self) -> &str {
+        self.distribution.as_deref().unwrap_or("unstable")
+    }
+
+    pub fn urgency(&self) -> &str {
+        self.urgency.as_deref().unwrap_or("medium")
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (what, value) in [
+            ("distribution", &self.distribution),
+            ("urgency", &self.urgency),
+        ] {
+            if let Some(value) = value {
+                if value.is_empty() || !value.chars().all(|c| c.is_ascii_graphic() && c != ';') {
+                    return err!("invalid {} of the Debian changelog `{}`", what, value);
+                }
+            }
+        }
+        if let Some(range) = &self.git {
+            let is_valid_char = |c: char| {
+                c.is_ascii_alphanumeric() || matches!(c, '.' | '/' | '_' | '-' | '~' | '^' | '@')
+            };
+            if range.is_empty() || range.starts_with('-') || !range.chars().all(is_valid_char) {
+                return err!(
+                    "invalid git revision range of the Debian changelog `{}`",
+                    range
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -202,13 +572,70 @@ pub struct DebInfo {
     pub replaces: Option<Dependencies>,
     pub enhances: Option<Dependencies>,
 
+    pub preinst_script: Option<String>,
     pub postinst_script: Option<String>,
+    pub prerm_script: Option<String>,
+    pub postrm_script: Option<String>,
+    pub triggers: Option<String>,
+    /// Absolute paths of configuration files preserved by dpkg on upgrade
+    pub conffiles: Option<Vec<String>>,
+    /// Additional fields of the control file. Values of relationship fields like `Breaks` are
+    /// merged with the ones generated from the recipe
+    pub fields: BTreeMap<String, String>,
+    pub compression: Option<Compression>,
+    pub compression_level: Option<u32>,
+    pub changelog: Option<DebChangelog>,
+}
+
+/// Fields of the control file generated from other fields of the recipe that can't be set in
+/// `fields`
+const DEB_GENERATED_FIELDS: &[&str] = &[
+    "Package",
+    "Version",
+    "Architecture",
+    "Description",
+    "Essential",
+    "Maintainer",
+    "Source",
+    "Section",
+    "Priority",
+    "Installed-Size",
+    "Homepage",
+    "Built-Using",
+];
+
+fn validate_deb_field(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with(['#', '-'])
+        || !name.chars().all(|c| c.is_ascii_graphic() && c != ':')
+    {
+        return err!("invalid name of a DEB control field `{}`", name);
+    }
+    if let Some(field) = DEB_GENERATED_FIELDS
+        .iter()
+        .find(|field| field.eq_ignore_ascii_case(name))
+    {
+        return err!(
+            "DEB control field `{}` is generated from the recipe and can't be set in `fields`",
+            field
+        );
+    }
+    Ok(())
 }
 
 impl TryFrom<DebRep> for DebInfo {
     type Error = Error;
 
     fn try_from(rep: DebRep) -> Result<Self> {
+        if rep.compression == Some(Compression::Bzip2) {
+            return err!("DEB packages can't be compressed with bzip2");
+        }
+        if let Some(level) = rep.compression_level {
+            // without a compression dpkg-deb uses xz
+            rep.compression
+                .unwrap_or(Compression::Xz)
+                .check_level(level)?;
+        }
         Ok(Self {
             priority: rep.priority,
             built_using: rep.built_using,
@@ -221,7 +648,38 @@ impl TryFrom<DebRep> for DebInfo {
             replaces: Dependencies::try_from(rep.replaces).ok(),
             enhances: Dependencies::try_from(rep.enhances).ok(),
 
+            preinst_script: rep.preinst_script,
             postinst_script: rep.postinst_script,
+            prerm_script: rep.prerm_script,
+            postrm_script: rep.postrm_script,
+            triggers: rep.triggers,
+            fields: {
+                for name in rep.fields.keys() {
+                    validate_deb_field(name)?;
+                }
+                rep.fields
+            },
+            conffiles: rep.conffiles.map(|files| {
+                files
+                    .into_iter()
+                    .map(|file| {
+                        if file.starts_with('/') {
+                            file
+                        } else {
+                            format!("/{}", file)
+                        }
+                    })
+                    .collect()
+            }),
+            compression: rep.compression,
+            compression_level: rep.compression_level,
+            changelog: match rep.changelog {
+                Some(changelog) => {
+                    changelog.validate()?;
+                    Some(changelog)
+                }
+                None => None,
+            },
         })
     }
 }
@@ -248,13 +706,116 @@ pub struct RpmRep {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub postun_script: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub posttrans_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Deprecated, the path is added to `files.config_noreplace`
     pub config_noreplace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Generate a deltarpm against the previous RPM of the package found in the output directory
+    pub deltarpm: Option<bool>,
+    #[serde(default)]
+    /// Classification of installed files in the `%files` section of the spec
+    pub files: RpmFiles,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Path to a spec file relative to the recipe directory used instead of the generated spec
+    pub spec_template: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Path to a file relative to the recipe directory with the content of the `%changelog`
+    /// section used instead of the `changelog` of the recipe
+    pub changelog_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+/// Paths of installed files marked with an attribute in the `%files` section of the spec. A path
+/// of a directory applies to all files inside of it.
+pub struct RpmFiles {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub config: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub config_noreplace: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub doc: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub license: Vec<String>,
+}
+
+impl RpmFiles {
+    fn normalized(self) -> Self {
+        let normalize = |paths: Vec<String>| -> Vec<String> {
+            paths
+                .into_iter()
+                .map(|path| {
+                    let path = path.trim_end_matches('/');
+                    if path.starts_with('/') {
+                        path.to_string()
+                    } else {
+                        format!("/{}", path)
+                    }
+                })
+                .collect()
+        };
+        Self {
+            config: normalize(self.config),
+            config_noreplace: normalize(self.config_noreplace),
+            doc: normalize(self.doc),
+            license: normalize(self.license),
+        }
+    }
+
+    /// Returns the attribute of the `file` in the `%files` section like `config(noreplace)` or
+    /// `None` if the file has no attribute. The most specific path matching the file wins, an exact
+    /// path over any directory and an inner directory over outer ones. Between equally specific
+    /// paths the first of `config(noreplace)`, `config`, `license` and `doc` wins.
+    pub fn attribute(&self, file: &str) -> Option<&'static str> {
+        let specificity = |paths: &[String]| {
+            paths
+                .iter()
+                .filter_map(|path| {
+                    if file == path {
+                        Some(usize::MAX)
+                    } else if matches!(file.strip_prefix(path.as_str()), Some(rest) if rest.starts_with('/'))
+                    {
+                        Some(path.len())
+                    } else {
+                        None
+                    }
+                })
+                .max()
+        };
+        let mut best: Option<(&'static str, usize)> = None;
+        for (attribute, paths) in [
+            ("config(noreplace)", &self.config_noreplace),
+            ("config", &self.config),
+            ("license", &self.license),
+            ("doc", &self.doc),
+        ] {
+            if let Some(specificity) = specificity(paths) {
+                if !matches!(best, Some((_, best)) if best >= specificity) {
+                    best = Some((attribute, specificity));
+                }
+            }
+        }
+        best.map(|(attribute, _)| attribute)
+    }
 }
 
 impl TryFrom<RpmRep> for RpmInfo {
     type Error = Error;
 
     fn try_from(rep: RpmRep) -> Result<Self> {
+        let mut files = rep.files;
+        if let Some(path) = rep.config_noreplace {
+            if path.contains('%') {
+                warn!(path = %path, "`rpm.config_noreplace` is deprecated and macros are not expanded, ignoring it, list the path in `rpm.files.config_noreplace` instead");
+            } else {
+                warn!(path = %path, "`rpm.config_noreplace` is deprecated, list the path in `rpm.files.config_noreplace` instead");
+                files.config_noreplace.push(path);
+            }
+        }
         Ok(Self {
             obsoletes: Dependencies::try_from(rep.obsoletes).ok(),
             vendor: rep.vendor,
@@ -265,7 +826,27 @@ impl TryFrom<RpmRep> for RpmInfo {
             post_script: rep.post_script,
             preun_script: rep.preun_script,
             postun_script: rep.postun_script,
-            config_noreplace: rep.config_noreplace,
+            posttrans_script: rep.posttrans_script,
+            deltarpm: rep.deltarpm.unwrap_or_default(),
+            files: files.normalized(),
+            spec_template: match rep.spec_template {
+                Some(path) if path.is_absolute() => {
+                    return err!(
+                        "path of the spec template `{}` must be relative to the recipe directory",
+                        path.display()
+                    )
+                }
+                path => path,
+            },
+            changelog_file: match rep.changelog_file {
+                Some(path) if path.is_absolute() => {
+                    return err!(
+                        "path of the changelog file `{}` must be relative to the recipe directory",
+                        path.display()
+                    )
+                }
+                path => path,
+            },
         })
     }
 }
@@ -281,7 +862,11 @@ pub struct RpmInfo {
     pub post_script: Option<String>,
     pub preun_script: Option<String>,
     pub postun_script: Option<String>,
-    pub config_noreplace: Option<String>,
+    pub posttrans_script: Option<String>,
+    pub deltarpm: bool,
+    pub files: RpmFiles,
+    pub spec_template: Option<PathBuf>,
+    pub changelog_file: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -298,14 +883,37 @@ pub struct Metadata {
     pub maintainer: Option<String>,
     /// The URL of the web site for this package
     pub url: Option<String>,
-    /// http/https or file system source pointing to a tar.gz or tar.xz package
-    pub source: Option<String>,
+    /// http/https or file system sources pointing to archives, files or directories
+    pub source: Vec<Source>,
     /// Git repository as source
     pub git: Option<GitSource>,
     /// Whether default dependencies should be installed before the build
     pub skip_default_deps: Option<bool>,
     /// Directories to exclude when creating the package
     pub exclude: Option<Vec<String>>,
+    /// Directories inside of the container that will be saved after a successful build and
+    /// restored before the next build of this recipe on the same image
+    pub cache_dirs: Vec<String>,
+    /// License files relative to the build directory that will be installed to the license
+    /// directory of the package
+    pub license_files: Vec<String>,
+    /// Documentation files relative to the build directory that will be installed to the
+    /// documentation directory of the package
+    pub doc_files: Vec<String>,
+    /// Man pages relative to the build directory that will be compressed and installed to the
+    /// directory of their section
+    pub man_pages: Vec<String>,
+    /// Shell completion scripts relative to the build directory
+    pub completions: Option<Completions>,
+    /// Systemd unit files relative to the build directory installed and enabled by the package
+    pub systemd: Option<Systemd>,
+    /// Processing of ELF binaries like stripping and RPATH policies applied before packaging
+    pub binaries: Option<Binaries>,
+    /// Whether generated and uploaded text files should be validated as UTF-8 and have their line
+    /// endings converted to Unix ones. Defaults to `true`
+    pub normalize_text: bool,
+    /// Whether the build info should be recorded in the package. Defaults to `false`
+    pub build_info: bool,
     /// Works as section in DEB and group in RPM
     pub group: Option<String>,
     /// The release number. This is usually a positive integer number that allows to differentiate
@@ -313,12 +921,32 @@ pub struct Metadata {
     pub release: Option<String>,
     /// Used to force the package to be seen as newer than any previous version with a lower epoch
     pub epoch: Option<String>,
+    /// Installation prefix of the package like `/usr` or `/opt/<vendor>`
+    pub prefix: String,
 
     pub build_depends: Option<Dependencies>,
 
     pub depends: Option<Dependencies>,
     pub conflicts: Option<Dependencies>,
     pub provides: Option<Dependencies>,
+    pub replaces: Option<Dependencies>,
+    /// Previous names of this package
+    pub renamed_from: Vec<String>,
+    /// Releases of the package sorted from the newest to the oldest
+    pub changelog: Vec<ChangelogEntry>,
+    /// Additional packages built from files of the output directory
+    pub subpackages: Vec<Subpackage>,
+    /// Owners, groups and modes of paths in the output directory with absolute paths
+    pub files: Vec<FileAttributes>,
+    /// Symbolic links created in the output directory before packaging
+    pub symlinks: Vec<Symlink>,
+    /// System users created when the package is installed
+    pub users: Vec<SystemUser>,
+    /// System groups created when the package is installed, primary groups of users are
+    /// created even if they are not listed
+    pub groups: Vec<SystemGroup>,
+    /// Paths of the package registered with `update-alternatives`
+    pub alternatives: Vec<Alternative>,
 
     pub patches: Option<Patches>,
 
@@ -329,6 +957,12 @@ pub struct Metadata {
     pub pkg: Option<PkgInfo>,
 
     pub apk: Option<ApkInfo>,
+
+    pub msi: Option<MsiInfo>,
+
+    pub gzip: Option<GzipInfo>,
+
+    pub ebuild: Option<EbuildInfo>,
 }
 
 impl Metadata {
@@ -340,12 +974,244 @@ impl Metadata {
             "0"
         }
     }
+
+    /// Returns the directory where license files of this package are installed
+    pub fn license_dir(&self) -> String {
+        format!("{}/share/licenses/{}", self.prefix, self.name)
+    }
+
+    /// Returns the directory where documentation files of this package are installed
+    pub fn doc_dir(&self) -> String {
+        format!("{}/share/doc/{}", self.prefix, self.name)
+    }
+
+    /// Returns true if the recipe changes the owner or group of any files.
+    pub fn sets_file_ownership(&self) -> bool {
+        self.files
+            .iter()
+            .any(|entry| entry.owner.is_some() || entry.group.is_some())
+    }
+
+    /// Returns true if the recipe declares system users or groups.
+    pub fn has_accounts(&self) -> bool {
+        !self.users.is_empty() || !self.groups.is_empty()
+    }
+
+    /// Returns the shell commands creating the system users and groups of the recipe that don't
+    /// exist yet.
+    pub fn accounts_script(&self) -> String {
+        users::accounts_script(&self.users, &self.groups)
+    }
+
+    /// Returns the `sysusers.d` configuration creating the system users and groups of the recipe.
+    pub fn sysusers_conf(&self) -> String {
+        users::sysusers_conf(&self.users, &self.groups)
+    }
+
+    /// Returns true if commands are generated for any install scripts of DEB and RPM packages.
+    fn generates_scripts(&self) -> bool {
+        self.systemd.is_some()
+            || self.has_accounts()
+            || !self.alternatives.is_empty()
+            || self.sets_file_ownership()
+    }
+
+    /// Returns the install `script` of the recipe used as `scriptlet` with the generated commands
+    /// creating system accounts, setting owners of files, registering alternatives and managing
+    /// systemd units of the recipe added to it. Owners are set and alternatives are registered
+    /// before units are started and alternatives are unregistered after units are stopped.
+    pub fn install_script(&self, scriptlet: Scriptlet, script: Option<&String>) -> Option<String> {
+        let systemd = self.systemd.as_ref();
+        let alternatives = &self.alternatives;
+        let (snippets, placement) = match scriptlet {
+            Scriptlet::DebPreinst | Scriptlet::RpmPre => (
+                vec![if self.has_accounts() {
+                    Some(self.accounts_script())
+                } else {
+                    None
+                }],
+                Placement::Before,
+            ),
+            Scriptlet::DebPostinst => (
+                vec![
+                    files::deb_postinst(&self.files),
+                    alternatives::deb_postinst(alternatives),
+                    systemd.map(Systemd::deb_postinst),
+                ],
+                Placement::After,
+            ),
+            Scriptlet::DebPrerm => (
+                vec![
+                    systemd.map(Systemd::deb_prerm),
+                    alternatives::deb_prerm(alternatives),
+                ],
+                Placement::Before,
+            ),
+            Scriptlet::DebPostrm => (vec![systemd.map(Systemd::deb_postrm)], Placement::After),
+            Scriptlet::RpmPost => (
+                vec![
+                    alternatives::rpm_post(alternatives),
+                    systemd.map(Systemd::rpm_post),
+                ],
+                Placement::After,
+            ),
+            Scriptlet::RpmPreun => (
+                vec![
+                    systemd.map(Systemd::rpm_preun),
+                    alternatives::rpm_preun(alternatives),
+                ],
+                Placement::Before,
+            ),
+            Scriptlet::RpmPostun => (vec![systemd.map(Systemd::rpm_postun)], Placement::After),
+        };
+        let snippet: String = snippets.into_iter().flatten().collect();
+        let snippet = if snippet.is_empty() {
+            None
+        } else {
+            Some(snippet)
+        };
+        scripts::with_snippet(script.map(String::as_str), snippet, placement)
+    }
+
+    /// Returns the maintainer scripts of DEB packages like `postinst` with their names. Commands
+    /// creating system accounts, setting owners of files, registering alternatives and managing
+    /// the systemd units of the recipe are added to the scripts of the `deb` section.
+    pub fn deb_scripts(&self) -> Vec<(&'static str, String)> {
+        let deb = self.deb.as_ref();
+        [
+            (
+                "preinst",
+                Scriptlet::DebPreinst,
+                deb.and_then(|deb| deb.preinst_script.as_ref()),
+            ),
+            (
+                "postinst",
+                Scriptlet::DebPostinst,
+                deb.and_then(|deb| deb.postinst_script.as_ref()),
+            ),
+            (
+                "prerm",
+                Scriptlet::DebPrerm,
+                deb.and_then(|deb| deb.prerm_script.as_ref()),
+            ),
+            (
+                "postrm",
+                Scriptlet::DebPostrm,
+                deb.and_then(|deb| deb.postrm_script.as_ref()),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(name, scriptlet, script)| {
+            let generated = self.generates_scripts();
+            self.install_script(scriptlet, script).map(|script| {
+                if generated && !script.starts_with("#!") {
+                    (name, format!("#!/bin/sh\n{}", script))
+                } else {
+                    (name, script)
+                }
+            })
+        })
+        .collect()
+    }
+
+    /// Returns the directory where systemd unit files are installed for `target`.
+    pub fn systemd_unit_dir(&self, target: &BuildTarget) -> &'static str {
+        systemd::unit_dir(target)
+    }
+
+    /// Returns the `%attr` of RPM of the absolute `file` of the package if the recipe sets any of
+    /// its attributes.
+    pub fn rpm_file_attr(&self, file: &str) -> Option<String> {
+        files::rpm_attr(&self.files, file)
+    }
+
+    /// Returns the version of this package in the `[epoch:]version-release` format used by DEB
+    /// and RPM version comparisons
+    pub fn full_version(&self) -> String {
+        match &self.epoch {
+            Some(epoch) => format!("{}:{}-{}", epoch, self.version, self.release()),
+            None => format!("{}-{}", self.version, self.release()),
+        }
+    }
+
+    /// Returns the version of this package for APK. APK doesn't accept `~`, so a pre-release
+    /// like `1.2.3~rc1` becomes `1.2.3_rc1`. Pre-releases other than `alpha`, `beta`, `pre` and
+    /// `rc` become `_pre` with the first number of the pre-release, like `1.2.3_pre20240501`
+    /// for `1.2.3~git20240501`.
+    pub fn apk_version(&self) -> String {
+        let (version, pre_release) = match self.version.split_once('~') {
+            Some(split) => split,
+            None => return self.version.clone(),
+        };
+        let kind = pre_release.trim_end_matches(|c: char| c.is_ascii_digit());
+        if ["alpha", "beta", "pre", "rc"].contains(&kind) {
+            return format!("{}_{}", version, pre_release);
+        }
+        let number: String = pre_release
+            .chars()
+            .skip_while(|c| !c.is_ascii_digit())
+            .take_while(char::is_ascii_digit)
+            .collect();
+        format!("{}_pre{}", version, number)
+    }
+
+    /// Returns the version of this package for PKG. pacman treats `~` like any other character
+    /// but sorts versions with trailing letters before the release, so `1.2.3~rc1` becomes
+    /// `1.2.3rc1` and pre-releases starting with a digit get a `pre` prefix. Characters that are
+    /// not allowed in `pkgver` are replaced with `.`.
+    pub fn pkg_version(&self) -> String {
+        let (version, pre_release) = match self.version.split_once('~') {
+            Some(split) => split,
+            None => return self.version.clone(),
+        };
+        let pre_release: String = pre_release
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                    c
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        if pre_release.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            format!("{}{}", version, pre_release)
+        } else {
+            format!("{}pre{}", version, pre_release)
+        }
+    }
+}
+
+/// Default installation prefix of packages.
+pub const DEFAULT_PREFIX: &str = "/usr";
+
+/// Verifies that `prefix` is an absolute path other than `/` and returns it without the trailing
+/// `/`.
+pub fn validate_prefix(prefix: &str) -> Result<String> {
+    let trimmed = prefix.trim_end_matches('/');
+    if !prefix.starts_with('/') || trimmed.is_empty() {
+        return err!(
+            "prefix `{}` has to be an absolute path other than `/`",
+            prefix
+        );
+    }
+    Ok(trimmed.to_string())
 }
 
 impl TryFrom<MetadataRep> for Metadata {
     type Error = Error;
 
     fn try_from(rep: MetadataRep) -> Result<Self> {
+        let prefix = validate_prefix(rep.prefix.as_deref().unwrap_or(DEFAULT_PREFIX))?;
+        let changelog = changelog::validate_changelog(rep.changelog, rep.maintainer.as_deref())?;
+        let subpackages = validate_subpackages(&rep.name, rep.subpackages)?;
+        let files = files::validate_files(rep.files)?;
+        let symlinks = files::validate_symlinks(rep.symlinks)?;
+        if let Some(systemd) = &rep.systemd {
+            systemd.validate()?;
+        }
+        users::validate_accounts(&rep.users, &rep.groups)?;
+        alternatives::validate_alternatives(&rep.alternatives)?;
         Ok(Self {
             name: rep.name,
             version: rep.version,
@@ -360,18 +1226,37 @@ impl TryFrom<MetadataRep> for Metadata {
                 .unwrap_or_else(|| BuildArch::All),
             maintainer: rep.maintainer,
             url: rep.url,
-            source: rep.source,
+            source: parse_sources(&rep.source)?,
             git: GitSource::try_from(rep.git).ok(),
             skip_default_deps: rep.skip_default_deps,
             exclude: rep.exclude,
+            cache_dirs: rep.cache_dirs,
+            license_files: rep.license_files,
+            doc_files: rep.doc_files,
+            man_pages: rep.man_pages,
+            completions: rep.completions,
+            systemd: rep.systemd,
+            binaries: rep.binaries,
+            normalize_text: rep.normalize_text.unwrap_or(true),
+            build_info: rep.build_info.unwrap_or_default(),
             group: rep.group,
             release: rep.release,
             epoch: rep.epoch,
+            prefix,
 
             build_depends: Dependencies::try_from(rep.build_depends).ok(),
             depends: Dependencies::try_from(rep.depends).ok(),
             conflicts: Dependencies::try_from(rep.conflicts).ok(),
             provides: Dependencies::try_from(rep.provides).ok(),
+            replaces: Dependencies::try_from(rep.replaces).ok(),
+            renamed_from: rep.renamed_from,
+            changelog,
+            subpackages,
+            files,
+            symlinks,
+            users: rep.users,
+            groups: rep.groups,
+            alternatives: rep.alternatives,
 
             patches: Patches::try_from(rep.patches).ok(),
 
@@ -379,6 +1264,9 @@ impl TryFrom<MetadataRep> for Metadata {
             rpm: if_let_some_ty!(rep.rpm, RpmInfo),
             pkg: if_let_some_ty!(rep.pkg, PkgInfo),
             apk: if_let_some_ty!(rep.apk, ApkInfo),
+            msi: if_let_some_ty!(rep.msi, MsiInfo),
+            gzip: if_let_some_ty!(rep.gzip, GzipInfo),
+            ebuild: if_let_some_ty!(rep.ebuild, EbuildInfo),
         })
     }
 }
@@ -408,6 +1296,124 @@ pub struct ApkInfo {
     pub private_key: Option<std::path::PathBuf>,
 }
 
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct MsiRep {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Manufacturer of the product, defaults to the maintainer
+    pub manufacturer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// GUID shared by all versions of the product, derived from the name of the package by default
+    pub upgrade_code: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MsiInfo {
+    pub manufacturer: Option<String>,
+    pub upgrade_code: Option<String>,
+}
+
+impl TryFrom<MsiRep> for MsiInfo {
+    type Error = Error;
+
+    fn try_from(rep: MsiRep) -> Result<Self> {
+        if let Some(code) = &rep.upgrade_code {
+            let is_guid = code.len() == 36
+                && code.char_indices().all(|(i, c)| match i {
+                    8 | 13 | 18 | 23 => c == '-',
+                    _ => c.is_ascii_hexdigit(),
+                });
+            if !is_guid {
+                return err!(
+                    "invalid MSI upgrade code `{}`, expected a GUID like `01234567-89AB-CDEF-0123-456789ABCDEF`",
+                    code
+                );
+            }
+        }
+        Ok(Self {
+            manufacturer: rep.manufacturer,
+            upgrade_code: rep.upgrade_code.map(|code| code.to_uppercase()),
+        })
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct GzipRep {
+    #[serde(default)]
+    /// Compression of the tarball, one of `gzip`, `xz`, `zstd` or `bzip2`
+    pub compression: Compression,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Compression level, defaults to the default level of the compression
+    pub level: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GzipInfo {
+    pub compression: Compression,
+    pub level: Option<u32>,
+}
+
+impl TryFrom<GzipRep> for GzipInfo {
+    type Error = Error;
+
+    fn try_from(rep: GzipRep) -> Result<Self> {
+        if let Some(level) = rep.level {
+            rep.compression.check_level(level)?;
+        }
+        Ok(Self {
+            compression: rep.compression,
+            level: rep.level,
+        })
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct EbuildRep {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Category of the package in the overlay, defaults to `app-misc`
+    pub category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Slot of the package, defaults to `0`
+    pub slot: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Keywords of the ebuild, defaults to the testing keyword of the architecture of the recipe
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Additional eclasses inherited by the ebuild
+    pub inherit: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EbuildInfo {
+    pub category: String,
+    pub slot: Option<String>,
+    pub keywords: Vec<String>,
+    pub inherit: Vec<String>,
+}
+
+impl TryFrom<EbuildRep> for EbuildInfo {
+    type Error = Error;
+
+    fn try_from(rep: EbuildRep) -> Result<Self> {
+        let category = rep.category.unwrap_or_else(|| "app-misc".to_string());
+        if category.is_empty()
+            || category.starts_with('-')
+            || !category
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '_' | '.' | '-'))
+        {
+            return err!("invalid ebuild category `{}`", category);
+        }
+        Ok(Self {
+            category,
+            slot: rep.slot,
+            keywords: rep.keywords,
+            inherit: rep.inherit,
+        })
+    }
+}
+
 impl TryFrom<ApkRep> for ApkInfo {
     type Error = Error;
 
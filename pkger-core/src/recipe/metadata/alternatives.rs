@@ -0,0 +1,182 @@
+use crate::{err, Error, Result};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+/// Path of the package registered with `update-alternatives` as one of the alternatives of a
+/// link group
+pub struct Alternative {
+    /// Name of the link group like `editor`
+    pub name: String,
+    /// Generic link shared by all alternatives of the group like `/usr/bin/editor`
+    pub link: String,
+    /// Path of the alternative installed by the package
+    pub path: String,
+    /// Alternatives with higher priority are chosen in automatic mode
+    pub priority: u32,
+}
+
+/// Verifies that names of link groups are valid and unique and that links and paths are absolute
+/// paths that can be used in shell commands.
+pub fn validate_alternatives(alternatives: &[Alternative]) -> Result<()> {
+    let is_valid_path = |path: &str| {
+        path.starts_with('/')
+            && path.len() > 1
+            && !path
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control() || "'\"\\$`;&|<>".contains(c))
+    };
+    for (i, alternative) in alternatives.iter().enumerate() {
+        let name = &alternative.name;
+        if name.is_empty()
+            || name.starts_with('-')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'))
+        {
+            return err!("invalid name of alternative `{}`", name);
+        }
+        if alternatives[..i].iter().any(|other| &other.name == name) {
+            return err!("alternative `{}` is declared more than once", name);
+        }
+        for path in [&alternative.link, &alternative.path] {
+            if !is_valid_path(path) {
+                return err!(
+                    "invalid path `{}` of alternative `{}`, expected an absolute path",
+                    path,
+                    name
+                );
+            }
+        }
+        if alternative.link == alternative.path {
+            return err!(
+                "path of alternative `{}` can't be the same as its link",
+                name
+            );
+        }
+    }
+    Ok(())
+}
+
+fn install_commands(alternatives: &[Alternative], indent: &str) -> String {
+    alternatives
+        .iter()
+        .map(|alternative| {
+            format!(
+                "{}update-alternatives --install {} {} {} {}\n",
+                indent, alternative.link, alternative.name, alternative.path, alternative.priority
+            )
+        })
+        .collect()
+}
+
+fn remove_commands(alternatives: &[Alternative], indent: &str) -> String {
+    alternatives
+        .iter()
+        .map(|alternative| {
+            format!(
+                "{}update-alternatives --remove {} {}\n",
+                indent, alternative.name, alternative.path
+            )
+        })
+        .collect()
+}
+
+/// Commands of `postinst` that register the alternatives when the package is configured.
+pub fn deb_postinst(alternatives: &[Alternative]) -> Option<String> {
+    if alternatives.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "if [ \"$1\" = \"configure\" ]; then\n{}fi\n",
+        install_commands(alternatives, "    ")
+    ))
+}
+
+/// Commands of `prerm` that unregister the alternatives when the package is removed or
+/// deconfigured.
+pub fn deb_prerm(alternatives: &[Alternative]) -> Option<String> {
+    if alternatives.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "if [ \"$1\" = \"remove\" ] || [ \"$1\" = \"deconfigure\" ]; then\n{}fi\n",
+        remove_commands(alternatives, "    ")
+    ))
+}
+
+/// Commands of `%post` that register the alternatives on installation and upgrade.
+pub fn rpm_post(alternatives: &[Alternative]) -> Option<String> {
+    if alternatives.is_empty() {
+        return None;
+    }
+    Some(install_commands(alternatives, ""))
+}
+
+/// Commands of `%preun` that unregister the alternatives when the package is removed.
+pub fn rpm_preun(alternatives: &[Alternative]) -> Option<String> {
+    if alternatives.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "if [ $1 -eq 0 ]; then\n{}fi\n",
+        remove_commands(alternatives, "    ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn renders_alternatives_scriptlets() {
+        let alternatives: Vec<Alternative> = serde_yaml::from_str(
+            r#"
+- name: editor
+  link: /usr/bin/editor
+  path: /usr/bin/pkger-edit
+  priority: 50
+- name: pkger.conf
+  link: /etc/pkger.conf
+  path: /etc/pkger/default.conf
+  priority: 10
+"#,
+        )
+        .unwrap();
+        validate_alternatives(&alternatives).unwrap();
+
+        assert_eq!(
+            deb_postinst(&alternatives).unwrap(),
+            "if [ \"$1\" = \"configure\" ]; then
+    update-alternatives --install /usr/bin/editor editor /usr/bin/pkger-edit 50
+    update-alternatives --install /etc/pkger.conf pkger.conf /etc/pkger/default.conf 10
+fi
+"
+        );
+        assert_eq!(
+            rpm_preun(&alternatives[..1]).unwrap(),
+            "if [ $1 -eq 0 ]; then
+    update-alternatives --remove editor /usr/bin/pkger-edit
+fi
+"
+        );
+        assert_eq!(deb_prerm(&[]), None);
+
+        let invalid = |name: &str, link: &str, path: &str| {
+            validate_alternatives(&[Alternative {
+                name: name.to_string(),
+                link: link.to_string(),
+                path: path.to_string(),
+                priority: 1,
+            }])
+            .is_err()
+        };
+        assert!(invalid("", "/usr/bin/editor", "/usr/bin/pkger-edit"));
+        assert!(invalid("editor", "usr/bin/editor", "/usr/bin/pkger-edit"));
+        assert!(invalid("editor", "/usr/bin/editor", "/usr/bin/pkger edit"));
+        assert!(invalid("editor", "/usr/bin/editor", "/usr/bin/editor"));
+        let duplicate = vec![alternatives[0].clone(), alternatives[0].clone()];
+        assert!(validate_alternatives(&duplicate).is_err());
+    }
+}
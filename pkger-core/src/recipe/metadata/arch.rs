@@ -18,11 +18,11 @@ impl From<&str> for BuildArch {
     fn from(s: &str) -> Self {
         match &s.to_lowercase()[..] {
             "all" | "any" | "noarch" => Self::All,
-            "x86_64" | "amd64" => Self::x86_64,
-            "i386" | "x86" => Self::x86,
+            "x86_64" | "amd64" | "x64" => Self::x86_64,
+            "i386" | "i686" | "x86" => Self::x86,
             "armel" | "arm" => Self::Arm,
-            "armv6hl" | "armv6h" => Self::Armv6h,
-            "armv7hl" | "armv7h" | "armhf" => Self::Armv7h,
+            "armv6hl" | "armv6h" | "armv6l" => Self::Armv6h,
+            "armv7hl" | "armv7h" | "armhf" | "armv7l" => Self::Armv7h,
             "aarch64" | "arm64" => Self::Arm64,
             arch => Self::Other(arch.to_string()),
         }
@@ -52,6 +52,23 @@ impl fmt::Display for BuildArch {
 }
 
 impl BuildArch {
+    /// Returns the architecture of the host that pkger runs on.
+    pub fn host() -> Self {
+        Self::from(std::env::consts::ARCH)
+    }
+
+    /// Returns true if packages of this architecture can't be built natively on the `host` and
+    /// need an emulator like QEMU registered with `binfmt_misc`. 32-bit packages can be built on
+    /// 64-bit hosts of the same family, unknown architectures are assumed to be native.
+    pub fn requires_emulation(&self, host: &BuildArch) -> bool {
+        use BuildArch::*;
+        match (self, host) {
+            (All, _) | (Other(_), _) | (_, Other(_)) => false,
+            (x86, x86_64) | (Arm | Armv6h | Armv7h, Arm64) => false,
+            (arch, host) => arch != host,
+        }
+    }
+
     pub fn deb_name(&self) -> &str {
         use BuildArch::*;
         match &self {
@@ -107,4 +124,47 @@ impl BuildArch {
             Other(arch) => arch,
         }
     }
+
+    /// Returns the name of the architecture used in keywords of Gentoo ebuilds.
+    pub fn gentoo_name(&self) -> &str {
+        use BuildArch::*;
+        match &self {
+            All => "*",
+            x86_64 => "amd64",
+            x86 => "x86",
+            Arm | Armv6h | Armv7h => "arm",
+            Arm64 => "arm64",
+            Other(arch) => arch,
+        }
+    }
+
+    pub fn xbps_name(&self) -> &str {
+        use BuildArch::*;
+        match &self {
+            All => "noarch",
+            x86_64 => "x86_64",
+            x86 => "i686",
+            Arm | Armv6h => "armv6l",
+            Armv7h => "armv7l",
+            Arm64 => "aarch64",
+            Other(arch) => arch,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_emulation() {
+        assert!(!BuildArch::All.requires_emulation(&BuildArch::Arm64));
+        assert!(!BuildArch::Arm64.requires_emulation(&BuildArch::Arm64));
+        assert!(!BuildArch::x86.requires_emulation(&BuildArch::x86_64));
+        assert!(!BuildArch::Armv7h.requires_emulation(&BuildArch::Arm64));
+        assert!(!BuildArch::from("riscv64").requires_emulation(&BuildArch::x86_64));
+        assert!(BuildArch::x86_64.requires_emulation(&BuildArch::Arm64));
+        assert!(BuildArch::Arm64.requires_emulation(&BuildArch::x86_64));
+        assert!(BuildArch::x86_64.requires_emulation(&BuildArch::x86));
+    }
 }
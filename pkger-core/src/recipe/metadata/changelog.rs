@@ -0,0 +1,190 @@
+use crate::{err, Error, Result};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+/// Date of a changelog entry in the `YYYY-MM-DD` format.
+pub struct ChangelogDate(NaiveDate);
+
+impl ChangelogDate {
+    /// Formats the date the way RPM expects it in the `%changelog` section, like
+    /// `Wed May 01 2024`.
+    pub fn rpm(&self) -> String {
+        self.0.format("%a %b %d %Y").to_string()
+    }
+
+    /// Formats the date the way Debian changelogs expect it, like
+    /// `Wed, 01 May 2024 00:00:00 +0000`.
+    pub fn rfc2822(&self) -> String {
+        self.0.format("%a, %d %b %Y 00:00:00 +0000").to_string()
+    }
+}
+
+impl TryFrom<String> for ChangelogDate {
+    type Error = Error;
+
+    fn try_from(date: String) -> Result<Self> {
+        // the length check rejects dates without leading zeros that chrono would accept
+        match NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+            Ok(parsed) if date.len() == 10 => Ok(ChangelogDate(parsed)),
+            _ => err!("invalid changelog date `{}`, expected `YYYY-MM-DD`", date),
+        }
+    }
+}
+
+impl From<ChangelogDate> for String {
+    fn from(date: ChangelogDate) -> Self {
+        date.to_string()
+    }
+}
+
+impl fmt::Display for ChangelogDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.format("%Y-%m-%d"))
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+/// Single release in the changelog of a package
+pub struct ChangelogEntry {
+    pub date: ChangelogDate,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Version of the release in the `[epoch:]version-release` format, defaults to the version
+    /// of the recipe
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Author of the changes in the `Name <email>` format, defaults to the maintainer
+    pub author: Option<String>,
+    pub changes: Vec<String>,
+}
+
+impl ChangelogEntry {
+    /// Renders this entry for the `%changelog` section of a spec file using the `version` and
+    /// `author` when the entry doesn't set them. Percent signs are escaped so that the changes
+    /// are not expanded as macros.
+    pub fn render_rpm(&self, version: &str, author: &str) -> String {
+        let mut entry = format!(
+            "* {} {} - {}",
+            self.date.rpm(),
+            self.author.as_deref().unwrap_or(author),
+            self.version.as_deref().unwrap_or(version)
+        );
+        for change in &self.changes {
+            entry.push_str("\n- ");
+            entry.push_str(&change.replace('%', "%%").replace('\n', "\n  "));
+        }
+        entry.push('\n');
+        entry
+    }
+
+    /// Renders this entry for a Debian changelog of the package `name` using the `version` and
+    /// `author` when the entry doesn't set them.
+    pub fn render_deb(
+        &self,
+        name: &str,
+        version: &str,
+        author: &str,
+        distribution: &str,
+        urgency: &str,
+    ) -> String {
+        let mut entry = format!(
+            "{} ({}) {}; urgency={}\n\n",
+            name,
+            self.version.as_deref().unwrap_or(version),
+            distribution,
+            urgency
+        );
+        for change in &self.changes {
+            entry.push_str("  * ");
+            entry.push_str(&change.replace('\n', "\n    "));
+            entry.push('\n');
+        }
+        entry.push_str(&format!(
+            "\n -- {}  {}\n",
+            self.author.as_deref().unwrap_or(author),
+            self.date.rfc2822()
+        ));
+        entry
+    }
+}
+
+/// Verifies that all `entries` have an author or that there is a `maintainer` to fall back to
+/// and sorts them from the newest to the oldest.
+pub fn validate_changelog(
+    mut entries: Vec<ChangelogEntry>,
+    maintainer: Option<&str>,
+) -> Result<Vec<ChangelogEntry>> {
+    if maintainer.is_none() {
+        if let Some(entry) = entries.iter().find(|entry| entry.author.is_none()) {
+            return err!(
+                "changelog entry from {} has no author and the recipe has no maintainer",
+                entry.date
+            );
+        }
+    }
+    entries.sort_by_key(|entry| Reverse(entry.date));
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn renders_changelog_entries() {
+        let date = |date: &str| ChangelogDate::try_from(date.to_string()).unwrap();
+        assert_eq!(date("2024-05-01").rpm(), "Wed May 01 2024");
+        assert_eq!(date("2000-02-29").rpm(), "Tue Feb 29 2000");
+        assert_eq!(date("1969-12-31").rpm(), "Wed Dec 31 1969");
+        assert_eq!(
+            date("2024-05-01").rfc2822(),
+            "Wed, 01 May 2024 00:00:00 +0000"
+        );
+        for invalid in [
+            "2023-02-29",
+            "2024-13-01",
+            "2024-5-1",
+            "01-05-2024",
+            "today",
+        ] {
+            assert!(ChangelogDate::try_from(invalid.to_string()).is_err());
+        }
+
+        let entries: Vec<ChangelogEntry> = serde_yaml::from_str(
+            r#"
+- date: 2024-01-15
+  changes: [Initial release]
+- date: 2024-05-01
+  version: 1:1.1.0-2
+  author: Jane Doe <jane@example.com>
+  changes:
+    - "Use 100% less memory"
+    - |-
+      Fix a crash
+      on startup
+"#,
+        )
+        .unwrap();
+        assert!(validate_changelog(entries.clone(), None).is_err());
+        let entries = validate_changelog(entries, Some("John Doe <john@example.com>")).unwrap();
+        assert_eq!(entries[0].date, date("2024-05-01"));
+        assert_eq!(
+            entries[0].render_rpm("1.0.0-1", "John Doe <john@example.com>"),
+            "* Wed May 01 2024 Jane Doe <jane@example.com> - 1:1.1.0-2\n- Use 100%% less memory\n- Fix a crash\n  on startup\n"
+        );
+        assert_eq!(
+            entries[1].render_rpm("1.0.0-1", "John Doe <john@example.com>"),
+            "* Mon Jan 15 2024 John Doe <john@example.com> - 1.0.0-1\n- Initial release\n"
+        );
+        assert_eq!(
+            entries[0].render_deb("pkg", "1.0.0-1", "John Doe <john@example.com>", "unstable", "low"),
+            "pkg (1:1.1.0-2) unstable; urgency=low\n\n  * Use 100% less memory\n  * Fix a crash\n    on startup\n\n -- Jane Doe <jane@example.com>  Wed, 01 May 2024 00:00:00 +0000\n"
+        );
+    }
+}
@@ -6,6 +6,14 @@ use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 
 pub static COMMON_DEPS_KEY: &str = "all";
+/// Prefix of build dependencies on packages built by pkger from other recipes, like
+/// `pkger:toolchain`
+pub static PKGER_DEP_PREFIX: &str = "pkger:";
+
+/// Returns the name of the recipe if the `dependency` is a package built by pkger from it.
+pub fn pkger_recipe(dependency: &str) -> Option<&str> {
+    dependency.strip_prefix(PKGER_DEP_PREFIX)
+}
 
 type DepsMap = HashMap<String, HashSet<String>>;
 
@@ -0,0 +1,333 @@
+use crate::shell;
+use crate::{err, Error, Result};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+/// Owner and group of a path in the output directory and everything below it and the mode of the
+/// path itself
+pub struct FileAttributes {
+    /// Path relative to the output directory
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Name or id of the user owning the files
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Name or id of the group owning the files
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Octal mode of the path like `0640`
+    pub mode: Option<String>,
+}
+
+impl FileAttributes {
+    /// Returns true if the absolute `file` of the package is this path or is inside of it.
+    fn matches(&self, file: &str) -> bool {
+        file == self.path
+            || matches!(file.strip_prefix(self.path.as_str()), Some(rest) if rest.starts_with('/'))
+    }
+
+    /// Returns the owner and group like `owner:group` as accepted by `chown` or `None` if neither
+    /// is set.
+    pub fn ownership(&self) -> Option<String> {
+        match (&self.owner, &self.group) {
+            (Some(owner), Some(group)) => Some(format!("{}:{}", owner, group)),
+            (Some(owner), None) => Some(owner.clone()),
+            (None, Some(group)) => Some(format!(":{}", group)),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns true if the owner and group that are set are numeric ids, which unlike names don't
+    /// depend on the accounts of the system the files are owned on.
+    pub fn has_numeric_ownership(&self) -> bool {
+        self.owner
+            .iter()
+            .chain(self.group.iter())
+            .all(|id| id.chars().all(|c| c.is_ascii_digit()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Symbolic link created in the output directory before packaging
+pub struct Symlink {
+    /// Absolute path of the link in the package
+    pub link: String,
+    /// Path the link points to as declared in the recipe
+    pub target: String,
+}
+
+impl Symlink {
+    /// Returns the target of the link relative to the directory of the link so that the link
+    /// also resolves inside of an archive extracted to a different root. Relative targets are
+    /// returned unchanged.
+    pub fn relative_target(&self) -> String {
+        let target = match self.target.strip_prefix('/') {
+            Some(target) => target,
+            None => return self.target.clone(),
+        };
+        let mut link_dir: Vec<_> = self.link.split('/').filter(|p| !p.is_empty()).collect();
+        link_dir.pop();
+        let target: Vec<_> = target.split('/').filter(|p| !p.is_empty()).collect();
+        let common = link_dir
+            .iter()
+            .zip(target.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut relative: Vec<_> = link_dir[common..].iter().map(|_| "..").collect();
+        relative.extend_from_slice(&target[common..]);
+        if relative.is_empty() {
+            ".".to_string()
+        } else {
+            relative.join("/")
+        }
+    }
+}
+
+/// Returns `path` as an absolute path of the package or `None` if it doesn't point inside of the
+/// output directory.
+fn package_path(path: &str) -> Option<String> {
+    let path = path.trim_matches('/');
+    if path.is_empty() || path.split('/').any(|part| part == "..") {
+        None
+    } else {
+        Some(format!("/{}", path))
+    }
+}
+
+/// Verifies the `symlinks` of a recipe mapping paths of links to their targets and returns them
+/// with links normalized to absolute paths of the package.
+pub fn validate_symlinks(symlinks: BTreeMap<String, String>) -> Result<Vec<Symlink>> {
+    let mut validated = vec![];
+    for (link, target) in symlinks {
+        let normalized = match package_path(&link) {
+            Some(link) => link,
+            None => {
+                return err!(
+                    "invalid symlink `{}`, expected a path inside of the output directory",
+                    link
+                )
+            }
+        };
+        if target.trim().is_empty() {
+            return err!("symlink `{}` has no target", link);
+        }
+        validated.push(Symlink {
+            link: normalized,
+            target,
+        });
+    }
+    Ok(validated)
+}
+
+/// Verifies that the owners, groups and modes of `entries` are valid and returns them with paths
+/// normalized to absolute paths of the package.
+pub fn validate_files(entries: Vec<FileAttributes>) -> Result<Vec<FileAttributes>> {
+    let is_valid_name = |name: &str| {
+        !name.is_empty()
+            && !name.starts_with('-')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    let mut validated = vec![];
+    for mut entry in entries {
+        entry.path = match package_path(&entry.path) {
+            Some(path) => path,
+            None => {
+                return err!(
+                    "invalid path `{}` in files, expected a path inside of the output directory",
+                    entry.path
+                )
+            }
+        };
+        if entry.owner.is_none() && entry.group.is_none() && entry.mode.is_none() {
+            return err!(
+                "files entry `{}` has to set an owner, a group or a mode",
+                entry.path
+            );
+        }
+        for name in entry.owner.iter().chain(entry.group.iter()) {
+            if !is_valid_name(name) {
+                return err!("invalid owner or group `{}` of `{}`", name, entry.path);
+            }
+        }
+        if let Some(mode) = &entry.mode {
+            if !(3..=4).contains(&mode.len()) || !mode.chars().all(|c| ('0'..='7').contains(&c)) {
+                return err!(
+                    "invalid mode `{}` of `{}`, expected an octal mode like `0644`",
+                    mode,
+                    entry.path
+                );
+            }
+        }
+        validated.push(entry);
+    }
+    Ok(validated)
+}
+
+/// Returns the `%attr` of RPM like `0755,root,-` of the absolute `file` of the package or `None`
+/// if no entry applies to it. Owners and groups are inherited from entries of parent directories
+/// while modes only apply to the exact path. When multiple entries apply to the file the last one
+/// setting a value wins, same as when the entries are applied in the container.
+pub fn rpm_attr(entries: &[FileAttributes], file: &str) -> Option<String> {
+    let matching: Vec<_> = entries.iter().filter(|entry| entry.matches(file)).collect();
+    let last = |value: fn(&FileAttributes) -> &Option<String>, exact: bool| {
+        matching
+            .iter()
+            .rev()
+            .filter(|entry| !exact || entry.path == file)
+            .find_map(|entry| value(entry).as_deref())
+    };
+    let mode = last(|entry| &entry.mode, true);
+    let owner = last(|entry| &entry.owner, false);
+    let group = last(|entry| &entry.group, false);
+    if mode.is_none() && owner.is_none() && group.is_none() {
+        return None;
+    }
+    Some(format!(
+        "{},{},{}",
+        mode.unwrap_or("-"),
+        owner.unwrap_or("-"),
+        group.unwrap_or("-")
+    ))
+}
+
+/// Commands of `postinst` that set the owners and groups of `entries` when the package is
+/// configured, once its system accounts exist. Modes are applied again afterwards as changing the
+/// owner clears setuid and setgid bits.
+pub fn deb_postinst(entries: &[FileAttributes]) -> Option<String> {
+    let mut commands: String = entries
+        .iter()
+        .filter_map(|entry| {
+            entry.ownership().map(|ownership| {
+                format!("    chown -R {} {}\n", ownership, shell::quote(&entry.path))
+            })
+        })
+        .collect();
+    if commands.is_empty() {
+        return None;
+    }
+    for entry in entries {
+        if let Some(mode) = &entry.mode {
+            commands.push_str(&format!(
+                "    chmod {} {}\n",
+                mode,
+                shell::quote(&entry.path)
+            ));
+        }
+    }
+    Some(format!(
+        "if [ \"$1\" = \"configure\" ]; then\n{}fi\n",
+        commands
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn maps_file_attributes() {
+        let entries: Vec<FileAttributes> = serde_yaml::from_str(
+            r#"
+- path: etc/pkger/
+  owner: pkger
+  group: pkger
+  mode: 0750
+- path: /etc/pkger/secret.key
+  mode: "0600"
+- path: usr/bin/pkger-helper
+  mode: 4755
+"#,
+        )
+        .unwrap();
+        let entries = validate_files(entries).unwrap();
+        assert_eq!(entries[0].path, "/etc/pkger");
+        assert_eq!(entries[0].mode.as_deref(), Some("0750"));
+
+        assert_eq!(
+            rpm_attr(&entries, "/etc/pkger").as_deref(),
+            Some("0750,pkger,pkger")
+        );
+        assert_eq!(
+            rpm_attr(&entries, "/etc/pkger/pkger.conf").as_deref(),
+            Some("-,pkger,pkger")
+        );
+        assert_eq!(
+            rpm_attr(&entries, "/etc/pkger/secret.key").as_deref(),
+            Some("0600,pkger,pkger")
+        );
+        assert_eq!(
+            rpm_attr(&entries, "/usr/bin/pkger-helper").as_deref(),
+            Some("4755,-,-")
+        );
+        assert_eq!(rpm_attr(&entries, "/etc/pkger-other"), None);
+        assert_eq!(
+            deb_postinst(&entries).unwrap(),
+            r#"if [ "$1" = "configure" ]; then
+    chown -R pkger:pkger '/etc/pkger'
+    chmod 0750 '/etc/pkger'
+    chmod 0600 '/etc/pkger/secret.key'
+    chmod 4755 '/usr/bin/pkger-helper'
+fi
+"#
+        );
+        assert_eq!(deb_postinst(&entries[1..]), None);
+
+        let invalid = |path: &str, owner: Option<&str>, mode: Option<&str>| {
+            validate_files(vec![FileAttributes {
+                path: path.to_string(),
+                owner: owner.map(String::from),
+                group: None,
+                mode: mode.map(String::from),
+            }])
+            .is_err()
+        };
+        assert!(invalid("../etc", Some("root"), None));
+        assert!(invalid("/", Some("root"), None));
+        assert!(invalid("etc", None, None));
+        assert!(invalid("etc", Some("-R"), None));
+        assert!(invalid("etc", None, Some("0855")));
+        assert!(invalid("etc", None, Some("u+x")));
+    }
+
+    #[test]
+    fn validates_symlinks() {
+        let symlinks: BTreeMap<String, String> = serde_yaml::from_str(
+            r#"
+/usr/bin/pkger: /opt/pkger/bin/pkger
+usr/lib/libpkger.so: libpkger.so.1
+/opt/pkger/current/: /opt/pkger
+"#,
+        )
+        .unwrap();
+        let symlinks = validate_symlinks(symlinks).unwrap();
+        let relative: Vec<_> = symlinks
+            .iter()
+            .map(|symlink| (symlink.link.as_str(), symlink.relative_target()))
+            .collect();
+        assert_eq!(
+            relative,
+            vec![
+                ("/opt/pkger/current", ".".to_string()),
+                ("/usr/bin/pkger", "../../opt/pkger/bin/pkger".to_string()),
+                ("/usr/lib/libpkger.so", "libpkger.so.1".to_string()),
+            ]
+        );
+
+        let invalid = |link: &str, target: &str| {
+            validate_symlinks(
+                vec![(link.to_string(), target.to_string())]
+                    .into_iter()
+                    .collect(),
+            )
+            .is_err()
+        };
+        assert!(invalid("../usr/bin/pkger", "/opt/pkger"));
+        assert!(invalid("/", "/opt/pkger"));
+        assert!(invalid("/usr/bin/pkger", " "));
+    }
+}
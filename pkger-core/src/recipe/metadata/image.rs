@@ -1,8 +1,11 @@
+use crate::network::NetworkConfig;
 use crate::recipe::{BuildTarget, Os};
-use crate::{Error, Result};
+use crate::security::SecurityConfig;
+use crate::{err, Error, Result};
 
 use serde::{Deserialize, Serialize};
 use serde_yaml::{Mapping, Value as YamlValue};
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 
 #[derive(Clone, Deserialize, Serialize, Debug, Eq, PartialEq, Hash)]
@@ -12,6 +15,35 @@ pub struct ImageTarget {
     #[serde(rename = "target")]
     pub build_target: BuildTarget,
     pub os: Option<Os>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Entrypoint of build containers, defaults to `/bin/sh -c`
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Command that keeps build containers running, defaults to `sleep infinity`
+    pub cmd: Option<Vec<String>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Signal used to stop build containers, defaults to `SIGKILL`
+    pub stop_signal: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// DNS settings and extra hosts of build containers applied on top of the configuration
+    pub network: Option<Box<NetworkConfig>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Seccomp and AppArmor profiles of build containers applied on top of the configuration
+    pub security: Option<Box<SecurityConfig>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Name of the directory in `images_dir` the image is built from, defaults to the name of
+    /// the image
+    pub dir: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    /// Values of `ARG` instructions of the Dockerfile the image is built with
+    pub build_args: BTreeMap<String, String>,
 }
 
 impl ImageTarget {
@@ -24,8 +56,25 @@ impl ImageTarget {
             image: image.into(),
             build_target,
             os: os.map(|os| Os::new(os, None::<&str>).unwrap()),
+            entrypoint: None,
+            cmd: None,
+            stop_signal: None,
+            network: None,
+            security: None,
+            dir: None,
+            build_args: BTreeMap::new(),
         }
     }
+
+    /// Returns the name of the directory in `images_dir` this image is built from.
+    pub fn dir(&self) -> &str {
+        self.dir.as_deref().unwrap_or(&self.image)
+    }
+
+    /// Returns `true` if this is the image `name` or one of the variants of the image `name`.
+    pub fn matches(&self, name: &str) -> bool {
+        self.image == name || self.dir.as_deref() == Some(name)
+    }
 }
 
 pub fn deserialize_images<'de, D>(deserializer: D) -> Result<Vec<ImageTarget>, D::Error>
@@ -39,7 +88,7 @@ where
     for value in mapping {
         match value {
             serde_yaml::Value::Mapping(map) => {
-                images.push(ImageTarget::try_from(map).map_err(D::Error::custom)?);
+                images.extend(expand_variants(map).map_err(D::Error::custom)?);
             }
             _ => {
                 return Err(D::Error::custom(
@@ -51,6 +100,93 @@ where
     Ok(images)
 }
 
+/// Reads the value of `key` from `map` as a list of strings. A single string is treated as a list
+/// with one element.
+fn strings(map: &Mapping, key: &str) -> Result<Option<Vec<String>>> {
+    match map.get(&YamlValue::from(key)) {
+        None => Ok(None),
+        Some(YamlValue::String(value)) => Ok(Some(vec![value.to_string()])),
+        Some(YamlValue::Sequence(values)) => values
+            .iter()
+            .map(|value| {
+                value.as_str().map(str::to_string).ok_or_else(|| {
+                    anyhow!("expected a string in image {}, found `{:?}`", key, value)
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some),
+        Some(value) => Err(anyhow!(
+            "expected a string or a list of strings as image {}, found `{:?}`",
+            key,
+            value
+        )),
+    }
+}
+
+/// Verifies that `name` can be used as a name of a build argument.
+fn validate_build_arg(name: &str, value: &str) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return err!("invalid name of image build arg `{}`", name);
+    }
+    if value.contains('\n') {
+        return err!(
+            "value of image build arg `{}` can't contain new lines",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Parses the image target from `map`. When the image defines `variants`, a mapping of build
+/// args to lists of values, an image target is returned for each combination of the values
+/// named `<name>-<value>[-<value>...]` that is built from the directory of the image.
+fn expand_variants(map: Mapping) -> Result<Vec<ImageTarget>> {
+    let variants = match map.get(&YamlValue::from("variants")) {
+        Some(YamlValue::Mapping(variants)) => variants.clone(),
+        Some(value) => {
+            return err!(
+                "expected a mapping of build args to values as image variants, found `{:?}`",
+                value
+            )
+        }
+        None => return Ok(vec![ImageTarget::try_from(map)?]),
+    };
+    let base = ImageTarget::try_from(map)?;
+
+    let mut targets = vec![base.clone()];
+    for (arg, _) in &variants {
+        let arg = arg
+            .as_str()
+            .ok_or_else(|| anyhow!("expected a string as image variant, found `{:?}`", arg))?;
+        let values = strings(&variants, arg)?.unwrap_or_default();
+        if values.is_empty() {
+            return err!("image variant `{}` has no values", arg);
+        }
+        let mut expanded = vec![];
+        for target in &targets {
+            for value in &values {
+                validate_build_arg(arg, value)?;
+                let mut variant = target.clone();
+                variant.image = format!("{}-{}", target.image, value);
+                variant
+                    .build_args
+                    .insert(arg.to_string(), value.to_string());
+                expanded.push(variant);
+            }
+        }
+        targets = expanded;
+    }
+
+    let dir = base.dir().to_string();
+    for target in &mut targets {
+        target.dir = Some(dir.clone());
+    }
+    Ok(targets)
+}
+
 impl TryFrom<Mapping> for ImageTarget {
     type Error = Error;
 
@@ -90,7 +226,67 @@ impl TryFrom<Mapping> for ImageTarget {
                 None
             };
 
+            let stop_signal = if let Some(signal) = map.get(&YamlValue::from("stop_signal")) {
+                if !signal.is_string() {
+                    return Err(anyhow!(
+                        "expected a string as image stop signal, found `{:?}`",
+                        signal
+                    ));
+                }
+                signal.as_str().map(str::to_string)
+            } else {
+                None
+            };
+
+            let network = if let Some(network) = map.get(&YamlValue::from("network")) {
+                let network: NetworkConfig = serde_yaml::from_value(network.clone())
+                    .map_err(|e| anyhow!("invalid image network `{:?}` - {}", network, e))?;
+                network.validate()?;
+                Some(Box::new(network))
+            } else {
+                None
+            };
+
+            let security = if let Some(security) = map.get(&YamlValue::from("security")) {
+                let security: SecurityConfig = serde_yaml::from_value(security.clone())
+                    .map_err(|e| anyhow!("invalid image security `{:?}` - {}", security, e))?;
+                security.validate()?;
+                Some(Box::new(security))
+            } else {
+                None
+            };
+
+            let dir = if let Some(dir) = map.get(&YamlValue::from("dir")) {
+                if !dir.is_string() {
+                    return Err(anyhow!(
+                        "expected a string as image directory, found `{:?}`",
+                        dir
+                    ));
+                }
+                dir.as_str().map(str::to_string)
+            } else {
+                None
+            };
+
+            let build_args = if let Some(args) = map.get(&YamlValue::from("build_args")) {
+                let args: BTreeMap<String, String> = serde_yaml::from_value(args.clone())
+                    .map_err(|e| anyhow!("invalid image build args `{:?}` - {}", args, e))?;
+                for (name, value) in &args {
+                    validate_build_arg(name, value)?;
+                }
+                args
+            } else {
+                BTreeMap::new()
+            };
+
             Ok(ImageTarget {
+                entrypoint: strings(&map, "entrypoint")?,
+                network,
+                security,
+                dir,
+                build_args,
+                cmd: strings(&map, "cmd")?,
+                stop_signal,
                 image,
                 build_target: target,
                 os,
@@ -110,6 +306,13 @@ impl TryFrom<YamlValue> for ImageTarget {
                 image,
                 build_target: BuildTarget::default(),
                 os: None,
+                entrypoint: None,
+                cmd: None,
+                stop_signal: None,
+                network: None,
+                security: None,
+                dir: None,
+                build_args: BTreeMap::new(),
             }),
             value => Err(anyhow!(
                 "expected a map or string for image, found `{:?}`",
@@ -118,3 +321,85 @@ impl TryFrom<YamlValue> for ImageTarget {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_container_overrides() {
+        let map: Mapping = serde_yaml::from_str(
+            r#"
+name: custom
+target: deb
+entrypoint: ["/bin/bash", "-c"]
+cmd: tail -f /dev/null
+stop_signal: SIGTERM
+"#,
+        )
+        .unwrap();
+
+        let target = ImageTarget::try_from(map).unwrap();
+
+        assert_eq!(
+            target.entrypoint,
+            Some(vec!["/bin/bash".to_string(), "-c".to_string()])
+        );
+        assert_eq!(target.cmd, Some(vec!["tail -f /dev/null".to_string()]));
+        assert_eq!(target.stop_signal.as_deref(), Some("SIGTERM"));
+
+        let map: Mapping = serde_yaml::from_str("name: custom\nentrypoint: 1").unwrap();
+        assert!(ImageTarget::try_from(map).is_err());
+    }
+
+    #[test]
+    fn expands_image_variants() {
+        let map: Mapping = serde_yaml::from_str(
+            r#"
+name: debian12
+target: deb
+build_args:
+  BASE: debian:12
+variants:
+  toolchain: [gcc, clang]
+  arch: [amd64, arm64]
+"#,
+        )
+        .unwrap();
+
+        let targets = expand_variants(map).unwrap();
+        let names: Vec<_> = targets.iter().map(|target| target.image.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "debian12-gcc-amd64",
+                "debian12-gcc-arm64",
+                "debian12-clang-amd64",
+                "debian12-clang-arm64"
+            ]
+        );
+        let target = &targets[2];
+        assert_eq!(target.dir(), "debian12");
+        assert!(target.matches("debian12"));
+        assert!(target.matches("debian12-clang-amd64"));
+        assert!(!target.matches("debian12-gcc-amd64"));
+        assert_eq!(target.build_target, BuildTarget::Deb);
+        assert_eq!(
+            target.build_args.iter().collect::<Vec<_>>(),
+            vec![
+                (&"BASE".to_string(), &"debian:12".to_string()),
+                (&"arch".to_string(), &"amd64".to_string()),
+                (&"toolchain".to_string(), &"clang".to_string())
+            ]
+        );
+
+        let map: Mapping = serde_yaml::from_str("name: debian12").unwrap();
+        assert_eq!(expand_variants(map).unwrap()[0].dir(), "debian12");
+        let map: Mapping =
+            serde_yaml::from_str("name: debian12\nvariants:\n  tool-chain: [gcc]").unwrap();
+        assert!(expand_variants(map).is_err());
+        let map: Mapping = serde_yaml::from_str("name: debian12\nvariants: [gcc]").unwrap();
+        assert!(expand_variants(map).is_err());
+    }
+}
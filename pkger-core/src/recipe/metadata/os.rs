@@ -2,6 +2,7 @@ use crate::{Error, Result};
 
 use serde::{Deserialize, Serialize};
 use std::convert::{AsRef, TryFrom};
+use std::path::Path;
 
 //####################################################################################################
 
@@ -36,6 +37,10 @@ impl Os {
         self.distribution.as_ref()
     }
 
+    pub fn distribution(&self) -> Distro {
+        self.distribution
+    }
+
     pub fn package_manager(&self) -> PackageManager {
         let version: u8 = self.version().parse().unwrap_or_default();
         match self.distribution {
@@ -46,6 +51,7 @@ impl Os {
             Distro::Rocky => PackageManager::Dnf,
             Distro::RedHat | Distro::CentOS | Distro::Fedora => PackageManager::Yum,
             Distro::Alpine => PackageManager::Apk,
+            Distro::Void => PackageManager::Xbps,
         }
     }
 }
@@ -63,6 +69,7 @@ pub enum Distro {
     Ubuntu,
     Rocky,
     Alpine,
+    Void,
 }
 
 impl AsRef<str> for Distro {
@@ -77,6 +84,7 @@ impl AsRef<str> for Distro {
             Ubuntu => "ubuntu",
             Rocky => "rocky",
             Alpine => "alpine",
+            Void => "void",
         }
     }
 }
@@ -95,6 +103,7 @@ impl TryFrom<&str> for Distro {
             ("ubuntu", Ubuntu),
             ("rocky", Rocky),
             ("alpine", Alpine),
+            ("void", Void),
         ];
         let out = s.to_lowercase();
         for (name, distro) in DISTROS.iter() {
@@ -116,6 +125,7 @@ pub enum PackageManager {
     Pacman,
     Yum,
     Apk,
+    Xbps,
 }
 
 impl AsRef<str> for PackageManager {
@@ -126,6 +136,7 @@ impl AsRef<str> for PackageManager {
             Self::Pacman => "pacman",
             Self::Yum => "yum",
             Self::Apk => "apk",
+            Self::Xbps => "xbps-install",
         }
     }
 }
@@ -138,6 +149,54 @@ impl PackageManager {
             Self::Pacman => vec!["-S", "--noconfirm"],
             Self::Yum => vec!["install", "-y"],
             Self::Apk => vec!["add"],
+            Self::Xbps => vec!["-y"],
+        }
+    }
+
+    /// Arguments that install packages from local files passed as paths.
+    fn install_local_args(&self) -> Vec<&'static str> {
+        match self {
+            Self::Apt => vec!["install", "-y"],
+            Self::Dnf => vec!["install", "-y"],
+            Self::Pacman => vec!["-U", "--noconfirm"],
+            Self::Yum => vec!["localinstall", "-y"],
+            Self::Apk => vec!["add", "--allow-untrusted"],
+            Self::Xbps => vec!["-y"],
+        }
+    }
+
+    /// Returns the command that installs the local package files `packages` located in `dir`.
+    /// xbps can only install packages from a repository so a local one is created in `dir`.
+    pub fn install_local_cmd(&self, dir: &Path, packages: &[String]) -> String {
+        let paths: Vec<_> = packages
+            .iter()
+            .map(|package| dir.join(package).to_string_lossy().to_string())
+            .collect();
+        match self {
+            Self::Xbps => {
+                let pkgvers: Vec<_> = packages
+                    .iter()
+                    .map(|package| {
+                        let package = package.trim_end_matches(".xbps");
+                        package
+                            .rsplit_once('.')
+                            .map(|(pkgver, _)| pkgver)
+                            .unwrap_or(package)
+                    })
+                    .collect();
+                format!(
+                    "xbps-rindex -a {} && xbps-install -y --repository {} {}",
+                    paths.join(" "),
+                    dir.display(),
+                    pkgvers.join(" ")
+                )
+            }
+            _ => format!(
+                "{} {} {}",
+                self.as_ref(),
+                self.install_local_args().join(" "),
+                paths.join(" ")
+            ),
         }
     }
 
@@ -147,6 +206,8 @@ impl PackageManager {
             Self::Dnf | Self::Yum => vec!["clean", "metadata"],
             Self::Pacman => vec!["-Sy", "--noconfirm"],
             Self::Apk => vec!["update"],
+            // xbps has to be updated before any other package can be installed
+            Self::Xbps => vec!["-Syu", "xbps"],
         }
     }
 
@@ -156,15 +217,20 @@ impl PackageManager {
             Self::Dnf | Self::Yum => vec!["update", "-y"],
             Self::Pacman => vec!["-Syu", "--noconfirm"],
             Self::Apk => vec!["upgrade"],
+            Self::Xbps => vec!["-Syu"],
         }
     }
 
-    pub fn clean_cache(&self) -> Vec<&'static str> {
-        match self {
-            Self::Apt => vec!["clean"],
-            Self::Dnf | Self::Yum => vec!["clean", "metadata"],
-            Self::Pacman => vec!["-Sc"],
-            Self::Apk => vec!["cache", "clean"],
-        }
+    /// Returns the command that cleans the cache of this package manager. Unlike other package
+    /// managers xbps cleans its cache with a different binary than the one installing packages.
+    pub fn clean_cache_cmd(&self) -> String {
+        let args = match self {
+            Self::Apt => "clean",
+            Self::Dnf | Self::Yum => "clean metadata",
+            Self::Pacman => "-Sc",
+            Self::Apk => "cache clean",
+            Self::Xbps => return "xbps-remove -O".to_string(),
+        };
+        format!("{} {}", self.as_ref(), args)
     }
 }
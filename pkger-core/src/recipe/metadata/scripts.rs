@@ -0,0 +1,56 @@
+/// Install scripts of DEB and RPM packages that pkger adds generated commands to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scriptlet {
+    DebPreinst,
+    DebPostinst,
+    DebPrerm,
+    DebPostrm,
+    RpmPre,
+    RpmPost,
+    RpmPreun,
+    RpmPostun,
+}
+
+/// Where generated commands are placed in an install script of the recipe
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// After the commands of the recipe, used by scripts run after installing or removing files.
+    /// The commands of the recipe run in a subshell so that an `exit` doesn't skip the snippet.
+    After,
+    /// Before the commands of the recipe, used by scripts run before installing or removing files
+    Before,
+}
+
+/// Returns the install `script` of the recipe with the generated `snippet` placed according to
+/// `placement`. The snippet and the subshell of the commands of the recipe follow the shebang of
+/// the script. Returns `None` if there is neither a script nor a snippet.
+pub fn with_snippet(
+    script: Option<&str>,
+    snippet: Option<String>,
+    placement: Placement,
+) -> Option<String> {
+    let snippet = match snippet {
+        Some(snippet) => snippet,
+        None => return script.map(String::from),
+    };
+    let script = match script.filter(|script| !script.trim().is_empty()) {
+        Some(script) => script,
+        None => return Some(snippet),
+    };
+    let (shebang, commands) = match script.split_once('\n') {
+        Some((shebang, commands)) if shebang.starts_with("#!") => {
+            (format!("{}\n", shebang), commands)
+        }
+        None if script.starts_with("#!") => (format!("{}\n", script), ""),
+        _ => (String::new(), script),
+    };
+    Some(match placement {
+        Placement::After => format!(
+            "{}(\n{}\n) || exit $?\n{}",
+            shebang,
+            commands.trim_end(),
+            snippet
+        ),
+        Placement::Before => format!("{}{}{}", shebang, snippet, commands),
+    })
+}
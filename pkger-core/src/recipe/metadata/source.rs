@@ -0,0 +1,256 @@
+use crate::Result;
+
+use anyhow::Context as ErrContext;
+use serde::Deserialize;
+use serde_yaml::Value as YamlValue;
+use std::convert::TryFrom;
+
+fn default_unpack() -> bool {
+    true
+}
+
+/// A remote or file system source of the recipe.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Source {
+    source: String,
+    /// Whether archives should be extracted to the build directory instead of being copied
+    #[serde(default = "default_unpack")]
+    unpack: bool,
+    /// Number of leading path components to remove when extracting tar archives
+    #[serde(default)]
+    strip_components: u32,
+    /// URLs tried in order when fetching the source fails
+    #[serde(default)]
+    mirrors: Vec<String>,
+    /// Expected SHA256 checksum of the fetched file
+    sha256: Option<String>,
+}
+
+impl Source {
+    pub fn new<S: Into<String>>(source: S, unpack: bool, strip_components: u32) -> Self {
+        Self {
+            source: source.into(),
+            unpack,
+            strip_components,
+            mirrors: vec![],
+            sha256: None,
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn unpack(&self) -> bool {
+        self.unpack
+    }
+
+    pub fn strip_components(&self) -> u32 {
+        self.strip_components
+    }
+
+    pub fn mirrors(&self) -> &[String] {
+        &self.mirrors
+    }
+
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+}
+
+/// A source distributed over a peer-to-peer network that is fetched on the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerSource<'a> {
+    /// A magnet link or a path or URL of a `.torrent` file prefixed with `torrent:`
+    Torrent(&'a str),
+    /// An IPFS path like `ipfs://<cid>/file.tar.gz`
+    Ipfs(&'a str),
+}
+
+impl<'a> PeerSource<'a> {
+    /// Returns `None` for locations that aren't peer sources and for values starting with `-`
+    /// that the downloader would otherwise treat as options.
+    pub fn parse(location: &'a str) -> Option<Self> {
+        match Self::strip_prefix(location)? {
+            value if value.starts_with('-') => None,
+            value if location.starts_with("torrent:") => Some(PeerSource::Torrent(value)),
+            value => Some(PeerSource::Ipfs(value)),
+        }
+    }
+
+    fn strip_prefix(location: &str) -> Option<&str> {
+        location
+            .strip_prefix("torrent:")
+            .or_else(|| location.strip_prefix("ipfs://"))
+    }
+}
+
+impl From<&str> for Source {
+    fn from(source: &str) -> Self {
+        Self::new(source, true, 0)
+    }
+}
+
+impl TryFrom<&YamlValue> for Source {
+    type Error = crate::Error;
+
+    fn try_from(value: &YamlValue) -> Result<Self> {
+        let source: Self = match value {
+            YamlValue::String(source) => Self::from(source.as_str()),
+            YamlValue::Mapping(_) => {
+                serde_yaml::from_value(value.clone()).context("deserializing source")?
+            }
+            value => {
+                return Err(anyhow!(
+                    "expected a string or a mapping as source, found `{:?}`",
+                    value
+                ))
+            }
+        };
+        if PeerSource::strip_prefix(&source.source).map_or(false, |value| value.starts_with('-')) {
+            return Err(anyhow!(
+                "source `{}` is invalid, torrent and IPFS sources can't start with `-`",
+                source.source
+            ));
+        }
+        if PeerSource::parse(&source.source).is_some() && source.sha256.is_none() {
+            return Err(anyhow!(
+                "source `{}` requires a `sha256` checksum, it is mandatory for torrent and IPFS sources",
+                source.source
+            ));
+        }
+        Ok(source)
+    }
+}
+
+/// Parses the `source` field of the recipe that can be a single source or an array of sources.
+pub fn parse_sources(value: &YamlValue) -> Result<Vec<Source>> {
+    match value {
+        YamlValue::Null => Ok(vec![]),
+        YamlValue::Sequence(sources) => sources.iter().map(Source::try_from).collect(),
+        value => Source::try_from(value).map(|source| vec![source]),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Tar,
+    Zip,
+}
+
+impl ArchiveKind {
+    /// Recognizes the kind of the archive from the `file_name`.
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        static TAR_EXTENSIONS: &[&str] = &[".tgz", ".tbz", ".txz", ".tlz", ".tsz", ".taz", ".tz"];
+        if file_name.contains(".tar") || TAR_EXTENSIONS.iter().any(|ext| file_name.ends_with(ext)) {
+            Some(ArchiveKind::Tar)
+        } else if file_name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_sources() {
+        let value: YamlValue = serde_yaml::from_str(
+            r#"
+- https://example.com/pkger-0.1.0.tar.gz
+- source: firmware.tar.xz
+  unpack: false
+- source: vendor.tgz
+  strip_components: 1
+- source: https://example.com/blob.bin
+  mirrors:
+    - https://mirror.example.com/blob.bin
+  sha256: d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            parse_sources(&value).unwrap(),
+            vec![
+                Source::new("https://example.com/pkger-0.1.0.tar.gz", true, 0),
+                Source::new("firmware.tar.xz", false, 0),
+                Source::new("vendor.tgz", true, 1),
+                Source {
+                    mirrors: vec!["https://mirror.example.com/blob.bin".to_string()],
+                    sha256: Some(
+                        "d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26"
+                            .to_string()
+                    ),
+                    ..Source::from("https://example.com/blob.bin")
+                },
+            ]
+        );
+        assert_eq!(
+            parse_sources(&YamlValue::from("src.zip")).unwrap(),
+            vec![Source::from("src.zip")]
+        );
+        assert!(parse_sources(&YamlValue::Null).unwrap().is_empty());
+        assert!(parse_sources(&YamlValue::from(1)).is_err());
+    }
+
+    #[test]
+    fn requires_checksums_of_peer_sources() {
+        assert_eq!(
+            PeerSource::parse("torrent:magnet:?xt=urn:btih:abc"),
+            Some(PeerSource::Torrent("magnet:?xt=urn:btih:abc"))
+        );
+        assert_eq!(
+            PeerSource::parse("ipfs://bafybeigdyr/model.tar.gz"),
+            Some(PeerSource::Ipfs("bafybeigdyr/model.tar.gz"))
+        );
+        assert_eq!(PeerSource::parse("https://example.com/model.tar.gz"), None);
+        assert_eq!(
+            PeerSource::parse("torrent:--on-download-complete=/tmp/hook.sh"),
+            None
+        );
+        assert_eq!(PeerSource::parse("ipfs://-x"), None);
+
+        assert_eq!(
+            parse_sources(&YamlValue::from("ipfs://bafybeigdyr/model.tar.gz"))
+                .unwrap_err()
+                .to_string(),
+            "source `ipfs://bafybeigdyr/model.tar.gz` requires a `sha256` checksum, it is mandatory for torrent and IPFS sources"
+        );
+        let value: YamlValue = serde_yaml::from_str(
+            r#"
+source: torrent:https://example.com/model.torrent
+sha256: d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26
+"#,
+        )
+        .unwrap();
+        assert!(parse_sources(&value).is_ok());
+
+        let value: YamlValue = serde_yaml::from_str(
+            r#"
+source: torrent:--on-download-complete=/tmp/hook.sh
+sha256: d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parse_sources(&value).unwrap_err().to_string(),
+            "source `torrent:--on-download-complete=/tmp/hook.sh` is invalid, torrent and IPFS sources can't start with `-`"
+        );
+    }
+
+    #[test]
+    fn recognizes_archives() {
+        assert_eq!(
+            ArchiveKind::from_file_name("pkger-0.1.0.tar.gz"),
+            Some(ArchiveKind::Tar)
+        );
+        assert_eq!(ArchiveKind::from_file_name("a.txz"), Some(ArchiveKind::Tar));
+        assert_eq!(ArchiveKind::from_file_name("a.zip"), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::from_file_name("firmware.bin"), None);
+    }
+}
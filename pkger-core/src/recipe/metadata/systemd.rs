@@ -0,0 +1,278 @@
+use crate::recipe::BuildTarget;
+use crate::{err, Error, Result};
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Extensions of unit files that can be installed with the `systemd` section
+static UNIT_EXTENSIONS: &[&str] = &[
+    "service",
+    "socket",
+    "timer",
+    "path",
+    "target",
+    "mount",
+    "automount",
+    "swap",
+    "slice",
+];
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+/// Systemd units installed by the package
+pub struct Systemd {
+    /// Unit files relative to the build directory
+    pub units: Vec<String>,
+    #[serde(default = "default_true")]
+    /// Enable the units when the package is installed and disable them when it's removed
+    pub enable: bool,
+    #[serde(default = "default_true")]
+    /// Start the units when the package is installed and restart them when it's upgraded
+    pub start: bool,
+}
+
+impl Systemd {
+    /// Verifies that all units have names of systemd unit files.
+    pub fn validate(&self) -> Result<()> {
+        if self.units.is_empty() {
+            return err!("systemd section has no units");
+        }
+        for unit in &self.units {
+            let name = unit_name(unit);
+            let extension = name.rsplit_once('.').map(|(_, extension)| extension);
+            let is_valid = name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@' | ':'))
+                && matches!(extension, Some(extension) if UNIT_EXTENSIONS.contains(&extension));
+            if !is_valid {
+                return err!(
+                    "invalid systemd unit `{}`, expected a unit file like `pkger.service`",
+                    unit
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the names of the unit files without their directories.
+    pub fn unit_names(&self) -> Vec<&str> {
+        self.units.iter().map(|unit| unit_name(unit)).collect()
+    }
+
+    fn units_arg(&self) -> String {
+        self.unit_names().join(" ")
+    }
+
+    /// Commands of `postinst` that reload systemd, enable and start the units on installation
+    /// and restart them on upgrade, same as the ones added by `dh_installsystemd`.
+    pub fn deb_postinst(&self) -> String {
+        let units = self.units_arg();
+        let mut script = String::from(
+            "if [ \"$1\" = \"configure\" ] || [ \"$1\" = \"abort-upgrade\" ]; then\n    if [ -d /run/systemd/system ]; then\n        systemctl --system daemon-reload >/dev/null || true\n    fi\n",
+        );
+        if self.enable {
+            script.push_str(&format!(
+                "    if [ -z \"$2\" ]; then\n        systemctl enable {} >/dev/null 2>&1 || true\n    fi\n",
+                units
+            ));
+        }
+        if self.start {
+            script.push_str(&format!(
+                "    if [ -d /run/systemd/system ]; then\n        if [ -z \"$2\" ]; then\n            systemctl start {0} >/dev/null || true\n        else\n            systemctl try-restart {0} >/dev/null || true\n        fi\n    fi\n",
+                units
+            ));
+        }
+        script.push_str("fi\n");
+        script
+    }
+
+    /// Commands of `prerm` that stop and disable the units when the package is removed.
+    pub fn deb_prerm(&self) -> String {
+        let units = self.units_arg();
+        let mut script = format!(
+            "if [ \"$1\" = \"remove\" ]; then\n    if [ -d /run/systemd/system ]; then\n        systemctl stop {} >/dev/null || true\n    fi\n",
+            units
+        );
+        if self.enable {
+            script.push_str(&format!(
+                "    systemctl disable {} >/dev/null 2>&1 || true\n",
+                units
+            ));
+        }
+        script.push_str("fi\n");
+        script
+    }
+
+    /// Commands of `postrm` that reload systemd once the unit files are removed.
+    pub fn deb_postrm(&self) -> String {
+        "if [ -d /run/systemd/system ]; then\n    systemctl --system daemon-reload >/dev/null || true\nfi\n"
+            .to_string()
+    }
+
+    /// Commands of `%post` equivalent to `%systemd_post` that enable and start the units on
+    /// installation.
+    pub fn rpm_post(&self) -> String {
+        let units = self.units_arg();
+        let mut script = String::from(
+            "if [ -d /run/systemd/system ]; then\n    systemctl daemon-reload >/dev/null 2>&1 || :\nfi\n",
+        );
+        if self.enable || self.start {
+            script.push_str("if [ $1 -eq 1 ]; then\n");
+            if self.enable {
+                script.push_str(&format!(
+                    "    systemctl enable {} >/dev/null 2>&1 || :\n",
+                    units
+                ));
+            }
+            if self.start {
+                script.push_str(&format!(
+                    "    if [ -d /run/systemd/system ]; then\n        systemctl start {} >/dev/null 2>&1 || :\n    fi\n",
+                    units
+                ));
+            }
+            script.push_str("fi\n");
+        }
+        script
+    }
+
+    /// Commands of `%preun` equivalent to `%systemd_preun` that stop and disable the units when
+    /// the package is removed.
+    pub fn rpm_preun(&self) -> String {
+        let units = self.units_arg();
+        let mut script = format!(
+            "if [ $1 -eq 0 ]; then\n    if [ -d /run/systemd/system ]; then\n        systemctl stop {} >/dev/null 2>&1 || :\n    fi\n",
+            units
+        );
+        if self.enable {
+            script.push_str(&format!(
+                "    systemctl --no-reload disable {} >/dev/null 2>&1 || :\n",
+                units
+            ));
+        }
+        script.push_str("fi\n");
+        script
+    }
+
+    /// Commands of `%postun` equivalent to `%systemd_postun_with_restart` that reload systemd
+    /// and restart the units on upgrade.
+    pub fn rpm_postun(&self) -> String {
+        let mut script = String::from(
+            "if [ -d /run/systemd/system ]; then\n    systemctl daemon-reload >/dev/null 2>&1 || :\n",
+        );
+        if self.start {
+            script.push_str(&format!(
+                "    if [ $1 -ge 1 ]; then\n        systemctl try-restart {} >/dev/null 2>&1 || :\n    fi\n",
+                self.units_arg()
+            ));
+        }
+        script.push_str("fi\n");
+        script
+    }
+}
+
+/// Returns the directory where unit files are installed for `target`.
+pub fn unit_dir(target: &BuildTarget) -> &'static str {
+    match target {
+        BuildTarget::Deb => "/lib/systemd/system",
+        _ => "/usr/lib/systemd/system",
+    }
+}
+
+fn unit_name(unit: &str) -> &str {
+    Path::new(unit)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::scripts::{with_snippet, Placement};
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn renders_systemd_scriptlets() {
+        let systemd: Systemd =
+            serde_yaml::from_str("units: [contrib/pkger.service, pkger.timer]\nstart: false")
+                .unwrap();
+        systemd.validate().unwrap();
+        assert!(systemd.enable);
+        assert_eq!(systemd.unit_names(), vec!["pkger.service", "pkger.timer"]);
+        assert_eq!(
+            systemd.rpm_preun(),
+            "if [ $1 -eq 0 ]; then
+    if [ -d /run/systemd/system ]; then
+        systemctl stop pkger.service pkger.timer >/dev/null 2>&1 || :
+    fi
+    systemctl --no-reload disable pkger.service pkger.timer >/dev/null 2>&1 || :
+fi
+"
+        );
+        assert_eq!(
+            systemd.rpm_postun(),
+            "if [ -d /run/systemd/system ]; then
+    systemctl daemon-reload >/dev/null 2>&1 || :
+fi
+"
+        );
+        assert!(!systemd.deb_postinst().contains("systemctl start"));
+
+        assert_eq!(
+            with_snippet(
+                Some("#!/bin/sh\nrm -rf /var/cache/pkger\n"),
+                Some(systemd.deb_prerm()),
+                Placement::Before
+            )
+            .unwrap(),
+            "#!/bin/sh
+if [ \"$1\" = \"remove\" ]; then
+    if [ -d /run/systemd/system ]; then
+        systemctl stop pkger.service pkger.timer >/dev/null || true
+    fi
+    systemctl disable pkger.service pkger.timer >/dev/null 2>&1 || true
+fi
+rm -rf /var/cache/pkger
+"
+        );
+        assert_eq!(
+            with_snippet(
+                Some("#!/bin/sh\nuseradd pkger\nexit 0\n"),
+                Some(systemd.deb_postrm()),
+                Placement::After
+            )
+            .unwrap(),
+            "#!/bin/sh
+(
+useradd pkger
+exit 0
+) || exit $?
+if [ -d /run/systemd/system ]; then
+    systemctl --system daemon-reload >/dev/null || true
+fi
+"
+        );
+        assert_eq!(
+            with_snippet(Some("echo"), None, Placement::After).as_deref(),
+            Some("echo")
+        );
+        assert_eq!(with_snippet(None, None, Placement::After), None);
+
+        let invalid = |unit: &str| {
+            Systemd {
+                units: vec![unit.to_string()],
+                enable: true,
+                start: true,
+            }
+            .validate()
+            .is_err()
+        };
+        assert!(invalid("pkger"));
+        assert!(invalid("pkger.conf"));
+        assert!(invalid("pkger service.service"));
+        assert!(!invalid("getty@.service"));
+    }
+}
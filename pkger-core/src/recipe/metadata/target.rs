@@ -11,6 +11,10 @@ pub enum BuildTarget {
     Gzip,
     Pkg,
     Apk,
+    /// Experimental Windows installer built with msitools
+    Msi,
+    /// Void Linux package built with `xbps-create`
+    Xbps,
 }
 
 impl Default for BuildTarget {
@@ -29,6 +33,8 @@ impl TryFrom<&str> for BuildTarget {
             "gzip" => Ok(Self::Gzip),
             "pkg" => Ok(Self::Pkg),
             "apk" => Ok(Self::Apk),
+            "msi" => Ok(Self::Msi),
+            "xbps" => Ok(Self::Xbps),
             target => Err(anyhow!("unknown build target `{}`", target)),
         }
     }
@@ -42,6 +48,8 @@ impl AsRef<str> for BuildTarget {
             BuildTarget::Gzip => "gzip",
             BuildTarget::Pkg => "pkg",
             BuildTarget::Apk => "apk",
+            BuildTarget::Msi => "msi",
+            BuildTarget::Xbps => "xbps",
         }
     }
 }
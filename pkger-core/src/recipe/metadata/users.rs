@@ -0,0 +1,224 @@
+use crate::{err, Error, Result};
+
+use serde::{Deserialize, Serialize};
+
+/// Shell of system users that don't log in
+static NOLOGIN: &str = "/usr/sbin/nologin";
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+/// System user created when the package is installed
+pub struct SystemUser {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Primary group of the user. Defaults to a group with the name of the user
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Description of the user like `pkger daemon`
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Home directory of the user, defaults to `/`
+    pub home: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Login shell of the user, defaults to `/usr/sbin/nologin`
+    pub shell: Option<String>,
+}
+
+impl SystemUser {
+    /// Returns the primary group of the user.
+    pub fn group(&self) -> &str {
+        self.group.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+/// System group created when the package is installed
+pub struct SystemGroup {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+}
+
+/// Verifies that names of `users` and `groups` are valid names of system accounts and that
+/// their paths and descriptions can be used in shell commands and `sysusers.d` files.
+pub fn validate_accounts(users: &[SystemUser], groups: &[SystemGroup]) -> Result<()> {
+    let is_valid_name = |name: &str| {
+        !name.is_empty()
+            && name.len() <= 32
+            && !name.starts_with('-')
+            && !name.chars().next().unwrap_or_default().is_ascii_digit()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    let is_valid_path = |path: &str| {
+        path.starts_with('/')
+            && !path
+                .chars()
+                .any(|c| c.is_whitespace() || "'\"\\".contains(c))
+    };
+    for group in groups {
+        if !is_valid_name(&group.name) {
+            return err!("invalid name of system group `{}`", group.name);
+        }
+    }
+    for user in users {
+        if !is_valid_name(&user.name) || !is_valid_name(user.group()) {
+            return err!("invalid name or group of system user `{}`", user.name);
+        }
+        for path in user.home.iter().chain(user.shell.iter()) {
+            if !is_valid_path(path) {
+                return err!("invalid path `{}` of system user `{}`", path, user.name);
+            }
+        }
+        if let Some(description) = &user.description {
+            if description
+                .chars()
+                .any(|c| c.is_control() || "'\"\\:".contains(c))
+            {
+                return err!(
+                    "description of system user `{}` can't contain quotes, colons or control characters",
+                    user.name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the groups to create, the declared ones followed by primary groups of users that are
+/// not declared, each with its id if known.
+fn all_groups<'a>(
+    users: &'a [SystemUser],
+    groups: &'a [SystemGroup],
+) -> Vec<(&'a str, Option<u32>)> {
+    let mut all: Vec<_> = groups
+        .iter()
+        .map(|group| (group.name.as_str(), group.gid))
+        .collect();
+    for user in users {
+        if !all.iter().any(|(name, _)| *name == user.group()) {
+            all.push((user.group(), None));
+        }
+    }
+    all
+}
+
+/// Returns the shell commands that create `groups` and `users` that don't exist yet. The commands
+/// are used both in install scripts of packages and in build containers.
+pub fn accounts_script(users: &[SystemUser], groups: &[SystemGroup]) -> String {
+    let mut script = String::new();
+    for (name, gid) in all_groups(users, groups) {
+        let gid = gid.map(|gid| format!(" -g {}", gid)).unwrap_or_default();
+        script.push_str(&format!(
+            "getent group {0} >/dev/null || groupadd -r{1} {0}\n",
+            name, gid
+        ));
+    }
+    for user in users {
+        let mut opts = format!(
+            "-r -M -g {} -d {} -s {}",
+            user.group(),
+            user.home.as_deref().unwrap_or("/"),
+            user.shell.as_deref().unwrap_or(NOLOGIN)
+        );
+        if let Some(uid) = user.uid {
+            opts.push_str(&format!(" -u {}", uid));
+        }
+        if let Some(description) = &user.description {
+            opts.push_str(&format!(" -c '{}'", description));
+        }
+        script.push_str(&format!(
+            "getent passwd {0} >/dev/null || useradd {1} {0}\n",
+            user.name, opts
+        ));
+    }
+    script
+}
+
+/// Renders a `sysusers.d` configuration creating `groups` and `users`.
+pub fn sysusers_conf(users: &[SystemUser], groups: &[SystemGroup]) -> String {
+    let mut conf = String::new();
+    for (name, gid) in all_groups(users, groups) {
+        let gid = gid.map(|gid| gid.to_string()).unwrap_or_else(|| "-".into());
+        conf.push_str(&format!("g {} {}\n", name, gid));
+    }
+    for user in users {
+        let uid = user
+            .uid
+            .map(|uid| uid.to_string())
+            .unwrap_or_else(|| "-".into());
+        conf.push_str(&format!(
+            "u {} {}:{} \"{}\" {} {}\n",
+            user.name,
+            uid,
+            user.group(),
+            user.description.as_deref().unwrap_or(""),
+            user.home.as_deref().unwrap_or("/"),
+            user.shell.as_deref().unwrap_or(NOLOGIN)
+        ));
+    }
+    conf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn renders_system_accounts() {
+        let users: Vec<SystemUser> = serde_yaml::from_str(
+            r#"
+- name: pkger
+  description: pkger daemon
+  home: /var/lib/pkger
+- name: pkger-worker
+  group: pkger-jobs
+  uid: 990
+"#,
+        )
+        .unwrap();
+        let groups: Vec<SystemGroup> =
+            serde_yaml::from_str("- name: pkger-jobs\n  gid: 991\n").unwrap();
+        validate_accounts(&users, &groups).unwrap();
+
+        assert_eq!(
+            accounts_script(&users, &groups),
+            "getent group pkger-jobs >/dev/null || groupadd -r -g 991 pkger-jobs
+getent group pkger >/dev/null || groupadd -r pkger
+getent passwd pkger >/dev/null || useradd -r -M -g pkger -d /var/lib/pkger -s /usr/sbin/nologin -c 'pkger daemon' pkger
+getent passwd pkger-worker >/dev/null || useradd -r -M -g pkger-jobs -d / -s /usr/sbin/nologin -u 990 pkger-worker
+"
+        );
+        assert_eq!(
+            sysusers_conf(&users, &groups),
+            "g pkger-jobs 991
+g pkger -
+u pkger -:pkger \"pkger daemon\" /var/lib/pkger /usr/sbin/nologin
+u pkger-worker 990:pkger-jobs \"\" / /usr/sbin/nologin
+"
+        );
+
+        let user = |name: &str, home: Option<&str>, description: Option<&str>| SystemUser {
+            name: name.to_string(),
+            group: None,
+            uid: None,
+            description: description.map(String::from),
+            home: home.map(String::from),
+            shell: None,
+        };
+        let invalid = |user: SystemUser| validate_accounts(&[user], &[]).is_err();
+        assert!(invalid(user("1pkger", None, None)));
+        assert!(invalid(user("pkger;rm", None, None)));
+        assert!(invalid(user("pkger", Some("var/lib/pkger"), None)));
+        assert!(invalid(user("pkger", Some("/var/lib/pkger dir"), None)));
+        assert!(invalid(user("pkger", None, Some("pkger's daemon"))));
+        assert!(!invalid(user(
+            "pkger",
+            Some("/var/lib/pkger"),
+            Some("pkger")
+        )));
+    }
+}
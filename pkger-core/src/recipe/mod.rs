@@ -1,31 +1,78 @@
 mod cmd;
 mod envs;
+mod include;
 mod metadata;
+mod schema;
 
 pub use cmd::Command;
 pub use envs::Env;
 pub use metadata::{
-    deserialize_images, BuildArch, BuildTarget, DebInfo, DebRep, Dependencies, Distro, GitSource,
-    ImageTarget, Metadata, MetadataRep, Os, PackageManager, Patch, Patches, PkgInfo, PkgRep,
-    RpmInfo, RpmRep,
+    deserialize_images, pkger_recipe, validate_prefix, Alternative, ArchiveKind, Binaries,
+    BuildArch, BuildTarget, ChangelogDate, ChangelogEntry, Completions, DebChangelog, DebInfo,
+    DebRep, Dependencies, Distro, EbuildInfo, EbuildRep, FileAttributes, GitSource, GzipInfo,
+    GzipRep, ImageTarget, Metadata, MetadataRep, Os, PackageManager, Patch, Patchelf, Patches,
+    PeerSource, PkgInfo, PkgRep, RpathPolicy, RpmFiles, RpmInfo, RpmRep, Scriptlet, Source,
+    Subpackage, SubpackageRep, Symlink, SystemGroup, SystemUser, Systemd,
 };
+pub use schema::SCHEMA_VERSION;
 
+use crate::build::info::BuildInfo;
+use crate::render;
+use crate::template;
 use crate::{err, Error, Result};
 
 use anyhow::Context;
 use apkbuild::ApkBuild;
 use deb_control::{binary::BinaryDebControl, DebControlBuilder};
+use ebuild::Ebuild;
 use pkgbuild::PkgBuild;
 use rpmspec::RpmSpec;
 use serde::{Deserialize, Serialize};
-use serde_yaml::Mapping;
+use serde_yaml::{Mapping, Value as YamlValue};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fs::{self, DirEntry};
+use std::io;
 use std::path::Path;
 use std::path::PathBuf;
 use tracing::{info_span, trace, warn};
 
 const DEFAULT_RECIPE_FILE: &str = "recipe.yml";
+/// Maximum length of the name of a recipe.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Verifies that `name` is a valid name of a recipe. Allowed characters are ASCII letters, digits,
+/// `-`, `_`, `.` and `+` and the name has to start with a letter or a digit.
+pub fn validate_name(name: &str) -> Result<()> {
+    let is_valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+');
+
+    if name.is_empty() {
+        return err!("recipe name can't be empty");
+    }
+    if name.len() > MAX_NAME_LEN {
+        return err!(
+            "recipe name `{}` is too long, the maximum length is {} characters",
+            name,
+            MAX_NAME_LEN
+        );
+    }
+    if !name.starts_with(|c: char| c.is_ascii_alphanumeric()) || !name.chars().all(is_valid_char) {
+        let suggestion: String = name
+            .trim()
+            .chars()
+            .map(|c| if is_valid_char(c) { c } else { '-' })
+            .skip_while(|c| !c.is_ascii_alphanumeric())
+            .collect();
+        return err!(
+            "invalid recipe name `{}` - only ASCII letters, digits, `-`, `_`, `.` and `+` are allowed and the name has to start with a letter or a digit, for example `{}`",
+            name,
+            suggestion
+        );
+    }
+
+    Ok(())
+}
 
 #[derive(Clone, Deserialize, Serialize, Debug, Eq, PartialEq, Hash)]
 pub struct RecipeTarget {
@@ -53,11 +100,16 @@ impl RecipeTarget {
     pub fn image_os(&self) -> &Option<Os> {
         &self.image_target.os
     }
+
+    pub fn image_target(&self) -> &ImageTarget {
+        &self.image_target
+    }
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Loader {
     path: PathBuf,
+    strict: bool,
 }
 
 impl Loader {
@@ -73,9 +125,16 @@ impl Loader {
 
         Ok(Loader {
             path: path.to_path_buf(),
+            strict: false,
         })
     }
 
+    /// Makes `load_all` fail on recipes that can't be read instead of skipping them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
     pub fn load(&self, recipe: &str) -> Result<Recipe> {
         let base_path = self.path.join(recipe);
         let mut path = base_path.join("recipe.yml");
@@ -119,11 +178,17 @@ impl Loader {
                             trace!(recipe = ?recipe);
                             recipes.push(recipe);
                         }
+                        Err(e) if self.strict => {
+                            return Err(e.context(format!("failed to read recipe `{}`", filename)))
+                        }
                         Err(e) => {
                             warn!(recipe = %filename, reason = %format!("{:?}", e), "failed to read recipe")
                         }
                     }
                 }
+                Err(e) if self.strict => {
+                    return Err(Error::from(e).context("invalid entry in recipes directory"))
+                }
                 Err(e) => warn!(reason = %format!("{:?}", e), "invalid entry"),
             }
         }
@@ -136,17 +201,58 @@ impl Loader {
 pub struct Recipe {
     pub metadata: Metadata,
     pub env: Env,
+    /// Names of environment variables of the host forwarded to the container, a name ending with
+    /// `*` matches all variables with the prefix
+    pub pass_env: Vec<String>,
     pub configure_script: Option<ConfigureScript>,
     pub build_script: BuildScript,
     pub install_script: Option<InstallScript>,
+    /// Shell used by all phases that don't set their own
+    pub shell: Option<String>,
     pub recipe_dir: PathBuf,
+    /// Paths of all files included in this recipe
+    pub includes: Vec<PathBuf>,
+    /// Hash of the content of this recipe, included files and other files in the recipe directory
+    /// like patches
+    pub hash: String,
+    /// Same as `hash` but without the scripts of the recipe, changes when the inputs of the steps
+    /// like the source, patches or environment change
+    pub inputs_hash: String,
 }
 
 impl Recipe {
     pub fn new(rep: RecipeRep, recipe_dir: PathBuf) -> Result<Self> {
+        Self::with_hashed_dir(rep, recipe_dir, true)
+    }
+
+    /// Creates a recipe from the content of a single recipe file outside of the recipes
+    /// directory, like one generated from a template in CI. Includes, local sources and patches
+    /// are relative to `dir`. Unlike with `new` only the recipe and the files it references are
+    /// hashed as `dir` may contain unrelated files.
+    pub fn from_standalone(data: &[u8], dir: PathBuf) -> Result<Self> {
+        let rep = RecipeRep::load_bytes(data, &dir)?;
+        Self::with_hashed_dir(rep, dir, false)
+    }
+
+    /// Returns the local files used by this recipe, its includes, patches and local sources.
+    /// Remote and templated locations are skipped.
+    pub fn referenced_files(&self) -> Vec<PathBuf> {
+        referenced_files(&self.metadata, &self.includes, &self.recipe_dir)
+    }
+
+    fn with_hashed_dir(rep: RecipeRep, recipe_dir: PathBuf, hash_dir: bool) -> Result<Self> {
+        validate_name(&rep.metadata.name)?;
+        let metadata = Metadata::try_from(rep.metadata.clone())?;
+        let (hash, inputs_hash) = if hash_dir {
+            content_hashes(&rep, Some(&recipe_dir), &[])?
+        } else {
+            let files = referenced_files(&metadata, &rep.include, &recipe_dir);
+            content_hashes(&rep, None, &files)?
+        };
         Ok(Self {
-            metadata: Metadata::try_from(rep.metadata)?,
+            metadata,
             env: Env::from(rep.env),
+            pass_env: rep.pass_env,
             configure_script: if let Some(script) = rep.configure {
                 Some(ConfigureScript::try_from(script)?)
             } else {
@@ -158,18 +264,185 @@ impl Recipe {
             } else {
                 None
             },
+            shell: rep.shell,
             recipe_dir,
+            includes: rep.include,
+            hash,
+            inputs_hash,
         })
     }
 
+    /// Overrides a setting of this recipe. `prefix` overrides the installation prefix, any other
+    /// `key` sets an environment variable of the recipe. The hash of the recipe is updated so
+    /// that builds with different overrides aren't considered the same.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        if key == "prefix" {
+            self.metadata.prefix = validate_prefix(value)?;
+        } else {
+            self.env.insert(key, value);
+        }
+
+        self.update_hash(&format!("{}={}", key, value));
+        Ok(())
+    }
+
+    /// Appends the `suffix` to the version of the recipe, for example `~git20240501` to mark a
+    /// snapshot build.
+    pub fn append_version_suffix(&mut self, suffix: &str) -> Result<()> {
+        validate_version_part("version suffix", suffix)?;
+        self.metadata.version.push_str(suffix);
+        self.update_hash(&format!("version_suffix={}", suffix));
+        Ok(())
+    }
+
+    /// Overrides the release number of the recipe.
+    pub fn set_release(&mut self, release: &str) -> Result<()> {
+        validate_version_part("release", release)?;
+        self.metadata.release = Some(release.to_string());
+        self.update_hash(&format!("release={}", release));
+        Ok(())
+    }
+
+    /// Mixes an override of the recipe into its hash so that builds with different overrides are
+    /// not considered up to date with each other.
+    fn update_hash(&mut self, value: &str) {
+        for hash in [&mut self.hash, &mut self.inputs_hash] {
+            let mut hasher = Sha256::new();
+            hasher.update(&*hash);
+            hasher.update(value);
+            *hash = format!("{:x}", hasher.finalize());
+        }
+    }
+
     #[inline]
     pub fn images(&self) -> &[String] {
         &self.metadata.images
     }
 }
 
+/// Verifies that the `value` of a part of the package version doesn't break the naming schemes
+/// of packages that use `-` to separate the version from the release.
+fn validate_version_part(what: &str, value: &str) -> Result<()> {
+    if value.is_empty() || value.contains(|c: char| c == '-' || c.is_whitespace()) {
+        return err!(
+            "{} `{}` can't be empty or contain `-` or whitespace",
+            what,
+            value
+        );
+    }
+    Ok(())
+}
+
+/// Returns the local files used by the recipe, its includes, patches and local sources, resolved
+/// from `recipe_dir`. Remote and templated locations are skipped.
+fn referenced_files(metadata: &Metadata, includes: &[PathBuf], recipe_dir: &Path) -> Vec<PathBuf> {
+    let patches = metadata
+        .patches
+        .iter()
+        .flat_map(|patches| patches.inner().values().flatten())
+        .map(Patch::patch);
+    let sources = metadata
+        .source
+        .iter()
+        .map(Source::source)
+        .filter(|source| PeerSource::parse(source).is_none() && !source.contains("{{"));
+    let mut files = patches
+        .chain(sources)
+        .filter(|location| !location.starts_with("http"))
+        .map(|location| recipe_dir.join(location))
+        .chain(includes.iter().map(|include| recipe_dir.join(include)))
+        .collect::<Vec<_>>();
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// Computes a hash of the recipe with all files merged into it and, if given, of other files in
+/// the `recipe_dir` like patches and of the referenced `files`, followed by the same hash of the
+/// recipe without its scripts. Content is used instead of modification times so that the hashes
+/// change only when the recipe actually does. The files are read once for both hashes, they are
+/// streamed through the hasher and symlinked directories are hashed by their target instead of
+/// being followed.
+fn content_hashes(
+    rep: &RecipeRep,
+    recipe_dir: Option<&Path>,
+    files: &[PathBuf],
+) -> Result<(String, String)> {
+    fn hash_dir(hasher: &mut Sha256, base: &Path, dir: &Path) -> Result<()> {
+        let mut entries = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        for path in entries {
+            let name = path.strip_prefix(base).unwrap_or(&path);
+            let file_type = fs::symlink_metadata(&path)?.file_type();
+            if file_type.is_dir() {
+                hash_dir(hasher, base, &path)?;
+            } else if file_type.is_symlink() && path.is_dir() {
+                hasher.update(name.to_string_lossy().as_bytes());
+                hasher.update(fs::read_link(&path)?.to_string_lossy().as_bytes());
+            } else if name != Path::new(DEFAULT_RECIPE_FILE) && name != Path::new("recipe.yaml") {
+                hasher.update(name.to_string_lossy().as_bytes());
+                io::copy(&mut fs::File::open(&path)?, hasher)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut dir_hasher = Sha256::new();
+    if let Some(recipe_dir) = recipe_dir.filter(|dir| dir.is_dir()) {
+        hash_dir(&mut dir_hasher, recipe_dir, recipe_dir)
+            .context("failed to hash files of the recipe directory")?;
+    }
+    // the locations of the files are part of the recipe so only their content is hashed, missing
+    // files are skipped as fetching them fails the build anyway
+    for file in files {
+        if file.is_dir() {
+            hash_dir(&mut dir_hasher, file, file)
+                .context(format!("failed to hash directory `{}`", file.display()))?;
+        } else if file.is_file() {
+            io::copy(&mut fs::File::open(file)?, &mut dir_hasher)
+                .context(format!("failed to hash file `{}`", file.display()))?;
+        }
+    }
+    let dir_hash = dir_hasher.finalize();
+
+    let hash = |rep: &RecipeRep| -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_yaml::to_string(rep).context("failed to serialize recipe")?);
+        hasher.update(dir_hash);
+        Ok(format!("{:x}", hasher.finalize()))
+    };
+    let inputs = RecipeRep {
+        configure: None,
+        build: BuildRep::default(),
+        install: None,
+        shell: None,
+        ..rep.clone()
+    };
+    Ok((hash(rep)?, hash(&inputs)?))
+}
+
 impl Recipe {
-    pub fn as_deb_control(&self, image: &str, installed_size: Option<&str>) -> BinaryDebControl {
+    /// Renders the packaging metadata of the `target`, like the spec file of RPM or the control
+    /// file of DEB, as generated for the `image` but without the sources and files that are only
+    /// known during the build. Used to preview how the metadata changes between builds.
+    pub fn preview_packaging(&self, target: BuildTarget, image: &str) -> Option<String> {
+        render::packaging_files(self, target, image)
+            .ok()?
+            .into_iter()
+            .next()
+            .map(|file| file.content)
+    }
+
+    pub fn as_deb_control(
+        &self,
+        image: &str,
+        installed_size: Option<&str>,
+        build_info: &BuildInfo,
+    ) -> BinaryDebControl {
         let name = if self.metadata.name.contains('_') {
             warn!("Debian package names can't contain `_`, converting to `-`");
             self.metadata.name.replace('_', "-")
@@ -198,6 +471,29 @@ impl Recipe {
         if let Some(provides) = &self.metadata.provides {
             builder = builder.add_provides_entries(provides.resolve_names(image));
         }
+        if let Some(replaces) = &self.metadata.replaces {
+            builder = builder.add_replaces_entries(replaces.resolve_names(image));
+        }
+        if !self.metadata.renamed_from.is_empty() {
+            let version = self.metadata.full_version();
+            let renamed_from = &self.metadata.renamed_from;
+            builder = builder
+                .add_provides_entries(
+                    renamed_from
+                        .iter()
+                        .map(|old| format!("{} (= {})", old, version)),
+                )
+                .add_replaces_entries(
+                    renamed_from
+                        .iter()
+                        .map(|old| format!("{} (<< {})", old, version)),
+                )
+                .add_breaks_entries(
+                    renamed_from
+                        .iter()
+                        .map(|old| format!("{} (<< {})", old, version)),
+                );
+        }
         if let Some(maintainer) = &self.metadata.maintainer {
             builder = builder.maintainer(maintainer);
         }
@@ -237,12 +533,184 @@ impl Recipe {
             if let Some(enchances) = &deb.enhances {
                 builder = builder.add_enchances_entries(enchances.resolve_names(image));
             }
+
+            for (name, value) in &deb.fields {
+                let entries = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty());
+                builder = match name.to_ascii_lowercase().as_str() {
+                    "pre-depends" => builder.add_pre_depends_entries(entries),
+                    "depends" => builder.add_depends_entries(entries),
+                    "recommends" => builder.add_recommends_entries(entries),
+                    "suggests" => builder.add_suggests_entries(entries),
+                    "breaks" => builder.add_breaks_entries(entries),
+                    "conflicts" => builder.add_conflicts_entries(entries),
+                    "provides" => builder.add_provides_entries(entries),
+                    "replaces" => builder.add_replaces_entries(entries),
+                    "enhances" => builder.add_enchances_entries(entries),
+                    // continuation lines of multiline values start with a space and empty lines
+                    // are marked with a dot
+                    _ => builder.add_user_defined_fields_entries(vec![format!(
+                        "{}: {}",
+                        name,
+                        value
+                            .trim_end()
+                            .lines()
+                            .map(|line| if line.trim().is_empty() { "." } else { line })
+                            .collect::<Vec<_>>()
+                            .join("\n ")
+                    )]),
+                };
+            }
+        }
+        if self.metadata.build_info {
+            builder = builder.add_user_defined_fields_entries(build_info.deb_fields());
+        }
+
+        builder.build()
+    }
+
+    /// Renders the Debian changelog of the package from the `entries`, which default to the
+    /// `changelog` of the recipe. Returns `None` if there are no entries.
+    pub fn render_deb_changelog(&self, entries: Option<&[ChangelogEntry]>) -> Option<String> {
+        let entries = entries.unwrap_or(&self.metadata.changelog);
+        if entries.is_empty() {
+            return None;
+        }
+        let settings = self
+            .metadata
+            .deb
+            .as_ref()
+            .and_then(|deb| deb.changelog.clone())
+            .unwrap_or_default();
+        let name = self.metadata.name.replace('_', "-");
+        let version = self.metadata.full_version();
+        let author = self.metadata.maintainer.as_deref().unwrap_or_default();
+        Some(
+            entries
+                .iter()
+                .map(|entry| {
+                    entry.render_deb(
+                        &name,
+                        &version,
+                        author,
+                        settings.distribution(),
+                        settings.urgency(),
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+
+    /// Returns the control file of the `-dbgsym` package containing the debug information of
+    /// this package.
+    pub fn as_deb_dbgsym_control(&self) -> BinaryDebControl {
+        let name = self.metadata.name.replace('_', "-");
+        let mut builder = DebControlBuilder::binary_package_builder(format!("{}-dbgsym", name))
+            .version(&self.metadata.version)
+            .revision(self.metadata.release())
+            .description(format!("debug symbols for {}", name))
+            .architecture(self.metadata.arch.deb_name())
+            .source(&name)
+            .section("debug")
+            .priority("optional")
+            .add_depends_entries(vec![format!(
+                "{} (= {})",
+                name,
+                self.metadata.full_version()
+            )]);
+
+        if let Some(epoch) = &self.metadata.epoch {
+            builder = builder.epoch(epoch);
+        }
+        if let Some(maintainer) = &self.metadata.maintainer {
+            builder = builder.maintainer(maintainer);
         }
 
         builder.build()
     }
 
-    pub fn as_rpm_spec(&self, sources: &[String], files: &[String], image: &str) -> RpmSpec {
+    /// Returns the control file of the `subpackage` built for the `image`.
+    pub fn as_deb_subpackage_control(
+        &self,
+        subpackage: &Subpackage,
+        image: &str,
+        installed_size: Option<&str>,
+    ) -> BinaryDebControl {
+        let mut builder =
+            DebControlBuilder::binary_package_builder(subpackage.name.replace('_', "-"))
+                .version(&self.metadata.version)
+                .revision(self.metadata.release())
+                .description(
+                    subpackage
+                        .description
+                        .as_deref()
+                        .unwrap_or(&self.metadata.description),
+                )
+                .architecture(self.metadata.arch.deb_name())
+                .source(self.metadata.name.replace('_', "-"));
+
+        if let Some(epoch) = &self.metadata.epoch {
+            builder = builder.epoch(epoch);
+        }
+        if let Some(group) = &self.metadata.group {
+            builder = builder.section(group);
+        }
+        builder = builder.add_depends_entries(vec![format!(
+            "{} (= {})",
+            self.metadata.name.replace('_', "-"),
+            self.metadata.full_version()
+        )]);
+        if let Some(depends) = &subpackage.depends {
+            builder = builder.add_depends_entries(depends.resolve_names(image));
+        }
+        if let Some(maintainer) = &self.metadata.maintainer {
+            builder = builder.maintainer(maintainer);
+        }
+        if let Some(homepage) = &self.metadata.url {
+            builder = builder.homepage(homepage);
+        }
+        if let Some(installed_size) = installed_size {
+            builder = builder.installed_size(installed_size)
+        }
+
+        builder.build()
+    }
+
+    pub fn as_rpm_spec(
+        &self,
+        sources: &[String],
+        files: &[String],
+        image: &str,
+        build_info: &BuildInfo,
+    ) -> RpmSpec {
+        let doc_dir = format!("{}/", self.metadata.doc_dir());
+        let license_dir = format!("{}/", self.metadata.license_dir());
+        let attribute = |file: &String| {
+            let attribute = self
+                .metadata
+                .rpm
+                .as_ref()
+                .and_then(|rpm| rpm.files.attribute(file));
+            if attribute.is_some() {
+                attribute
+            } else if file.starts_with(&license_dir) {
+                Some("license")
+            } else if file.starts_with(&doc_dir) {
+                Some("doc")
+            } else {
+                None
+            }
+        };
+        let with_attribute = |attr: Option<&'static str>| {
+            files
+                .iter()
+                .filter(move |file| attribute(file) == attr)
+                .collect::<Vec<_>>()
+        };
+
         let install_script = sources
             .iter()
             .enumerate()
@@ -258,11 +726,20 @@ impl Recipe {
             .license(&self.metadata.license)
             .version(&self.metadata.version)
             .release(self.metadata.release())
-            .add_files_entries(files)
+            .add_files_entries(with_attribute(None))
+            .add_doc_files_entries(with_attribute(Some("doc")))
+            .add_license_files_entries(with_attribute(Some("license")))
+            .add_config_files_entries(with_attribute(Some("config")))
+            .add_config_noreplace_files_entries(with_attribute(Some("config(noreplace)")))
             .add_sources_entries(sources)
             .add_macro("__os_install_post", None::<&str>, "%{nil}") // disable binary stripping
             .install_script(&install_script)
             .description(&self.metadata.description);
+        for file in files {
+            if let Some(attr) = self.metadata.rpm_file_attr(file) {
+                builder = builder.add_file_attr(file, attr);
+            }
+        }
 
         if let Some(rpm) = &self.metadata.rpm {
             if let Some(obsoletes) = &rpm.obsoletes {
@@ -279,20 +756,8 @@ impl Recipe {
                 builder = builder.disable_auto_req_prov();
             }
 
-            if let Some(pre_script) = &rpm.pre_script {
-                builder = builder.pre_script(pre_script);
-            }
-            if let Some(post_script) = &rpm.post_script {
-                builder = builder.post_script(post_script);
-            }
-            if let Some(preun_script) = &rpm.preun_script {
-                builder = builder.preun_script(preun_script);
-            }
-            if let Some(post_script) = &rpm.post_script {
-                builder = builder.post_script(post_script);
-            }
-            if let Some(config_noreplace) = &rpm.config_noreplace {
-                builder = builder.config_noreplace(config_noreplace);
+            if let Some(posttrans_script) = &rpm.posttrans_script {
+                builder = builder.posttrans_script(posttrans_script);
             }
             if let Some(summary) = &rpm.summary {
                 builder = builder.summary(summary);
@@ -300,6 +765,31 @@ impl Recipe {
                 builder = builder.summary(&self.metadata.description);
             }
         }
+        let rpm = self.metadata.rpm.as_ref();
+        if let Some(pre_script) = self.metadata.install_script(
+            Scriptlet::RpmPre,
+            rpm.and_then(|rpm| rpm.pre_script.as_ref()),
+        ) {
+            builder = builder.pre_script(&pre_script);
+        }
+        if let Some(post_script) = self.metadata.install_script(
+            Scriptlet::RpmPost,
+            rpm.and_then(|rpm| rpm.post_script.as_ref()),
+        ) {
+            builder = builder.post_script(&post_script);
+        }
+        if let Some(preun_script) = self.metadata.install_script(
+            Scriptlet::RpmPreun,
+            rpm.and_then(|rpm| rpm.preun_script.as_ref()),
+        ) {
+            builder = builder.preun_script(&preun_script);
+        }
+        if let Some(postun_script) = self.metadata.install_script(
+            Scriptlet::RpmPostun,
+            rpm.and_then(|rpm| rpm.postun_script.as_ref()),
+        ) {
+            builder = builder.postun_script(&postun_script);
+        }
         if let Some(group) = &self.metadata.group {
             builder = builder.group(group);
         }
@@ -318,9 +808,233 @@ impl Recipe {
         if let Some(provides) = &self.metadata.provides {
             builder = builder.add_provides_entries(provides.resolve_names(image));
         }
+        if let Some(obsoletes) = &self.metadata.replaces {
+            builder = builder.add_obsoletes_entries(obsoletes.resolve_names(image));
+        }
+        if !self.metadata.renamed_from.is_empty() {
+            let version = self.metadata.full_version();
+            let renamed_from = &self.metadata.renamed_from;
+            builder = builder
+                .add_provides_entries(
+                    renamed_from
+                        .iter()
+                        .map(|old| format!("{} = {}", old, version)),
+                )
+                .add_obsoletes_entries(
+                    renamed_from
+                        .iter()
+                        .map(|old| format!("{} < {}", old, version)),
+                );
+        }
         if let Some(requires) = &self.metadata.depends {
             builder = builder.add_requires_entries(requires.resolve_names(image));
         }
+        if self.metadata.has_accounts() {
+            // `%pre` creates the accounts with `groupadd` and `useradd`
+            builder = builder.add_requires_pre_entries(vec!["shadow-utils"]);
+        }
+        if !self.metadata.alternatives.is_empty() {
+            // `%post` and `%preun` register and unregister the alternatives
+            builder = builder
+                .add_requires_post_entries(vec!["alternatives"])
+                .add_requires_preun_entries(vec!["alternatives"]);
+        }
+        if self.metadata.build_info {
+            if let Some(vcs) = build_info.rpm_vcs() {
+                builder = builder.vcs(vcs);
+            }
+        }
+        builder = builder.add_changelog_entries(self.rpm_changelog());
+
+        builder.build()
+    }
+
+    /// Returns the entries of the `%changelog` section rendered from the `changelog` of the
+    /// recipe. Returns no entries if the recipe has a `changelog_file` that replaces them.
+    fn rpm_changelog(&self) -> Vec<String> {
+        if matches!(&self.metadata.rpm, Some(rpm) if rpm.changelog_file.is_some()) {
+            return vec![];
+        }
+        let version = self.metadata.full_version();
+        let author = self.metadata.maintainer.as_deref().unwrap_or_default();
+        self.metadata
+            .changelog
+            .iter()
+            .map(|entry| entry.render_rpm(&version, author))
+            .collect()
+    }
+
+    /// Reads the `changelog_file` of the recipe if it has one.
+    fn read_rpm_changelog_file(&self) -> Result<Option<String>> {
+        match self
+            .metadata
+            .rpm
+            .as_ref()
+            .and_then(|rpm| rpm.changelog_file.as_ref())
+        {
+            Some(file) => {
+                let file = self.recipe_dir.join(file);
+                fs::read_to_string(&file)
+                    .context(format!(
+                        "failed to read changelog file `{}`",
+                        file.display()
+                    ))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Renders the spec file of the package. When the recipe has a `spec_template` the template is
+    /// rendered with `vars` and variables describing the package like `RPM_FILES` instead of
+    /// generating the spec.
+    pub fn render_rpm_spec(
+        &self,
+        sources: &[String],
+        files: &[String],
+        image: &str,
+        build_info: &BuildInfo,
+        vars: &Env,
+    ) -> Result<String> {
+        let spec = self.as_rpm_spec(sources, files, image, build_info);
+        let changelog_file = self.read_rpm_changelog_file()?;
+        let template = match self
+            .metadata
+            .rpm
+            .as_ref()
+            .and_then(|rpm| rpm.spec_template.as_ref())
+        {
+            Some(template) => self.recipe_dir.join(template),
+            None => {
+                let mut spec = spec.render();
+                // the `%changelog` section is always the last one of the generated spec
+                if let Some(changelog) = changelog_file {
+                    spec.push_str(&changelog);
+                }
+                return Ok(spec);
+            }
+        };
+        let text = fs::read_to_string(&template).context(format!(
+            "failed to read spec template `{}`",
+            template.display()
+        ))?;
+
+        let mut requires: Vec<_> = self
+            .metadata
+            .depends
+            .as_ref()
+            .map(|depends| depends.resolve_names(image).into_iter().collect())
+            .unwrap_or_default();
+        requires.sort_unstable();
+
+        let mut vars = vars.inner().clone();
+        for (key, value) in [
+            ("RECIPE", self.metadata.name.clone()),
+            ("RECIPE_VERSION", self.metadata.version.clone()),
+            ("RECIPE_RELEASE", self.metadata.release().to_string()),
+            ("RECIPE_DESCRIPTION", self.metadata.description.clone()),
+            ("RECIPE_LICENSE", self.metadata.license.clone()),
+            ("RPM_ARCH", self.metadata.arch.rpm_name().to_string()),
+            ("RPM_SOURCES", sources.join(" ")),
+            ("RPM_REQUIRES", requires.join(", ")),
+            ("RPM_FILES", spec.render_files()),
+            (
+                "RPM_CHANGELOG",
+                changelog_file.unwrap_or_else(|| self.rpm_changelog().join("\n")),
+            ),
+        ] {
+            vars.insert(key.to_string(), value);
+        }
+
+        Ok(template::render(text, &vars))
+    }
+
+    /// Returns the spec of the `-debuginfo` package with the debug information saved to
+    /// `debug_dir` inside of the container.
+    pub fn as_rpm_debuginfo_spec(&self, debug_dir: &str) -> RpmSpec {
+        let name = format!("{}-debuginfo", self.metadata.name);
+        let summary = format!("Debug information for package {}", self.metadata.name);
+        let mut builder = RpmSpec::builder()
+            .name(&name)
+            .build_arch(self.metadata.arch.rpm_name())
+            .summary(&summary)
+            .description(&summary)
+            .license(&self.metadata.license)
+            .version(&self.metadata.version)
+            .release(self.metadata.release())
+            .add_files_entries(vec!["/usr/lib/debug"])
+            .add_requires_entries(vec![format!(
+                "{} = {}",
+                self.metadata.name,
+                self.metadata.full_version()
+            )])
+            .add_macro("__os_install_post", None::<&str>, "%{nil}") // disable binary stripping
+            .disable_auto_req_prov()
+            .install_script(format!("cp -a {}/. %{{buildroot}}/\n", debug_dir));
+
+        if let Some(epoch) = &self.metadata.epoch {
+            builder = builder.epoch(epoch);
+        }
+        if let Some(maintainer) = &self.metadata.maintainer {
+            builder = builder.packager(maintainer);
+        }
+        if let Some(url) = &self.metadata.url {
+            builder = builder.url(url);
+        }
+
+        builder.build()
+    }
+
+    /// Returns the spec of the `subpackage` built for the `image` with the `files` saved to `dir`
+    /// inside of the container.
+    pub fn as_rpm_subpackage_spec(
+        &self,
+        subpackage: &Subpackage,
+        dir: &str,
+        files: &[String],
+        image: &str,
+    ) -> RpmSpec {
+        let description = subpackage
+            .description
+            .as_deref()
+            .unwrap_or(&self.metadata.description);
+        let mut builder = RpmSpec::builder()
+            .name(&subpackage.name)
+            .build_arch(self.metadata.arch.rpm_name())
+            .summary(description)
+            .description(description)
+            .license(&self.metadata.license)
+            .version(&self.metadata.version)
+            .release(self.metadata.release())
+            .add_files_entries(files)
+            .add_macro("__os_install_post", None::<&str>, "%{nil}") // disable binary stripping
+            .install_script(format!("cp -a {}/. %{{buildroot}}/\n", dir));
+        for file in files {
+            if let Some(attr) = self.metadata.rpm_file_attr(file) {
+                builder = builder.add_file_attr(file, attr);
+            }
+        }
+
+        if let Some(epoch) = &self.metadata.epoch {
+            builder = builder.epoch(epoch);
+        }
+        if let Some(group) = &self.metadata.group {
+            builder = builder.group(group);
+        }
+        if let Some(maintainer) = &self.metadata.maintainer {
+            builder = builder.packager(maintainer);
+        }
+        if let Some(url) = &self.metadata.url {
+            builder = builder.url(url);
+        }
+        builder = builder.add_requires_entries(vec![format!(
+            "{} = {}",
+            self.metadata.name,
+            self.metadata.full_version()
+        )]);
+        if let Some(requires) = &subpackage.depends {
+            builder = builder.add_requires_entries(requires.resolve_names(image));
+        }
 
         builder.build()
     }
@@ -333,7 +1047,7 @@ impl Recipe {
 
         let mut builder = PkgBuild::builder()
             .pkgname(&self.metadata.name)
-            .pkgver(&self.metadata.version)
+            .pkgver(self.metadata.pkg_version())
             .pkgdesc(&self.metadata.description)
             .add_license_entries(vec![&self.metadata.license])
             .add_arch_entries(vec![self.metadata.arch.pkg_name().to_string()])
@@ -356,6 +1070,41 @@ impl Recipe {
         if let Some(provides) = &self.metadata.provides {
             builder = builder.add_provides_entries(provides.resolve_names(image));
         }
+        if let Some(replaces) = &self.metadata.replaces {
+            builder = builder.add_replaces_entries(replaces.resolve_names(image));
+        }
+        if let Some(replaces) = self
+            .metadata
+            .pkg
+            .as_ref()
+            .and_then(|pkg| pkg.replaces.as_ref())
+        {
+            builder = builder.add_replaces_entries(replaces.resolve_names(image));
+        }
+        if !self.metadata.renamed_from.is_empty() {
+            let version = format!(
+                "{}-{}",
+                self.metadata.pkg_version(),
+                self.metadata.release()
+            );
+            let renamed_from = &self.metadata.renamed_from;
+            builder = builder
+                .add_provides_entries(
+                    renamed_from
+                        .iter()
+                        .map(|old| format!("{}={}", old, version)),
+                )
+                .add_conflicts_entries(renamed_from)
+                .add_replaces_entries(renamed_from);
+        }
+        if let Some(install) = self
+            .metadata
+            .pkg
+            .as_ref()
+            .and_then(|pkg| pkg.install_file(&self.metadata.name))
+        {
+            builder = builder.install(install);
+        }
 
         builder = builder.pkgrel(self.metadata.release());
 
@@ -373,7 +1122,7 @@ impl Recipe {
 
         let mut builder = ApkBuild::builder()
             .pkgname(&self.metadata.name)
-            .pkgver(&self.metadata.version)
+            .pkgver(self.metadata.apk_version())
             .pkgdesc(&self.metadata.description)
             .add_license_entries(vec![&self.metadata.license])
             .add_arch_entries(vec![self.metadata.arch.apk_name().to_string()])
@@ -388,29 +1137,269 @@ impl Recipe {
         if let Some(provides) = &self.metadata.provides {
             builder = builder.add_provides_entries(provides.resolve_names(image));
         }
+        if let Some(replaces) = &self.metadata.replaces {
+            builder = builder.add_replaces_entries(replaces.resolve_names(image));
+        }
+        if let Some(replaces) = self
+            .metadata
+            .apk
+            .as_ref()
+            .and_then(|apk| apk.replaces.as_ref())
+        {
+            builder = builder.add_replaces_entries(replaces.resolve_names(image));
+        }
+        if !self.metadata.renamed_from.is_empty() {
+            let version = format!(
+                "{}-r{}",
+                self.metadata.apk_version(),
+                self.metadata.release()
+            );
+            let renamed_from = &self.metadata.renamed_from;
+            builder = builder
+                .add_provides_entries(
+                    renamed_from
+                        .iter()
+                        .map(|old| format!("{}={}", old, version)),
+                )
+                .add_replaces_entries(renamed_from);
+        }
 
         builder = builder.pkgrel(self.metadata.release());
 
         builder.build()
     }
+
+    /// Returns the path of the ebuild in an overlay, `<category>/<name>/<name>-<version>.ebuild`.
+    /// Releases other than `0` are added as the revision of the ebuild.
+    pub fn ebuild_path(&self) -> PathBuf {
+        let metadata = &self.metadata;
+        let category = metadata
+            .ebuild
+            .as_ref()
+            .map(|ebuild| ebuild.category.as_str())
+            .unwrap_or("app-misc");
+        let revision = match metadata.release() {
+            "0" => String::new(),
+            release => format!("-r{}", release),
+        };
+        PathBuf::from(category).join(&metadata.name).join(format!(
+            "{}-{}{}.ebuild",
+            metadata.name, metadata.version, revision
+        ))
+    }
+
+    /// Renders the `steps` that run on the `image` as the body of an ebuild
+    /// function. Variables set by pkger are replaced with their ebuild counterparts.
+    fn ebuild_func(
+        steps: &[Command],
+        working_dir: Option<&str>,
+        image: &str,
+        vars: &HashMap<String, String>,
+    ) -> Option<String> {
+        let steps: Vec<_> = steps
+            .iter()
+            .filter(|step| {
+                if step.script().is_some() {
+                    warn!(step = %step.label(), "script steps are not supported in ebuilds, skipping");
+                    return false;
+                }
+                !step.has_target_specified()
+                    && step
+                        .images
+                        .as_ref()
+                        .map(|images| images.iter().any(|i| i == image))
+                        .unwrap_or(true)
+            })
+            .map(|step| template::render(step.cmd.trim(), vars))
+            .collect();
+        if steps.is_empty() {
+            return None;
+        }
+
+        let mut body = vec![];
+        if let Some(dir) = working_dir {
+            body.push(format!("\tcd \"{}\" || die", template::render(dir, vars)));
+        }
+        for step in steps {
+            if step.contains('\n') {
+                let lines: Vec<_> = step.lines().map(|line| format!("\t\t{}", line)).collect();
+                body.push(format!("\t(\n{}\n\t) || die", lines.join("\n")));
+            } else {
+                body.push(format!("\t{} || die", step));
+            }
+        }
+        Some(body.join("\n"))
+    }
+
+    /// Generates a Gentoo ebuild of this recipe. Dependencies are resolved for the `image`.
+    pub fn as_ebuild(&self, image: &str) -> Ebuild {
+        let metadata = &self.metadata;
+        let ebuild = metadata.ebuild.as_ref();
+
+        let mut vars: HashMap<String, String> = self
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        for (var, value) in [
+            ("PKGER_BLD_DIR", "${WORKDIR}"),
+            ("PKGER_OUT_DIR", "${D}"),
+            ("PKGER_PREFIX", metadata.prefix.as_str()),
+            ("PKGER_OS", "gentoo"),
+            ("RECIPE", "${PN}"),
+            ("RECIPE_VERSION", "${PV}"),
+            ("RECIPE_RELEASE", metadata.release()),
+        ] {
+            vars.insert(var.to_string(), value.to_string());
+        }
+        let sorted = |names: HashSet<&str>| {
+            let mut names: Vec<_> = names.into_iter().map(str::to_string).collect();
+            names.sort_unstable();
+            names
+        };
+
+        let mut builder = Ebuild::builder()
+            .description(&metadata.description)
+            .add_license_entries(vec![&metadata.license])
+            .add_src_uri_entries(
+                metadata
+                    .source
+                    .iter()
+                    .filter(|source| source.source().contains("://"))
+                    .map(|source| template::render(source.source(), &vars)),
+            )
+            .s("${WORKDIR}");
+
+        if let Some(url) = &metadata.url {
+            builder = builder.homepage(url);
+        }
+        let mut inherit = vec![];
+        if let Some(git) = &metadata.git {
+            inherit.push("git-r3".to_string());
+            builder = builder
+                .egit_repo_uri(git.url())
+                .egit_checkout_dir("${WORKDIR}");
+            builder = match git.branch().strip_prefix("refs/tags/") {
+                Some(tag) => builder.egit_commit(tag),
+                None => builder.egit_branch(git.branch().trim_start_matches("refs/heads/")),
+            };
+        }
+        if let Some(ebuild) = ebuild {
+            inherit.extend(ebuild.inherit.iter().cloned());
+            if let Some(slot) = &ebuild.slot {
+                builder = builder.slot(slot);
+            }
+        }
+        builder = builder.add_inherit_entries(inherit);
+
+        let keywords = match ebuild {
+            Some(ebuild) if !ebuild.keywords.is_empty() => ebuild.keywords.clone(),
+            _ if metadata.arch == BuildArch::All => vec![],
+            _ => vec![format!("~{}", metadata.arch.gentoo_name())],
+        };
+        builder = builder.add_keywords_entries(keywords);
+
+        let mut rdepend = metadata
+            .depends
+            .as_ref()
+            .map(|depends| sorted(depends.resolve_names(image)))
+            .unwrap_or_default();
+        if let Some(conflicts) = &metadata.conflicts {
+            rdepend.extend(
+                sorted(conflicts.resolve_names(image))
+                    .into_iter()
+                    .map(|name| format!("!{}", name)),
+            );
+        }
+        if !rdepend.is_empty() {
+            builder = builder
+                .add_rdepend_entries(rdepend)
+                .add_depend_entries(vec!["${RDEPEND}"]);
+        }
+        if let Some(build_depends) = &metadata.build_depends {
+            // packages built by pkger are only installed in build containers
+            let mut bdepend = build_depends.resolve_names(image);
+            bdepend.retain(|dep| pkger_recipe(dep).is_none());
+            builder = builder.add_bdepend_entries(sorted(bdepend));
+        }
+
+        if let Some(script) = &self.configure_script {
+            let dir = script.working_dir.as_ref().map(|dir| dir.to_string_lossy());
+            if let Some(func) = Self::ebuild_func(&script.steps, dir.as_deref(), image, &vars) {
+                builder = builder.src_configure(func);
+            }
+        }
+        let dir = self
+            .build_script
+            .working_dir
+            .as_ref()
+            .map(|dir| dir.to_string_lossy());
+        if let Some(func) =
+            Self::ebuild_func(&self.build_script.steps, dir.as_deref(), image, &vars)
+        {
+            builder = builder.src_compile(func);
+        }
+        if let Some(script) = &self.install_script {
+            let dir = script
+                .working_dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy())
+                .unwrap_or_else(|| "${D}".into());
+            if let Some(func) = Self::ebuild_func(&script.steps, Some(&dir), image, &vars) {
+                builder = builder.src_install(func);
+            }
+        }
+
+        builder.build()
+    }
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct RecipeRep {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Version of the recipe format the recipe was written for, recipes without it are migrated
+    /// from the format used before versioning
+    pub schema_version: Option<u64>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Files with YAML fragments that will be merged into this recipe
+    pub include: Vec<PathBuf>,
     pub metadata: MetadataRep,
     pub env: Option<Mapping>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Names of environment variables of the host forwarded to the container
+    pub pass_env: Vec<String>,
     pub configure: Option<ConfigureRep>,
     pub build: BuildRep,
     pub install: Option<InstallRep>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Shell used by all phases that don't set their own
+    pub shell: Option<String>,
 }
 
 impl RecipeRep {
     pub fn from_yaml_bytes(data: &[u8]) -> Result<Self> {
-        Ok(serde_yaml::from_slice(data)?)
+        let mut rep: YamlValue = serde_yaml::from_slice(data)?;
+        schema::migrate(&mut rep)?;
+        Ok(serde_yaml::from_value(rep)?)
     }
 
+    /// Loads the recipe from the given path merging all included files.
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Self::from_yaml_bytes(&fs::read(&path)?)
+        let path = path.as_ref();
+        Self::load_bytes(
+            &fs::read(path)?,
+            path.parent().unwrap_or_else(|| Path::new(".")),
+        )
+    }
+
+    /// Loads the recipe from its content merging all included files relative to `dir`.
+    pub fn load_bytes(data: &[u8], dir: &Path) -> Result<Self> {
+        let mut rep: YamlValue = serde_yaml::from_slice(data)?;
+        schema::migrate(&mut rep)?;
+        include::resolve(&mut rep, dir)?;
+        Ok(serde_yaml::from_value(rep)?)
     }
 }
 
@@ -437,6 +1426,9 @@ macro_rules! impl_step_rep {
             type Error = Error;
 
             fn try_from(rep: $ty_rep) -> Result<Self> {
+                for step in &rep.steps {
+                    step.validate()?;
+                }
                 Ok(Self {
                     steps: rep.steps,
                     working_dir: rep.working_dir,
@@ -446,12 +1438,17 @@ macro_rules! impl_step_rep {
         }
 
         impl $ty {
+            /// Returns the commands of all steps as a single shell script. Script steps are
+            /// skipped as they may run with other interpreters.
             pub fn steps_as_script(&self) -> String {
                 let mut script = String::new();
-                self.steps.iter().for_each(|step| {
-                    script.push_str(&step.cmd);
-                    script.push('\n');
-                });
+                self.steps
+                    .iter()
+                    .filter(|step| step.script().is_none())
+                    .for_each(|step| {
+                        script.push_str(&step.cmd);
+                        script.push('\n');
+                    });
                 script
             }
         }
@@ -494,4 +1491,602 @@ mod tests {
         assert_eq!(install.working_dir, rep_install.working_dir);
         assert_eq!(install.shell, rep_install.shell);
     }
+
+    #[test]
+    fn loads_standalone_recipes() {
+        let dir = tempdir::TempDir::new("pkger-standalone").unwrap();
+        fs::write(
+            dir.path().join("common.yml"),
+            "metadata:\n  patches: [fix.patch]\n  source: [src/]\nenv:\n  COMMON: enabled\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("unrelated.txt"), "1").unwrap();
+        fs::write(dir.path().join("fix.patch"), "1").unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.c"), "1").unwrap();
+        let mut data = b"include: [common.yml]\n".to_vec();
+        data.extend_from_slice(TEST_RECIPE);
+
+        let recipe = Recipe::from_standalone(&data, dir.path().to_path_buf()).unwrap();
+        assert_eq!(recipe.recipe_dir, dir.path());
+        assert_eq!(recipe.includes, vec![dir.path().join("common.yml")]);
+        assert_eq!(
+            recipe.env.inner().get("COMMON").map(String::as_str),
+            Some("enabled")
+        );
+
+        fs::write(dir.path().join("unrelated.txt"), "2").unwrap();
+        let reloaded = Recipe::from_standalone(&data, dir.path().to_path_buf()).unwrap();
+        assert_eq!(reloaded.hash, recipe.hash);
+        fs::write(dir.path().join("fix.patch"), "2").unwrap();
+        let patched = Recipe::from_standalone(&data, dir.path().to_path_buf()).unwrap();
+        assert_ne!(patched.hash, recipe.hash);
+        fs::write(dir.path().join("src/main.c"), "2").unwrap();
+        let changed = Recipe::from_standalone(&data, dir.path().to_path_buf()).unwrap();
+        assert_ne!(changed.hash, patched.hash);
+        assert!(Recipe::from_standalone(b"metadata: {}", dir.path().to_path_buf()).is_err());
+    }
+
+    #[test]
+    fn hashes_files_of_recipe_directory() {
+        let dir = tempdir::TempDir::new("pkger-hash").unwrap();
+        fs::write(dir.path().join("fix.patch"), "1").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(".", dir.path().join("loop")).unwrap();
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+
+        let (hash, inputs_hash) = content_hashes(&rep, Some(dir.path()), &[]).unwrap();
+        assert_ne!(hash, inputs_hash);
+        assert_eq!(
+            content_hashes(&rep, Some(dir.path()), &[]).unwrap(),
+            (hash.clone(), inputs_hash.clone())
+        );
+        rep.shell = Some("/bin/bash".to_string());
+        let (changed_hash, unchanged_inputs_hash) =
+            content_hashes(&rep, Some(dir.path()), &[]).unwrap();
+        assert_ne!(changed_hash, hash);
+        assert_eq!(unchanged_inputs_hash, inputs_hash);
+        fs::write(dir.path().join("fix.patch"), "2").unwrap();
+        assert_ne!(
+            content_hashes(&rep, Some(dir.path()), &[]).unwrap().1,
+            inputs_hash
+        );
+    }
+
+    #[test]
+    fn validates_recipe_names() {
+        assert!(validate_name("pkger-test_1.0+git").is_ok());
+        assert!(validate_name("Test").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("-test").is_err());
+        assert!(validate_name("my recipe").is_err());
+        assert!(validate_name(&"a".repeat(MAX_NAME_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn renders_replaces() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.replaces = serde_yaml::from_str(
+            "debian10: [\"legacy-name (<< 2.0)\"]\ncentos8: [\"legacy-name < 2.0\"]",
+        )
+        .unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        recipe.metadata.deb = None;
+        recipe.metadata.rpm = None;
+
+        let control = recipe
+            .as_deb_control("debian10", None, &BuildInfo::default())
+            .render();
+        assert!(control.contains("Replaces:       legacy-name (<< 2.0)\n"));
+
+        let spec = recipe
+            .as_rpm_spec(&[], &[], "centos8", &BuildInfo::default())
+            .render();
+        assert!(spec.contains("obsoletes:     legacy-name < 2.0\n"));
+        assert!(!spec.contains("legacy-name (<< 2.0)"));
+    }
+
+    #[test]
+    fn requires_shadow_utils_for_system_accounts() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        let spec = |rep: RecipeRep| {
+            Recipe::new(rep, PathBuf::new())
+                .unwrap()
+                .as_rpm_spec(&[], &[], "centos8", &BuildInfo::default())
+                .render()
+        };
+        assert!(!spec(rep.clone()).contains("Requires(pre)"));
+
+        rep.metadata.users = serde_yaml::from_str("- name: pkger").unwrap();
+        assert!(spec(rep).contains("Requires(pre): shadow-utils\n"));
+    }
+
+    #[test]
+    fn requires_alternatives_for_scriptlets() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.alternatives = serde_yaml::from_str(
+            "- {name: editor, link: /usr/bin/editor, path: /usr/bin/test-suite, priority: 50}",
+        )
+        .unwrap();
+        let spec = Recipe::new(rep, PathBuf::new())
+            .unwrap()
+            .as_rpm_spec(&[], &[], "centos8", &BuildInfo::default())
+            .render();
+        assert!(spec.contains("Requires(post): alternatives\nRequires(preun): alternatives\n"));
+    }
+
+    #[test]
+    fn renders_subpackages() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.subpackages = serde_yaml::from_str(
+            r#"
+- name: test-suite-devel
+  description: headers of test-suite
+  files: [usr/include/]
+  depends:
+    debian10: [libtest-dev]
+    centos8: [libtest-devel]
+"#,
+        )
+        .unwrap();
+        let recipe = Recipe::new(rep.clone(), PathBuf::new()).unwrap();
+        let subpackage = &recipe.metadata.subpackages[0];
+
+        let control = recipe
+            .as_deb_subpackage_control(subpackage, "debian10", Some("12"))
+            .render();
+        assert!(control.starts_with("Package:        test-suite-devel\n"));
+        assert!(control.contains("Source:         test-suite\n"));
+        assert!(control.contains("Depends:        test-suite (= 0.2.0-0), libtest-dev\n"));
+        assert!(control.contains("Description:    headers of test-suite\n"));
+
+        let spec = recipe
+            .as_rpm_subpackage_spec(
+                subpackage,
+                "/tmp/test-suite-devel-subpackage",
+                &["/usr/include/test.h".to_string()],
+                "centos8",
+            )
+            .render();
+        assert!(spec.contains("Name:          test-suite-devel\n"));
+        assert!(spec.contains("requires:      test-suite = 0.2.0-0\n"));
+        assert!(spec.contains("requires:      libtest-devel\n"));
+        assert!(spec.contains("cp -a /tmp/test-suite-devel-subpackage/. %{buildroot}/\n"));
+        assert!(spec.contains("\"/usr/include/test.h\"\n"));
+
+        for name in ["test-suite", "test suite"] {
+            let mut rep = rep.clone();
+            rep.metadata.subpackages[0].name = name.to_string();
+            assert!(Recipe::new(rep, PathBuf::new()).is_err());
+        }
+        rep.metadata.subpackages[0].files.clear();
+        assert!(Recipe::new(rep, PathBuf::new()).is_err());
+    }
+
+    #[test]
+    fn renders_renamed_from() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.renamed_from = vec!["old-name".to_string()];
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        recipe.metadata.version = "1.2.0".to_string();
+        recipe.metadata.release = Some("3".to_string());
+        recipe.metadata.epoch = None;
+        recipe.metadata.deb = None;
+        recipe.metadata.rpm = None;
+        recipe.metadata.provides = None;
+
+        let control = recipe
+            .as_deb_control("centos8", None, &BuildInfo::default())
+            .render();
+        assert!(control.contains("Provides:       old-name (= 1.2.0-3)\n"));
+        assert!(control.contains("Replaces:       old-name (<< 1.2.0-3)\n"));
+        assert!(control.contains("Breaks:         old-name (<< 1.2.0-3)\n"));
+
+        let spec = recipe
+            .as_rpm_spec(&[], &[], "centos8", &BuildInfo::default())
+            .render();
+        assert!(spec.contains("provides:      old-name = 1.2.0-3\n"));
+        assert!(spec.contains("obsoletes:     old-name < 1.2.0-3\n"));
+    }
+
+    #[test]
+    fn renders_pkg_install_functions() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.pkg = Some(
+            serde_yaml::from_str(
+                "post_install_script: |\n  echo installed\n\n  systemctl daemon-reload\npost_remove_script: echo removed",
+            )
+            .unwrap(),
+        );
+        let recipe = Recipe::new(rep.clone(), PathBuf::new()).unwrap();
+        let pkg = recipe.metadata.pkg.as_ref().unwrap();
+        assert_eq!(
+            pkg.render_install().unwrap(),
+            "post_install() {
+    echo installed
+
+    systemctl daemon-reload
+}
+
+post_remove() {
+    echo removed
+}
+"
+        );
+        let pkgbuild = recipe.as_pkgbuild("arch", &[], &[]).render();
+        assert!(pkgbuild.contains(&format!("install={}.install\n", recipe.metadata.name)));
+
+        rep.metadata.pkg = Some(
+            serde_yaml::from_str("install: .install\npost_upgrade_script: echo upgraded").unwrap(),
+        );
+        assert!(Recipe::new(rep, PathBuf::new()).is_err());
+    }
+
+    #[test]
+    fn renders_custom_deb_fields() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.deb = Some(
+            serde_yaml::from_str(
+                "breaks: [old]\nfields:\n  XB-Custom-Field: value\n  Breaks: other (<< 2), another\n  X-Notes: |\n    first\n\n    second\n",
+            )
+            .unwrap(),
+        );
+        let recipe = Recipe::new(rep.clone(), PathBuf::new()).unwrap();
+
+        let control = recipe
+            .as_deb_control("centos8", None, &BuildInfo::default())
+            .render();
+        assert!(control.contains("Breaks:         old, other (<< 2), another\n"));
+        assert!(control.contains("\nX-Notes: first\n .\n second\nXB-Custom-Field: value\n"));
+
+        rep.metadata.deb = Some(serde_yaml::from_str("fields:\n  version: '1.0'").unwrap());
+        assert!(Recipe::new(rep.clone(), PathBuf::new()).is_err());
+        rep.metadata.deb = Some(serde_yaml::from_str("fields:\n  'X-Bad:': value").unwrap());
+        assert!(Recipe::new(rep, PathBuf::new()).is_err());
+    }
+
+    #[test]
+    fn renders_rpm_scriptlets() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.rpm = Some(
+            serde_yaml::from_str(
+                "pre_script: echo pre\npost_script: echo post\npreun_script: echo preun\npostun_script: echo postun\nposttrans_script: echo posttrans",
+            )
+            .unwrap(),
+        );
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+
+        let spec = recipe
+            .as_rpm_spec(&[], &[], "centos8", &BuildInfo::default())
+            .render();
+        assert!(spec.contains(
+            "%pre\necho pre\n\n%post\necho post\n\n%preun\necho preun\n\n%postun\necho postun\n\n%posttrans\necho posttrans\n\n"
+        ));
+    }
+
+    #[test]
+    fn renders_rpm_spec_templates() {
+        let dir = tempdir::TempDir::new("pkger-spec-template").unwrap();
+        fs::write(
+            dir.path().join("pkger.spec.in"),
+            "Name: ${RECIPE}\nVersion: ${RECIPE_VERSION}\nSource0: ${RPM_SOURCES}\n%files\n${RPM_FILES}%{_unitdir}\n",
+        )
+        .unwrap();
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.rpm = Some(serde_yaml::from_str("spec_template: pkger.spec.in").unwrap());
+        let recipe = Recipe::new(rep.clone(), dir.path().to_path_buf()).unwrap();
+
+        let spec = recipe
+            .render_rpm_spec(
+                &["test-suite.tar.gz".to_string()],
+                &["/usr/bin/test".to_string()],
+                "centos8",
+                &BuildInfo::default(),
+                &recipe.env,
+            )
+            .unwrap();
+        assert_eq!(
+            spec,
+            format!(
+                "Name: test-suite\nVersion: {}\nSource0: test-suite.tar.gz\n%files\n\"/usr/bin/test\"\n%{{_unitdir}}\n",
+                recipe.metadata.version
+            )
+        );
+
+        rep.metadata.rpm = Some(serde_yaml::from_str("spec_template: /pkger.spec.in").unwrap());
+        assert!(Recipe::new(rep, dir.path().to_path_buf()).is_err());
+    }
+
+    #[test]
+    fn renders_rpm_changelog() {
+        let dir = tempdir::TempDir::new("pkger-changelog").unwrap();
+        fs::write(
+            dir.path().join("changelog"),
+            "* Mon Jan 15 2024 Packager - 0.1.0-1\n- Initial release\n",
+        )
+        .unwrap();
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.maintainer = Some("John Doe <john@example.com>".to_string());
+        rep.metadata.changelog =
+            serde_yaml::from_str("- date: 2024-05-01\n  changes: [Bump version]").unwrap();
+        let recipe = Recipe::new(rep.clone(), dir.path().to_path_buf()).unwrap();
+
+        let spec = recipe
+            .render_rpm_spec(&[], &[], "centos8", &BuildInfo::default(), &recipe.env)
+            .unwrap();
+        assert!(spec.ends_with(&format!(
+            "\n%changelog\n* Wed May 01 2024 John Doe <john@example.com> - {}-0\n- Bump version\n\n",
+            recipe.metadata.version
+        )));
+
+        rep.metadata.rpm = Some(serde_yaml::from_str("changelog_file: changelog").unwrap());
+        let recipe = Recipe::new(rep, dir.path().to_path_buf()).unwrap();
+        let spec = recipe
+            .render_rpm_spec(&[], &[], "centos8", &BuildInfo::default(), &recipe.env)
+            .unwrap();
+        assert!(spec
+            .ends_with("\n%changelog\n* Mon Jan 15 2024 Packager - 0.1.0-1\n- Initial release\n"));
+    }
+
+    #[test]
+    fn renders_deb_changelog() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.maintainer = Some("John Doe <john@example.com>".to_string());
+        rep.metadata.changelog =
+            serde_yaml::from_str("- date: 2024-05-01\n  changes: [Bump version]").unwrap();
+        rep.metadata.deb = Some(
+            serde_yaml::from_str("changelog:\n  distribution: bookworm\n  urgency: low").unwrap(),
+        );
+        let recipe = Recipe::new(rep.clone(), PathBuf::new()).unwrap();
+        assert_eq!(
+            recipe.render_deb_changelog(None).unwrap(),
+            format!(
+                "test-suite ({}-0) bookworm; urgency=low\n\n  * Bump version\n\n -- John Doe <john@example.com>  Wed, 01 May 2024 00:00:00 +0000\n",
+                recipe.metadata.version
+            )
+        );
+
+        rep.metadata.deb =
+            Some(serde_yaml::from_str("changelog:\n  git: v1.0.0..; rm -rf /").unwrap());
+        assert!(Recipe::new(rep.clone(), PathBuf::new()).is_err());
+        rep.metadata.changelog = vec![];
+        rep.metadata.deb = None;
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        assert_eq!(recipe.render_deb_changelog(None), None);
+    }
+
+    #[test]
+    fn overrides_recipe_settings() {
+        let rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        recipe.metadata.name = "test".to_string();
+        let hash = recipe.hash.clone();
+        assert_eq!(recipe.metadata.prefix, "/usr");
+        assert_eq!(recipe.metadata.license_dir(), "/usr/share/licenses/test");
+
+        recipe.set("prefix", "/opt/acme/").unwrap();
+        recipe.set("ACME_FEATURES", "full").unwrap();
+
+        assert_eq!(recipe.metadata.prefix, "/opt/acme");
+        assert_eq!(recipe.metadata.doc_dir(), "/opt/acme/share/doc/test");
+        assert_eq!(
+            recipe.env.inner().get("ACME_FEATURES").map(String::as_str),
+            Some("full")
+        );
+        assert_ne!(recipe.hash, hash);
+        assert!(recipe.set("prefix", "opt/acme").is_err());
+        assert!(recipe.set("prefix", "/").is_err());
+    }
+
+    #[test]
+    fn overrides_version_and_release() {
+        let rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let version = recipe.metadata.version.clone();
+        let hash = recipe.hash.clone();
+
+        recipe.append_version_suffix("~git20240501").unwrap();
+        recipe.set_release("2").unwrap();
+
+        assert_eq!(recipe.metadata.version, format!("{}~git20240501", version));
+        assert_eq!(recipe.metadata.release(), "2");
+        assert_ne!(recipe.hash, hash);
+        assert!(recipe.append_version_suffix("-nightly").is_err());
+        assert!(recipe.set_release("").is_err());
+        assert!(recipe.set_release("1 2").is_err());
+    }
+
+    #[test]
+    fn converts_pre_release_versions_for_apk_and_pkg() {
+        let rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        let mut versions = |version: &str| {
+            recipe.metadata.version = version.to_string();
+            (recipe.metadata.apk_version(), recipe.metadata.pkg_version())
+        };
+
+        assert_eq!(versions("1.2.3"), ("1.2.3".into(), "1.2.3".into()));
+        assert_eq!(
+            versions("1.2.3~rc1"),
+            ("1.2.3_rc1".into(), "1.2.3rc1".into())
+        );
+        assert_eq!(
+            versions("1.2.3~beta"),
+            ("1.2.3_beta".into(), "1.2.3beta".into())
+        );
+        assert_eq!(
+            versions("1.2.3~git20240501"),
+            ("1.2.3_pre20240501".into(), "1.2.3git20240501".into())
+        );
+        assert_eq!(
+            versions("1.2.3~20240501+abc"),
+            ("1.2.3_pre20240501".into(), "1.2.3pre20240501.abc".into())
+        );
+    }
+
+    #[test]
+    fn marks_license_and_doc_files_in_rpm_spec() {
+        let rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        recipe.metadata.name = "test".to_string();
+        let files = vec![
+            "/usr/bin/test".to_string(),
+            "/usr/share/doc/test/README.md".to_string(),
+            "/usr/share/licenses/test/LICENSE".to_string(),
+        ];
+
+        let spec = recipe
+            .as_rpm_spec(&[], &files, "centos8", &BuildInfo::default())
+            .render();
+
+        assert!(spec.contains("%files\n\"/usr/bin/test\"\n"));
+        assert!(spec.contains("%doc \"/usr/share/doc/test/README.md\"\n"));
+        assert!(spec.contains("%license \"/usr/share/licenses/test/LICENSE\"\n"));
+    }
+
+    #[test]
+    fn marks_configured_files_in_rpm_spec() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.rpm = Some(
+            serde_yaml::from_str(
+                "config_noreplace: /etc/test/legacy.conf\nfiles:\n  config: [/etc/test/defaults.conf]\n  config_noreplace: [etc/test/]\n  doc: [/usr/share/test/examples]\n  license: [/usr/share/test/COPYING, /usr/share/test]",
+            )
+            .unwrap(),
+        );
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        recipe.metadata.name = "test".to_string();
+        let files = vec![
+            "/usr/bin/test".to_string(),
+            "/etc/test/defaults.conf".to_string(),
+            "/etc/test/test.conf".to_string(),
+            "/etc/test/legacy.conf".to_string(),
+            "/etc/testing.conf".to_string(),
+            "/usr/share/test/examples/basic.yml".to_string(),
+            "/usr/share/test/COPYING".to_string(),
+        ];
+
+        let spec = recipe
+            .as_rpm_spec(&[], &files, "centos8", &BuildInfo::default())
+            .render();
+
+        // exact paths win over directories, the innermost directory wins over outer ones
+        let files_section = spec
+            .split("%files\n")
+            .nth(1)
+            .and_then(|section| section.split("\n%changelog").next())
+            .unwrap();
+        assert_eq!(
+            files_section,
+            r#""/usr/bin/test"
+"/etc/testing.conf"
+%doc "/usr/share/test/examples/basic.yml"
+%license "/usr/share/test/COPYING"
+%config "/etc/test/defaults.conf"
+%config(noreplace) "/etc/test/test.conf"
+%config(noreplace) "/etc/test/legacy.conf"
+"#
+        );
+    }
+
+    #[test]
+    fn renders_debug_packages() {
+        let rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        let mut recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+        recipe.metadata.name = "test_pkg".to_string();
+        recipe.metadata.version = "1.2.0".to_string();
+        recipe.metadata.release = Some("3".to_string());
+        recipe.metadata.epoch = None;
+
+        let control = recipe.as_deb_dbgsym_control().render();
+        assert!(control.starts_with("Package:        test-pkg-dbgsym\nVersion:        1.2.0-3\n"));
+        assert!(control.contains("Section:        debug\n"));
+        assert!(control.contains("Depends:        test-pkg (= 1.2.0-3)\n"));
+
+        let spec = recipe.as_rpm_debuginfo_spec("/tmp/test-debug").render();
+        assert!(spec.contains("requires:      test_pkg = 1.2.0-3\n"));
+        assert!(spec.contains("cp -a /tmp/test-debug/. %{buildroot}/\n"));
+        assert!(spec.contains("%files\n\"/usr/lib/debug\"\n"));
+    }
+
+    #[test]
+    fn renders_ebuilds() {
+        let rep = RecipeRep::from_yaml_bytes(
+            br#"
+metadata:
+  name: pkger
+  description: Package building tool
+  arch: x86_64
+  license: MIT
+  version: 0.7.0
+  release: "2"
+  url: https://github.com/vv9k/pkger
+  source:
+    - https://github.com/vv9k/pkger/archive/$RECIPE_VERSION.tar.gz
+    - pkger.service
+  depends:
+    gentoo: [sys-libs/zlib, dev-libs/openssl]
+    debian: [libssl1.1]
+  build_depends:
+    gentoo: [dev-lang/rust, "pkger:libpkger"]
+  conflicts: [app-misc/pkger-bin]
+  ebuild:
+    category: dev-util
+env:
+  CARGO_FLAGS: --release --locked
+build:
+  steps:
+    - cmd: cd $PKGER_BLD_DIR/${RECIPE}-$RECIPE_VERSION && cargo build $CARGO_FLAGS
+    - cmd: strip target/release/pkger
+      images: [debian]
+    - cmd: echo only rpm
+      rpm: true
+install:
+  steps:
+    - cmd: |-
+        mkdir -p usr/bin
+        cp $PKGER_BLD_DIR/${RECIPE}-$RECIPE_VERSION/target/release/pkger usr/bin/
+"#,
+        )
+        .unwrap();
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+
+        assert_eq!(
+            recipe.ebuild_path(),
+            PathBuf::from("dev-util/pkger/pkger-0.7.0-r2.ebuild")
+        );
+        assert_eq!(
+            recipe.as_ebuild("gentoo").render(),
+            r#"EAPI=8
+
+DESCRIPTION="Package building tool"
+HOMEPAGE="https://github.com/vv9k/pkger"
+SRC_URI="https://github.com/vv9k/pkger/archive/${PV}.tar.gz"
+S="${WORKDIR}"
+
+LICENSE="MIT"
+SLOT="0"
+KEYWORDS="~amd64"
+
+RDEPEND="
+	dev-libs/openssl
+	sys-libs/zlib
+	!app-misc/pkger-bin
+"
+DEPEND="${RDEPEND}"
+BDEPEND="dev-lang/rust"
+
+src_compile() {
+	cd ${WORKDIR}/${PN}-${PV} && cargo build --release --locked || die
+}
+
+src_install() {
+	cd "${D}" || die
+	(
+		mkdir -p usr/bin
+		cp ${WORKDIR}/${PN}-${PV}/target/release/pkger usr/bin/
+	) || die
+}
+"#
+        );
+    }
 }
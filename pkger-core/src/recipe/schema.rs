@@ -0,0 +1,117 @@
+//! Versions of the recipe format. Recipes declare the version they were written for with the top
+//! level `schema_version` field and older recipes are migrated to the current format when they're
+//! loaded. Recipes without the field were written before versioning and have the version 0.
+
+use crate::{err, Error, Result};
+
+use serde_yaml::{Mapping, Value as YamlValue};
+use tracing::{debug, info_span};
+
+/// Name of the top level recipe field with the version of the recipe format.
+pub static SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Version of the recipe format of this pkger.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Changes of the recipe format applied to recipes with a version lower than `to`
+struct Migration {
+    to: u64,
+    description: &'static str,
+    apply: fn(&mut Mapping),
+}
+
+static MIGRATIONS: &[Migration] = &[Migration {
+    to: 1,
+    description: "rename `metadata.section` to `metadata.group`",
+    apply: rename_section,
+}];
+
+/// Returns the version declared by `recipe` or 0 if it doesn't declare one.
+pub fn version(recipe: &YamlValue) -> Result<u64> {
+    match recipe.get(SCHEMA_VERSION_KEY) {
+        None | Some(YamlValue::Null) => Ok(0),
+        Some(version) => match version.as_u64() {
+            Some(version) => Ok(version),
+            None => err!("`{}` must be a non-negative integer", SCHEMA_VERSION_KEY),
+        },
+    }
+}
+
+/// Applies the migrations of all versions newer than the one declared by `recipe` to it. Fails
+/// if the recipe was written for a newer version of pkger. The declared version is kept so that
+/// the hash of the recipe doesn't change.
+pub fn migrate(recipe: &mut YamlValue) -> Result<()> {
+    let version = version(recipe)?;
+    if version > SCHEMA_VERSION {
+        return err!(
+            "recipe uses schema version {} but this pkger supports versions up to {}, upgrade pkger to use it",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    let recipe = match recipe.as_mapping_mut() {
+        Some(recipe) => recipe,
+        None => return Ok(()),
+    };
+    let span = info_span!("migrate-recipe", version = %version);
+    let _enter = span.enter();
+
+    for migration in MIGRATIONS.iter().filter(|m| m.to > version) {
+        debug!(to = %migration.to, "{}", migration.description);
+        (migration.apply)(recipe);
+    }
+    Ok(())
+}
+
+/// Version 1 uses `group` for the section of DEB packages and the group of RPM packages. The
+/// value of `group` wins if both are set.
+fn rename_section(recipe: &mut Mapping) {
+    let metadata = match recipe
+        .get_mut(&YamlValue::from("metadata"))
+        .and_then(YamlValue::as_mapping_mut)
+    {
+        Some(metadata) => metadata,
+        None => return,
+    };
+    if let Some(section) = metadata.remove(&YamlValue::from("section")) {
+        let group = YamlValue::from("group");
+        if !metadata.contains_key(&group) {
+            metadata.insert(group, section);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn migrates_recipes() {
+        let mut recipe: YamlValue =
+            serde_yaml::from_str("metadata:\n  name: pkger\n  section: utils\n").unwrap();
+        assert_eq!(version(&recipe).unwrap(), 0);
+        migrate(&mut recipe).unwrap();
+        assert_eq!(
+            recipe,
+            serde_yaml::from_str::<YamlValue>("metadata:\n  name: pkger\n  group: utils\n")
+                .unwrap()
+        );
+
+        let mut recipe: YamlValue =
+            serde_yaml::from_str("schema_version: 1\nmetadata:\n  name: pkger\n  section: utils\n")
+                .unwrap();
+        let current = recipe.clone();
+        migrate(&mut recipe).unwrap();
+        assert_eq!(recipe, current);
+
+        let mut recipe: YamlValue =
+            serde_yaml::from_str(&format!("schema_version: {}\n", SCHEMA_VERSION + 1)).unwrap();
+        assert!(migrate(&mut recipe)
+            .unwrap_err()
+            .to_string()
+            .contains("upgrade pkger"));
+        let mut recipe: YamlValue = serde_yaml::from_str("schema_version: -1\n").unwrap();
+        assert!(migrate(&mut recipe).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Rendering of packaging files like the spec file of RPM or the control file of DEB without a
+//! container. Files are rendered the same way as during a build on the given image except for
+//! the parts only known once the package is built, like the list of files, the installed size or
+//! the checksums of sources.
+
+use crate::build::info::BuildInfo;
+use crate::build::package::{msi, xbps};
+use crate::recipe::{BuildTarget, Recipe};
+use crate::Result;
+
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackagingFile {
+    /// Name of the file as it is saved during the build, like `control` or `pkger.spec`
+    pub name: String,
+    pub content: String,
+}
+
+impl PackagingFile {
+    fn new(name: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            content: content.into(),
+        }
+    }
+}
+
+/// Renders all packaging files of the `recipe` built for the `target` on the `image`. The main
+/// file like the spec or the control file comes first. Returns no files for targets without
+/// packaging metadata like GZIP.
+pub fn packaging_files(
+    recipe: &Recipe,
+    target: BuildTarget,
+    image: &str,
+) -> Result<Vec<PackagingFile>> {
+    let build_info = BuildInfo::default();
+    let name = &recipe.metadata.name;
+    let files = match target {
+        BuildTarget::Deb => {
+            let mut files = vec![PackagingFile::new(
+                "control",
+                recipe.as_deb_control(image, None, &build_info).render(),
+            )];
+            for (file, content) in recipe.metadata.deb_scripts() {
+                files.push(PackagingFile::new(file, content));
+            }
+            if let Some(deb) = &recipe.metadata.deb {
+                if let Some(triggers) = &deb.triggers {
+                    files.push(PackagingFile::new("triggers", triggers));
+                }
+                if let Some(conffiles) = deb.conffiles.as_ref().filter(|files| !files.is_empty()) {
+                    files.push(PackagingFile::new(
+                        "conffiles",
+                        format!("{}\n", conffiles.join("\n")),
+                    ));
+                }
+            }
+            if let Some(changelog) = recipe.render_deb_changelog(None) {
+                files.push(PackagingFile::new("changelog.Debian", changelog));
+            }
+            files
+        }
+        BuildTarget::Rpm => vec![PackagingFile::new(
+            format!("{}.spec", name),
+            recipe.render_rpm_spec(&[], &[], image, &build_info, &recipe.env)?,
+        )],
+        BuildTarget::Pkg => {
+            let mut files = vec![PackagingFile::new(
+                "PKGBUILD",
+                recipe.as_pkgbuild(image, &[], &[]).render(),
+            )];
+            if let Some(pkg) = &recipe.metadata.pkg {
+                if let (Some(file), Some(install)) = (
+                    pkg.install_file(&recipe.metadata.name),
+                    pkg.render_install(),
+                ) {
+                    files.push(PackagingFile::new(file, install));
+                }
+            }
+            files
+        }
+        BuildTarget::Apk => vec![PackagingFile::new(
+            "APKBUILD",
+            recipe.as_apkbuild(image, &[], Path::new("")).render(),
+        )],
+        BuildTarget::Msi => vec![PackagingFile::new(
+            format!("{}.wxs", name),
+            msi::render_wxs(recipe)?,
+        )],
+        BuildTarget::Xbps => vec![PackagingFile::new(
+            format!("{}.props", name),
+            xbps::render_properties(recipe, image)?,
+        )],
+        BuildTarget::Gzip => vec![],
+    };
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::RecipeRep;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    const TEST_RECIPE: &[u8] = include_bytes!("../../example/recipes/test-suite/recipe.yml");
+
+    #[test]
+    fn renders_packaging_files() {
+        let mut rep = RecipeRep::from_yaml_bytes(TEST_RECIPE).unwrap();
+        rep.metadata.deb = Some(
+            serde_yaml::from_str("postinst_script: echo 1\nconffiles: [etc/test.conf]").unwrap(),
+        );
+        let recipe = Recipe::new(rep, PathBuf::new()).unwrap();
+
+        let files = packaging_files(&recipe, BuildTarget::Deb, "debian10").unwrap();
+        let names: Vec<_> = files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, vec!["control", "postinst", "conffiles"]);
+        assert!(files[0].content.starts_with("Package:        test-suite\n"));
+        assert_eq!(files[2].content, "/etc/test.conf\n");
+        assert_eq!(
+            recipe.preview_packaging(BuildTarget::Deb, "debian10"),
+            Some(files[0].content.clone())
+        );
+
+        let files = packaging_files(&recipe, BuildTarget::Rpm, "centos8").unwrap();
+        assert_eq!(files[0].name, "test-suite.spec");
+        assert!(packaging_files(&recipe, BuildTarget::Gzip, "debian10")
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{err, ErrContext, Error, Result};
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Value of profiles that disables the confinement.
+static UNCONFINED: &str = "unconfined";
+/// AppArmor profile docker loads by default.
+static DOCKER_DEFAULT_APPARMOR: &str = "docker-default";
+/// Capabilities kept by the `hardened` preset, the ones package managers and builds need to
+/// install files owned by other users.
+static HARDENED_CAP_ADD: &[&str] = &[
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "FOWNER",
+    "FSETID",
+    "SETGID",
+    "SETUID",
+];
+
+#[derive(Clone, Copy, Deserialize, Debug, Serialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "lowercase")]
+/// Predefined confinement settings
+pub enum SecurityPreset {
+    /// Drops all capabilities except the ones needed to install packages, makes the root
+    /// filesystem read-only, enables `no_new_privileges` and the default AppArmor profile of docker
+    /// if the host supports AppArmor
+    Hardened,
+}
+
+#[derive(Clone, Default, Deserialize, Debug, Serialize, Eq, PartialEq, Hash)]
+/// Seccomp and AppArmor profiles and other confinement of build containers
+pub struct SecurityConfig {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Preset that provides the defaults of settings that are not set
+    pub preset: Option<SecurityPreset>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Absolute path to a JSON seccomp profile on the host or `unconfined`. Defaults to the
+    /// default profile of docker
+    pub seccomp_profile: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Name of an AppArmor profile loaded on the host or `unconfined`
+    pub apparmor_profile: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Prevents processes of the container from gaining privileges, for example through setuid
+    /// binaries
+    pub no_new_privileges: Option<bool>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Capabilities dropped from the container, `ALL` drops every capability
+    pub cap_drop: Option<Vec<String>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Capabilities added to the container after the dropped ones are removed
+    pub cap_add: Option<Vec<String>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Mounts the root filesystem of build containers read-only, only the temporary directories
+    /// and the home directory of root stay writable
+    pub read_only_rootfs: Option<bool>,
+}
+
+/// Returns whether the security options reported by the Docker daemon, like `name=apparmor`,
+/// include AppArmor.
+pub fn apparmor_enabled(daemon_options: &[String]) -> bool {
+    daemon_options.iter().any(|option| {
+        option
+            .split(',')
+            .any(|field| field == "name=apparmor" || field == "apparmor")
+    })
+}
+
+impl SecurityConfig {
+    /// Verifies that the profiles are valid.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(profile) = &self.seccomp_profile {
+            if profile != UNCONFINED && !Path::new(profile).is_absolute() {
+                return err!(
+                    "seccomp profile `{}` has to be an absolute path or `{}`",
+                    profile,
+                    UNCONFINED
+                );
+            }
+        }
+        if let Some(profile) = &self.apparmor_profile {
+            let is_valid = !profile.is_empty()
+                && profile
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+            if !is_valid {
+                return err!("invalid AppArmor profile `{}`", profile);
+            }
+        }
+        for cap in self.cap_drop.iter().chain(&self.cap_add).flatten() {
+            let is_valid = !cap.is_empty()
+                && cap
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+            if !is_valid {
+                return err!("invalid capability `{}`", cap);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the configuration with settings of `other`, like the ones of an image, applied on
+    /// top of this one. Each setting of `other` wins if set.
+    pub fn merge(&self, other: &SecurityConfig) -> SecurityConfig {
+        SecurityConfig {
+            preset: other.preset.or(self.preset),
+            seccomp_profile: other
+                .seccomp_profile
+                .clone()
+                .or_else(|| self.seccomp_profile.clone()),
+            apparmor_profile: other
+                .apparmor_profile
+                .clone()
+                .or_else(|| self.apparmor_profile.clone()),
+            no_new_privileges: other.no_new_privileges.or(self.no_new_privileges),
+            cap_drop: other.cap_drop.clone().or_else(|| self.cap_drop.clone()),
+            cap_add: other.cap_add.clone().or_else(|| self.cap_add.clone()),
+            read_only_rootfs: other.read_only_rootfs.or(self.read_only_rootfs),
+        }
+    }
+
+    /// Returns the configuration used for sandboxed builds. The `hardened` preset and
+    /// `no_new_privileges` are enforced and `unconfined` profiles are replaced by the defaults.
+    pub fn sandboxed(&self) -> SecurityConfig {
+        let confined = |profile: &Option<String>| profile.clone().filter(|p| p != UNCONFINED);
+        SecurityConfig {
+            preset: Some(SecurityPreset::Hardened),
+            seccomp_profile: confined(&self.seccomp_profile),
+            apparmor_profile: confined(&self.apparmor_profile),
+            no_new_privileges: Some(true),
+            cap_drop: self.cap_drop.clone(),
+            cap_add: self.cap_add.clone(),
+            read_only_rootfs: self.read_only_rootfs,
+        }
+    }
+
+    /// Returns the configuration with settings that are not set taken from the preset. The
+    /// default AppArmor profile of the preset is only used if `apparmor` is supported by the host.
+    pub fn resolve(&self, apparmor: bool) -> SecurityConfig {
+        let mut resolved = self.clone();
+        if self.preset == Some(SecurityPreset::Hardened) {
+            if apparmor {
+                resolved
+                    .apparmor_profile
+                    .get_or_insert_with(|| DOCKER_DEFAULT_APPARMOR.to_string());
+            }
+            resolved.no_new_privileges.get_or_insert(true);
+            resolved
+                .cap_drop
+                .get_or_insert_with(|| vec!["ALL".to_string()]);
+            resolved.cap_add.get_or_insert_with(|| {
+                HARDENED_CAP_ADD.iter().map(|cap| cap.to_string()).collect()
+            });
+            resolved.read_only_rootfs.get_or_insert(true);
+        }
+        resolved
+    }
+
+    /// Returns the security options of a container with this resolved configuration. Seccomp
+    /// profiles are read from the host as the docker API expects the content of the profile.
+    pub fn security_options(&self) -> Result<Vec<String>> {
+        let mut options = vec![];
+        if let Some(profile) = &self.seccomp_profile {
+            let profile = if profile == UNCONFINED {
+                profile.clone()
+            } else {
+                fs::read_to_string(profile)
+                    .context(format!("failed to read seccomp profile `{}`", profile))?
+                    .trim()
+                    .to_string()
+            };
+            options.push(format!("seccomp={}", profile));
+        }
+        if let Some(profile) = &self.apparmor_profile {
+            options.push(format!("apparmor={}", profile));
+        }
+        if self.no_new_privileges == Some(true) {
+            options.push("no-new-privileges".to_string());
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn renders_security_options() {
+        let dir = tempdir::TempDir::new("pkger-security").unwrap();
+        let profile = dir.path().join("seccomp.json");
+        fs::write(&profile, "{\"defaultAction\": \"SCMP_ACT_ERRNO\"}\n").unwrap();
+
+        let config = SecurityConfig {
+            preset: Some(SecurityPreset::Hardened),
+            ..Default::default()
+        };
+        let image = SecurityConfig {
+            seccomp_profile: Some(profile.to_string_lossy().to_string()),
+            no_new_privileges: Some(false),
+            ..Default::default()
+        };
+        let security = config.merge(&image);
+        security.validate().unwrap();
+        assert_eq!(
+            security.resolve(true).security_options().unwrap(),
+            vec![
+                "seccomp={\"defaultAction\": \"SCMP_ACT_ERRNO\"}".to_string(),
+                "apparmor=docker-default".to_string(),
+            ]
+        );
+        assert_eq!(
+            config.resolve(true).security_options().unwrap(),
+            vec!["apparmor=docker-default", "no-new-privileges"]
+        );
+        assert_eq!(
+            config.resolve(false),
+            SecurityConfig {
+                preset: Some(SecurityPreset::Hardened),
+                no_new_privileges: Some(true),
+                cap_drop: Some(vec!["ALL".to_string()]),
+                cap_add: Some(
+                    [
+                        "CHOWN",
+                        "DAC_OVERRIDE",
+                        "FOWNER",
+                        "FSETID",
+                        "SETGID",
+                        "SETUID"
+                    ]
+                    .iter()
+                    .map(|cap| cap.to_string())
+                    .collect()
+                ),
+                read_only_rootfs: Some(true),
+                ..Default::default()
+            }
+        );
+        assert!(SecurityConfig::default()
+            .resolve(true)
+            .security_options()
+            .unwrap()
+            .is_empty());
+
+        let unconfined = SecurityConfig {
+            seccomp_profile: Some("unconfined".into()),
+            apparmor_profile: Some("unconfined".into()),
+            no_new_privileges: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            unconfined
+                .sandboxed()
+                .resolve(true)
+                .security_options()
+                .unwrap(),
+            vec!["apparmor=docker-default", "no-new-privileges"]
+        );
+
+        let invalid = |seccomp: &str, apparmor: &str| SecurityConfig {
+            seccomp_profile: Some(seccomp.into()),
+            apparmor_profile: Some(apparmor.into()),
+            ..Default::default()
+        };
+        assert!(invalid("unconfined", "pkger-build").validate().is_ok());
+        assert!(invalid("seccomp.json", "pkger-build").validate().is_err());
+        assert!(invalid("unconfined", "pkger build").validate().is_err());
+        let caps = SecurityConfig {
+            cap_add: Some(vec!["net_admin".into()]),
+            ..Default::default()
+        };
+        assert!(caps.validate().is_err());
+    }
+
+    #[test]
+    fn detects_apparmor_of_daemon() {
+        let options = |options: &[&str]| options.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        assert!(apparmor_enabled(&options(&[
+            "name=apparmor",
+            "name=seccomp,profile=default"
+        ])));
+        assert!(!apparmor_enabled(&options(&[
+            "name=seccomp,profile=default",
+            "name=selinux"
+        ])));
+        assert!(!apparmor_enabled(&[]));
+    }
+}
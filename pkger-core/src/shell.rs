@@ -0,0 +1,19 @@
+//! Helpers for shell commands run in containers and install scripts of packages
+
+/// Quotes `s` with single quotes so that the shell passes it as a single word without expanding
+/// anything in it.
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r#"'\''"#))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn quotes_words() {
+        assert_eq!(quote("usr/share/doc/my file"), "'usr/share/doc/my file'");
+        assert_eq!(quote("it's $HOME"), r#"'it'\''s $HOME'"#);
+    }
+}
@@ -0,0 +1,39 @@
+//! Helpers for text files uploaded to containers
+
+use crate::{ErrContext, Result};
+
+use std::borrow::Cow;
+
+/// UTF-8 byte order mark often prepended to files by editors on Windows.
+static BOM: &str = "\u{feff}";
+
+/// Validates that `data` is valid UTF-8, strips a leading byte order mark and converts Windows
+/// line endings (`\r\n`) to Unix ones (`\n`). The `name` of the file is only used in the error
+/// message.
+pub fn normalize<'data>(name: &str, data: &'data [u8]) -> Result<Cow<'data, [u8]>> {
+    let text = std::str::from_utf8(data).context(format!("file `{}` is not valid UTF-8", name))?;
+    let text = text.strip_prefix(BOM).unwrap_or(text);
+
+    if text.contains("\r\n") {
+        Ok(Cow::Owned(text.replace("\r\n", "\n").into_bytes()))
+    } else {
+        Ok(Cow::Borrowed(text.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn normalizes_text() {
+        let data = "\u{feff}#!/bin/sh\r\necho 'test'\r\nexit 0\n";
+        let expected = "#!/bin/sh\necho 'test'\nexit 0\n";
+
+        let normalized = normalize("test", data.as_bytes()).unwrap();
+
+        assert_eq!(std::str::from_utf8(&normalized).unwrap(), expected);
+        assert!(normalize("test", &[0x66, 0xff, 0x0a]).is_err());
+    }
+}
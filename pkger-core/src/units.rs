@@ -0,0 +1,59 @@
+//! Human-friendly formatting of sizes and durations displayed in the output
+
+use std::time::Duration;
+
+const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+/// Formats `bytes` in the largest binary unit in which the value is at least 1, like `45.2 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if value < 1024. {
+            break;
+        }
+        value /= 1024.;
+        unit = next;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+/// Formats the `duration` with the two most significant units like `1h 23m` or `4m 5s`. Durations
+/// shorter than a minute are displayed with a precision of a tenth of a second.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else if duration.as_millis() >= 100 {
+        format!("{:.1}s", duration.as_secs_f32())
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(2048), "2.0 KiB");
+        assert_eq!(format_size(47_395_635), "45.2 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_millis(42)), "42ms");
+        assert_eq!(format_duration(Duration::from_millis(12_340)), "12.3s");
+        assert_eq!(format_duration(Duration::from_secs(245)), "4m 5s");
+        assert_eq!(format_duration(Duration::from_secs(4980)), "1h 23m");
+    }
+}